@@ -0,0 +1,79 @@
+//! Build script for `vicut`.
+//!
+//! Collects information about the build, which is baked into the binary and printed by `--build-info`.
+use std::{env, fs, path::Path, process::Command};
+
+/// Dependencies whose versions are reported in the build info, along with the feature that pulls them in
+const REPORTED_DEPS: [(&str,Option<&str>);4] = [
+	("regex", None),
+	("tikv-jemallocator", Some("JEMALLOC")),
+	("rayon", Some("PARALLEL")),
+	("pest", Some("SCRIPT")),
+];
+
+fn main() {
+	let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+	let manifest_dir = Path::new(&manifest_dir);
+
+	println!("cargo:rustc-env=VICUT_GIT_HASH={}", git_hash(manifest_dir));
+	println!("cargo:rustc-env=VICUT_TARGET={}", env::var("TARGET").unwrap_or_default());
+	println!("cargo:rustc-env=VICUT_PROFILE={}", env::var("PROFILE").unwrap_or_default());
+
+	// jemalloc is only used for linux targets that link against glibc.
+	// On musl we fall back to the system allocator, so that static builds just work.
+	println!("cargo::rustc-check-cfg=cfg(jemalloc)");
+	let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+	let target_env = env::var("CARGO_CFG_TARGET_ENV").unwrap_or_default();
+	if env::var_os("CARGO_FEATURE_JEMALLOC").is_some() && target_os == "linux" && target_env != "musl" {
+		println!("cargo:rustc-cfg=jemalloc");
+	}
+
+	let mut features = env::vars()
+		.filter_map(|(key,_)| key.strip_prefix("CARGO_FEATURE_").map(|feat| feat.to_lowercase().replace('_', "-")))
+		.collect::<Vec<_>>();
+	features.sort();
+	println!("cargo:rustc-env=VICUT_FEATURES={}", features.join(","));
+
+	let lockfile = fs::read_to_string(manifest_dir.join("Cargo.lock")).unwrap_or_default();
+	let deps = REPORTED_DEPS.iter()
+		.filter(|(_,feature)| feature.is_none_or(|feat| env::var_os(format!("CARGO_FEATURE_{feat}")).is_some()))
+		.filter_map(|(dep,_)| locked_version(&lockfile, dep).map(|ver| format!("{dep}={ver}")))
+		.collect::<Vec<_>>();
+	println!("cargo:rustc-env=VICUT_DEP_VERSIONS={}", deps.join(","));
+
+	println!("cargo:rerun-if-changed=Cargo.lock");
+	// Only watch git files that actually exist, otherwise cargo reruns this script on every build
+	let git_head = manifest_dir.join(".git/HEAD");
+	if git_head.exists() {
+		println!("cargo:rerun-if-changed=.git/HEAD");
+		if let Some(head_ref) = fs::read_to_string(&git_head).ok()
+			.and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+			&& manifest_dir.join(".git").join(&head_ref).exists() {
+			println!("cargo:rerun-if-changed=.git/{head_ref}");
+		}
+	}
+}
+
+/// Get the short hash of the current git commit, or "unknown" if we aren't in a git repo
+fn git_hash(dir: &Path) -> String {
+	Command::new("git")
+		.args(["rev-parse", "--short", "HEAD"])
+		.current_dir(dir)
+		.output()
+		.ok()
+		.filter(|out| out.status.success())
+		.map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+		.unwrap_or_else(|| "unknown".into())
+}
+
+/// Find the version of a package in the contents of Cargo.lock
+fn locked_version(lockfile: &str, name: &str) -> Option<String> {
+	let name_line = format!("name = \"{name}\"");
+	let mut lines = lockfile.lines();
+	lines.find(|line| line.trim() == name_line)?;
+	lines.next()?
+		.trim()
+		.strip_prefix("version = \"")?
+		.strip_suffix('"')
+		.map(|ver| ver.to_string())
+}
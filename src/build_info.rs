@@ -0,0 +1,50 @@
+//! Information about how this binary was built.
+//!
+//! The values here are collected by `build.rs`, and are printed by `--build-info` (or `--version --verbose`)
+//! to help with triaging bug reports.
+use serde_json::{Map, Value};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("VICUT_GIT_HASH");
+pub const TARGET: &str = env!("VICUT_TARGET");
+pub const PROFILE: &str = env!("VICUT_PROFILE");
+/// Comma separated list of enabled cargo features
+pub const FEATURES: &str = env!("VICUT_FEATURES");
+/// Comma separated list of `name=version` pairs
+pub const DEP_VERSIONS: &str = env!("VICUT_DEP_VERSIONS");
+
+/// The name of the global allocator in use
+pub fn allocator() -> &'static str {
+	if cfg!(jemalloc) {
+		"jemalloc"
+	} else {
+		"system"
+	}
+}
+
+/// Build the build info as a JSON object
+pub fn as_json() -> Value {
+	let mut obj = Map::new();
+	obj.insert("name".into(), Value::String("vicut".into()));
+	obj.insert("version".into(), Value::String(VERSION.into()));
+	obj.insert("git_commit".into(), Value::String(GIT_HASH.into()));
+	obj.insert("target".into(), Value::String(TARGET.into()));
+	obj.insert("profile".into(), Value::String(PROFILE.into()));
+	obj.insert("allocator".into(), Value::String(allocator().into()));
+	obj.insert("static".into(), Value::Bool(cfg!(target_feature = "crt-static")));
+
+	let features = FEATURES.split(',')
+		.filter(|feat| !feat.is_empty())
+		.map(|feat| Value::String(feat.into()))
+		.collect();
+	obj.insert("features".into(), Value::Array(features));
+
+	let mut deps = Map::new();
+	for dep in DEP_VERSIONS.split(',') {
+		let Some((name,version)) = dep.split_once('=') else { continue };
+		deps.insert(name.into(), Value::String(version.into()));
+	}
+	obj.insert("dependencies".into(), Value::Object(deps));
+
+	Value::Object(obj)
+}
@@ -1,7 +1,8 @@
 //! This module contains the `ViCut` struct, which is the central container for state in the program.
 //!
 //! Everything that moves through this program passes through the `ViCut` struct at some point.
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt::Display;
 
 use log::{debug, trace};
@@ -9,14 +10,15 @@ use regex::Regex;
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::keys::{KeyCode, KeyEvent, ModKeys};
-use crate::linebuf::{ordered, ordered_signed, ClampedUsize, MotionKind};
+use crate::linebuf::{apply_hunks, coerce_case, diff_hunks, ordered, ordered_signed, token_frequencies, BufSettings, ClampedUsize, CustomTextObj, Hunk, MotionKind, WORD_TOKEN};
 use crate::modes::ex::ViEx;
 use crate::modes::search::ViSearch;
 use crate::reader::{KeyReader, RawReader};
-use crate::register::read_register;
+use crate::register::{read_register, RegisterContent};
+use crate::shell::ShellCfg;
 use crate::vic::{BinOp, BoolOp, CmdArg, Expr};
-use crate::vicmd::{Bound, LineAddr, Word};
-use crate::{complain_and_exit, Cmd, ExecCtx};
+use crate::vicmd::{Bound, Case, LineAddr, Word, TEXT_OBJ_KEYS};
+use crate::{complain_and_exit, Cmd, ExecCtx, FieldVal, Unwind};
 
 use super::linebuf::{LineBuf, SelectAnchor, SelectMode};
 use super::vicmd::{CmdFlags, Motion, MotionCmd, RegisterName, Verb, VerbCmd, ViCmd};
@@ -29,6 +31,7 @@ pub enum Val {
 	Str(String),
 	Arr(Vec<Val>),
 	Num(isize),
+	Float(f64),
 	Bool(bool),
 	Regex(Regex),
 }
@@ -39,6 +42,9 @@ impl PartialEq for Val {
 			(Val::Str(s1), Val::Str(s2)) => s1 == s2,
 			(Val::Arr(a1), Val::Arr(a2)) => a1 == a2,
 			(Val::Num(n1), Val::Num(n2)) => n1 == n2,
+			(Val::Float(f1), Val::Float(f2)) => f1 == f2,
+			(Val::Num(n), Val::Float(f)) |
+			(Val::Float(f), Val::Num(n)) => *n as f64 == *f,
 			(Val::Bool(b1), Val::Bool(b2)) => b1 == b2,
 			(Val::Null, Val::Null) => true,
 			(Val::Regex(r1), Val::Regex(r2)) => r1.as_str() == r2.as_str(),
@@ -72,7 +78,7 @@ pub enum CompoundVal {
 impl CompoundVal {
 	pub fn len(&self) -> usize {
 		match self {
-			CompoundVal::Str(s) => s.len(),
+			CompoundVal::Str(s) => s.graphemes(true).count(),
 			CompoundVal::Arr(arr) => arr.len(),
 		}
 	}
@@ -86,8 +92,8 @@ impl CompoundVal {
 		let len = self.len();
 		match self {
 			CompoundVal::Str(s) => {
-				if let Some((i,_)) = s.grapheme_indices(true).nth(index) {
-					s.replace_range(i..=i, &value.to_string());
+				if let Some((i,g)) = s.grapheme_indices(true).nth(index) {
+					s.replace_range(i..i + g.len(), &value.to_string());
 				} else {
 					let padding = index.saturating_sub(len);
 					if padding > 0 {
@@ -103,6 +109,36 @@ impl CompoundVal {
 			}
 		}
 	}
+	/// Turn a possibly negative index into a position, where negative indices count from the end.
+	///
+	/// Returns `None` if the index falls before the start.
+	pub fn resolve_index(&self, index: isize) -> Option<usize> {
+		if index < 0 {
+			self.len().checked_sub(index.unsigned_abs())
+		} else {
+			Some(index as usize)
+		}
+	}
+	/// Take the elements (or graphemes) between `start` and `end`.
+	///
+	/// Missing bounds default to the start and end, negative bounds count from the end,
+	/// and bounds past either end are clamped, so slicing never fails.
+	pub fn slice(self, start: Option<isize>, end: Option<isize>, inclusive: bool) -> CompoundVal {
+		let len = self.len();
+		let start = start.map(|i| self.resolve_index(i).unwrap_or(0)).unwrap_or(0).min(len);
+		let end = match end {
+			Some(i) => {
+				let end = self.resolve_index(i).map(|e| if inclusive { e + 1 } else { e });
+				end.unwrap_or(0).min(len)
+			}
+			None => len
+		};
+		let count = end.saturating_sub(start);
+		match self {
+			CompoundVal::Str(s) => CompoundVal::Str(s.graphemes(true).skip(start).take(count).collect()),
+			CompoundVal::Arr(arr) => CompoundVal::Arr(arr.into_iter().skip(start).take(count).collect()),
+		}
+	}
 }
 
 impl IntoIterator for CompoundVal {
@@ -134,21 +170,138 @@ impl TryFrom<Val> for CompoundVal {
 	}
 }
 
+/// Hunks are given to scripts as `[line, removed, added]`, with a line number counting from one
+impl From<Hunk> for Val {
+	fn from(hunk: Hunk) -> Self {
+		let lines = |lines: Vec<String>| Val::Arr(lines.into_iter().map(Val::Str).collect());
+		Val::Arr(vec![
+			Val::Num(hunk.line as isize + 1),
+			lines(hunk.removed),
+			lines(hunk.added),
+		])
+	}
+}
+
+impl TryFrom<&Val> for Hunk {
+	type Error = String;
+
+	fn try_from(value: &Val) -> Result<Self, Self::Error> {
+		let lines = |lines: &[Val]| lines.iter().map(|line| line.to_string()).collect::<Vec<_>>();
+		match value {
+			Val::Arr(hunk) => match hunk.as_slice() {
+				[Val::Num(line), Val::Arr(removed), Val::Arr(added)] if *line > 0 => Ok(Hunk {
+					line: *line as usize - 1,
+					removed: lines(removed),
+					added: lines(added),
+				}),
+				_ => Err(format!("Expected a hunk like [line, removed, added], got {value}"))
+			}
+			_ => Err(format!("Expected a hunk, got {}", value.display_type()))
+		}
+	}
+}
+
 impl Val {
 	pub fn display_type(&self) -> String {
 		match self {
 			Self::Str(_) => "string".to_string(),
 			Self::Num(_) => "number".to_string(),
+			Self::Float(_) => "float".to_string(),
 			Self::Arr(_) => "array".to_string(),
 			Self::Bool(_) => "boolean".to_string(),
 			Self::Regex(_) => "regex".to_string(),
 			Self::Null => "null".to_string()
 		}
 	}
+	pub fn is_number(&self) -> bool {
+		matches!(self, Self::Num(_) | Self::Float(_))
+	}
+	pub fn as_float(&self) -> Option<f64> {
+		match self {
+			Self::Num(n) => Some(*n as f64),
+			Self::Float(f) => Some(*f),
+			_ => None
+		}
+	}
+	/// Apply an arithmetic operator to two numbers.
+	///
+	/// Two integers give an integer, and anything involving a float gives a float.
+	pub fn arith(op: &BinOp, left: &Val, right: &Val) -> Result<Val,String> {
+		match (left, right) {
+			(Val::Num(left), Val::Num(right)) => {
+				let (left, right) = (*left, *right);
+				if matches!(op, BinOp::Div | BinOp::Mod) && right == 0 {
+					return Err("Division by zero".to_string())
+				}
+				if matches!(op, BinOp::Pow) && right < 0 {
+					// A negative exponent can't produce an integer
+					return Ok(Val::Float((left as f64).powf(right as f64)))
+				}
+				// Integer arithmetic is checked, so that counters over huge inputs fail loudly instead of wrapping
+				let result = match op {
+					BinOp::Add => left.checked_add(right),
+					BinOp::Sub => left.checked_sub(right),
+					BinOp::Mult => left.checked_mul(right),
+					BinOp::Div => left.checked_div(right),
+					BinOp::Mod => left.checked_rem(right),
+					BinOp::Pow => u32::try_from(right).ok().and_then(|exp| left.checked_pow(exp)),
+					BinOp::Equals => Some(right)
+				};
+				result
+					.map(Val::Num)
+					.ok_or_else(|| format!("Integer overflow in {left} {op} {right}"))
+			}
+			(left, right) if left.is_number() && right.is_number() => {
+				let (left, right) = (left.as_float().unwrap(), right.as_float().unwrap());
+				if matches!(op, BinOp::Div | BinOp::Mod) && right == 0.0 {
+					return Err("Division by zero".to_string())
+				}
+				Ok(Val::Float(match op {
+					BinOp::Add => left + right,
+					BinOp::Sub => left - right,
+					BinOp::Mult => left * right,
+					BinOp::Div => left / right,
+					BinOp::Mod => left % right,
+					BinOp::Pow => left.powf(right),
+					BinOp::Equals => right
+				}))
+			}
+			(left, _) if !left.is_number() => Err(format!("Left value {left} is not a number")),
+			(_, right) => Err(format!("Right value {right} is not a number"))
+		}
+	}
+	/// Order two values, for the `<`, `<=`, `>`, and `>=` operators.
+	///
+	/// Numbers are ordered numerically, and strings lexicographically.
+	/// Arrays are ordered element by element, and an array that is a prefix of another one comes first.
+	pub fn try_cmp(&self, other: &Val) -> Result<Ordering,String> {
+		match (self, other) {
+			(Val::Num(n1), Val::Num(n2)) => Ok(n1.cmp(n2)),
+			(v1, v2) if v1.is_number() && v2.is_number() => {
+				let (f1, f2) = (v1.as_float().unwrap(), v2.as_float().unwrap());
+				f1.partial_cmp(&f2).ok_or_else(|| "Cannot order NaN".to_string())
+			}
+			(Val::Str(s1), Val::Str(s2)) => Ok(s1.cmp(s2)),
+			(Val::Arr(a1), Val::Arr(a2)) => {
+				for (v1,v2) in a1.iter().zip(a2) {
+					let ordering = v1.try_cmp(v2)?;
+					if ordering.is_ne() {
+						return Ok(ordering)
+					}
+				}
+				Ok(a1.len().cmp(&a2.len()))
+			}
+			(v1, v2) if std::mem::discriminant(v1) == std::mem::discriminant(v2) => {
+				Err(format!("Cannot order {} values", v1.display_type()))
+			}
+			(v1, v2) => Err(format!("Cannot compare {} with {}", v1.display_type(), v2.display_type()))
+		}
+	}
 	pub fn is_truthy(&self) -> bool {
 		match self {
 			Self::Str(s) => !s.is_empty(),
 			Self::Num(n) => *n != 0,
+			Self::Float(f) => *f != 0.0,
 			Self::Arr(arr) => !arr.is_empty(),
 			Self::Bool(b) => *b,
 			Self::Null => false,
@@ -171,6 +324,8 @@ impl Display for Val {
 			}
 			Self::Str(s) => write!(f, "{s}"),
 			Self::Num(n) => write!(f, "{n}"),
+			// Debug formatting keeps the '.0' on whole numbers, so floats still look like floats
+			Self::Float(float) => write!(f, "{float:?}"),
 			Self::Bool(b) => write!(f, "{b}"),
 			Self::Regex(r) => write!(f, "{r}"),
 			Self::Null => write!(f, "null")
@@ -178,12 +333,74 @@ impl Display for Val {
 	}
 }
 
+/// What happens to the line endings in a captured field, set with `--field-newlines`
+#[derive(Default,Clone,Copy,Debug,PartialEq)]
+pub enum FieldNewlines {
+	/// Leave the field the way that it was captured
+	#[default]
+	Keep,
+	/// Drop the line ending at the end of the field, which linewise captures like `V` pick up
+	Strip,
+	/// Drop the line ending at the end of the field, and write the rest of them as `\n`, so that every field fits on one line
+	Escape
+}
+
+impl FieldNewlines {
+	pub fn apply(&self, field: String) -> String {
+		if *self == FieldNewlines::Keep {
+			return field
+		}
+		let stripped = field.strip_suffix('\n').map(|field| field.strip_suffix('\r').unwrap_or(field));
+		let field = stripped.unwrap_or(&field);
+		match self {
+			FieldNewlines::Escape => field.replace('\\', "\\\\").replace('\r', "\\r").replace('\n', "\\n"),
+			_ => field.to_string()
+		}
+	}
+}
+
+impl TryFrom<&str> for FieldNewlines {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value.trim().to_ascii_lowercase().as_str() {
+			"keep" => Ok(FieldNewlines::Keep),
+			"strip" => Ok(FieldNewlines::Strip),
+			"escape" => Ok(FieldNewlines::Escape),
+			_ => Err(format!("Expected 'keep', 'strip', or 'escape' for field newlines, found '{value}'"))
+		}
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct VicFunc {
 	pub args: Vec<String>,
 	pub body: Vec<Cmd>,
 }
 
+/// The read-only variables that describe where the input came from
+///
+/// Every record sets these, so they live here instead of in a stack frame where they would have to be hashed in each time.
+#[derive(Default,Debug,Clone)]
+pub struct InputVars {
+	pub filename: String,
+	pub filepath: String,
+	pub filedir: String,
+	pub filetype: String,
+}
+
+impl InputVars {
+	const NAMES: [&str;4] = ["filename", "filepath", "filedir", "filetype"];
+	fn get(&self, name: &str) -> Option<&str> {
+		match name {
+			"filename" => Some(&self.filename),
+			"filepath" => Some(&self.filepath),
+			"filedir" => Some(&self.filedir),
+			"filetype" => Some(&self.filetype),
+			_ => None
+		}
+	}
+}
+
 pub struct ViCut {
 	pub reader: RawReader,
 	pub mode: Box<dyn ViMode>,
@@ -197,16 +414,37 @@ pub struct ViCut {
 	/// So you can shadow variables in vic
 	/// The outer-most hashmap always contains the built-in variables
 	pub variables: Vec<HashMap<String, Val>>,
+	/// The names in each stack frame that can't be written to.
+	/// These are the `const` declarations, and built-ins like `fields`.
+	pub constants: Vec<HashSet<String>>,
+	/// `$filename` and the other read-only variables that describe the input
+	pub input_vars: InputVars,
 	/// We do the same stack frame thing for functions
 	/// Though might not be as necessary,
 	/// we do want all of our user definitions, variable or otherwise, to be scoped
 	/// This way we can get away with having built-in functions *and* not reserving the function names
 	pub functions: Vec<HashMap<String, VicFunc>>,
+	/// Set by `--sandbox`. Refuses any command that would touch the world outside of the buffer.
+	pub sandbox: bool,
+	/// Settings for shell commands, handed to every buffer we open
+	pub shell: ShellCfg,
+	/// What every buffer we open is set up with
+	pub settings: BufSettings,
+	/// Set by `--field-newlines`, and applied to every field read with `read_field()`
+	pub field_newlines: FieldNewlines,
+	/// The register that `q{reg}` is recording into
+	pub recording: Option<RegisterName>,
+	/// The register that was last played with `@{reg}`, for `@@`
+	pub last_macro: Option<char>,
+	/// How many macros are being played inside of each other right now
+	pub macro_depth: usize,
 }
 
 
 impl ViCut {
-	const BUILTINS: [&str;12] = [
+	/// How deeply macros can play each other, so that a macro that plays itself fails instead of hanging
+	const MAX_MACRO_DEPTH: usize = 100;
+	const BUILTINS: [&str;14] = [
 		"col",
 		"line",
 		"lines",
@@ -218,7 +456,9 @@ impl ViCut {
 		"is_eof",
 		"is_eol",
 		"is_bof",
-		"char"
+		"char",
+		"sub_count",
+		"sub_changes"
 	];
 	pub fn new(input: String, cursor: usize) -> Result<Self,String> {
 		Ok(Self {
@@ -237,7 +477,16 @@ impl ViCut {
 			// User definitions can shadow built-ins this way.
 			// Never allow these vectors to dip below length 2.
 			variables: vec![HashMap::new(),HashMap::new()],
+			constants: vec![HashSet::new(),HashSet::new()],
+			input_vars: InputVars::default(),
 			functions: vec![HashMap::new(),HashMap::new()],
+			sandbox: false,
+			shell: ShellCfg::default(),
+			settings: BufSettings::default(),
+			field_newlines: FieldNewlines::default(),
+			recording: None,
+			last_macro: None,
+			macro_depth: 0,
 		})
 	}
 	pub fn exec_loop(&mut self) -> Result<(),String> {
@@ -245,6 +494,10 @@ impl ViCut {
 			let Some(key) = self.reader.read_key() else {
 				break
 			};
+			if self.is_end_of_recording(&key) {
+				self.stop_recording();
+				continue
+			}
 
 			let Some(mut cmd) = self.mode.handle_key_fallible(key)? else {
 				continue
@@ -284,26 +537,106 @@ impl ViCut {
 
 	pub fn push_buffer(&mut self, buffer: impl ToString) {
 		let buf = buffer.to_string();
-		let new_buffer = LineBuf::new().with_initial(buf, 0);
+		let mut new_buffer = LineBuf::new().with_initial(buf, 0).with_settings(&self.settings);
+		new_buffer.shell = self.shell.clone();
 		self.buffers.push(new_buffer);
 		self.editor.set_max(self.buffers.len());
 	}
 
+	pub fn set_shell_cfg(&mut self, shell: ShellCfg) {
+		for buf in self.buffers.iter_mut() {
+			buf.shell = shell.clone();
+		}
+		self.shell = shell;
+	}
+
+	pub fn set_settings(&mut self, settings: BufSettings) {
+		for buf in self.buffers.iter_mut() {
+			buf.apply_settings(&settings);
+		}
+		self.settings = settings;
+	}
+
+	pub fn set_field_newlines(&mut self, policy: FieldNewlines) {
+		self.field_newlines = policy;
+	}
+
+	/// Make `i{key}` and `a{key}` select `obj`, replacing any earlier object with the same key
+	pub fn define_text_obj(&mut self, key: char, obj: CustomTextObj) -> Result<(),String> {
+		if TEXT_OBJ_KEYS.contains(key) {
+			return Err(format!("'{key}' is already used by a built-in text object"))
+		}
+		if let CustomTextObj::Delim(open,close) = &obj && (open.is_empty() || close.is_empty()) {
+			return Err("The delimiters of a text object can't be empty".to_string())
+		}
+		for buf in self.buffers.iter_mut() {
+			buf.custom_text_objs.insert(key, obj.clone());
+		}
+		self.settings.custom_text_objs.insert(key, obj);
+		Ok(())
+	}
+
 	pub fn current_buffer_index(&self) -> usize {
 		self.editor.get()
 	}
 
+	/// Hand `lines` to the current buffer, which moves them along with its edits until they are taken back with `take_line_marks()`.
+	///
+	/// Returns the index of the buffer that has them.
+	pub fn mark_lines(&mut self, lines: VecDeque<usize>) -> usize {
+		self.current_buffer().line_marks.push(lines);
+		self.current_buffer_index()
+	}
+
+	/// Take back the lines that `mark_lines()` handed to buffer `idx`
+	pub fn take_line_marks(&mut self, idx: usize) -> VecDeque<usize> {
+		self.buffers.get_mut(idx)
+			.and_then(|buf| buf.line_marks.pop())
+			.unwrap_or_default()
+	}
+
 	pub fn pop_buffer(&mut self) -> String {
 		let mut popped = self.buffers.pop().unwrap_or_default(); // Should never be empty, but just in case
 		if self.buffers.is_empty() {
-			self.buffers.push(LineBuf::new()); // Always keep at least one buffer
-																				 // Similar to how Vim works interactively
+			let mut new_buffer = LineBuf::new().with_settings(&self.settings);
+			new_buffer.shell = self.shell.clone();
+			self.buffers.push(new_buffer); // Always keep at least one buffer
+																		 // Similar to how Vim works interactively
 		}
 		self.editor.set_max(self.buffers.len());
 		popped.take_buf()
 	}
 
-	pub fn read_field(&mut self, cmd: &str) -> Result<String,String> {
+	/// Match `regex` against the buffer from the cursor onward, for `-c pattern=`.
+	///
+	/// Returns the name and text of each named group, in the order that they appear in the pattern.
+	/// Groups that didn't take part in the match come back empty. The cursor is left just past the match.
+	/// If nothing matches, there are no fields and the cursor stays put.
+	pub fn read_pattern_fields(&mut self, regex: &Regex) -> Vec<(String,String)> {
+		let buf = self.current_buffer();
+		let start = buf.cursor_byte_pos();
+		let Some(caps) = regex.captures_at(&buf.buffer, start) else {
+			return vec![]
+		};
+		let fields = regex.capture_names()
+			.flatten()
+			.map(|name| (name.to_string(), caps.name(name).map_or("", |mat| mat.as_str()).to_string()))
+			.collect();
+		let end = caps.get(0).unwrap().end();
+		let end = buf.units.count(&buf.buffer[..end]);
+		buf.cursor.set(end);
+		fields
+	}
+	/// Run `cmd` and capture what it moved over or selected, with the `--field-newlines` policy applied
+	pub fn read_field(&mut self, cmd: &str) -> Result<FieldVal,String> {
+		let policy = self.field_newlines;
+		Ok(match self.read_raw_field(cmd)? {
+			FieldVal::Str(field) => FieldVal::Str(policy.apply(field)),
+			FieldVal::Block(lines) => FieldVal::Block(lines.into_iter().map(|line| policy.apply(line)).collect()),
+			FieldVal::Buffer(text) => FieldVal::Buffer(text)
+		})
+	}
+	fn read_raw_field(&mut self, cmd: &str) -> Result<FieldVal,String> {
 		self.load_input(cmd);
 		let mut start = self.current_buffer().cursor.get();
 		let mut end;
@@ -318,13 +651,18 @@ impl ViCut {
 
 
 
-		if self.current_buffer().select_range().is_some() {
+		if let Some(block) = self.current_buffer().selected_block_content() {
+			// A visual block keeps its lines apart, so that they can be indexed in templates and come out as a JSON array
+			Ok(FieldVal::Block(block))
+		} else if self.current_buffer().select_range().is_some() {
 			// We are in visual mode if we've made it here
 			// So we are going to use the editor's selected content
-			Ok(self.current_buffer().selected_content().unwrap())
+			self.current_buffer().selected_content()
+				.map(FieldVal::from)
+				.ok_or_else(|| "The selection is outside of the buffer".to_string())
 		} else {
 			if self.current_buffer().buffer.is_empty() {
-				return Ok(String::new())
+				return Ok(FieldVal::default())
 			}
 			let start = ClampedUsize::new(start, self.current_buffer().cursor.cap(), true);
 			let end = ClampedUsize::new(end, self.current_buffer().cursor.cap(), false);
@@ -339,7 +677,7 @@ impl ViCut {
 			} else {
 				trace!("Failed to slice buffer from cursor motion");
 			}
-			slice
+			slice.map(FieldVal::from)
 		}
 	}
 
@@ -395,16 +733,18 @@ impl ViCut {
 			}
 
 			Verb::VisualModeSelectLast => {
-				if self.mode.report_mode() != ModeReport::Visual {
-					self.current_buffer().start_selecting(SelectMode::Char(SelectAnchor::Start));
+				// Like vim, there is nothing to do if nothing has been selected yet
+				if !self.current_buffer().reselect_last() {
+					return Ok(())
 				}
+				self.current_buffer().extend_selection(count.saturating_sub(1));
 				self.current_buffer().inserting_from_visual = false;
 				let mut mode: Box<dyn ViMode> = Box::new(ViVisual::new());
 				std::mem::swap(&mut mode, &mut self.mode);
 				let should_clamp = self.mode.clamp_cursor();
 				self.current_buffer().set_cursor_clamp(should_clamp);
 
-				return self.current_buffer().exec_cmd(cmd)
+				return Ok(())
 			}
 			Verb::VisualMode => {
 				select_mode = Some(SelectMode::Char(SelectAnchor::Start));
@@ -454,6 +794,7 @@ impl ViCut {
 
 		if let Some(select_mode) = select_mode {
 			self.current_buffer().start_selecting(select_mode);
+			self.current_buffer().extend_selection(count.saturating_sub(1));
 		} else {
 			self.current_buffer().stop_selecting();
 		}
@@ -539,6 +880,14 @@ impl ViCut {
 	}
 
 	pub fn exec_cmd(&mut self, mut cmd: ViCmd) -> Result<(),String> {
+		if self.sandbox && cmd.verb().is_some_and(|v| v.1.is_external()) {
+			return Err(format!("'{}' is not allowed in sandbox mode",cmd.raw_seq.trim()))
+		}
+		// Like Vim, an empty expression puts the last result again
+		if let Some(expr) = cmd.expr_register().filter(|expr| !expr.trim().is_empty()) {
+			let val = self.eval_expr_register(expr)?;
+			cmd.register.write_to_register(RegisterContent::Span(val.to_string()));
+		}
 		if cmd.is_mode_transition() {
 			return self.handle_mode_transition(cmd)
 
@@ -554,6 +903,9 @@ impl ViCut {
 		} else if cmd.is_ex_normal() {
 			return self.exec_ex_normal(cmd)
 
+		} else if cmd.is_macro() {
+			return self.exec_macro(cmd)
+
 		}
 
 		if cmd.is_repeatable() {
@@ -579,7 +931,8 @@ impl ViCut {
 		self.current_buffer().set_cursor_clamp(should_clamp);
 		self.current_buffer().exec_cmd(cmd.clone())?;
 
-		if self.mode.report_mode() == ModeReport::Visual && cmd.verb().is_some_and(|v| v.1.is_edit()) {
+		// Yanking ends the selection too, even though it doesn't change anything
+		if self.mode.report_mode() == ModeReport::Visual && cmd.verb().is_some_and(|v| v.1.is_edit() || v.1 == Verb::Yank) {
 			self.current_buffer().stop_selecting();
 			let mut mode: Box<dyn ViMode> = Box::new(ViNormal::new());
 			std::mem::swap(&mut mode, &mut self.mode);
@@ -606,6 +959,76 @@ impl ViCut {
 
 		Ok(())
 	}
+	/// A 'q' in normal mode with nothing pending ends the recording, like in vim
+	fn is_end_of_recording(&self, key: &KeyEvent) -> bool {
+		self.reader.is_recording()
+			&& self.mode.report_mode() == ModeReport::Normal
+			&& self.mode.pending_seq().is_none_or(|seq| seq.is_empty())
+			&& *key == KeyEvent(KeyCode::Char('q'), ModKeys::NONE)
+	}
+	fn stop_recording(&mut self) {
+		let Some(mut keys) = self.reader.stop_recording() else { return };
+		keys.pop(); // The 'q' that ended the recording
+		if let Some(register) = self.recording.take() {
+			register.write_to_register(RegisterContent::Span(String::from_utf8_lossy(&keys).into_owned()));
+		}
+	}
+	/// Evaluate an expression given to the `"=` register.
+	///
+	/// Variables and functions come from the current scope, but the expression gets a context of its own,
+	/// so anything it captures with 'return' is not part of the record.
+	///
+	/// A bad expression only skips the command that it was given to, so it also fails the run.
+	fn eval_expr_register(&mut self, raw: &str) -> Result<Val,String> {
+		let args = crate::Opts::default();
+		let mut ctx = ExecCtx::new(&args);
+		let result = crate::vic::parse_expr(raw).and_then(|expr| self.eval_expr(&expr, &mut ctx));
+		if result.is_err() {
+			crate::set_exit_code(1);
+		}
+		result
+	}
+	fn exec_macro(&mut self, cmd: ViCmd) -> Result<(),String> {
+		let VerbCmd(count, verb) = cmd.verb.unwrap();
+		match verb {
+			Verb::RecordMacro(reg) => {
+				self.recording = Some(RegisterName::new(Some(reg), None));
+				self.reader.start_recording();
+			}
+			Verb::PlayMacro(reg) => {
+				let reg = match reg {
+					'@' => {
+						let Some(reg) = self.last_macro else { return Ok(()) };
+						reg
+					}
+					_ => reg.to_ascii_lowercase()
+				};
+				self.last_macro = Some(reg);
+				let Some(keys) = read_register(Some(reg)).map(|content| content.to_string()) else {
+					return Ok(())
+				};
+				if self.macro_depth >= Self::MAX_MACRO_DEPTH {
+					return Err(format!("'@{reg}' was played more than {} macros deep, does it play itself?", Self::MAX_MACRO_DEPTH))
+				}
+
+				// The keys that come after '@{reg}' have to wait until the macro is done.
+				// Recording is paused too, since the '@{reg}' that was just read is already part of the recording.
+				let rest = std::mem::take(&mut self.reader.bytes);
+				let recording = self.reader.recording.take();
+				self.macro_depth += 1;
+				let result = (0..count).try_for_each(|_| {
+					self.reader.load_bytes(keys.as_bytes());
+					self.exec_loop()
+				});
+				self.macro_depth -= 1;
+				self.reader.set_bytes(rest);
+				self.reader.recording = recording;
+				result?;
+			}
+			_ => unreachable!()
+		}
+		Ok(())
+	}
 	fn exec_ex_normal(&mut self, cmd: ViCmd) -> Result<(),String> {
 		let ViCmd { register: _, verb, motion, raw_seq: _, flags: _ } = cmd;
 		let VerbCmd(_,Verb::Normal(seq)) = verb.unwrap() else { unreachable!() };
@@ -648,12 +1071,14 @@ impl ViCut {
 	}
 	pub fn descend(&mut self) {
 		self.variables.push(HashMap::new());
+		self.constants.push(HashSet::new());
 		self.functions.push(HashMap::new());
 	}
 	pub fn ascend(&mut self) {
 		// Never pop the built-in/global scopes
 		if self.variables.len() > 2 {
 			self.variables.pop();
+			self.constants.pop();
 		}
 		if self.functions.len() > 2 {
 			self.functions.pop();
@@ -681,6 +1106,9 @@ impl ViCut {
 				return frame.get(name).cloned()
 			}
 		}
+		if let Some(val) = self.input_vars.get(name) {
+			return Some(Val::Str(val.to_string()))
+		}
 		if Self::BUILTINS.contains(&name) {
 			// If the variable is a built-in, we return it as a Val::Str
 			// This is to allow for built-in variables like 'col', 'line', etc.
@@ -721,6 +1149,14 @@ impl ViCut {
 					.map(|gr| Val::Str(gr.to_string()))
 					.unwrap_or(Val::Str(String::new()))
 			}
+			"sub_count" => Val::Num(self.current_buffer().sub_spans.len() as isize),
+			"sub_changes" => {
+				let buf = self.current_buffer();
+				let changes = buf.sub_spans.clone().into_iter()
+					.map(|(start,end)| Val::Str(buf.slice(start..end).unwrap_or_default().to_string()))
+					.collect();
+				Val::Arr(changes)
+			}
 			_ => unreachable!()
 		})
 	}
@@ -728,10 +1164,82 @@ impl ViCut {
 		if &name == "buffers" {
 			return Err("'buffers' is a reserved variable name and cannot be set".to_string())
 		}
+		self.check_writable(&name)?;
+		let Some(frame) = self.variables.last_mut() else {
+			panic!("There is supposed to be a stack frame here")
+		};
+		frame.insert(name, value);
+		Ok(())
+	}
+	/// Bind a variable in the innermost scope, even if a constant outside of it has the same name.
+	///
+	/// For the variables that loops set on their own, which the script never asked for.
+	pub fn set_local_var(&mut self, name: String, value: Val) {
 		let Some(frame) = self.variables.last_mut() else {
 			panic!("There is supposed to be a stack frame here")
 		};
 		frame.insert(name, value);
+	}
+	/// Declare a variable that can't be written to or shadowed afterwards
+	pub fn set_const(&mut self, name: String, value: Val) -> Result<(),String> {
+		self.set_var(name.clone(), value)?;
+		let Some(frame) = self.constants.last_mut() else {
+			panic!("There is supposed to be a stack frame here")
+		};
+		frame.insert(name);
+		Ok(())
+	}
+	/// Set one of the read-only variables that describe the input, like `filename`.
+	///
+	/// These go in the outer-most stack frame.
+	pub fn set_builtin_var(&mut self, name: String, value: Val) {
+		self.variables[0].insert(name.clone(), value);
+		self.constants[0].insert(name);
+	}
+	/// Set the variables that describe the input, like `filename`. These can't be written to.
+	pub fn set_input_vars(&mut self, input_vars: InputVars) {
+		self.input_vars = input_vars;
+	}
+	/// Describe every visible variable and named register, for `vars()` and `--dump-state`
+	pub fn dump_state(&self) -> String {
+		let input_vars = InputVars::NAMES.map(|name| (name.to_string(), Val::Str(self.input_vars.get(name).unwrap_or_default().to_string())));
+		// Inner frames are inserted last, so shadowed variables are left out
+		let mut visible = BTreeMap::new();
+		for (name, val) in &input_vars {
+			visible.insert(name, val);
+		}
+		for frame in &self.variables {
+			for (name, val) in frame {
+				visible.insert(name, val);
+			}
+		}
+		let mut dump = String::from("variables:\n");
+		for (name, val) in visible {
+			let read_only = if self.is_read_only(name) { " (read-only)" } else { "" };
+			let val_type = val.display_type();
+			let val = match val {
+				Val::Str(s) => format!("{s:?}"),
+				_ => val.to_string()
+			};
+			dump.push_str(&format!("  {name}: {val_type}{read_only} = {val}\n"));
+		}
+		dump.push_str("registers:\n");
+		for reg in 'a'..='z' {
+			let Some(content) = read_register(Some(reg)) else { continue };
+			if content.is_empty() {
+				continue
+			}
+			dump.push_str(&format!("  \"{reg}: {:?}\n", content.to_string()));
+		}
+		dump
+	}
+	pub fn is_read_only(&self, name: &str) -> bool {
+		InputVars::NAMES.contains(&name) || self.constants.iter().any(|frame| frame.contains(name))
+	}
+	fn check_writable(&self, name: &str) -> Result<(),String> {
+		if self.is_read_only(name) {
+			return Err(format!("'{name}' is read-only and cannot be set"))
+		}
 		Ok(())
 	}
 	pub fn clear_var(&mut self, name: &str) {
@@ -794,6 +1302,7 @@ impl ViCut {
 				};
 				Ok(val.clone())
 			}
+			CmdArg::Null => Ok(Val::Null),
 			_ => unreachable!()
 		}
 	}
@@ -851,7 +1360,7 @@ impl ViCut {
 			Expr::Null => Val::Null,
 			Expr::Regex(raw) => {
 				// 'raw' is a String, we compile the regex during evaluation here.
-				let regex = Regex::new(raw)
+				let regex = crate::new_regex(raw)
 					.map_err(|e| format!("Invalid regex: {e}"))?;
 				Val::Regex(regex)
 			}
@@ -892,9 +1401,21 @@ impl ViCut {
 				let Val::Num(idx) = val else {
 					return Err(format!("Attempt to index variable {name} with non-number {val}"))
 				};
-				let idx = idx as usize;
 				self.read_index_var(name.to_string(), idx)?
 			}
+			Expr::VarSlice { name, start, end, inclusive } => {
+				let mut bounds = [None, None];
+				for (bound, expr) in bounds.iter_mut().zip([start, end]) {
+					let Some(expr) = expr else { continue };
+					let val = self.eval_expr(expr,ctx)?;
+					let Val::Num(idx) = val else {
+						return Err(format!("Attempt to slice variable {name} with non-number {val}"))
+					};
+					*bound = Some(idx);
+				}
+				let [start, end] = bounds;
+				self.read_slice_var(name, start, end, *inclusive)?
+			}
 			Expr::Var(var) => {
 				let Some(var) = self.get_var(var) else {
 					return Err(format!("Variable {var} not found"))
@@ -914,6 +1435,7 @@ impl ViCut {
 				Val::Str(expanded)
 			}
 			Expr::Int(int) => Val::Num(*int as isize),
+			Expr::Float(float) => Val::Float(*float),
 			Expr::TernaryExp { cond, true_case, false_case } => self.eval_ternary_expr(cond, true_case, false_case,ctx)?,
 			Expr::BinExp { op, left, right } => self.eval_bin_expr(op, left, right)?,
 			Expr::BoolExp { op, left, right } => self.eval_bool_expr(op, left, right.as_ref(),ctx)?,
@@ -922,7 +1444,7 @@ impl ViCut {
 				let Ok(field) = self.read_field(cmd) else {
 					return Err("Failed to read field".to_string())
 				};
-				Val::Str(field)
+				Val::Str(field.into_text())
 			}
 			Expr::FuncCall(name,args) => {
 				let args = args.iter()
@@ -942,6 +1464,9 @@ impl ViCut {
 				Ok(Val::Str(arg.display_type()))
 			}
 			"env" => {
+				if self.sandbox {
+					return Err("env() is not allowed in sandbox mode".to_string())
+				}
 				if args.len() != 1 {
 					return Err("env expects exactly one argument".to_string())
 				}
@@ -953,6 +1478,275 @@ impl ViCut {
 				let env_value = std::env::var(var_name).unwrap_or_default();
 				Ok(Val::Str(env_value))
 			}
+			"printf" => {
+				let Some((fmt, fmt_args)) = args.split_first() else {
+					return Err("printf expects at least one argument".to_string())
+				};
+				let Val::Str(fmt) = fmt else {
+					return Err(format!("Expected format string in printf(), got {}",fmt.display_type()))
+				};
+				let output = format_printf(fmt, fmt_args)?;
+				crate::print_stdout(&output);
+				Ok(Val::Null)
+			}
+			"int" => {
+				let [arg] = args.as_slice() else {
+					return Err("int expects exactly one argument".to_string())
+				};
+				match arg {
+					Val::Num(n) => Ok(Val::Num(*n)),
+					Val::Float(f) => float_to_int(f.trunc()).map(Val::Num),
+					Val::Bool(b) => Ok(Val::Num(*b as isize)),
+					Val::Str(s) => {
+						if let Ok(n) = s.trim().parse::<isize>() {
+							return Ok(Val::Num(n))
+						}
+						let f = s.trim().parse::<f64>()
+							.map_err(|_| format!("Cannot convert '{s}' to an integer"))?;
+						float_to_int(f.trunc()).map(Val::Num)
+					}
+					_ => Err(format!("Cannot convert {} to an integer", arg.display_type()))
+				}
+			}
+			"float" => {
+				let [arg] = args.as_slice() else {
+					return Err("float expects exactly one argument".to_string())
+				};
+				match arg {
+					Val::Str(s) => s.trim().parse::<f64>()
+						.map(Val::Float)
+						.map_err(|_| format!("Cannot convert '{s}' to a float")),
+					_ => arg.as_float()
+						.map(Val::Float)
+						.ok_or_else(|| format!("Cannot convert {} to a float", arg.display_type()))
+				}
+			}
+			"round" => {
+				let (num, digits) = match args.as_slice() {
+					[num] => (num, 0),
+					[num, Val::Num(digits)] => (num, *digits),
+					_ => return Err("round expects a number, and optionally a number of digits".to_string())
+				};
+				let Some(num) = num.as_float() else {
+					return Err(format!("Expected number in round(), got {}", num.display_type()))
+				};
+				if digits <= 0 {
+					return float_to_int(num.round()).map(Val::Num)
+				}
+				let scale = 10f64.powi(digits as i32);
+				Ok(Val::Float((num * scale).round() / scale))
+			}
+			"exit" => {
+				let code = match args.as_slice() {
+					[] => 0,
+					[Val::Num(code)] => *code,
+					_ => return Err("exit expects an optional exit code".to_string())
+				};
+				ctx.unwind = Some(Unwind::Exit(code));
+				Ok(Val::Null)
+			}
+			"die" => {
+				let msg = match args.as_slice() {
+					[] => "died".to_string(),
+					[msg] => msg.to_string(),
+					_ => return Err("die expects an optional message".to_string())
+				};
+				Err(msg)
+			}
+			"assert" => {
+				let (cond, msg) = match args.as_slice() {
+					[cond] => (cond, "assertion failed".to_string()),
+					[cond, msg] => (cond, msg.to_string()),
+					_ => return Err("assert expects a condition, and optionally a message".to_string())
+				};
+				if !cond.is_truthy() {
+					let source = self.get_var("filepath").map(|path| path.to_string()).unwrap_or_default();
+					crate::record_assert_failure(format!("{source}: {msg}"));
+				}
+				Ok(Val::Bool(cond.is_truthy()))
+			}
+			"vars" => {
+				if !args.is_empty() {
+					return Err("vars expects no arguments".to_string())
+				}
+				eprint!("{}", self.dump_state());
+				Ok(Val::Null)
+			}
+			"natcmp" => {
+				let [a, b] = args.as_slice() else {
+					return Err("natcmp expects exactly two arguments".to_string())
+				};
+				let ordering = natural_cmp(&a.to_string(), &b.to_string());
+				Ok(Val::Num(ordering as isize))
+			}
+			"to_case" => {
+				let [text, case] = args.as_slice() else {
+					return Err("to_case expects a string and the name of a case".to_string())
+				};
+				let case = Case::try_from(case.to_string().as_str())?;
+				Ok(Val::Str(coerce_case(&text.to_string(), case)))
+			}
+			"shell_args" => {
+				if self.sandbox {
+					return Err("shell_args() is not allowed in sandbox mode".to_string())
+				}
+				if self.shell.disabled {
+					return Err("shell_args() is disabled by '--no-shell'".to_string())
+				}
+				let (argv, input) = match args.as_slice() {
+					[Val::Arr(argv)] => (argv, None),
+					[Val::Arr(argv), input] => (argv, Some(input.to_string())),
+					_ => return Err("shell_args expects an array of arguments, and optionally a string to pipe into the program".to_string())
+				};
+				let argv = argv.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+				let output = self.shell.run_args(&argv, input.as_deref())?;
+				Ok(Val::Str(output))
+			}
+			"freq" => {
+				let tokenizer = match args.as_slice() {
+					[] => WORD_TOKEN.clone(),
+					[Val::Regex(regex)] => regex.clone(),
+					[Val::Str(pattern)] => crate::new_regex(pattern)
+						.map_err(|e| format!("Invalid regex in freq(): {e}"))?,
+					_ => return Err("freq expects an optional tokenizer pattern".to_string())
+				};
+				let counts = token_frequencies(&self.current_buffer().buffer, &tokenizer);
+				Ok(Val::Arr(counts.into_iter()
+					.map(|(token,count)| Val::Arr(vec![Val::Str(token), Val::Num(count as isize)]))
+					.collect()))
+			}
+			"diff" => {
+				if self.sandbox {
+					return Err("diff() is not allowed in sandbox mode".to_string())
+				}
+				let [path] = args.as_slice() else {
+					return Err("diff expects exactly one path".to_string())
+				};
+				let path = path.to_string();
+				let other = std::fs::read_to_string(&path)
+					.map_err(|e| format!("Failed to read '{path}' in diff(): {e}"))?;
+				let hunks = diff_hunks(&self.current_buffer().buffer, &other);
+				Ok(Val::Arr(hunks.into_iter().map(Val::from).collect()))
+			}
+			"apply_patch" => {
+				let [Val::Arr(hunks)] = args.as_slice() else {
+					return Err("apply_patch expects exactly one array of hunks".to_string())
+				};
+				let hunks = hunks.iter()
+					.map(Hunk::try_from)
+					.collect::<Result<Vec<_>,_>>()?;
+				let buf = self.current_buffer();
+				let old = buf.buffer.clone();
+				let new = apply_hunks(&old, &hunks).map_err(|e| format!("apply_patch: {e}"))?;
+				let cursor = buf.cursor.get();
+				buf.set_buffer(new.clone());
+				buf.handle_edit(old, new, cursor);
+				Ok(Val::Num(hunks.len() as isize))
+			}
+			"len" => {
+				let [arg] = args.as_slice() else {
+					return Err("len expects exactly one argument".to_string())
+				};
+				let compound = CompoundVal::try_from(arg.clone())
+					.map_err(|_| format!("Cannot take the length of {}", arg.display_type()))?;
+				Ok(Val::Num(compound.len() as isize))
+			}
+			"contains" => {
+				let [haystack, needle] = args.as_slice() else {
+					return Err("contains expects exactly two arguments".to_string())
+				};
+				match (haystack, needle) {
+					(Val::Arr(arr), _) => Ok(Val::Bool(arr.iter().any(|item| item == needle))),
+					(Val::Str(s), Val::Regex(regex)) => Ok(Val::Bool(regex.is_match(s))),
+					(Val::Str(s), _) => Ok(Val::Bool(s.contains(&needle.to_string()))),
+					_ => Err(format!("Expected array or string in contains(), got {}", haystack.display_type()))
+				}
+			}
+			"sort" => {
+				let [Val::Arr(arr)] = args.as_slice() else {
+					return Err("sort expects exactly one array".to_string())
+				};
+				let mut sorted = arr.clone();
+				let mut error = None;
+				sorted.sort_by(|a, b| {
+					a.try_cmp(b).unwrap_or_else(|e| {
+						error.get_or_insert(e);
+						Ordering::Equal
+					})
+				});
+				match error {
+					Some(e) => Err(format!("{e} in sort()")),
+					None => Ok(Val::Arr(sorted))
+				}
+			}
+			"unique" => {
+				let [Val::Arr(arr)] = args.as_slice() else {
+					return Err("unique expects exactly one array".to_string())
+				};
+				// Keeps the first occurrence of each value, in order
+				let mut unique: Vec<Val> = vec![];
+				for item in arr {
+					if !unique.contains(item) {
+						unique.push(item.clone());
+					}
+				}
+				Ok(Val::Arr(unique))
+			}
+			"filter" | "map" => {
+				let [Val::Arr(arr), Val::Str(func)] = args.as_slice() else {
+					return Err(format!("{name} expects an array and a function name"))
+				};
+				let mut result = vec![];
+				for item in arr {
+					let val = self.eval_function(func.clone(), vec![item.clone()], ctx)?;
+					if name == "map" {
+						result.push(val);
+					} else if val.is_truthy() {
+						result.push(item.clone());
+					}
+				}
+				Ok(Val::Arr(result))
+			}
+			"slice" => {
+				let (compound, start, end) = match args.as_slice() {
+					[compound, Val::Num(start)] => (compound, *start, None),
+					[compound, Val::Num(start), Val::Num(end)] => (compound, *start, Some(*end)),
+					_ => return Err("slice expects a value, a start index, and optionally an end index".to_string())
+				};
+				let compound = CompoundVal::try_from(compound.clone())?;
+				Ok(compound.slice(Some(start), end, false).into())
+			}
+			"join" => {
+				let (arr, sep) = match args.as_slice() {
+					[Val::Arr(arr)] => (arr, String::new()),
+					[Val::Arr(arr), sep] => (arr, sep.to_string()),
+					_ => return Err("join expects an array, and optionally a separator".to_string())
+				};
+				let joined = arr.iter()
+					.map(|item| item.to_string())
+					.collect::<Vec<_>>()
+					.join(&sep);
+				Ok(Val::Str(joined))
+			}
+			"textobj" => {
+				let (key, obj) = match args.as_slice() {
+					[key, Val::Regex(regex)] => (key, CustomTextObj::Pattern(regex.clone())),
+					[key, Val::Str(pattern)] => {
+						let regex = crate::new_regex(pattern)
+							.map_err(|e| format!("Invalid regex in textobj(): {e}"))?;
+						(key, CustomTextObj::Pattern(regex))
+					}
+					[key, open, close] => (key, CustomTextObj::Delim(open.to_string(), close.to_string())),
+					_ => return Err("textobj expects a key and a pattern, or a key and a pair of delimiters".to_string())
+				};
+				let key = key.to_string();
+				let mut chars = key.chars();
+				let (Some(key), None) = (chars.next(), chars.next()) else {
+					return Err(format!("The key for textobj() must be a single character, found '{key}'"))
+				};
+				self.define_text_obj(key, obj)?;
+				Ok(Val::Null)
+			}
 			_ => Err(format!("Function {name} not found"))
 		}
 	}
@@ -973,8 +1767,20 @@ impl ViCut {
 			// Here we can use our existing mutable reference to self
 			// Along with the function cmd and the ctx we have
 			// To maintain context even in nested function calls
-			ret_val = super::exec_cmd(cmd, self, ctx);
-			if ret_val.is_some() { break } // We got a 'return' call, so we break now
+			super::exec_cmd(cmd, self, ctx);
+			match ctx.unwind.take() {
+				// We got a 'return' call, possibly from inside of a nested block, so we break now
+				Some(Unwind::Return(val)) => {
+					ret_val = Some(val);
+					break
+				}
+				// exit() and timeouts have to get all the way to the top
+				Some(exit) => {
+					ctx.unwind = Some(exit);
+					break
+				}
+				None => {}
+			}
 		}
 		self.ascend();
 		Ok(ret_val.unwrap_or(Val::Null))
@@ -988,6 +1794,17 @@ impl ViCut {
 		}
 	}
 	pub fn eval_bool_expr(&mut self, op: &BoolOp, left: &(bool,Box<Expr>), right: Option<&(bool,Box<Expr>)>, ctx: &mut ExecCtx) -> Result<Val,String> {
+		if let (BoolOp::And | BoolOp::Or, Some(right)) = (op, right) {
+			// Both sides are judged by their truthiness, and the right side is only evaluated if it needs to be
+			let left = self.eval_expr(&left.1,ctx)?.is_truthy() != left.0;
+			match op {
+				BoolOp::And if !left => return Ok(Val::Bool(false)),
+				BoolOp::Or if left => return Ok(Val::Bool(true)),
+				_ => {}
+			}
+			let right = self.eval_expr(&right.1,ctx)?.is_truthy() != right.0;
+			return Ok(Val::Bool(right))
+		}
 		let left_negated = left.0;
 		let left = self.eval_expr(&left.1,ctx)?;
 
@@ -1025,135 +1842,68 @@ impl ViCut {
 			}
 		}
 
-		match left {
-			Val::Null => {
-				if matches!(op, BoolOp::Eq | BoolOp::Ne) {
-					if matches!(right, Val::Null) {
-						Ok(Val::Bool(op == &BoolOp::Eq))
-					} else {
-						Ok(Val::Bool(false)) // Null is not equal to anything but null
-					}
-				} else {
-					Err(format!("Cannot compare null with {op}"))
-				}
-			}
-			Val::Arr(l_arr) => {
-				let Val::Arr(r_arr) = right else {
-					return Err(format!("Expected array, got {}",right.display_type()))
-				};
-				match op {
-					BoolOp::Eq => Ok(Val::Bool(l_arr == r_arr)),
-					BoolOp::Ne => Ok(Val::Bool(l_arr != r_arr)),
-					BoolOp::Lt => Err("Cannot compare arrays with <".into()),
-					BoolOp::LtEq => Err("Cannot compare arrays with <=".into()),
-					BoolOp::Gt => Err("Cannot compare arrays with >".into()),
-					BoolOp::GtEq => Err("Cannot compare arrays with >=".into()),
-					_ => todo!()
-				}
-			}
-			Val::Str(l_string) => {
-				let Val::Str(r_string) = right else {
-					return Err(format!("Expected string, got {}",right.display_type()))
-				};
-				if r_string == "\\\"" {
-					panic!("we didn't unescape the string properly, got {r_string}");
-				}
-				match op {
-					BoolOp::Eq => Ok(Val::Bool(l_string == r_string)),
-					BoolOp::Ne => Ok(Val::Bool(l_string != r_string)),
-					BoolOp::Lt => Err("Cannot compare strings with <".into()),
-					BoolOp::LtEq => Err("Cannot compare strings with <=".into()),
-					BoolOp::Gt => Err("Cannot compare strings with >".into()),
-					BoolOp::GtEq => Err("Cannot compare strings with >=".into()),
-					_ => todo!()
-				}
-			}
-			Val::Num(l_num) => {
-				let Val::Num(r_num) = right else {
-					return Err(format!("Expected number, got {}",right.display_type()))
-				};
-				match op {
-					BoolOp::Eq => Ok(Val::Bool(l_num == r_num)),
-					BoolOp::Ne => Ok(Val::Bool(l_num != r_num)),
-					BoolOp::Lt => Ok(Val::Bool(l_num < r_num)),
-					BoolOp::LtEq => Ok(Val::Bool(l_num <= r_num)),
-					BoolOp::Gt => Ok(Val::Bool(l_num > r_num)),
-					BoolOp::GtEq => Ok(Val::Bool(l_num >= r_num)),
-					_ => todo!()
+		let (left, right) = match (left, right) {
+			(Val::Bool(l_bool), Val::Bool(r_bool)) => (Val::Bool(l_bool != left_negated), Val::Bool(r_bool != right_negated)),
+			(left, right) => (left, right)
+		};
+		match op {
+			BoolOp::Eq | BoolOp::Ne => {
+				let comparable = matches!(left, Val::Null)
+					|| matches!(right, Val::Null)
+					|| (left.is_number() && right.is_number())
+					|| std::mem::discriminant(&left) == std::mem::discriminant(&right);
+				if !comparable {
+					return Err(format!("Cannot compare {} with {}", left.display_type(), right.display_type()))
 				}
+				// Null is not equal to anything but null
+				Ok(Val::Bool((left == right) == (op == &BoolOp::Eq)))
 			}
-			Val::Bool(l_bool) => {
-				let l_bool = if left_negated {
-					!l_bool
-				} else {
-					l_bool
-				};
-				let Val::Bool(r_bool) = right else {
-					return Err(format!("Expected boolean, got {}",right.display_type()))
-				};
-				let r_bool = if right_negated {
-					!r_bool
-				} else {
-					r_bool
-				};
-				match op {
-					BoolOp::And => Ok(Val::Bool(l_bool && r_bool)),
-					BoolOp::Or => Ok(Val::Bool(l_bool || r_bool)),
-					// The structure of vic's grammar should mean that we only get And/Or here
-					// (probably)
+			BoolOp::Lt | BoolOp::LtEq | BoolOp::Gt | BoolOp::GtEq => {
+				let ordering = left.try_cmp(&right)
+					.map_err(|e| format!("{e} using {op}"))?;
+				Ok(Val::Bool(match op {
+					BoolOp::Lt => ordering.is_lt(),
+					BoolOp::LtEq => ordering.is_le(),
+					BoolOp::Gt => ordering.is_gt(),
+					BoolOp::GtEq => ordering.is_ge(),
 					_ => unreachable!()
-				}
-			}
-			Val::Regex(_) => {
-				// Already handled above
-				unreachable!()
+				}))
 			}
+			// The structure of vic's grammar should mean that we only get comparisons here
+			_ => unreachable!()
 		}
 	}
 	pub fn eval_bin_expr(&mut self, op: &BinOp, left: &Expr, right: &Expr) -> Result<Val,String> {
-		let left = match left {
+		let left = self.eval_bin_operand(left)?;
+		let right = self.eval_bin_operand(right)?;
+		Val::arith(op, &left, &right)
+	}
+	fn eval_bin_operand(&mut self, operand: &Expr) -> Result<Val,String> {
+		Ok(match operand {
 			Expr::Var(var) => {
-				let Some(var) = self.get_var(var) else {
+				let Some(val) = self.get_var(var) else {
 					return Err(format!("Variable {var} not found"))
 				};
-				let Val::Num(_) = &var else {
+				if !val.is_number() {
 					return Err(format!("Variable {var} is not a number"))
-				};
-				var.clone()
+				}
+				val
 			}
-			Expr::Int(int) => Val::Num(*int as isize),
-			Expr::BinExp { op, left, right } => self.eval_bin_expr(op, left, right)?,
-			_ => unreachable!(),
-		};
-		let right = match right {
-			Expr::Var(var) => {
-				let Some(var) = self.get_var(var) else {
-					return Err(format!("Variable {var} not found"))
+			Expr::VarIndex(var, index) => {
+				let Val::Num(index) = self.eval_bin_operand(index)? else {
+					return Err(format!("Attempt to index variable {var} with a non-number"))
 				};
-				let Val::Num(_) = &var else {
-					return Err(format!("Variable {var} is not a number"))
-				};
-				var.clone()
+				let val = self.read_index_var(var.to_string(), index)?;
+				if !val.is_number() {
+					return Err(format!("Element {index} of {var} is not a number"))
+				}
+				val
 			}
 			Expr::Int(int) => Val::Num(*int as isize),
+			Expr::Float(float) => Val::Float(*float),
 			Expr::BinExp { op, left, right } => self.eval_bin_expr(op, left, right)?,
 			_ => unreachable!(),
-		};
-		let Val::Num(left) = left else {
-			return Err(format!("Left value {left} is not a number"))
-		};
-		let Val::Num(right) = right else {
-			return Err(format!("Right value {right} is not a number"))
-		};
-		Ok(Val::Num(match op {
-			BinOp::Add => left + right,
-			BinOp::Sub => left - right,
-			BinOp::Mult => left * right,
-			BinOp::Div => left / right,
-			BinOp::Mod => left % right,
-			BinOp::Pow => left.pow(right as u32),
-			BinOp::Equals => unreachable!() // Not used in this context
-		}))
+		})
 	}
 	pub fn mutate_var(&mut self, name: String, op: BinOp, value: Val) -> Result<(),String> {
 		if name == "buffers" {
@@ -1161,12 +1911,15 @@ impl ViCut {
 				return Err(format!("Expected array for 'buffers' assignment, found {}",value.display_type()))
 			};
 			let arr = arr.into_iter()
-				.map(|v| LineBuf::new().with_initial(v.to_string(), 0))
+				.map(|v| {
+					LineBuf::new().with_initial(v.to_string(), 0).with_settings(&self.settings)
+				})
 				.collect::<Vec<_>>();
 			self.buffers = arr;
 			self.editor.set_max(self.buffers.len());
 			return Ok(())
 		}
+		self.check_writable(&name)?;
 		let Some(var) = self.get_var_mut(&name) else {
 			return Err(format!("Variable {name} not found"))
 		};
@@ -1184,42 +1937,18 @@ impl ViCut {
 					}
 				}
 			}
-			Val::Num(var) => {
-				let Val::Num(value) = value else {
+			Val::Num(_) | Val::Float(_) => {
+				if !value.is_number() {
 					return Err(format!("Value {value} is not a number"))
-				};
-				match op {
-					BinOp::Equals => {
-						*var = value;
-					}
-					BinOp::Add => {
-						*var += value;
-					}
-					BinOp::Sub => {
-						*var -= value;
-					}
-					BinOp::Mult => {
-						*var *= value;
-					}
-					BinOp::Div => {
-						if value == 0 {
-							return Err("Division by zero".to_string())
-						}
-						*var /= value;
-					}
-					BinOp::Mod => {
-						*var %= value;
-					}
-					BinOp::Pow => {
-						*var = var.pow(value as u32);
-					}
 				}
+				*var = Val::arith(&op, var, &value)?;
 			}
 			_ => unimplemented!()
 		}
 		Ok(())
 	}
-	pub fn set_index_var(&mut self, name: String, index: usize, value: Val) -> Result<(),String> {
+	pub fn set_index_var(&mut self, name: String, index: isize, value: Val) -> Result<(),String> {
+		self.check_writable(&name)?;
 		let Some(var) = self.get_var_mut(&name) else {
 			return Err(format!("Variable {name} not found"))
 		};
@@ -1227,14 +1956,14 @@ impl ViCut {
 			return Err(format!("Variable {name} is not indexable"))
 		};
 		let len = compound.len();
-		if index >= len {
-			return Err(format!("Index {index} out of bounds for array {name}, length is {len}",))
-		}
-		compound.set(index, value);
-		self.set_var(name, compound.into())?;
+		let Some(pos) = compound.resolve_index(index).filter(|pos| *pos < len) else {
+			return Err(format!("Index {index} out of bounds for {name}, length is {len}",))
+		};
+		compound.set(pos, value);
+		*var = compound.into();
 		Ok(())
 	}
-	pub fn read_index_var(&mut self, name: String, index: usize) -> Result<Val,String> {
+	pub fn read_index_var(&mut self, name: String, index: isize) -> Result<Val,String> {
 		let Some(var) = self.get_var_mut(&name) else {
 			return Err(format!("Variable {name} not found"))
 		};
@@ -1242,9 +1971,20 @@ impl ViCut {
 			return Err(format!("Variable {name} is not indexable"))
 		};
 		let len = compound.len();
-		let item = compound.into_iter().nth(index).ok_or(format!("Index {index} out of bounds for array {name}, length is {len}",))?;
+		let item = compound.resolve_index(index)
+			.and_then(|pos| compound.into_iter().nth(pos))
+			.ok_or(format!("Index {index} out of bounds for {name}, length is {len}",))?;
 		Ok(item)
 	}
+	pub fn read_slice_var(&mut self, name: &str, start: Option<isize>, end: Option<isize>, inclusive: bool) -> Result<Val,String> {
+		let Some(var) = self.get_var(name) else {
+			return Err(format!("Variable {name} not found"))
+		};
+		let Some(compound) = CompoundVal::try_from(var).ok() else {
+			return Err(format!("Variable {name} is not indexable"))
+		};
+		Ok(compound.slice(start, end, inclusive).into())
+	}
 	pub fn expand_literal(&mut self, literal: &str) -> Result<String,String> {
 		let mut expanded = String::new();
 		let mut var_name = String::new();
@@ -1269,36 +2009,38 @@ impl ViCut {
 					continue
 				}
 				'$' => {
-					match (chars.next(), chars.next()) {
-						(Some('{'), Some('{')) => {
-							// This is a variable
-							let mut closed = false;
-							while let Some(ch) = chars.next() {
-								match ch {
-									'}' if chars.peek() == Some(&'}') => {
-										// End of variable
-										closed = true;
-										chars.next();
-										break
-									}
-									_ => {
-										var_name.push(ch);
-									}
-								}
+					if chars.next_if_eq(&'{').is_none() {
+						// Not a variable, just push what we got
+						expanded.push('$');
+						continue
+					}
+					// Both ${var} and the older ${{var}} are accepted
+					let double = chars.next_if_eq(&'{').is_some();
+					let mut closed = false;
+					while let Some(ch) = chars.next() {
+						match ch {
+							'}' if !double => {
+								closed = true;
+								break
 							}
-							if !closed {
-								return Err("Unmatched ${{".to_string())
+							'}' if chars.peek() == Some(&'}') => {
+								// End of variable
+								closed = true;
+								chars.next();
+								break
 							}
-							if let Some(var) = self.get_var(&std::mem::take(&mut var_name)) {
-								expanded.push_str(&var.to_string());
+							_ => {
+								var_name.push(ch);
 							}
 						}
-						(ch1,ch2) => {
-							// Not a variable, just push what we got
-							expanded.push('$');
-							if let Some(ch1) = ch1 { expanded.push(ch1); }
-							if let Some(ch2) = ch2 { expanded.push(ch2); }
-						}
+					}
+					if !closed {
+						let open = if double { "${{" } else { "${" };
+						return Err(format!("Unmatched {open} in '{literal}'"))
+					}
+					let name = std::mem::take(&mut var_name);
+					if let Some(var) = self.get_var(name.trim()) {
+						expanded.push_str(&var.to_string());
 					}
 				}
 				_ => {
@@ -1310,3 +2052,183 @@ impl ViCut {
 		Ok(expanded)
 	}
 }
+
+/// Convert a float to an integer, refusing values that don't fit instead of saturating.
+fn float_to_int(f: f64) -> Result<isize,String> {
+	if f.is_nan() || f < isize::MIN as f64 || f >= isize::MAX as f64 {
+		return Err(format!("Integer overflow converting {f:?} to an integer"))
+	}
+	Ok(f as isize)
+}
+
+/// Compare two strings the way a human would sort them, so that "file2" comes before "file10".
+///
+/// Runs of digits are compared by their numeric value, and everything else is compared character by character.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+	let mut a_chars = a.chars().peekable();
+	let mut b_chars = b.chars().peekable();
+	loop {
+		match (a_chars.peek().copied(), b_chars.peek().copied()) {
+			(None, None) => return Ordering::Equal,
+			(None, Some(_)) => return Ordering::Less,
+			(Some(_), None) => return Ordering::Greater,
+			(Some(a_ch), Some(b_ch)) if a_ch.is_ascii_digit() && b_ch.is_ascii_digit() => {
+				let mut a_num = String::new();
+				while let Some(ch) = a_chars.next_if(char::is_ascii_digit) {
+					a_num.push(ch);
+				}
+				let mut b_num = String::new();
+				while let Some(ch) = b_chars.next_if(char::is_ascii_digit) {
+					b_num.push(ch);
+				}
+				let a_num = a_num.trim_start_matches('0');
+				let b_num = b_num.trim_start_matches('0');
+				// With the leading zeroes gone, a longer number is a bigger one
+				let ordering = a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num));
+				if ordering.is_ne() {
+					return ordering
+				}
+			}
+			(Some(a_ch), Some(b_ch)) => {
+				let ordering = a_ch.cmp(&b_ch);
+				if ordering.is_ne() {
+					return ordering
+				}
+				a_chars.next();
+				b_chars.next();
+			}
+		}
+	}
+}
+
+/// Format `args` according to a printf-style format string.
+///
+/// Supports the `%s`, `%d`/`%i`, `%f`, `%x`/`%X`, `%o`, `%b`, `%c`, and `%%` conversions,
+/// along with the `-`, `0`, and `+` flags, a field width, and a precision.
+/// The escapes `\n`, `\t`, `\r` and `\\` are also expanded.
+pub fn format_printf(fmt: &str, args: &[Val]) -> Result<String,String> {
+	let mut output = String::new();
+	let mut args = args.iter();
+	let mut chars = fmt.chars().peekable();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' => {
+				match chars.next() {
+					Some('n') => output.push('\n'),
+					Some('t') => output.push('\t'),
+					Some('r') => output.push('\r'),
+					Some('\\') => output.push('\\'),
+					Some(other) => {
+						output.push('\\');
+						output.push(other);
+					}
+					None => output.push('\\'),
+				}
+			}
+			'%' => {
+				if chars.peek() == Some(&'%') {
+					chars.next();
+					output.push('%');
+					continue
+				}
+				let mut left_align = false;
+				let mut zero_pad = false;
+				let mut plus_sign = false;
+				while let Some(flag) = chars.next_if(|ch| matches!(ch, '-' | '0' | '+')) {
+					match flag {
+						'-' => left_align = true,
+						'0' => zero_pad = true,
+						_ => plus_sign = true,
+					}
+				}
+				let mut width = String::new();
+				while let Some(digit) = chars.next_if(|ch| ch.is_ascii_digit()) {
+					width.push(digit);
+				}
+				let width = width.parse::<usize>().unwrap_or(0);
+				let mut precision = None;
+				if chars.next_if_eq(&'.').is_some() {
+					let mut digits = String::new();
+					while let Some(digit) = chars.next_if(|ch| ch.is_ascii_digit()) {
+						digits.push(digit);
+					}
+					precision = Some(digits.parse::<usize>().unwrap_or(0));
+				}
+				let Some(conv) = chars.next() else {
+					return Err("Unterminated format specifier in printf()".to_string())
+				};
+				let Some(arg) = args.next() else {
+					return Err(format!("Not enough arguments for format string '{fmt}' in printf()"))
+				};
+				let num_arg = || match arg {
+					Val::Num(n) => Ok(*n),
+					Val::Float(f) => Ok(*f as isize),
+					Val::Str(s) => s.trim().parse::<isize>()
+						.map_err(|_| format!("Expected a number for '%{conv}' in printf(), got '{s}'")),
+					_ => Err(format!("Expected a number for '%{conv}' in printf(), got {}", arg.display_type()))
+				};
+				let formatted = match conv {
+					's' => {
+						let s = arg.to_string();
+						match precision {
+							Some(prec) => s.chars().take(prec).collect(),
+							None => s
+						}
+					}
+					'd' | 'i' => {
+						let n = num_arg()?;
+						if plus_sign && n >= 0 { format!("+{n}") } else { n.to_string() }
+					}
+					'f' => {
+						let n = match arg {
+							Val::Float(f) => *f,
+							Val::Str(s) if s.trim().parse::<f64>().is_ok() => s.trim().parse::<f64>().unwrap(),
+							_ => num_arg()? as f64
+						};
+						let prec = precision.unwrap_or(6);
+						if plus_sign && n >= 0.0 { format!("+{n:.prec$}") } else { format!("{n:.prec$}") }
+					}
+					'x' => format!("{:x}", num_arg()?),
+					'X' => format!("{:X}", num_arg()?),
+					'o' => format!("{:o}", num_arg()?),
+					'b' => format!("{:b}", num_arg()?),
+					'c' => {
+						match arg {
+							Val::Num(n) => u32::try_from(*n).ok()
+								.and_then(char::from_u32)
+								.map(|ch| ch.to_string())
+								.ok_or(format!("Invalid character code {n} in printf()"))?,
+							_ => arg.to_string().chars().next().map(|ch| ch.to_string()).unwrap_or_default()
+						}
+					}
+					_ => return Err(format!("Unknown format specifier '%{conv}' in printf()"))
+				};
+
+				let len = formatted.chars().count();
+				if len >= width {
+					output.push_str(&formatted);
+				} else if left_align {
+					output.push_str(&formatted);
+					output.push_str(&" ".repeat(width - len));
+				} else if zero_pad && !matches!(conv, 's' | 'c') {
+					// Zeroes go after the sign
+					let (sign, digits) = match formatted.strip_prefix(['-','+']) {
+						Some(digits) => formatted.split_at(formatted.len() - digits.len()),
+						None => ("", formatted.as_str())
+					};
+					output.push_str(sign);
+					output.push_str(&"0".repeat(width - len));
+					output.push_str(digits);
+				} else {
+					output.push_str(&" ".repeat(width - len));
+					output.push_str(&formatted);
+				}
+			}
+			_ => output.push(ch)
+		}
+	}
+	if args.next().is_some() {
+		return Err(format!("Too many arguments for format string '{fmt}' in printf()"))
+	}
+	Ok(output)
+}
@@ -0,0 +1,98 @@
+//! Lightweight filetype detection.
+//!
+//! The detected filetype is exposed to scripts as `$filetype`, and is also used to pick
+//! sensible defaults, like the formatter program used by `=`.
+use std::path::Path;
+
+/// Known file extensions, and the filetype they map to
+const EXTENSIONS: [(&str,&str);37] = [
+	("rs", "rust"),
+	("json", "json"),
+	("jsonl", "json"),
+	("py", "python"),
+	("sh", "sh"),
+	("bash", "sh"),
+	("zsh", "sh"),
+	("js", "javascript"),
+	("mjs", "javascript"),
+	("ts", "typescript"),
+	("toml", "toml"),
+	("yaml", "yaml"),
+	("yml", "yaml"),
+	("md", "markdown"),
+	("markdown", "markdown"),
+	("c", "c"),
+	("h", "c"),
+	("cpp", "cpp"),
+	("cc", "cpp"),
+	("hpp", "cpp"),
+	("go", "go"),
+	("xml", "xml"),
+	("html", "html"),
+	("htm", "html"),
+	("css", "css"),
+	("csv", "csv"),
+	("tsv", "tsv"),
+	("txt", "text"),
+	("lua", "lua"),
+	("rb", "ruby"),
+	("pl", "perl"),
+	("java", "java"),
+	("vic", "vic"),
+	("ini", "ini"),
+	("conf", "conf"),
+	("sql", "sql"),
+	("log", "log"),
+];
+
+/// Interpreters that can show up in a shebang line, and the filetype they map to
+const INTERPRETERS: [(&str,&str);13] = [
+	("sh", "sh"),
+	("bash", "sh"),
+	("zsh", "sh"),
+	("dash", "sh"),
+	("ksh", "sh"),
+	("python", "python"),
+	("node", "javascript"),
+	("deno", "typescript"),
+	("ruby", "ruby"),
+	("perl", "perl"),
+	("lua", "lua"),
+	("awk", "awk"),
+	("vicut", "vic"),
+];
+
+/// Detect the filetype of some content.
+///
+/// The file extension is checked first. If there is no path, or the extension is unknown,
+/// we fall back on sniffing the shebang line of the content.
+pub fn detect(path: Option<&Path>, content: &str) -> Option<&'static str> {
+	path.and_then(|path| path.extension())
+		.and_then(|ext| from_extension(&ext.to_string_lossy()))
+		.or_else(|| from_shebang(content))
+}
+
+/// Get the filetype for a file extension
+pub fn from_extension(ext: &str) -> Option<&'static str> {
+	EXTENSIONS.iter()
+		.find(|(known_ext,_)| known_ext.eq_ignore_ascii_case(ext))
+		.map(|(_,ft)| *ft)
+}
+
+/// Get the filetype from a shebang line like `#!/usr/bin/env python3`
+pub fn from_shebang(content: &str) -> Option<&'static str> {
+	let first_line = content.lines().next()?;
+	let shebang = first_line.strip_prefix("#!")?;
+	let mut words = shebang.split_whitespace();
+	let mut program = words.next()?.rsplit('/').next()?;
+	if program == "env" {
+		// Skip over any flags given to env, like '-S'
+		program = words.find(|word| !word.starts_with('-'))?;
+	}
+	// python3, python3.11, etc
+	let program = program.trim_end_matches(|ch: char| ch.is_ascii_digit() || ch == '.');
+
+	INTERPRETERS.iter()
+		.find(|(interp,_)| *interp == program)
+		.map(|(_,ft)| *ft)
+}
@@ -0,0 +1,134 @@
+//! Graceful handling of SIGINT and SIGTERM.
+//!
+//! The first signal stops any new records or files from being started. What happens to the ones
+//! already running is up to `--on-interrupt`. Whatever finished is still written out, and then vicut
+//! exits with `128 + <signal>`, the same code a shell reports for a process killed by that signal,
+//! so that wrappers can tell a partial run from a complete one.
+//!
+//! A second signal exits right away, without waiting for anything.
+
+use std::{io::{self, Read}, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, LazyLock}};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+/// What to do with the records and files that are being worked on when a signal arrives, set with `--on-interrupt`
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub enum InterruptPolicy {
+	/// Let them finish, and write them out with everything else that finished
+	#[default]
+	Finish,
+	/// Stop them as soon as possible, and leave them out of the output
+	Abort
+}
+
+impl TryFrom<&str> for InterruptPolicy {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value.trim().to_ascii_lowercase().as_str() {
+			"finish" => Ok(InterruptPolicy::Finish),
+			"abort" => Ok(InterruptPolicy::Abort),
+			_ => Err(format!("Expected 'finish' or 'abort' for '--on-interrupt', found '{value}'"))
+		}
+	}
+}
+
+/// Set by either signal
+static INTERRUPTED: LazyLock<Arc<AtomicBool>> = LazyLock::new(Default::default);
+/// Set by SIGTERM only, so that we know which one to report
+static TERMINATED: LazyLock<Arc<AtomicBool>> = LazyLock::new(Default::default);
+static ABORT_IN_FLIGHT: AtomicBool = AtomicBool::new(false);
+
+/// How many records or files were written out, and how many there were in all. `usize::MAX` if the total isn't known.
+static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+static TOTAL: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Start listening for SIGINT and SIGTERM
+pub fn install(policy: InterruptPolicy) -> Result<(),String> {
+	ABORT_IN_FLIGHT.store(policy == InterruptPolicy::Abort, Ordering::Relaxed);
+	let register = || -> std::io::Result<()> {
+		for signal in [SIGINT, SIGTERM] {
+			// Registered first, so that it only sees the flag set by an earlier signal
+			signal_hook::flag::register_conditional_shutdown(signal, 128 + signal, INTERRUPTED.clone())?;
+			signal_hook::flag::register(signal, INTERRUPTED.clone())?;
+		}
+		signal_hook::flag::register(SIGTERM, TERMINATED.clone())?;
+		#[cfg(unix)]
+		for signal in [SIGINT, SIGTERM] {
+			interrupt_blocking_reads(signal)?;
+		}
+		Ok(())
+	};
+	register().map_err(|e| format!("vicut: failed to set up signal handlers: {e}"))
+}
+
+/// Let `signal` cut short a read that is waiting for input.
+///
+/// signal-hook installs its handlers with `SA_RESTART`, so a read of stdin would just go back to waiting,
+/// and the signal wouldn't be noticed until the input ended.
+#[cfg(unix)]
+fn interrupt_blocking_reads(signal: i32) -> io::Result<()> {
+	// SAFETY: the handler that is already installed is read and put back as it was, minus the one flag
+	unsafe {
+		let mut action: libc::sigaction = std::mem::zeroed();
+		if libc::sigaction(signal, std::ptr::null(), &mut action) != 0 {
+			return Err(io::Error::last_os_error())
+		}
+		action.sa_flags &= !libc::SA_RESTART;
+		if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+			return Err(io::Error::last_os_error())
+		}
+	}
+	Ok(())
+}
+
+/// Input that ends at the first signal, instead of waiting for more.
+///
+/// Nothing read after the signal would be worked on anyway, so a read that the signal cut short is treated as the end of the input.
+pub struct Interruptible<R>(pub R);
+
+impl<R: Read> Read for Interruptible<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			if interrupted() {
+				return Ok(0)
+			}
+			match self.0.read(buf) {
+				Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+				result => return result
+			}
+		}
+	}
+}
+
+/// Whether a signal has arrived. Nothing new should be started once this is true.
+pub fn interrupted() -> bool {
+	INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Whether a signal has arrived and the records that are already running should stop too
+pub fn aborting() -> bool {
+	interrupted() && ABORT_IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+/// Remember how far a pathway got, for the summary printed by `exit_if_interrupted()`
+pub fn record_progress(completed: usize, total: Option<usize>) {
+	COMPLETED.store(completed, Ordering::Relaxed);
+	TOTAL.store(total.unwrap_or(usize::MAX), Ordering::Relaxed);
+}
+
+/// If a signal arrived, report how far we got on stderr and exit.
+///
+/// The summary is one line of `key=value` pairs, so that wrappers can pick it apart.
+pub fn exit_if_interrupted() {
+	if !interrupted() {
+		return
+	}
+	let signal = if TERMINATED.load(Ordering::Relaxed) { SIGTERM } else { SIGINT };
+	let name = if signal == SIGTERM { "SIGTERM" } else { "SIGINT" };
+	let total = match TOTAL.load(Ordering::Relaxed) {
+		usize::MAX => "unknown".to_string(),
+		total => total.to_string()
+	};
+	eprintln!("vicut: interrupted: signal={name} completed={} total={total}", COMPLETED.load(Ordering::Relaxed));
+	std::process::exit(128 + signal)
+}
@@ -3,21 +3,23 @@
 //! `LineBuf` is responsible for any and all mutations of the internal buffer.
 
 use std::cmp::Ordering;
-use std::env;
+use std::collections::{HashMap, VecDeque};
 use std::io::Write as IoWrite;
-use std::process::{Command,Stdio};
 use std::ops::{Range, RangeInclusive};
+use std::sync::LazyLock;
 use std::fmt::Write;
 
 use regex::Regex;
+use similar::{capture_diff_slices, Algorithm, DiffTag};
 use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthStr;
 
 use crate::exec::Val;
 use crate::register::RegisterContent;
+use crate::shell::ShellCfg;
 use crate::{modes::ex::SubFlags, vicmd::{LineAddr, ReadSrc, WriteDest}};
 
-use super::vicmd::{Anchor, Bound, CmdFlags, Dest, Direction, Motion, MotionCmd, RegisterName, TextObj, To, Verb, ViCmd, Word};
+use super::vicmd::{Anchor, Bound, Case, CmdFlags, Dest, Direction, Motion, MotionCmd, RegisterName, TextObj, To, Verb, ViCmd, Word};
 
 const PUNCTUATION: [&str;3] = [
 	"?",
@@ -221,6 +223,493 @@ impl MotionKind {
 	}
 }
 
+/// What a single position in the buffer refers to, set with `--units`
+///
+/// Graphemes are the default, which is what a person would call a character.
+/// Fixed-width data usually wants bytes or chars instead, so that columns line up with the file format.
+#[derive(Default,Clone,Copy,Debug,PartialEq)]
+pub enum TextUnit {
+	Bytes,
+	Chars,
+	#[default]
+	Graphemes
+}
+
+impl TextUnit {
+	/// The starting byte index of each unit in the given string
+	///
+	/// A byte inside a multi-byte character starts where that character does, so every index is safe to slice at,
+	/// and a range of bytes that ends inside the same character is empty.
+	pub fn indices(&self, s: &str) -> Vec<usize> {
+		match self {
+			Self::Bytes => (0..s.len()).map(|i| s.floor_char_boundary(i)).collect(),
+			Self::Chars => s.char_indices().map(|(i,_)| i).collect(),
+			Self::Graphemes => s.grapheme_indices(true).map(|(i,_)| i).collect(),
+		}
+	}
+	pub fn count(&self, s: &str) -> usize {
+		match self {
+			Self::Bytes => s.len(),
+			Self::Chars => s.chars().count(),
+			Self::Graphemes => s.graphemes(true).count(),
+		}
+	}
+}
+
+impl TryFrom<&str> for TextUnit {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value.trim().to_ascii_lowercase().as_str() {
+			"bytes" => Ok(TextUnit::Bytes),
+			"chars" => Ok(TextUnit::Chars),
+			"graphemes" => Ok(TextUnit::Graphemes),
+			_ => Err(format!("Expected 'bytes', 'chars', or 'graphemes' for units, found '{value}'"))
+		}
+	}
+}
+
+/// The numbers that `in`/`an` and `]n`/`[n` look for. Decimal, hex, and negative numbers are all one number.
+static NUMBER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"-?(?:0[xX][0-9a-fA-F]+|\d+(?:\.\d+)?)").unwrap());
+/// URLs for `iu`/`au`. Either a scheme or a leading `www.` is required.
+static URL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?:[a-zA-Z][a-zA-Z0-9+.-]*://|www\.)[^\s<>"'`]+"#).unwrap());
+/// Filesystem paths for `if`/`af`. At least one slash is required, so plain words don't count,
+/// and the path has to start after whitespace or an opening delimiter, so the tail of a URL doesn't count either.
+static PATH: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?m:^|[\s"'`(\[{<=,;])(?<obj>(?:~|\.\.?)?(?:/[\w.+@~-]+)+/?|[\w.+@~-]+(?:/[\w.+@~-]+)+/?)"#).unwrap());
+/// Email addresses for `ie`/`ae`
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+").unwrap());
+/// Opening, closing, and self-closing tags for `it`/`at`. Comments are matched too, so that the tags inside of them can be skipped.
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?s)<!--.*?-->|<(/?)([A-Za-z][\w:.-]*)(?:"[^"]*"|'[^']*'|[^'">])*?(/?)>"#).unwrap());
+/// The opening line of a heredoc, like `<<EOF`, `<<-'EOF'`, or `<<~"EOF"`
+static HEREDOC: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^<<([-~]?)(['"]?)([A-Za-z_][A-Za-z0-9_]*)(['"]?)"#).unwrap());
+/// Identifiers for the case coercion operators, which may already be split up by underscores or dashes
+static IDENTIFIER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[\p{Alphabetic}\p{Nd}]+(?:[_-]+[\p{Alphabetic}\p{Nd}]+)*").unwrap());
+/// The default tokenizer for `freq()` and `--freq`
+pub static WORD_TOKEN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\w+").unwrap());
+/// Punctuation that ends a sentence more often than it ends a URL or a path
+const TRAILING_PUNCT: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '}'];
+
+/// How far apart tab stops are, for motions that care about display columns
+pub const TABSTOP: usize = 8;
+/// The width of the virtual screen used by `gm`. This is Vim's default for 'columns'.
+pub const SCREEN_COLUMNS: usize = 80;
+/// How many lines the virtual window shows, unless `--window-height` says otherwise. This is Vim's default for 'lines'.
+pub const DEFAULT_WINDOW_HEIGHT: usize = 24;
+/// How wide `gq` makes lines, unless `--textwidth` says otherwise. Vim falls back to 79 when 'textwidth' is unset.
+pub const DEFAULT_TEXTWIDTH: usize = 79;
+/// What separates a key from its value for `iv`/`av`, unless `--kv-separators` says otherwise
+pub const DEFAULT_KV_SEPARATORS: [&str;3] = ["=>", "=", ":"];
+/// What escapes a quote inside of a string for `iq`/`aq`, unless `--string-escape` says otherwise
+pub const DEFAULT_STRING_ESCAPE: char = '\\';
+
+/// How many columns a grapheme takes up, if it starts at the given display column
+pub fn display_width(col: usize, grapheme: &str) -> usize {
+	if grapheme == "\t" {
+		TABSTOP - (col % TABSTOP)
+	} else {
+		grapheme.width()
+	}
+}
+
+/// How folds are found, set with `--fold-method`
+///
+/// There is no interactive display, so folds are never open or closed.
+/// They are only used to find the targets of `zj`/`zk` and the `iz`/`az` text objects.
+#[derive(Default,Clone,Copy,Debug,PartialEq)]
+pub enum FoldMethod {
+	/// Lines that are indented further than the line above them start a fold
+	#[default]
+	Indent,
+	/// Folds start on a line containing `{{{` and end on a line containing `}}}`
+	Marker
+}
+
+impl TryFrom<&str> for FoldMethod {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value.trim().to_ascii_lowercase().as_str() {
+			"indent" => Ok(FoldMethod::Indent),
+			"marker" => Ok(FoldMethod::Marker),
+			_ => Err(format!("Expected 'indent' or 'marker' for fold method, found '{value}'"))
+		}
+	}
+}
+
+/// How the replacement text of `:s` is read, set with `--sub-syntax`
+#[derive(Default,Clone,Copy,Debug,PartialEq)]
+pub enum SubSyntax {
+	/// Vim's `\0` through `\9`, `&`, and `~`, along with the `regex` crate's `$1` and `${name}`
+	#[default]
+	Vim,
+	/// The `regex` crate's syntax and nothing else, so that `\`, `&`, and `~` are just text
+	Regex
+}
+
+impl TryFrom<&str> for SubSyntax {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value.trim().to_ascii_lowercase().as_str() {
+			"vim" => Ok(SubSyntax::Vim),
+			"regex" => Ok(SubSyntax::Regex),
+			_ => Err(format!("Expected 'vim' or 'regex' for the substitute syntax, found '{value}'"))
+		}
+	}
+}
+
+/// A text object defined in a vic script with `textobj()`, used as `i{key}` and `a{key}`
+#[derive(Clone,Debug)]
+pub enum CustomTextObj {
+	/// The text between two delimiters, like `«` and `»`. `i` leaves the delimiters out.
+	Delim(String,String),
+	/// A match of a pattern. `i` selects the first capture group, if the pattern has one.
+	Pattern(Regex)
+}
+
+/// How far each line is indented, with tabs stretched to the next tab stop. Blank lines are `None`.
+pub fn line_indents(text: &str) -> Vec<Option<usize>> {
+	text.split('\n')
+		.map(|line| {
+			if line.trim().is_empty() {
+				return None
+			}
+			let mut col = 0;
+			for ch in line.chars() {
+				match ch {
+					' ' => col += 1,
+					'\t' => col += TABSTOP - (col % TABSTOP),
+					_ => break
+				}
+			}
+			Some(col)
+		}).collect()
+}
+
+/// Find the lines around `line_no` that are indented at least as far as it is.
+///
+/// Blank lines inside of the block are included, but not the ones at either end of it.
+/// Blank lines take on the indentation of the next line that isn't blank.
+/// Returns the first and last line of the block, and the line that the block hangs off of, if there is one.
+pub fn indent_block(indents: &[Option<usize>], line_no: usize) -> Option<(usize,usize,Option<usize>)> {
+	let level = indents.get(line_no..)?.iter().flatten().next()
+		.or_else(|| indents.get(..line_no)?.iter().rev().flatten().next())
+		.copied()?;
+	let in_block = |idx: &usize| indents[*idx].is_none_or(|indent| indent >= level);
+
+	let header = (0..line_no).rev().find(|idx| !in_block(idx));
+	let first = header.map(|h| h + 1).unwrap_or(0);
+	let last = (line_no..indents.len()).find(|idx| !in_block(idx))
+		.map(|idx| idx - 1)
+		.unwrap_or(indents.len() - 1);
+
+	// Trim the blank lines at either end
+	let first = (first..=last).find(|idx| indents[*idx].is_some())?;
+	let last = (first..=last).rev().find(|idx| indents[*idx].is_some())?;
+	Some((first,last,header))
+}
+
+/// Find every fold in the given text. Each fold is given as its first and last line.
+///
+/// Folds from markers include the lines that the markers are on.
+/// Folds from indentation don't include the line that they hang off of, just like Vim.
+pub fn find_folds(text: &str, method: FoldMethod) -> Vec<(usize,usize)> {
+	let mut folds = vec![];
+	match method {
+		FoldMethod::Marker => {
+			let mut open = vec![];
+			for (line_no, line) in text.split('\n').enumerate() {
+				if line.contains("{{{") {
+					open.push(line_no);
+				}
+				if line.contains("}}}")
+					&& let Some(start) = open.pop() {
+						folds.push((start,line_no));
+				}
+			}
+		}
+		FoldMethod::Indent => {
+			let indents = line_indents(text);
+			let mut prev_indent = 0;
+			for (line_no, indent) in indents.iter().enumerate() {
+				let Some(indent) = *indent else { continue };
+				if indent > prev_indent
+					&& let Some((first,last,_)) = indent_block(&indents, line_no) {
+						folds.push((first,last));
+				}
+				prev_indent = indent;
+			}
+		}
+	}
+	folds.sort();
+	folds
+}
+/// Find the end of a string that started right before `from`, closed by `delim`. Returns the start and end of the closing delimiter.
+///
+/// When `escape` is the quote character itself, as in SQL, a doubled quote is escaped and a single one closes the string.
+fn find_string_close(text: &str, from: usize, delim: &str, escape: char) -> Option<(usize,usize)> {
+	let doubled = delim.starts_with(escape);
+	let mut chars = text[from..].char_indices();
+	while let Some((offset,ch)) = chars.next() {
+		let here = &text[from + offset..];
+		if ch == escape && (!doubled || here[ch.len_utf8()..].starts_with(delim)) {
+			chars.next();
+		} else if here.starts_with(delim) {
+			return Some((from + offset, from + offset + delim.len()))
+		}
+	}
+	None
+}
+/// Find the body of a heredoc whose opening `<<TAG` ends at `from`. Returns the start of the body, and the start and end of the closing line.
+fn find_heredoc_close(text: &str, from: usize, tag: &str, indented: bool) -> Option<(usize,usize,usize)> {
+	let body_start = from + text[from..].find('\n')? + 1;
+	let mut line_start = body_start;
+	for line in text[body_start..].split('\n') {
+		let content = if indented { line.trim_start() } else { line };
+		if content == tag {
+			return Some((body_start, line_start, line_start + line.len()))
+		}
+		line_start += line.len() + 1;
+	}
+	None
+}
+/// Find every string literal in `text`, reading from the top so that quotes inside of other strings are never mistaken for the start of one.
+///
+/// Handles `"`, `'`, and `` ` `` strings, triple quoted strings, and shell style heredocs. Strings may span lines, and `escape` escapes
+/// the character after it, except in heredocs. A `'` right after a word character is an apostrophe, not a quote.
+/// Returns the byte range of each string with and without its delimiters.
+pub fn find_strings(text: &str, escape: char) -> Vec<(Range<usize>,Range<usize>)> {
+	let mut strings = vec![];
+	let mut pos = 0;
+	let mut prev: Option<char> = None;
+	while let Some(ch) = text[pos..].chars().next() {
+		let rest = &text[pos..];
+		let delim = ["\"\"\"", "'''", "\"", "`", "'"].into_iter()
+			.filter(|delim| !(delim.starts_with('\'') && prev.is_some_and(|prev| prev.is_alphanumeric() || prev == '_')))
+			.find(|delim| rest.starts_with(delim));
+		if let Some(delim) = delim
+			&& let Some((close_start,close_end)) = find_string_close(text, pos + delim.len(), delim, escape) {
+				strings.push((pos..close_end, pos + delim.len()..close_start));
+				pos = close_end;
+				prev = delim.chars().next();
+				continue
+		}
+		if let Some(caps) = HEREDOC.captures(rest)
+			&& caps[2] == caps[4]
+			&& let Some((body_start,close_start,close_end)) = find_heredoc_close(text, pos + caps[0].len(), &caps[3], !caps[1].is_empty()) {
+				strings.push((pos..close_end, body_start..close_start));
+				pos = close_end;
+				prev = None;
+				continue
+		}
+		// An escaped quote outside of a string doesn't start one
+		if ch == escape && delim.is_none() {
+			prev = rest[ch.len_utf8()..].chars().next();
+			pos += ch.len_utf8() + prev.map(char::len_utf8).unwrap_or(0);
+			continue
+		}
+		pos += ch.len_utf8();
+		prev = Some(ch);
+	}
+	strings
+}
+/// Find every element in `text` that has both an opening and a closing tag.
+///
+/// A closing tag closes the nearest open tag with the same name, ignoring ASCII case, and any tags left open inside of it, like `<br>` or `<li>`, are dropped.
+/// Self-closing tags, stray closing tags, and anything inside of a comment are skipped.
+/// Returns the byte range of each element with and without its tags.
+pub fn find_tags(text: &str) -> Vec<(Range<usize>,Range<usize>)> {
+	let mut elements = vec![];
+	let mut open: Vec<(&str,Range<usize>)> = vec![];
+	for caps in TAG.captures_iter(text) {
+		let (Some(closing),Some(name),Some(self_closing)) = (caps.get(1), caps.get(2), caps.get(3)) else {
+			continue // A comment
+		};
+		let tag = caps.get(0).unwrap().range();
+		if !self_closing.is_empty() {
+			continue
+		}
+		if closing.is_empty() {
+			open.push((name.as_str(), tag));
+			continue
+		}
+		let Some(idx) = open.iter().rposition(|(open_name,_)| open_name.eq_ignore_ascii_case(name.as_str())) else {
+			continue
+		};
+		let (_,open_tag) = open.drain(idx..).next().unwrap();
+		elements.push((open_tag.start..tag.end, open_tag.end..tag.start));
+	}
+	elements
+}
+/// Find every pair of `open` and `close` in `text`, where a `close` goes with the nearest unclosed `open`.
+///
+/// If `open` and `close` are the same, they just take turns, like quotes. Delimiters that are never closed are skipped.
+/// Returns the byte range of each pair with and without its delimiters.
+pub fn find_delim_pairs(text: &str, open: &str, close: &str) -> Vec<(Range<usize>,Range<usize>)> {
+	let mut pairs = vec![];
+	let mut unclosed: Vec<usize> = vec![];
+	let mut pos = 0;
+	while let Some(ch) = text[pos..].chars().next() {
+		let rest = &text[pos..];
+		let closes = rest.starts_with(close) && (open != close || !unclosed.is_empty());
+		if closes && let Some(start) = unclosed.pop() {
+			pairs.push((start..pos + close.len(), start + open.len()..pos));
+			pos += close.len();
+		} else if rest.starts_with(open) {
+			unclosed.push(pos);
+			pos += open.len();
+		} else {
+			pos += ch.len_utf8();
+		}
+	}
+	pairs
+}
+/// Pick an element from `elements` for a text object, where each element is its span with and without its delimiters.
+///
+/// This is the innermost element around `cursor`, with a count going outwards from there.
+/// If there is nothing around the cursor, the next element after it is used instead.
+fn enclosing_element(elements: &[(Range<usize>,Range<usize>)], cursor: usize, count: usize) -> Option<&(Range<usize>,Range<usize>)> {
+	let mut enclosing = elements.iter()
+		.filter(|(outer,_)| outer.contains(&cursor))
+		.collect::<Vec<_>>();
+	enclosing.sort_by_key(|(outer,_)| outer.len());
+	if enclosing.is_empty() {
+		elements.iter()
+			.filter(|(outer,_)| outer.start > cursor)
+			.min_by_key(|(outer,_)| outer.start)
+	} else {
+		enclosing.get(count.saturating_sub(1)).copied()
+	}
+}
+/// Find the smallest span of lines in `spans` that holds `line_no`, then keep going outwards `count - 1` more times
+pub fn enclosing_lines(spans: &[(usize,usize)], line_no: usize, count: usize) -> Option<(usize,usize)> {
+	let mut found: Option<(usize,usize)> = None;
+	for _ in 0..count.max(1) {
+		let (first,last) = found.unwrap_or((line_no,line_no));
+		// The smallest span that holds the current one is the next one out
+		let Some(&outer) = spans.iter()
+			.filter(|&&(start,end)| start <= first && end >= last && Some((start,end)) != found)
+			.min_by_key(|(start,end)| end - start) else { break };
+		found = Some(outer);
+	}
+	found
+}
+/// If `line` opens or closes a Markdown code fence, get the fence character and how many times it is repeated
+fn code_fence(line: &str) -> Option<(char,usize)> {
+	let trimmed = line.trim_start_matches(' ');
+	if line.len() - trimmed.len() > 3 {
+		return None
+	}
+	let ch = trimmed.chars().next().filter(|ch| *ch == '`' || *ch == '~')?;
+	let len = trimmed.chars().take_while(|c| *c == ch).count();
+	(len >= 3).then_some((ch,len))
+}
+/// Find the first and last line of every fenced code block in a Markdown document, fences included.
+///
+/// A block that is never closed runs to the end of the document.
+pub fn find_code_fences(text: &str) -> Vec<(usize,usize)> {
+	let mut fences = vec![];
+	let mut open: Option<(usize,char,usize)> = None;
+	let mut last_line = 0;
+	for (line_no, line) in text.split('\n').enumerate() {
+		last_line = line_no;
+		let Some((ch,len)) = code_fence(line) else { continue };
+		match open {
+			None => open = Some((line_no,ch,len)),
+			Some((start,open_ch,open_len)) => {
+				// Closing fences can't have an info string
+				if ch == open_ch && len >= open_len && line.trim().chars().all(|c| c == ch) {
+					fences.push((start,line_no));
+					open = None;
+				}
+			}
+		}
+	}
+	if let Some((start,_,_)) = open {
+		fences.push((start,last_line));
+	}
+	fences
+}
+/// Whether each line of a Markdown document is inside of a fenced code block
+fn fenced_lines(text: &str) -> Vec<bool> {
+	let mut fenced = vec![false; text.split('\n').count()];
+	for (start,end) in find_code_fences(text) {
+		fenced[start..=end].fill(true);
+	}
+	fenced
+}
+/// Get the level of a Markdown ATX heading like `## Title`
+fn heading_level(line: &str) -> Option<usize> {
+	let trimmed = line.trim_start_matches(' ');
+	if line.len() - trimmed.len() > 3 {
+		return None
+	}
+	let level = trimmed.chars().take_while(|c| *c == '#').count();
+	let rest = &trimmed[level..];
+	((1..=6).contains(&level) && (rest.is_empty() || rest.starts_with([' ','\t']))).then_some(level)
+}
+/// Find the sections of a Markdown document. A section goes from a heading to the line before the next heading of the same level or higher.
+pub fn find_sections(text: &str) -> Vec<(usize,usize)> {
+	let fenced = fenced_lines(text);
+	let headings = text.split('\n')
+		.enumerate()
+		.filter(|(line_no,_)| !fenced[*line_no])
+		.filter_map(|(line_no,line)| heading_level(line).map(|level| (line_no,level)))
+		.collect::<Vec<_>>();
+	let last_line = fenced.len() - 1;
+	headings.iter()
+		.enumerate()
+		.map(|(i,&(start,level))| {
+			let end = headings[i + 1..].iter()
+				.find(|(_,next_level)| *next_level <= level)
+				.map(|(line_no,_)| line_no - 1)
+				.unwrap_or(last_line);
+			(start,end)
+		}).collect()
+}
+/// Get the width of the marker at the start of a Markdown list item, like `- ` or `12. `, including the indentation before it
+pub fn list_marker_len(line: &str) -> Option<usize> {
+	let trimmed = line.trim_start_matches([' ','\t']);
+	let indent = line.len() - trimmed.len();
+	let marker = if trimmed.starts_with(['-','*','+']) {
+		1
+	} else {
+		let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+		if !(1..=9).contains(&digits) || !trimmed[digits..].starts_with(['.',')']) {
+			return None
+		}
+		digits + 1
+	};
+	let rest = &trimmed[marker..];
+	if !rest.is_empty() && !rest.starts_with([' ','\t']) {
+		return None
+	}
+	let spaces = rest.len() - rest.trim_start_matches([' ','\t']).len();
+	Some(indent + marker + spaces)
+}
+/// Find the list items of a Markdown document. An item goes from its marker to the last line that is indented past the marker.
+///
+/// Blank lines inside of an item are included, but not the ones after it.
+pub fn find_list_items(text: &str) -> Vec<(usize,usize)> {
+	let fenced = fenced_lines(text);
+	let lines = text.split('\n').collect::<Vec<_>>();
+	let indents = line_indents(text);
+	let mut items = vec![];
+	for (line_no, line) in lines.iter().enumerate() {
+		if fenced[line_no] || list_marker_len(line).is_none() {
+			continue
+		}
+		let Some(marker_indent) = indents[line_no] else { continue };
+		let mut end = line_no;
+		for (next_no, indent) in indents.iter().enumerate().skip(line_no + 1) {
+			match indent {
+				None => continue,
+				Some(indent) if *indent > marker_indent => end = next_no,
+				Some(_) => break
+			}
+		}
+		items.push((line_no,end));
+	}
+	items
+}
+
 /// Used for undo/redo logic.
 #[derive(Clone,Default,Debug)]
 pub struct Edit {
@@ -520,6 +1009,21 @@ impl ClampedIsize {
 	}
 }
 
+/// The settings that every buffer in a run is opened with.
+///
+/// They come from the command line, except for `custom_text_objs`, which vic scripts add to with `textobj()`.
+#[derive(Default,Clone,Debug)]
+pub struct BufSettings {
+	pub units: TextUnit,
+	pub window_height: Option<usize>, // Set by --window-height, otherwise DEFAULT_WINDOW_HEIGHT
+	pub textwidth: Option<usize>, // Set by --textwidth, otherwise DEFAULT_TEXTWIDTH
+	pub fold_method: FoldMethod,
+	pub sub_syntax: SubSyntax,
+	pub kv_separators: Option<Vec<String>>, // Set by --kv-separators, otherwise DEFAULT_KV_SEPARATORS
+	pub string_escape: Option<char>, // Set by --string-escape, otherwise DEFAULT_STRING_ESCAPE
+	pub custom_text_objs: HashMap<char,CustomTextObj>,
+}
+
 /// The central buffer and state manager for `vicut`'s editing logic.
 ///
 /// `LineBuf` operates entirely on **grapheme clusters** (not `char`s or byte offsets),
@@ -533,13 +1037,16 @@ impl ClampedIsize {
 ///
 /// ### Selections and Motion
 /// - `select_mode` and `select_range`: Represent active selections.
-/// - `last_selection`: Stores the most recent selection span.
+/// - `last_selection` and `last_select_mode`: The most recent selection, for `gv`, `'<`, and `'>`.
 /// - `saved_col`: Used for vertical motion and visual alignment.
 ///
 /// ### Command History
 /// - `last_pattern_search`: Most recent `/pattern` used.
 /// - `last_substitution`: Stores the last `:s` command and flags.
+/// - `sub_spans`: Where the replacements made by the last `:s` ended up, for `ir`, `ar`, `g;`, and `g,`.
 /// - `last_global`: Stores the last global command (`:g`, `:v`, etc).
+/// - `marks`: Positions set with `m{a-z}`, kept in place as the buffer is edited.
+/// - `line_marks`: The lines that `-g` and `normal!()` have yet to visit, one set for each of them that is running. Moved by every edit.
 ///
 /// ### Insert Mode
 /// - `insert_mode_start_pos`: Marks where insert mode began (for `.`, undo).
@@ -547,6 +1054,9 @@ impl ClampedIsize {
 /// ### Undo/Redo
 /// - `undo_stack` / `redo_stack`: Hold `Edit` entries representing mutations.
 ///
+/// ### Shell
+/// - `shell`: Timeout, working directory, and formatter used by `:!`, `:r !`, `:w !`, and `=`.
+///
 /// ### Notes
 /// Slicing, motion, and indexing are always performed using grapheme indices,
 /// with utility methods handling conversion to/from byte offsets internally.
@@ -556,14 +1066,28 @@ pub struct LineBuf {
 	pub buffer: String,
 	pub grapheme_indices: Option<Vec<usize>>, // Used to slice the buffer
 	pub cursor: ClampedUsize, // Used to index grapheme_indices
+	pub units: TextUnit, // What each entry in grapheme_indices refers to
+	pub window_top: usize, // The first line shown in the virtual window, used by H/M/L and scrolling
+	pub scroll_anchor: Option<(usize,usize)>, // The cursor's byte position and line at the last scroll, so the next one only counts the lines in between
+	pub window_height: Option<usize>, // Set by --window-height, otherwise DEFAULT_WINDOW_HEIGHT
+	pub textwidth: Option<usize>, // Set by --textwidth, otherwise DEFAULT_TEXTWIDTH
+	pub fold_method: FoldMethod,
+	pub sub_syntax: SubSyntax,
+	pub kv_separators: Option<Vec<String>>, // Set by --kv-separators, otherwise DEFAULT_KV_SEPARATORS
+	pub string_escape: Option<char>, // Set by --string-escape, otherwise DEFAULT_STRING_ESCAPE
+	pub custom_text_objs: HashMap<char,CustomTextObj>, // Defined with textobj() in vic scripts
 
 	pub select_mode: Option<SelectMode>,
 	pub select_range: Option<SelectRange>,
 
 	pub last_selection: Option<SelectRange>,
+	pub last_select_mode: Option<SelectMode>,
 	pub last_pattern_search: Option<Regex>,
 	pub last_substitution: Option<(Regex,String,SubFlags)>,
+	pub sub_spans: Vec<(usize,usize)>, // Forgotten when the buffer is edited by anything else
 	pub last_global: Option<Verb>,
+	pub marks: HashMap<char,usize>,
+	pub line_marks: Vec<VecDeque<usize>>,
 
 	pub insert_mode_start_pos: Option<usize>,
 	pub inserting_from_visual: bool,
@@ -571,6 +1095,8 @@ pub struct LineBuf {
 
 	pub undo_stack: Vec<Edit>,
 	pub redo_stack: Vec<Edit>,
+
+	pub shell: ShellCfg,
 }
 
 impl LineBuf {
@@ -585,14 +1111,34 @@ impl LineBuf {
 			self.update_graphemes();
 		}
 	}
+	/// Open the buffer with `settings`
+	pub fn with_settings(mut self, settings: &BufSettings) -> Self {
+		self.apply_settings(settings);
+		self
+	}
+	/// Switch an open buffer over to `settings`
+	pub fn apply_settings(&mut self, settings: &BufSettings) {
+		if self.units != settings.units {
+			self.set_units(settings.units);
+		}
+		self.window_height = settings.window_height;
+		self.textwidth = settings.textwidth;
+		self.fold_method = settings.fold_method;
+		self.sub_syntax = settings.sub_syntax;
+		self.kv_separators = settings.kv_separators.clone();
+		self.string_escape = settings.string_escape;
+		self.custom_text_objs = settings.custom_text_objs.clone();
+	}
 	/// Set the initial state of the editor
 	pub fn with_initial(mut self, buffer: String, cursor: usize) -> Self {
 		self.buffer = buffer;
+		self.scroll_anchor = None;
 		self.update_graphemes();
 		self.cursor = ClampedUsize::new(cursor, self.grapheme_indices().len(), self.cursor.exclusive);
 		self
 	}
 	pub fn take_buf(&mut self) -> String {
+		self.scroll_anchor = None;
 		std::mem::take(&mut self.buffer)
 	}
 	pub fn set_cursor_clamp(&mut self, yn: bool) {
@@ -625,13 +1171,22 @@ impl LineBuf {
 	/// Be careful with this. Code paths with any amount of regular traffic should use update_graphemes_lazy instead
 	/// Slicing the buffer with grapheme_indices(true) is surprisingly expensive
 	pub fn update_graphemes(&mut self) {
-		let indices: Vec<_> = self.buffer
-			.grapheme_indices(true)
-			.map(|(i,_)| i)
-			.collect();
+		let indices = self.units.indices(&self.buffer);
 		self.cursor.set_max(indices.len());
 		self.grapheme_indices = Some(indices)
 	}
+	/// Switch to addressing the buffer by bytes, chars, or graphemes.
+	///
+	/// In byte mode, a position inside a multi-byte character stands for the start of that character, so it is best kept to single-byte data.
+	pub fn set_units(&mut self, units: TextUnit) {
+		let cursor_byte_pos = self.grapheme_indices.as_ref().map(|_| self.read_cursor_byte_pos());
+		self.units = units;
+		self.update_graphemes();
+		if let Some(pos) = cursor_byte_pos {
+			let cursor = self.grapheme_indices().partition_point(|idx| *idx < pos);
+			self.cursor.set(cursor);
+		}
+	}
 	pub fn grapheme_indices(&self) -> &[usize] {
 		self.grapheme_indices.as_ref().unwrap()
 	}
@@ -783,43 +1338,60 @@ impl LineBuf {
 	}
 	pub fn remove(&mut self, pos: usize) {
 		let idx = self.index_byte_pos(pos);
-		self.buffer.remove(idx);
+		let len = self.buffer[idx..].chars().next().map_or(0, char::len_utf8);
+		self.splice(idx..idx + len, "");
 		self.update_graphemes();
 	}
 	pub fn drain(&mut self, start: usize, end: usize) -> String {
-		let drained = if end == self.grapheme_indices().len() {
+		let range = if end == self.grapheme_indices().len() {
 			if start == self.grapheme_indices().len() {
 				return String::new()
 			}
-			let start = self.grapheme_indices()[start];
-			self.buffer.drain(start..).collect()
+			self.grapheme_indices()[start]..self.buffer.len()
 		} else {
-			let start = self.grapheme_indices()[start];
-			let end = self.grapheme_indices()[end];
-			self.buffer.drain(start..end).collect()
+			self.grapheme_indices()[start]..self.grapheme_indices()[end]
 		};
+		let drained = self.buffer[range.clone()].to_string();
+		self.splice(range, "");
 		self.update_graphemes();
 		drained
 	}
 	pub fn push(&mut self, ch: char) {
-		self.buffer.push(ch);
-		self.update_graphemes();
+		self.push_str(ch.encode_utf8(&mut [0;4]));
 	}
 	pub fn push_str(&mut self, slice: &str) {
-		self.buffer.push_str(slice);
+		let end = self.buffer.len();
+		self.splice(end..end, slice);
 		self.update_graphemes();
 	}
+	/// Replace the bytes in `range` with `new`.
+	///
+	/// Every edit to the text goes through here, so that the line marks can follow it.
+	fn splice(&mut self, range: Range<usize>, new: &str) {
+		adjust_line_marks(&mut self.line_marks, &self.buffer, &range, new);
+		adjust_scroll_anchor(&mut self.scroll_anchor, &self.buffer, &range, new);
+		self.buffer.replace_range(range, new);
+	}
 	pub fn insert_at_cursor(&mut self, ch: char) {
 		self.insert_at(self.cursor.get(), ch);
 	}
 	pub fn insert_at(&mut self, pos: usize, ch: char) {
 		let pos = self.index_byte_pos(pos);
-		self.buffer.insert(pos, ch);
+		self.splice(pos..pos, ch.encode_utf8(&mut [0;4]));
 		self.update_graphemes();
 	}
+	/// Move the line marks for a new version of the whole buffer, treating it as a single edit
+	fn adjust_line_marks_for(&mut self, buffer: &str) {
+		if self.line_marks.iter().any(|marks| !marks.is_empty()) {
+			let edit = Edit::diff(&self.buffer, buffer, 0);
+			adjust_line_marks(&mut self.line_marks, &self.buffer, &(edit.pos..edit.pos + edit.old_diff.len()), &edit.new_diff);
+		}
+	}
 	pub fn set_buffer_lazy(&mut self, buffer: String) {
 		if buffer != self.buffer {
+			self.adjust_line_marks_for(&buffer);
 			self.buffer = buffer;
+			self.scroll_anchor = None;
 			// Here we set it to none
 			// The methods which access grapheme_indices will update it if it is None
 			// so this way, we only update it if we really need to
@@ -827,7 +1399,9 @@ impl LineBuf {
 		}
 	}
 	pub fn set_buffer(&mut self, buffer: String) {
+		self.adjust_line_marks_for(&buffer);
 		self.buffer = buffer;
+		self.scroll_anchor = None;
 		self.update_graphemes();
 	}
 	pub fn select_range(&self) -> Option<&SelectRange> {
@@ -926,11 +1500,179 @@ impl LineBuf {
 		self.select_range = Some(range);
 	}
 	pub fn stop_selecting(&mut self) {
-		self.select_mode = None;
+		let mode = self.select_mode.take();
+		if self.select_range.is_some() {
+			self.last_selection = self.select_range.take();
+			self.last_select_mode = mode;
+		}
+	}
+	/// Bring back the last selection for `gv`, with the cursor on the same side of it as before.
+	///
+	/// If something is selected already, it trades places with the last selection, like in vim.
+	/// Returns false if there is no last selection.
+	pub fn reselect_last(&mut self) -> bool {
+		let (Some(range), Some(mut mode)) = (self.last_selection.take(), self.last_select_mode.take()) else {
+			return false
+		};
 		if self.select_range.is_some() {
 			self.last_selection = self.select_range.take();
+			self.last_select_mode = self.select_mode.take();
+		}
+		// The buffer may have gotten shorter since then
+		let max = self.cursor.max;
+		let range = match range {
+			SelectRange::OneDim((start,end)) => SelectRange::OneDim((start.min(max),end.min(max))),
+			SelectRange::TwoDim(windows) => SelectRange::TwoDim(windows.into_iter()
+				.map(|(start,end)| (start.min(max),end.min(max)))
+				.collect())
+		};
+		let cursor = match (&range, &mut mode) {
+			(SelectRange::OneDim((start,end)), SelectMode::Char(anchor)) => match anchor {
+				SelectAnchor::Start => *end,
+				SelectAnchor::End => *start
+			},
+			(SelectRange::OneDim((start,end)), SelectMode::Line(anchor)) => match anchor {
+				SelectAnchor::Start => {
+					let last_line = self.index_line_number(end.saturating_sub(1).max(*start));
+					self.line_bounds(last_line).map_or(*start, |(line_start,_)| line_start)
+				}
+				SelectAnchor::End => *start
+			},
+			(SelectRange::TwoDim(windows), SelectMode::Block { anchor_pos, .. }) => {
+				*anchor_pos = (*anchor_pos).min(max);
+				let (Some(first), Some(last)) = (windows.first(), windows.last()) else {
+					return false
+				};
+				// The cursor goes in the corner across from the anchor
+				let anchor_line = self.index_line_number(*anchor_pos);
+				let cursor_window = if anchor_line == self.index_line_number(first.0) { last } else { first };
+				if self.index_col(*anchor_pos) == self.index_col(first.0) {
+					cursor_window.1.saturating_sub(1).max(cursor_window.0)
+				} else {
+					cursor_window.0
+				}
+			}
+			_ => return false
+		};
+		self.select_mode = Some(mode);
+		self.select_range = Some(range);
+		self.cursor.set(cursor);
+		self.update_select_range();
+		true
+	}
+	/// Grow the selection away from its anchor by `times` more copies of its own size
+	///
+	/// This is what a count does to `v`, `V`, Ctrl-V, and `gv`, so `3V` selects three lines and `2gv` brings back the last selection at twice the size.
+	pub fn extend_selection(&mut self, times: usize) {
+		let (Some(range), Some(mode)) = (self.select_range.clone(), self.select_mode) else {
+			return
+		};
+		if times == 0 {
+			return
+		}
+		let cursor = self.cursor.get();
+		let target = match (range, mode) {
+			(SelectRange::OneDim((start,end)), SelectMode::Char(anchor)) => {
+				let anchor_pos = match anchor {
+					SelectAnchor::Start => start,
+					SelectAnchor::End => end
+				};
+				let size = (cursor.abs_diff(anchor_pos) + 1) * times;
+				// The selection can't reach past the last character
+				let last = self.grapheme_indices().len().saturating_sub(1);
+				match anchor {
+					SelectAnchor::Start => (cursor + size).min(last),
+					SelectAnchor::End => cursor.saturating_sub(size)
+				}
+			}
+			(SelectRange::OneDim((start,end)), SelectMode::Line(anchor)) => {
+				let first = self.index_line_number(start);
+				let last = self.index_line_number(end.saturating_sub(1).max(start));
+				let size = (last - first + 1) * times;
+				let line = self.cursor_line_number();
+				let target_line = match anchor {
+					SelectAnchor::Start => (line + size).min(self.total_lines().saturating_sub(1)),
+					SelectAnchor::End => line.saturating_sub(size)
+				};
+				let col = self.cursor_col();
+				self.line_col_pos(target_line, col)
+			}
+			(SelectRange::TwoDim(_), SelectMode::Block { anchor_pos, .. }) => {
+				let col = self.cursor_col();
+				let anchor_col = self.index_col(anchor_pos);
+				let size = (col.abs_diff(anchor_col) + 1) * times;
+				let target_col = if col >= anchor_col {
+					col + size
+				} else {
+					col.saturating_sub(size)
+				};
+				let line = self.cursor_line_number();
+				self.line_col_pos(line, target_col)
+			}
+			_ => return
+		};
+		self.cursor.set(target);
+		self.update_select_range();
+	}
+	/// Change the wise-ness of an operator's motion, for `dvj`, `dVw`, and `d<c-v>j`
+	///
+	/// `v` turns a linewise motion into an exclusive charwise one, and otherwise flips between inclusive and exclusive.
+	/// `V` covers every line the motion touches, and Ctrl-V covers the block between the two ends of the motion.
+	fn force_motion(&mut self, motion: MotionCmd, kind: MotionKind, forced: CmdFlags) -> MotionKind {
+		let cursor = self.cursor.get();
+		// The span that the motion covers, with its end left out, and whether the motion counts its last character
+		let (start, end, inclusive) = match kind {
+			MotionKind::Null |
+			MotionKind::Lines(_) |
+			MotionKind::BlockRange(_) => return kind,
+			MotionKind::On(_) |
+			MotionKind::Onto(_) |
+			MotionKind::To(_) |
+			MotionKind::Inclusive(_) |
+			MotionKind::Exclusive(_) => {
+				let Some((start,end)) = self.range_from_motion(&kind) else {
+					return MotionKind::Null
+				};
+				let inclusive = matches!(kind, MotionKind::Inclusive(_)) || matches!(kind, MotionKind::Onto(pos) if pos > cursor);
+				(start, end, inclusive)
+			}
+			_ => {
+				// Linewise, so we need to know where the motion would leave the cursor
+				let motion = match motion {
+					MotionCmd(count, Motion::LineUp) => MotionCmd(count, Motion::LineUpCharwise),
+					MotionCmd(count, Motion::LineDown) => MotionCmd(count, Motion::LineDownCharwise),
+					motion => motion
+				};
+				let saved_col = self.saved_col;
+				let target = self.eval_motion(None, motion);
+				self.apply_motion(target);
+				let pos = self.cursor.get();
+				self.cursor.set(cursor);
+				self.saved_col = saved_col;
+				let (start,end) = ordered(cursor, pos);
+				// Counted as inclusive, so that 'v' makes it exclusive like in vim
+				(start, end + 1, true)
+			}
+		};
+		let last = end.saturating_sub(1).max(start);
+		match forced {
+			CmdFlags::VISUAL_LINE => MotionKind::LineRange(self.index_line_number(start), self.index_line_number(last)),
+			CmdFlags::VISUAL_BLOCK => {
+				let mode = SelectMode::Block { anchor: SelectAnchor::Start, anchor_pos: start };
+				self.cursor.set(last);
+				let windows = self.get_block_select_windows(&mode);
+				self.cursor.set(cursor);
+				MotionKind::BlockRange(windows)
+			}
+			_ if inclusive => MotionKind::Exclusive((start,last)),
+			_ => MotionKind::Inclusive((start,end))
 		}
 	}
+	/// The position on line `line_no` that is `col` places in, or the end of the line if it is shorter than that
+	fn line_col_pos(&self, line_no: usize, col: usize) -> usize {
+		let (start,end) = self.line_bounds(line_no).unwrap_or((0,self.cursor.max));
+		(start + col).min(end.saturating_sub(1)).max(start)
+	}
 	pub fn is_selecting(&self) -> bool {
 		self.select_mode.is_some() && self.select_range.is_some()
 	}
@@ -950,16 +1692,23 @@ impl LineBuf {
 					_ => unreachable!()
 				}
 			},
-			SelectRange::TwoDim(lines) => {
-				let mut content = vec![];
-				for (start,end) in lines {
-					if let Some(slice) = self.slice(start..end) {
-						content.push(slice.to_string());
-					}
-				}
-				Some(content.join("\n"))
+			SelectRange::TwoDim(_) => self.selected_block_content().map(|lines| lines.join("\n"))
+		}
+	}
+	/// The pieces of a visual block selection, one for each line it covers
+	///
+	/// Returns `None` if the selection is not a block.
+	pub fn selected_block_content(&mut self) -> Option<Vec<String>> {
+		let Some(SelectRange::TwoDim(lines)) = self.select_range().cloned() else {
+			return None
+		};
+		let mut content = vec![];
+		for (start,end) in lines {
+			if let Some(slice) = self.slice(start..end) {
+				content.push(slice.to_string());
 			}
 		}
+		Some(content)
 	}
 	pub fn total_lines(&self) -> usize {
 		self.buffer
@@ -1124,7 +1873,7 @@ impl LineBuf {
 		// Fine the start of the line
 		for _ in 0..n {
 			while let Some(idx) = idx_iter.next() {
-				let gr = self.read_grapheme_at(idx).unwrap();
+				let gr = self.read_grapheme_at(idx).unwrap_or_default();
 				if gr == "\n" {
 					start = (idx + 1).min(self.cursor.max);
 					break
@@ -1137,7 +1886,7 @@ impl LineBuf {
 		// Find the end of the line
 		while let Some(idx) = idx_iter.next() {
 			end = (end + 1).min(self.cursor.max);
-			let gr = self.read_grapheme_at(idx).unwrap();
+			let gr = self.read_grapheme_at(idx).unwrap_or_default();
 			if gr == "\n" {
 				found_newline = true;
 				break
@@ -1187,17 +1936,39 @@ impl LineBuf {
 		}
 	}
 
+	/// Move the marks so that they stay on the same text after `old` is edited into `new`.
+	///
+	/// Marks before the edit stay where they are, and marks after it are shifted by the change in length.
+	/// A mark inside the text that was replaced moves to the start of the replacement.
+	pub fn adjust_marks(&mut self, old: &str, new: &str) {
+		if self.marks.is_empty() {
+			return
+		}
+		let edit = Edit::diff(old, new, 0);
+		let units = self.units;
+		let start = units.count(&old[..edit.pos]);
+		let old_end = start + units.count(&edit.old_diff);
+		let new_len = units.count(&edit.new_diff);
+		for pos in self.marks.values_mut() {
+			if *pos >= old_end {
+				*pos = *pos - old_end + start + new_len;
+			} else if *pos > start {
+				*pos = start;
+			}
+		}
+	}
+
 	/// Check if a character is a word boundary
 	pub fn is_word_bound(&mut self, pos: usize, word: Word, dir: Direction) -> bool {
 		let clamped_pos = ClampedUsize::new(pos, self.cursor.max, true);
-		let Some(cur_char) = self.grapheme_at(clamped_pos.get()).map(|c| c.to_string()) else { return false };
+		let Some(cur_char) = self.word_char_at(clamped_pos.get()).map(|c| c.to_string()) else { return false };
 		let other_pos = match dir {
 			Direction::Forward => clamped_pos.ret_add(1),
 			Direction::Backward => clamped_pos.ret_sub(1)
 		};
 		if other_pos == clamped_pos.get() { return true }
 
-		let other_char = self.grapheme_at(other_pos).unwrap();
+		let other_char = self.word_char_at(other_pos).unwrap();
 		match word {
 			Word::Big => is_whitespace(other_char),
 			Word::Normal => is_other_class_or_is_ws(other_char, &cur_char)
@@ -1240,10 +2011,25 @@ impl LineBuf {
 					TextObj::Bracket(bound) |
 					TextObj::Brace(bound) |
 					TextObj::Angle(bound) => self.text_obj_delim(count, text_obj, bound),
+				TextObj::Arg(bound) => self.text_obj_arg(count, bound),
+				TextObj::Number(bound) => self.text_obj_number(count, bound),
+				TextObj::Url(bound) => self.text_obj_url(count, bound),
+				TextObj::Path(bound) => self.text_obj_path(count, bound),
+				TextObj::Email(bound) => self.text_obj_email(count, bound),
+				TextObj::KeyValue(bound) => self.text_obj_key_value(bound),
+				TextObj::CodeFence(bound) => self.text_obj_code_fence(bound),
+				TextObj::Section(bound) => self.text_obj_section(count, bound),
+				TextObj::ListItem(bound) => self.text_obj_list_item(count, bound),
+				TextObj::StringLiteral(bound) => self.text_obj_string(count, bound),
+				TextObj::SubChange(bound) => self.text_obj_sub_change(count, bound),
+
+				// Line blocks
+				TextObj::Fold(bound) => self.text_obj_fold(count, bound),
+				TextObj::Indent(bound) => self.text_obj_indent(count, bound),
 
 					// Other stuff
-				TextObj::Tag(bound) => todo!(),
-				TextObj::Custom(_) => todo!(),
+				TextObj::Tag(bound) => self.text_obj_tag(count, bound),
+				TextObj::Custom(key,bound) => self.text_obj_custom(count, key, bound),
 		}
 	}
 	pub fn text_obj_word(&mut self, count: usize, bound: Bound, word: Word) -> Option<(usize,usize)> {
@@ -1374,58 +2160,431 @@ impl LineBuf {
 		}
 		Some((start,end))
 	}
-	/// Get the span of the next delimited block in this line
-	pub fn text_obj_delim(&mut self, count: usize, text_obj: TextObj, bound: Bound) -> Option<(usize,usize)> {
-		let mut backward_indices = (0..self.cursor.get()).rev();
-		let (opener,closer) = match text_obj {
-			TextObj::Paren(_)   => ("(",")"),
-			TextObj::Bracket(_) => ("[","]"),
-			TextObj::Brace(_)   => ("{","}"),
-			TextObj::Angle(_)   => ("<",">"),
-			_ => unreachable!()
-		};
-
-		let mut start_pos = None;
-		let mut closer_count: u32 = 0;
-		while let Some(idx) = backward_indices.next() {
-			let gr = self.grapheme_at(idx)?.to_string();
-			if (gr != closer && gr != opener) || self.grapheme_is_escaped(idx) { continue }
-
-			if gr == closer {
-				closer_count += 1;
-			} else if closer_count == 0 {
-				start_pos = Some(idx);
-				break
-			} else {
-				closer_count = closer_count.saturating_sub(1)
+	/// Get the span of the innermost fold around the cursor. A count selects folds further out.
+	///
+	/// `az` also includes the marker lines, or the line that an indented fold hangs off of.
+	pub fn text_obj_fold(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		let cursor_line = self.cursor_line_number();
+		let folds = find_folds(&self.buffer, self.fold_method);
+		let (mut first, mut last) = enclosing_lines(&folds, cursor_line, count)?;
+		match (self.fold_method, bound) {
+			(FoldMethod::Marker, Bound::Inside) => {
+				if last - first < 2 {
+					return None
+				}
+				first += 1;
+				last -= 1;
+			}
+			(FoldMethod::Indent, Bound::Around) => {
+				first = first.saturating_sub(1);
 			}
+			_ => {}
+		}
+		let (start,_) = self.line_bounds(first)?;
+		let (_,end) = self.line_bounds(last)?;
+		Some((start,end))
+	}
+	/// Get the span of the comma separated argument under the cursor, inside of the innermost pair of brackets.
+	///
+	/// Commas inside of nested brackets or quotes don't split arguments. `aa` takes the comma after the argument along with it,
+	/// or the one before it if it is the last argument. A count selects arguments further out.
+	pub fn text_obj_arg(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		let mut pos = self.cursor.get();
+		let mut opener = self.enclosing_bracket(pos)?;
+		for _ in 1..count {
+			pos = opener;
+			opener = self.enclosing_bracket(pos)?;
 		}
 
-		let (mut start, mut end) = if let Some(pos) = start_pos {
-			let start = pos;
-			let mut forward_indices = start+1..self.cursor.max;
-			let mut end = None;
-			let mut opener_count: u32 = 0;
-
-			while let Some(idx) = forward_indices.next() {
-				if self.grapheme_is_escaped(idx) { continue }
-				match self.grapheme_at(idx)? {
-					gr if gr == opener => opener_count += 1,
-					gr if gr == closer => {
-						if opener_count == 0 {
-							end = Some(idx);
-							break
-						} else {
-							opener_count = opener_count.saturating_sub(1);
-						}
-					}
-					_ => { /* Continue */ }
+		// Split the contents of the brackets on the commas that belong to them
+		let mut separators = vec![opener];
+		let mut depth = 0usize;
+		let mut quote: Option<String> = None;
+		let mut idx = opener + 1;
+		let closer = loop {
+			let gr = self.read_grapheme_at(idx)?.to_string();
+			if let Some(q) = &quote {
+				if *q == gr && !self.grapheme_is_escaped(idx) {
+					quote = None;
+				}
+			} else if self.is_quote_start(idx) {
+				quote = Some(gr);
+			} else {
+				match gr.as_str() {
+					"(" | "[" | "{" => depth += 1,
+					")" | "]" | "}" if depth == 0 => break idx,
+					")" | "]" | "}" => depth -= 1,
+					"," if depth == 0 => separators.push(idx),
+					_ => {}
 				}
 			}
-
-			(start,end?)
-		} else {
-			let mut forward_indices = self.cursor.get()..self.cursor.max;
+			idx += 1;
+		};
+		separators.push(closer);
+
+		// The comma under the cursor belongs to the argument before it
+		let arg = separators.windows(2).position(|sep| pos > sep[0] && pos <= sep[1])?;
+		let (before, after) = (separators[arg], separators[arg + 1]);
+		let trimmed = |buf: &Self, start: usize, end: usize| -> (usize,usize) {
+			let is_ws = |idx: usize| buf.read_grapheme_at(idx).is_some_and(is_whitespace);
+			let start = (start..end).find(|idx| !is_ws(*idx)).unwrap_or(end);
+			let end = (start..end).rev().find(|idx| !is_ws(*idx)).map(|idx| idx + 1).unwrap_or(start);
+			(start,end)
+		};
+		let (start,end) = trimmed(self, before + 1, after);
+		match bound {
+			Bound::Inside => Some((start,end)),
+			Bound::Around => {
+				if arg + 2 < separators.len() {
+					// Take the comma after us, and the space before the next argument
+					let (next_start,_) = trimmed(self, after + 1, separators[arg + 2]);
+					Some((start,next_start))
+				} else if arg > 0 {
+					// We are the last argument, so take the comma before us instead
+					let (_,prev_end) = trimmed(self, separators[arg - 1] + 1, before);
+					Some((prev_end,end))
+				} else {
+					Some((start,end))
+				}
+			}
+		}
+	}
+	/// The span of every match of `pattern` in the buffer, in order. `trim` is stripped from the end of each match.
+	///
+	/// If the pattern has a group named `obj`, only that group is used.
+	pub fn pattern_spans(&mut self, pattern: &Regex, trim: &[char]) -> Vec<(usize,usize)> {
+		self.update_graphemes_lazy();
+		let indices = self.grapheme_indices();
+		let to_index = |byte_pos: usize| indices.partition_point(|idx| *idx < byte_pos);
+		pattern.captures_iter(&self.buffer)
+			.filter_map(|caps| caps.name("obj").or_else(|| caps.get(0)))
+			.map(|mat| (mat.start(), mat.start() + mat.as_str().trim_end_matches(trim).len()))
+			.filter(|(start,end)| end > start)
+			.map(|(start,end)| (to_index(start), to_index(end)))
+			.collect()
+	}
+	/// The span of every number in the buffer, in order
+	pub fn numbers(&mut self) -> Vec<(usize,usize)> {
+		self.pattern_spans(&NUMBER, &[])
+	}
+	/// Get the span of the number under the cursor, or the next one on the same line like Vim's Ctrl-A.
+	pub fn text_obj_number(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		self.text_obj_pattern(&NUMBER, &[], count, bound)
+	}
+	/// Get the span of the URL under the cursor, or the next one on the same line
+	pub fn text_obj_url(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		self.text_obj_pattern(&URL, TRAILING_PUNCT, count, bound)
+	}
+	/// Get the span of the filesystem path under the cursor, or the next one on the same line
+	pub fn text_obj_path(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		self.text_obj_pattern(&PATH, TRAILING_PUNCT, count, bound)
+	}
+	/// Get the span of the email address under the cursor, or the next one on the same line
+	pub fn text_obj_email(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		self.text_obj_pattern(&EMAIL, &[], count, bound)
+	}
+	/// Get the span of the `count`th match of `pattern` that ends after the cursor and starts on the cursor's line.
+	///
+	/// The around variant also takes the whitespace after the match, or the whitespace before it if there isn't any after.
+	fn text_obj_pattern(&mut self, pattern: &Regex, trim: &[char], count: usize, bound: Bound) -> Option<(usize,usize)> {
+		let cursor = self.cursor.get();
+		let (_,line_end) = self.this_line();
+		let (start,end) = self.pattern_spans(pattern, trim)
+			.into_iter()
+			.filter(|(start,end)| *end > cursor && *start < line_end)
+			.nth(count.saturating_sub(1))?;
+		match bound {
+			Bound::Inside => Some((start,end)),
+			Bound::Around => {
+				let is_blank = |buf: &Self, idx: usize| buf.read_grapheme_at(idx).is_some_and(|gr| gr == " " || gr == "\t");
+				let after = (end..self.cursor.max).find(|idx| !is_blank(self, *idx)).unwrap_or(self.cursor.max);
+				if after > end {
+					return Some((start,after))
+				}
+				let before = (0..start).rev().find(|idx| !is_blank(self, *idx)).map(|idx| idx + 1).unwrap_or(0);
+				Some((before,end))
+			}
+		}
+	}
+	/// Get the span of the text that the last `:s` put in under the cursor, or the next one after it. See `sub_spans`.
+	///
+	/// `ar` covers everything from the first replacement to the last one. A count selects replacements further ahead.
+	pub fn text_obj_sub_change(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		match bound {
+			Bound::Inside => {
+				let cursor = self.cursor.get();
+				self.sub_spans.iter()
+					.filter(|(start,end)| *end > cursor || *start >= cursor)
+					.nth(count.saturating_sub(1))
+					.copied()
+			}
+			Bound::Around => {
+				let (start,_) = self.sub_spans.first()?;
+				let (_,end) = self.sub_spans.last()?;
+				Some((*start,*end))
+			}
+		}
+	}
+	/// Get the span of the string literal under the cursor, or the next one that starts on the cursor's line. See `find_strings`.
+	///
+	/// `aq` includes the delimiters and any whitespace after the string on the same line. A count selects strings further ahead.
+	pub fn text_obj_string(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		self.update_graphemes_lazy();
+		let (_,line_end) = self.this_line();
+		let indices = self.grapheme_indices();
+		let byte_pos = |idx: usize| indices.get(idx).copied().unwrap_or(self.buffer.len());
+		let (cursor, line_end) = (byte_pos(self.cursor.get()), byte_pos(line_end));
+		let to_index = |byte_pos: usize| indices.partition_point(|idx| *idx < byte_pos);
+		let (outer,inner) = find_strings(&self.buffer, self.string_escape.unwrap_or(DEFAULT_STRING_ESCAPE))
+			.into_iter()
+			.filter(|(outer,_)| outer.end > cursor && outer.start < line_end)
+			.nth(count.saturating_sub(1))?;
+		match bound {
+			Bound::Inside => Some((to_index(inner.start), to_index(inner.end))),
+			Bound::Around => {
+				let after = &self.buffer[outer.end..];
+				let trailing = after.len() - after.trim_start_matches([' ','\t']).len();
+				Some((to_index(outer.start), to_index(outer.end + trailing)))
+			}
+		}
+	}
+	/// Get the span of the element around the cursor, or the next one after it if the cursor isn't in one. See `find_tags`.
+	///
+	/// `it` selects what is between the tags, and `at` includes the tags. A count selects elements further out.
+	pub fn text_obj_tag(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		self.update_graphemes_lazy();
+		let indices = self.grapheme_indices();
+		let cursor = indices.get(self.cursor.get()).copied().unwrap_or(self.buffer.len());
+		let to_index = |byte_pos: usize| indices.partition_point(|idx| *idx < byte_pos);
+		let elements = find_tags(&self.buffer);
+		let (outer,inner) = enclosing_element(&elements, cursor, count)?;
+		let span = match bound {
+			Bound::Inside => inner,
+			Bound::Around => outer
+		};
+		Some((to_index(span.start), to_index(span.end)))
+	}
+	/// Get the span of a text object that was defined with `textobj()` in a vic script.
+	///
+	/// Delimited objects work like tags: the innermost pair around the cursor, with a count going outwards, or the next pair if there is none.
+	/// Pattern objects use the match under the cursor, or the next one, with a count going forward from there.
+	pub fn text_obj_custom(&mut self, count: usize, key: char, bound: Bound) -> Option<(usize,usize)> {
+		let obj = self.custom_text_objs.get(&key)?.clone();
+		self.update_graphemes_lazy();
+		let indices = self.grapheme_indices();
+		let cursor = indices.get(self.cursor.get()).copied().unwrap_or(self.buffer.len());
+		let to_index = |byte_pos: usize| indices.partition_point(|idx| *idx < byte_pos);
+		let (outer,inner) = match obj {
+			CustomTextObj::Delim(open,close) => {
+				let pairs = find_delim_pairs(&self.buffer, &open, &close);
+				enclosing_element(&pairs, cursor, count)?.clone()
+			}
+			CustomTextObj::Pattern(regex) => {
+				regex.captures_iter(&self.buffer)
+					.map(|caps| {
+						let outer = caps.get(0).unwrap().range();
+						let inner = caps.get(1).map(|group| group.range()).unwrap_or(outer.clone());
+						(outer,inner)
+					})
+					.filter(|(outer,_)| outer.end > cursor)
+					.nth(count.saturating_sub(1))?
+			}
+		};
+		let span = match bound {
+			Bound::Inside => inner,
+			Bound::Around => outer
+		};
+		Some((to_index(span.start), to_index(span.end)))
+	}
+	/// Get the span of the value on the cursor's line, in a `key=value`, `key: value`, or `key => value` pair.
+	///
+	/// The key/value split happens at the first separator on the line, preferring the longest one when several start at the same place.
+	/// A single trailing `,` or `;` is left out, so hash literals and statements work too. `av` selects the whole pair instead.
+	pub fn text_obj_key_value(&mut self, bound: Bound) -> Option<(usize,usize)> {
+		let (line_start,line_end) = self.this_line();
+		let line = self.slice(line_start..line_end)?.trim_end_matches('\n').to_string();
+		let separators = match &self.kv_separators {
+			Some(seps) => seps.clone(),
+			None => DEFAULT_KV_SEPARATORS.iter().map(|sep| sep.to_string()).collect()
+		};
+		let key_start = line.len() - line.trim_start().len();
+		let (sep_start,sep) = separators.iter()
+			.filter_map(|sep| line[key_start..].find(sep.as_str()).map(|pos| (key_start + pos, sep)))
+			.min_by_key(|(pos,sep)| (*pos, std::cmp::Reverse(sep.len())))?;
+		if line[key_start..sep_start].trim().is_empty() {
+			return None
+		}
+		let after_sep = sep_start + sep.len();
+		let value = line[after_sep..].trim();
+		let value = value.strip_suffix([',', ';']).unwrap_or(value).trim_end();
+		if value.is_empty() {
+			return None
+		}
+		let value_start = after_sep + (line[after_sep..].len() - line[after_sep..].trim_start().len());
+		let value_end = value_start + value.len();
+		let to_index = |byte_pos: usize| line_start + self.units.count(&line[..byte_pos]);
+		match bound {
+			Bound::Inside => Some((to_index(value_start), to_index(value_end))),
+			Bound::Around => Some((to_index(key_start), to_index(value_end))),
+		}
+	}
+	/// Find the opening bracket of the innermost bracket pair around `pos`, skipping over quoted text
+	fn enclosing_bracket(&mut self, pos: usize) -> Option<usize> {
+		self.update_graphemes_lazy();
+		let mut openers = vec![];
+		let mut quote: Option<String> = None;
+		for idx in 0..pos {
+			let gr = self.read_grapheme_at(idx)?.to_string();
+			if let Some(q) = &quote {
+				if *q == gr && !self.grapheme_is_escaped(idx) {
+					quote = None;
+				}
+			} else if self.is_quote_start(idx) {
+				quote = Some(gr);
+			} else {
+				match gr.as_str() {
+					"(" | "[" | "{" => openers.push(idx),
+					")" | "]" | "}" => { openers.pop(); }
+					_ => {}
+				}
+			}
+		}
+		openers.pop()
+	}
+	/// Whether the grapheme at `idx` opens a quoted string.
+	///
+	/// A quote that comes right after a word character is treated as an apostrophe, so that text like "don't" doesn't throw us off.
+	fn is_quote_start(&self, idx: usize) -> bool {
+		let Some(gr) = self.read_grapheme_at(idx) else { return false };
+		if !matches!(gr, "\"" | "'" | "`") {
+			return false
+		}
+		idx == 0 || self.read_grapheme_at(idx - 1).is_none_or(|prev| !prev.chars().all(|c| c.is_alphanumeric() || c == '_'))
+	}
+	/// Get the fenced code block around the cursor. `ic` selects the code, and `ac` includes the fences.
+	pub fn text_obj_code_fence(&mut self, bound: Bound) -> Option<(usize,usize)> {
+		let fences = find_code_fences(&self.buffer);
+		let (mut first, mut last) = enclosing_lines(&fences, self.cursor_line_number(), 1)?;
+		if bound == Bound::Inside {
+			let closed = code_fence(self.buffer.split('\n').nth(last)?).is_some() && last > first;
+			first += 1;
+			if closed {
+				last = last.checked_sub(1)?;
+			}
+			if first > last {
+				return None
+			}
+		}
+		let (start,_) = self.line_bounds(first)?;
+		let (_,end) = self.line_bounds(last)?;
+		Some((start,end))
+	}
+	/// Get the Markdown section that the cursor is in. `ih` leaves out the heading, and a count selects the sections further out.
+	pub fn text_obj_section(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		let sections = find_sections(&self.buffer);
+		let (mut first, last) = enclosing_lines(&sections, self.cursor_line_number(), count)?;
+		if bound == Bound::Inside {
+			first += 1;
+			if first > last {
+				return None
+			}
+		}
+		let (start,_) = self.line_bounds(first)?;
+		let (_,end) = self.line_bounds(last)?;
+		Some((start,end))
+	}
+	/// Get the Markdown list item that the cursor is in, along with the items nested under it.
+	///
+	/// `il` starts after the list marker, and `al` selects whole lines. A count selects the items further out.
+	pub fn text_obj_list_item(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		let items = find_list_items(&self.buffer);
+		let (first,last) = enclosing_lines(&items, self.cursor_line_number(), count)?;
+		let (mut start,_) = self.line_bounds(first)?;
+		let (_,mut end) = self.line_bounds(last)?;
+		if bound == Bound::Inside {
+			let line = self.buffer.split('\n').nth(first)?;
+			let marker_len = list_marker_len(line)?;
+			start += self.units.count(&line[..marker_len]);
+			// Leave the line break alone, so that the marker keeps its line
+			if self.read_grapheme_at(end.saturating_sub(1)) == Some("\n") {
+				end -= 1;
+			}
+		}
+		Some((start,end))
+	}
+	/// Get the span of the lines around the cursor that are indented at least as far as it is.
+	///
+	/// `ai` also includes the line that the block hangs off of. A count selects blocks further out.
+	pub fn text_obj_indent(&mut self, count: usize, bound: Bound) -> Option<(usize,usize)> {
+		let indents = line_indents(&self.buffer);
+		let mut line_no = self.cursor_line_number();
+		let mut block = indent_block(&indents, line_no)?;
+		for _ in 1..count {
+			let Some(header) = block.2 else { break };
+			line_no = header;
+			block = indent_block(&indents, line_no)?;
+		}
+		let (first,last,header) = block;
+		let first = match bound {
+			Bound::Inside => first,
+			Bound::Around => header.unwrap_or(first),
+		};
+		let (start,_) = self.line_bounds(first)?;
+		let (_,end) = self.line_bounds(last)?;
+		Some((start,end))
+	}
+	/// Get the span of the next delimited block in this line
+	pub fn text_obj_delim(&mut self, count: usize, text_obj: TextObj, bound: Bound) -> Option<(usize,usize)> {
+		let mut backward_indices = (0..self.cursor.get()).rev();
+		let (opener,closer) = match text_obj {
+			TextObj::Paren(_)   => ("(",")"),
+			TextObj::Bracket(_) => ("[","]"),
+			TextObj::Brace(_)   => ("{","}"),
+			TextObj::Angle(_)   => ("<",">"),
+			_ => unreachable!()
+		};
+
+		let mut start_pos = None;
+		let mut closer_count: u32 = 0;
+		while let Some(idx) = backward_indices.next() {
+			let gr = self.grapheme_at(idx)?.to_string();
+			if (gr != closer && gr != opener) || self.grapheme_is_escaped(idx) { continue }
+
+			if gr == closer {
+				closer_count += 1;
+			} else if closer_count == 0 {
+				start_pos = Some(idx);
+				break
+			} else {
+				closer_count = closer_count.saturating_sub(1)
+			}
+		}
+
+		let (mut start, mut end) = if let Some(pos) = start_pos {
+			let start = pos;
+			let mut forward_indices = start+1..self.cursor.max;
+			let mut end = None;
+			let mut opener_count: u32 = 0;
+
+			while let Some(idx) = forward_indices.next() {
+				if self.grapheme_is_escaped(idx) { continue }
+				match self.grapheme_at(idx)? {
+					gr if gr == opener => opener_count += 1,
+					gr if gr == closer => {
+						if opener_count == 0 {
+							end = Some(idx);
+							break
+						} else {
+							opener_count = opener_count.saturating_sub(1);
+						}
+					}
+					_ => { /* Continue */ }
+				}
+			}
+
+			(start,end?)
+		} else {
+			let mut forward_indices = self.cursor.get()..self.cursor.max;
 			let mut start = None;
 			let mut end = None;
 			let mut opener_count: u32 = 0;
@@ -1778,9 +2937,32 @@ impl LineBuf {
 					}
 				}
 			});
+			if self.units == TextUnit::Bytes && pos.get() < self.buffer.len() {
+				// Don't stop partway through a multi-byte character.
+				// Starts of words go to its first byte, and ends go to its last one, so that the whole character is included.
+				let snapped = match to {
+					To::Start => self.buffer.floor_char_boundary(pos.get()),
+					To::End => self.buffer.ceil_char_boundary(pos.get() + 1) - 1
+				};
+				pos.set(snapped);
+			}
 		}
 		pos.get()
 	}
+	/// The character that word motions look at to classify the given position
+	///
+	/// In byte mode, every byte of a multi-byte character is classified by the whole character.
+	fn word_char_at(&mut self, pos: usize) -> Option<&str> {
+		if self.units != TextUnit::Bytes {
+			return self.grapheme_at(pos)
+		}
+		if pos >= self.buffer.len() {
+			return None
+		}
+		let start = self.buffer.floor_char_boundary(pos);
+		let end = self.buffer.ceil_char_boundary(pos + 1);
+		self.buffer.get(start..end)
+	}
 	pub fn start_of_word_forward(&mut self, mut pos: usize, word: Word, include_last_char: bool) -> usize {
 		let default = self.grapheme_indices().len();
 		let mut indices_iter = (pos..self.cursor.max).peekable();
@@ -1790,7 +2972,7 @@ impl LineBuf {
 				let Some(next) = indices_iter.peek() else {
 					return default
 				};
-				let on_boundary = self.grapheme_at(*next).is_none_or(is_whitespace);
+				let on_boundary = self.word_char_at(*next).is_none_or(is_whitespace);
 				if on_boundary {
 					let Some(idx) = indices_iter.next() else { return default };
 					// We have a 'cw' call, do not include the trailing whitespace
@@ -1802,14 +2984,14 @@ impl LineBuf {
 				}
 
 				// Check current grapheme
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else {
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else {
 					return default
 				};
 				let on_whitespace = is_whitespace(&cur_char);
 
 				// Find the next whitespace
 				if !on_whitespace {
-					let Some(ws_pos) = indices_iter.find(|i| self.grapheme_at(*i).is_some_and(is_whitespace)) else {
+					let Some(ws_pos) = indices_iter.find(|i| self.word_char_at(*i).is_some_and(is_whitespace)) else {
 						return default
 					};
 					if include_last_char {
@@ -1818,12 +3000,12 @@ impl LineBuf {
 				}
 
 				// Return the next visible grapheme position
-				indices_iter.find(|i| self.grapheme_at(*i).is_some_and(|c| !is_whitespace(c))).unwrap_or(default)
+				indices_iter.find(|i| self.word_char_at(*i).is_some_and(|c| !is_whitespace(c))).unwrap_or(default)
 			}
 			Word::Normal => {
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else { return default };
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else { return default };
 				let Some(next_idx) = indices_iter.peek() else { return default };
-				let on_boundary = !is_whitespace(&cur_char) && self.grapheme_at(*next_idx).is_none_or(|c| is_other_class_or_is_ws(c, &cur_char));
+				let on_boundary = !is_whitespace(&cur_char) && self.word_char_at(*next_idx).is_none_or(|c| is_other_class_or_is_ws(c, &cur_char));
 				if on_boundary {
 					if include_last_char {
 						return *next_idx
@@ -1832,7 +3014,7 @@ impl LineBuf {
 					}
 				}
 
-				let Some(next_char) = self.grapheme_at(pos).map(|c| c.to_string()) else {
+				let Some(next_char) = self.word_char_at(pos).map(|c| c.to_string()) else {
 					return default
 				};
 				if is_other_class_not_ws(&cur_char, &next_char) {
@@ -1844,7 +3026,7 @@ impl LineBuf {
 				if !on_whitespace {
 					let other_class_pos = indices_iter.find(
 						|i| {
-							self.grapheme_at(*i)
+							self.word_char_at(*i)
 								.is_some_and(|c| is_other_class_or_is_ws(c, &next_char))
 						}
 					);
@@ -1852,7 +3034,7 @@ impl LineBuf {
 						return default
 					};
 					// If we hit a different character class, we return here
-					if self.grapheme_at(other_class_pos).is_some_and(|c| !is_whitespace(c)) || include_last_char {
+					if self.word_char_at(other_class_pos).is_some_and(|c| !is_whitespace(c)) || include_last_char {
 						return other_class_pos
 					}
 				}
@@ -1860,7 +3042,7 @@ impl LineBuf {
 				// We are now certainly on a whitespace character. Advance until a non-whitespace character.
 				indices_iter.find(
 					|i| {
-						self.grapheme_at(*i)
+						self.word_char_at(*i)
 							.is_some_and(|c| !is_whitespace(c))
 					}
 				).unwrap_or(default)
@@ -1877,7 +3059,7 @@ impl LineBuf {
 				let Some(next) = indices_iter.peek() else {
 					return default
 				};
-				let on_boundary = self.grapheme_at(*next).is_none_or(is_whitespace);
+				let on_boundary = self.word_char_at(*next).is_none_or(is_whitespace);
 				if on_boundary {
 					let Some(idx) = indices_iter.next() else { return default };
 					// We have a 'cw' call, do not include the trailing whitespace
@@ -1889,14 +3071,14 @@ impl LineBuf {
 				}
 
 				// Check current grapheme
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else {
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else {
 					return default
 				};
 				let on_whitespace = is_whitespace(&cur_char);
 
 				// Find the next whitespace
 				if !on_whitespace {
-					let Some(ws_pos) = indices_iter.find(|i| self.grapheme_at(*i).is_some_and(is_whitespace)) else {
+					let Some(ws_pos) = indices_iter.find(|i| self.word_char_at(*i).is_some_and(is_whitespace)) else {
 						return default
 					};
 					if include_last_char {
@@ -1906,12 +3088,12 @@ impl LineBuf {
 
 				// Return the next visible grapheme position
 
-				indices_iter.find(|i| self.grapheme_at(*i).is_some_and(|c| !is_whitespace(c))).unwrap_or(default)
+				indices_iter.find(|i| self.word_char_at(*i).is_some_and(|c| !is_whitespace(c))).unwrap_or(default)
 			}
 			Word::Normal => {
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else { return default };
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else { return default };
 				let Some(next_idx) = indices_iter.peek() else { return default };
-				let on_boundary = !is_whitespace(&cur_char) && self.grapheme_at(*next_idx).is_none_or(|c| is_other_class_or_is_ws(c, &cur_char));
+				let on_boundary = !is_whitespace(&cur_char) && self.word_char_at(*next_idx).is_none_or(|c| is_other_class_or_is_ws(c, &cur_char));
 				if on_boundary {
 					if include_last_char {
 						return *next_idx
@@ -1920,7 +3102,7 @@ impl LineBuf {
 					}
 				}
 
-				let Some(next_char) = self.grapheme_at(pos).map(|c| c.to_string()) else {
+				let Some(next_char) = self.word_char_at(pos).map(|c| c.to_string()) else {
 					return default
 				};
 				if is_other_class_not_ws(&cur_char, &next_char) {
@@ -1932,7 +3114,7 @@ impl LineBuf {
 				if !on_whitespace {
 					let other_class_pos = indices_iter.find(
 						|i| {
-							self.grapheme_at(*i)
+							self.word_char_at(*i)
 								.is_some_and(|c| is_other_class_or_is_ws(c, &next_char))
 						}
 					);
@@ -1940,7 +3122,7 @@ impl LineBuf {
 						return default
 					};
 					// If we hit a different character class, we return here
-					if self.grapheme_at(other_class_pos).is_some_and(|c| !is_whitespace(c)) || include_last_char {
+					if self.word_char_at(other_class_pos).is_some_and(|c| !is_whitespace(c)) || include_last_char {
 						return other_class_pos
 					}
 				}
@@ -1949,7 +3131,7 @@ impl LineBuf {
 
 				indices_iter.find(
 					|i| {
-						self.grapheme_at(*i)
+						self.word_char_at(*i)
 							.is_some_and(|c| !is_whitespace(c))
 					}
 				).unwrap_or(default)
@@ -1965,28 +3147,28 @@ impl LineBuf {
 
 		match word {
 			Word::Big => {
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else { return default };
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else { return default };
 				let Some(next_idx) = fwd_indices.peek() else { return default };
-				let on_boundary = !is_whitespace(&cur_char) && self.grapheme_at(*next_idx).is_none_or(is_whitespace);
+				let on_boundary = !is_whitespace(&cur_char) && self.word_char_at(*next_idx).is_none_or(is_whitespace);
 				if on_boundary {
 					let Some(idx) = fwd_indices.next() else { return default };
 					pos = idx;
 				}
 				// Check current grapheme
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else {
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else {
 					return default
 				};
 				let on_whitespace = is_whitespace(&cur_char);
 
 				// Advance iterator to next visible grapheme
 				if on_whitespace {
-					let Some(_non_ws_pos) = fwd_indices.find(|i| self.grapheme_at(*i).is_some_and(|c| !is_whitespace(c))) else {
+					let Some(_non_ws_pos) = fwd_indices.find(|i| self.word_char_at(*i).is_some_and(|c| !is_whitespace(c))) else {
 						return default
 					};
 				}
 
 				// The position of the next whitespace will tell us where the end (or start) of the word is
-				let Some(next_ws_pos) = fwd_indices.find(|i| self.grapheme_at(*i).is_some_and(is_whitespace)) else {
+				let Some(next_ws_pos) = fwd_indices.find(|i| self.word_char_at(*i).is_some_and(is_whitespace)) else {
 					return default
 				};
 				pos = next_ws_pos;
@@ -2000,33 +3182,33 @@ impl LineBuf {
 				}
 			}
 			Word::Normal => {
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else { return default };
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else { return default };
 				let Some(next_idx) = fwd_indices.peek() else { return default };
-				let on_boundary = !is_whitespace(&cur_char) && self.grapheme_at(*next_idx).is_none_or(|c| is_other_class_or_is_ws(c, &cur_char));
+				let on_boundary = !is_whitespace(&cur_char) && self.word_char_at(*next_idx).is_none_or(|c| is_other_class_or_is_ws(c, &cur_char));
 				if on_boundary {
 					let next_idx = fwd_indices.next().unwrap();
 					pos = next_idx
 				}
 
 				// Check current grapheme
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else {
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else {
 					return default
 				};
 				let on_whitespace = is_whitespace(&cur_char);
 
 				// Proceed to next visible grapheme
 				if on_whitespace {
-					let Some(non_ws_pos) = fwd_indices.find(|i| self.grapheme_at(*i).is_some_and(|c| !is_whitespace(c))) else {
+					let Some(non_ws_pos) = fwd_indices.find(|i| self.word_char_at(*i).is_some_and(|c| !is_whitespace(c))) else {
 						return default
 					};
 					pos = non_ws_pos
 				}
 
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else {
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else {
 					return self.grapheme_indices().len()
 				};
 				// The position of the next differing character class will tell us where the start of the word is
-				let Some(next_ws_pos) = fwd_indices.find(|i| self.grapheme_at(*i).is_some_and(|c| is_other_class_or_is_ws(c, &cur_char))) else {
+				let Some(next_ws_pos) = fwd_indices.find(|i| self.word_char_at(*i).is_some_and(|c| is_other_class_or_is_ws(c, &cur_char))) else {
 					return default
 				};
 				pos = next_ws_pos;
@@ -2050,27 +3232,27 @@ impl LineBuf {
 			Word::Big => {
 				let on_boundary = 'bound_check: {
 					let Some(next_idx) = indices_iter.peek() else { break 'bound_check false };
-					self.grapheme_at(*next_idx).is_none_or(is_whitespace)
+					self.word_char_at(*next_idx).is_none_or(is_whitespace)
 				};
 				if on_boundary {
 					let Some(idx) = indices_iter.next() else { return default };
 					pos = idx;
 				}
 				// Check current grapheme
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else {
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else {
 					return default
 				};
 				let on_whitespace = is_whitespace(&cur_char);
 
 				// Advance iterator to next visible grapheme
 				if on_whitespace {
-					let Some(_non_ws_pos) = indices_iter.find(|i| self.grapheme_at(*i).is_some_and(|c| !is_whitespace(c))) else {
+					let Some(_non_ws_pos) = indices_iter.find(|i| self.word_char_at(*i).is_some_and(|c| !is_whitespace(c))) else {
 						return default
 					};
 				}
 
 				// The position of the next whitespace will tell us where the end (or start) of the word is
-				let Some(next_ws_pos) = indices_iter.find(|i| self.grapheme_at(*i).is_some_and(is_whitespace)) else {
+				let Some(next_ws_pos) = indices_iter.find(|i| self.word_char_at(*i).is_some_and(is_whitespace)) else {
 					return default
 				};
 				pos = next_ws_pos;
@@ -2084,10 +3266,10 @@ impl LineBuf {
 				}
 			}
 			Word::Normal => {
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else { return default };
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else { return default };
 				let on_boundary = 'bound_check: {
 					let Some(next_idx) = indices_iter.peek() else { break 'bound_check false };
-					!is_whitespace(&cur_char) && self.grapheme_at(*next_idx).is_some_and(|c| is_other_class_or_is_ws(c, &cur_char))
+					!is_whitespace(&cur_char) && self.word_char_at(*next_idx).is_some_and(|c| is_other_class_or_is_ws(c, &cur_char))
 				};
 				if on_boundary {
 					let next_idx = indices_iter.next().unwrap();
@@ -2095,24 +3277,24 @@ impl LineBuf {
 				}
 
 				// Check current grapheme
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else {
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else {
 					return default
 				};
 				let on_whitespace = is_whitespace(&cur_char);
 
 				// Proceed to next visible grapheme
 				if on_whitespace {
-					let Some(non_ws_pos) = indices_iter.find(|i| self.grapheme_at(*i).is_some_and(|c| !is_whitespace(c))) else {
+					let Some(non_ws_pos) = indices_iter.find(|i| self.word_char_at(*i).is_some_and(|c| !is_whitespace(c))) else {
 						return default
 					};
 					pos = non_ws_pos
 				}
 
-				let Some(cur_char) = self.grapheme_at(pos).map(|c| c.to_string()) else {
+				let Some(cur_char) = self.word_char_at(pos).map(|c| c.to_string()) else {
 					return self.grapheme_indices().len()
 				};
 				// The position of the next differing character class will tell us where the start of the word is
-				let Some(next_ws_pos) = indices_iter.find(|i| self.grapheme_at(*i).is_some_and(|c| is_other_class_or_is_ws(c, &cur_char))) else {
+				let Some(next_ws_pos) = indices_iter.find(|i| self.word_char_at(*i).is_some_and(|c| is_other_class_or_is_ws(c, &cur_char))) else {
 					return default
 				};
 				pos = next_ws_pos;
@@ -2127,7 +3309,40 @@ impl LineBuf {
 			}
 		}
 	}
+	/// Find the grapheme that covers the given display column of `line`, like Vim's `|`
+	///
+	/// Wide characters take up two columns, and tabs stretch to the next tab stop.
+	/// Columns past the end of the line land on its last character.
+	fn index_for_display_col(&self, line: &str, target_col: usize) -> usize {
+		let line = line.strip_suffix('\n').unwrap_or(line);
+		if self.units != TextUnit::Graphemes {
+			// Fixed-width data, every unit takes up exactly one column
+			return target_col.min(self.units.count(line).saturating_sub(1))
+		}
+		let mut col = 0;
+		let mut last = 0;
+		for (grapheme_index, g) in line.graphemes(true).enumerate() {
+			col += display_width(col, g);
+			if col > target_col {
+				return grapheme_index
+			}
+			last = grapheme_index;
+		}
+		last
+	}
 	fn grapheme_index_for_display_col(&self, line: &str, target_col: usize) -> usize {
+		if self.units != TextUnit::Graphemes {
+			// Fixed-width data, every unit takes up exactly one column
+			let (content, has_newline) = match line.find('\n') {
+				Some(i) => (&line[..i], true),
+				None => (line, false)
+			};
+			let len = self.units.count(content);
+			if target_col < len {
+				return target_col
+			}
+			return if has_newline && self.cursor.exclusive { len.saturating_sub(1) } else { len }
+		}
 		let mut col = 0;
 		for (grapheme_index, g) in line.graphemes(true).enumerate() {
 			if g == "\n" {
@@ -2161,6 +3376,43 @@ impl LineBuf {
 	pub fn cursor_at_eol(&mut self) -> bool {
 		self.grapheme_after_cursor().is_none_or(|gr| gr == "\n")
 	}
+	/// The first and last line that are visible in the virtual window
+	pub fn window_lines(&self) -> (usize,usize) {
+		let last_line = self.total_lines().saturating_sub(1);
+		let bottom = (self.window_top + self.window_height() - 1).min(last_line);
+		(self.window_top, bottom)
+	}
+	pub fn window_height(&self) -> usize {
+		self.window_height.unwrap_or(DEFAULT_WINDOW_HEIGHT).max(1)
+	}
+	pub fn textwidth(&self) -> usize {
+		self.textwidth.unwrap_or(DEFAULT_TEXTWIDTH).max(1)
+	}
+	/// Scroll the virtual window as little as possible to keep the cursor on screen
+	pub fn scroll_to_cursor(&mut self) {
+		let height = self.window_height();
+		let cursor_pos = self.cursor_byte_pos();
+		let cursor_line = match self.scroll_anchor {
+			Some((pos,line)) if pos <= cursor_pos => self.buffer.get(pos..cursor_pos)
+				.map(|slice| line + slice.matches('\n').count()),
+			Some((pos,line)) => self.buffer.get(cursor_pos..pos)
+				.map(|slice| line - slice.matches('\n').count()),
+			None => None
+		}.unwrap_or_else(|| self.byte_pos_line_number(cursor_pos));
+		self.scroll_anchor = Some((cursor_pos,cursor_line));
+		if cursor_line < self.window_top {
+			self.window_top = cursor_line;
+		} else if cursor_line >= self.window_top + height {
+			self.window_top = cursor_line + 1 - height;
+		}
+	}
+	/// The column of the first non-whitespace character on the given line
+	fn first_non_blank_col(&mut self, line_no: usize) -> usize {
+		let Some((start,end)) = self.line_bounds(line_no) else { return 0 };
+		(start..end)
+			.position(|idx| self.read_grapheme_at(idx).is_some_and(|gr| !is_whitespace(gr)))
+			.unwrap_or(0)
+	}
 	pub fn cursor_col(&mut self) -> usize {
 		let start = self.start_of_line();
 		let cursor_pos = self.cursor.get();
@@ -2176,14 +3428,14 @@ impl LineBuf {
 		let byte_pos = self.index_byte_pos(insert_idx);
 		match content {
 			RegisterContent::Span(text) => {
-				self.buffer.insert_str(byte_pos, &text);
+				self.splice(byte_pos..byte_pos, &text);
 				self.update_graphemes();
 			}
 			RegisterContent::Line(mut line) => {
 				if self.grapheme_before(insert_idx).is_some_and(|gr| gr != "\n") {
 					line = format!("\n{}", line);
 				}
-				self.buffer.insert_str(byte_pos, &line);
+				self.splice(byte_pos..byte_pos, &line);
 				self.update_graphemes();
 			}
 			RegisterContent::Block(windows) => {
@@ -2223,10 +3475,10 @@ impl LineBuf {
 							end // We are at the end of the buffer, so no newline
 						};
 						let byte_pos = self.index_byte_pos(insert_idx);
-						self.buffer.insert_str(byte_pos, &window);
+						self.splice(byte_pos..byte_pos, &window);
 					} else {
 						let byte_pos = self.index_byte_pos(insert_idx);
-						self.buffer.insert_str(byte_pos, window);
+						self.splice(byte_pos..byte_pos, window);
 					}
 
 				}
@@ -2236,110 +3488,161 @@ impl LineBuf {
 	}
 	pub fn insert_str_at(&mut self, pos: usize, new: &str) {
 		let idx = self.index_byte_pos(pos);
-		self.buffer.insert_str(idx, new);
+		self.splice(idx..idx, new);
 		self.update_graphemes();
 	}
 	pub fn replace_range(&mut self, start: usize, end: usize, new: &str) {
 		self.update_graphemes_lazy();
 		let start_byte_pos = self.grapheme_indices().get(start).copied().unwrap_or(0);
 		let end_byte_pos = self.grapheme_indices().get(end).copied().unwrap_or(self.buffer.len());
-		self.buffer.replace_range(start_byte_pos..end_byte_pos, new);
+		self.splice(start_byte_pos..end_byte_pos, new);
+	}
+	/// Add `amount` to the first number on line `line_no` that overlaps `from..to`.
+	///
+	/// Returns the position of the last character of the new number, which is where vim leaves the cursor.
+	pub fn increment_number_in_line(&mut self, line_no: usize, from: usize, to: usize, amount: i64) -> Option<usize> {
+		let (line_start,line_end) = self.line_bounds(line_no)?;
+		let line = self.slice(line_start..line_end)?.to_string();
+		let indices = self.units.indices(&line);
+		let byte_pos = |pos: usize| indices.get(pos.saturating_sub(line_start)).copied().unwrap_or(line.len());
+		let (from,to) = (byte_pos(from), byte_pos(to));
+
+		let mat = INCREMENTABLE.find_iter(&line).find(|mat| mat.end() > from && mat.start() < to)?;
+		let new = add_to_number(mat.as_str(), amount)?;
+		let start = line_start + self.units.count(&line[..mat.start()]);
+		let end = start + self.units.count(mat.as_str());
+		self.replace_range(start, end, &new);
+		self.update_graphemes();
+		Some(start + self.units.count(&new).saturating_sub(1))
 	}
 	pub fn replace_at_cursor(&mut self, new: &str) {
 		self.replace_at(self.cursor.get(), new);
 	}
 	pub fn force_replace_at(&mut self, pos: usize, new: &str) {
 		let Some(gr) = self.grapheme_at(pos).map(|gr| gr.to_string()) else {
-			self.buffer.push_str(new);
+			let end = self.buffer.len();
+			self.splice(end..end, new);
 			return
 		};
 		let start = self.index_byte_pos(pos);
 		let end = start + gr.len();
-		self.buffer.replace_range(start..end, new);
+		self.splice(start..end, new);
 	}
 	pub fn replace_at(&mut self, pos: usize, new: &str) {
 		let Some(gr) = self.grapheme_at(pos).map(|gr| gr.to_string()) else {
-			self.buffer.push_str(new);
+			let end = self.buffer.len();
+			self.splice(end..end, new);
 			return
 		};
 		if &gr == "\n" {
 			// Do not replace the newline, push it forward instead
 			let byte_pos = self.index_byte_pos(pos);
-			self.buffer.insert_str(byte_pos, new);
+			self.splice(byte_pos..byte_pos, new);
 			return
 		}
 		let start = self.index_byte_pos(pos);
 		let end = start + gr.len();
-		self.buffer.replace_range(start..end, new);
+		self.splice(start..end, new);
 	}
 	pub fn eval_line_addr(&mut self, addr: LineAddr) -> Option<usize> {
+		self.eval_line_addr_from(addr, None)
+	}
+	/// Evaluate a line address, relative to `base` if given, or to the cursor otherwise.
+	///
+	/// `base` is the line that the address is counted from, for the second half of `x;y`, and for searches like `/foo//bar/`.
+	fn eval_line_addr_from(&mut self, addr: LineAddr, base: Option<usize>) -> Option<usize> {
 		match addr {
 			LineAddr::Number(num) => Some(num.saturating_sub(1)), // Line ranges are one indexed for input, zero indexed internally
 																														// Both zero and one refer to the first line
-			LineAddr::Current => Some(self.cursor_line_number()),
-			LineAddr::Last => Some(self.total_lines()),
+			LineAddr::Current => Some(base.unwrap_or_else(|| self.cursor_line_number())),
+			LineAddr::Last => Some(self.total_lines().saturating_sub(1)),
 			LineAddr::Offset(offset) => {
-				let current = self.cursor_line_number();
+				let current = base.unwrap_or_else(|| self.cursor_line_number());
 				Some(current.saturating_add_signed(offset))
 			}
-			LineAddr::PatternRev(ref pat) |
-			LineAddr::Pattern(ref pat) => {
-				if let Ok(regex) = Regex::new(pat) {
-					self.last_pattern_search = Some(regex.clone());
-					let haystack = self.buffer.as_str();
-					let matches = regex.find_iter(haystack).collect::<Vec<_>>();
-					// We will use this match if we don't find any in our desired direction, just like vim
-					let wrap_match: Option<&regex::Match> = match &addr {
-						LineAddr::Pattern(_) => matches.first(),
-						LineAddr::PatternRev(_) => matches.last(),
-						_ => unreachable!()
-					};
-					let cursor_byte_pos = self.read_cursor_byte_pos();
-					match addr {
-						LineAddr::Pattern(_) => {
-							for mat in &matches {
-								if mat.start() > cursor_byte_pos {
-									let match_line_no = self.byte_pos_line_number(mat.start());
-									return Some(match_line_no)
-								}
-							}
-						}
-						LineAddr::PatternRev(_) => {
-							let matches = matches.iter().rev();
-							for mat in matches {
-								if mat.start() < cursor_byte_pos {
-									let match_line_no = self.byte_pos_line_number(mat.start());
-									return Some(match_line_no)
-								}
-							}
-						}
-						_ => unreachable!()
+			LineAddr::Relative(addr, offset) => {
+				let line = self.eval_line_addr_from(*addr, base)?;
+				Some(line.saturating_add_signed(offset))
+			}
+			LineAddr::From(first, second) => {
+				let line = self.eval_line_addr_from(*first, base)?;
+				self.eval_line_addr_from(*second, Some(line))
+			}
+			LineAddr::Mark(mark) => {
+				match mark {
+					'<' | '>' => {
+						let sel = self.select_range.as_ref().or(self.last_selection.as_ref())?;
+						let (start,end) = match sel {
+							SelectRange::OneDim((start,end)) => (*start,*end),
+							SelectRange::TwoDim(lines) => (lines.first()?.0, lines.last()?.1)
+						};
+						// Linewise selections end just past the newline of their last line
+						let mode = if self.select_range.is_some() { self.select_mode } else { self.last_select_mode };
+						let linewise = matches!(mode, Some(SelectMode::Line(_)));
+						let pos = match mark {
+							'<' => start,
+							_ if linewise => end - 1,
+							_ => end
+						};
+						Some(self.index_line_number(pos))
 					}
-					let mat = wrap_match?;
-					let match_line_no = self.byte_pos_line_number(mat.start());
-					Some(match_line_no)
-				} else {
-					match addr {
-						LineAddr::Pattern(_) => {
-							let haystack = self.slice_from_cursor()?;
-							let pos = haystack.as_bytes().windows(pat.len()).position(|win| win == pat.as_bytes())?;
-							let line_no = self.byte_pos_line_number(pos);
-							Some(line_no)
-						}
-						LineAddr::PatternRev(_) => {
-							let haystack = self.slice_from_cursor()?;
-							let haystack_rev = haystack.bytes().rev().collect::<Vec<_>>();
-							let pat_rev = pat.bytes().rev().collect::<Vec<_>>();
-							let pos = haystack_rev.windows(pat.len()).position(|win| win == pat_rev)?;
-							let line_no = self.byte_pos_line_number(pos);
-							Some(line_no)
-						}
-						_ => unreachable!()
+					_ => {
+						let pos = *self.marks.get(&mark)?;
+						Some(self.index_line_number(pos))
 					}
 				}
 			}
+			LineAddr::LastSearch(dir) => {
+				let regex = self.last_pattern_search.clone()?;
+				self.search_line_addr(&regex, dir, base)
+			}
+			LineAddr::LastSubstitute => {
+				let regex = self.last_substitution.as_ref().map(|(regex,_,_)| regex.clone())?;
+				self.search_line_addr(&regex, Direction::Forward, base)
+			}
+			LineAddr::Pattern(ref pat) |
+			LineAddr::PatternRev(ref pat) => {
+				let dir = match addr {
+					LineAddr::Pattern(_) => Direction::Forward,
+					_ => Direction::Backward
+				};
+				let regex = match crate::new_regex(pat) {
+					Ok(regex) => {
+						self.last_pattern_search = Some(regex.clone());
+						regex
+					}
+					// Not a valid regex, so look for the pattern literally
+					Err(_) => crate::new_regex(&regex::escape(pat)).ok()?
+				};
+				self.search_line_addr(&regex, dir, base)
+			}
 		}
 	}
+	/// Find the line of the next match for `regex` in the given direction.
+	///
+	/// Searching starts at the cursor, or after the end of the `base` line (before its start, going backward).
+	/// If nothing is found that way, the search wraps around to the other end of the buffer, just like vim.
+	fn search_line_addr(&self, regex: &Regex, dir: Direction, base: Option<usize>) -> Option<usize> {
+		let haystack = self.buffer.as_str();
+		let from = match base {
+			None => self.read_cursor_byte_pos(),
+			Some(line) => {
+				let mut line_starts = std::iter::once(0)
+					.chain(haystack.match_indices('\n').map(|(i,_)| i + 1));
+				let line_start = line_starts.nth(line).unwrap_or(haystack.len());
+				match dir {
+					Direction::Forward => haystack[line_start..].find('\n').map(|i| line_start + i).unwrap_or(haystack.len()),
+					Direction::Backward => line_start
+				}
+			}
+		};
+		let matches = regex.find_iter(haystack).map(|mat| mat.start()).collect::<Vec<_>>();
+		let found = match dir {
+			Direction::Forward => matches.iter().find(|start| **start > from).or(matches.first()),
+			Direction::Backward => matches.iter().rev().find(|start| **start < from).or(matches.last())
+		}?;
+		Some(self.byte_pos_line_number(*found))
+	}
 	pub fn should_handle_block_insert(&self) -> bool {
 		self.inserting_from_visual &&
 		self.last_selection.as_ref().is_some_and(|sel| matches!(sel, SelectRange::TwoDim(_)))
@@ -2372,51 +3675,58 @@ impl LineBuf {
 		}
 
 	}
+	/// Find the lines in `addr` that match `pattern`, or that don't if `polarity` is false, for `:g`, `:v`, and `-g`.
+	///
+	/// The lines come back last to first. With a `limit`, the scan stops at that many matches, counted from the top.
+	pub fn global_lines(&mut self, addr: &Motion, pattern: &Val, polarity: bool, limit: Option<usize>) -> Vec<usize> {
+		let (start_line,end_line) = match *addr {
+			Motion::Line(ref n) => {
+				let line_no = self.eval_line_addr(n.clone()).unwrap();
+				(line_no,line_no)
+			}
+			Motion::LineRange(ref s,ref e) => {
+				let start_ln = self.eval_line_addr(s.clone()).unwrap();
+				let end_ln = self.eval_line_addr(e.clone()).unwrap();
+				ordered(start_ln,end_ln)
+			}
+			_ => (0,self.total_lines().saturating_sub(1))
+		};
+		let mut lines = vec![];
+		let line_range = start_line..=end_line;
+		let regex = match pattern {
+			Val::Regex(regex) => regex.clone(),
+			_ => match crate::new_regex(&pattern.to_string()) {
+				Ok(regex) => regex,
+				Err(e) => {
+					eprintln!("vicut: {e}");
+					std::process::exit(1);
+				}
+			}
+		};
+		for line_no in line_range {
+			if crate::deadline_passed() {
+				// The caller will notice this and throw out the record
+				break
+			}
+			let Some((start,end)) = self.line_bounds(line_no) else { continue };
+			let line = self.slice(start..end).unwrap_or_default();
+
+			if regex.is_match(line) != polarity {
+				continue
+			}
+			lines.push(line_no);
+			if limit.is_some_and(|limit| lines.len() >= limit) {
+				break
+			}
+		}
+		lines.into_iter().rev().collect()
+	}
 	pub fn eval_motion(&mut self, verb: Option<&Verb>, motion: MotionCmd) -> MotionKind {
 		match motion {
 			MotionCmd(_,Motion::NotGlobal(ref addr, ref pattern)) |
 			MotionCmd(_,Motion::Global(ref addr, ref pattern)) => {
-				let (start_line,end_line) = match **addr {
-					Motion::Line(ref n) => {
-						let line_no = self.eval_line_addr(n.clone()).unwrap();
-						(line_no,line_no)
-					}
-					Motion::LineRange(ref s,ref e) => {
-						let start_ln = self.eval_line_addr(s.clone()).unwrap();
-						let end_ln = self.eval_line_addr(e.clone()).unwrap();
-						(start_ln,end_ln)
-					}
-					_ => (0,self.total_lines())
-				};
-				let mut lines = vec![];
-				let line_range = start_line..end_line;
-				let regex = match pattern {
-					Val::Regex(regex) => regex.clone(),
-					_ => match Regex::new(&pattern.to_string()) {
-						Ok(regex) => regex,
-						Err(e) => {
-							eprintln!("vicut: {e}");
-							std::process::exit(1);
-						}
-					}
-				};
-				for line_no in line_range {
-					let Some((start,end)) = self.line_bounds(line_no) else { continue };
-					let line = self.slice(start..end).unwrap_or_default();
-
-					match motion.1 {
-						Motion::NotGlobal(_,_) => {
-							let None = regex.find(line).map(|mat| (mat.start(),mat.end())) else { continue };
-						}
-						Motion::Global(_,_) => {
-							let Some(_) = regex.find(line).map(|mat| (mat.start(),mat.end())) else { continue };
-						}
-						_ => unreachable!()
-					}
-					lines.push(line_no);
-				}
-				let reversed = lines.into_iter().rev().collect::<Vec<_>>();
-				MotionKind::Lines(reversed)
+				let polarity = matches!(motion.1, Motion::Global(..));
+				MotionKind::Lines(self.global_lines(addr, pattern, polarity, None))
 			}
 			MotionCmd(count,Motion::WholeLineExclusive) |
 			MotionCmd(count,Motion::WholeLine) => {
@@ -2543,8 +3853,22 @@ impl LineBuf {
 					TextObj::Paren(_) |
 					TextObj::Bracket(_) |
 					TextObj::Brace(_) |
-					TextObj::Angle(_) => MotionKind::Exclusive((start,end)),
-					_ => todo!()
+					TextObj::Angle(_) |
+					TextObj::Tag(_) |
+					TextObj::Arg(_) |
+					TextObj::Number(_) |
+					TextObj::Url(_) |
+					TextObj::Path(_) |
+					TextObj::Email(_) |
+					TextObj::KeyValue(_) |
+					TextObj::CodeFence(_) |
+					TextObj::Section(_) |
+					TextObj::ListItem(_) |
+					TextObj::StringLiteral(_) |
+					TextObj::SubChange(_) |
+					TextObj::Custom(..) |
+					TextObj::Fold(_) |
+					TextObj::Indent(_) => MotionKind::Exclusive((start,end)),
 				}
 			}
 			MotionCmd(_,Motion::ToDelimMatch) => {
@@ -2615,6 +3939,8 @@ impl LineBuf {
 				}
 			MotionCmd(_,Motion::BeginningOfScreenLine) |
 			MotionCmd(_,Motion::BeginningOfLine) => MotionKind::On(self.start_of_line()),
+			// There is no line wrapping, so screen lines are just lines
+			MotionCmd(count,Motion::EndOfScreenLine) |
 			MotionCmd(count,Motion::EndOfLine) => {
 				let pos = if count == 1 {
 					self.end_of_line()
@@ -2744,7 +4070,7 @@ impl LineBuf {
 			}
 			MotionCmd(_count, Motion::PatternSearchRev(ref pat)) |
 			MotionCmd(_count, Motion::PatternSearch(ref pat)) => {
-				match Regex::new(pat) {
+				match crate::new_regex(pat) {
 					Ok(regex) => {
 						self.last_pattern_search = Some(regex.clone());
 						let haystack = self.buffer.as_str();
@@ -2868,10 +4194,104 @@ impl LineBuf {
 				self.saved_col = Some(cursor_col);
 				MotionKind::LineOffset(lines_down as isize)
 			}
+			MotionCmd(count,Motion::WindowTop) |
+			MotionCmd(count,Motion::WindowMiddle) |
+			MotionCmd(count,Motion::WindowBottom) => {
+				self.scroll_to_cursor();
+				let (top,bottom) = self.window_lines();
+				let target_line = match motion.1 {
+					Motion::WindowTop => (top + count.saturating_sub(1)).min(bottom),
+					Motion::WindowMiddle => top + (bottom - top) / 2,
+					Motion::WindowBottom => bottom.saturating_sub(count.saturating_sub(1)).max(top),
+					_ => unreachable!()
+				};
+				// Like Vim, we land on the first non-blank character
+				self.saved_col = Some(self.first_non_blank_col(target_line));
+				MotionKind::LineOffset(target_line as isize - self.cursor_line_number() as isize)
+			}
+			MotionCmd(_count,Motion::ToMark(mark)) => {
+				let Some(&pos) = self.marks.get(&mark) else {
+					return MotionKind::Null
+				};
+				MotionKind::On(pos.min(self.cursor.max))
+			}
+			MotionCmd(count,Motion::ToSubChange(direction)) => {
+				let index = match direction {
+					Direction::Forward => count.saturating_sub(1),
+					Direction::Backward => self.sub_spans.len().checked_sub(count.max(1)).unwrap_or(usize::MAX)
+				};
+				let Some(&(start,_)) = self.sub_spans.get(index) else {
+					return MotionKind::Null
+				};
+				MotionKind::On(start.min(self.cursor.max))
+			}
+			MotionCmd(_count,Motion::ToMarkLine(mark)) => {
+				let Some(&pos) = self.marks.get(&mark) else {
+					return MotionKind::Null
+				};
+				let target_line = self.index_line_number(pos.min(self.cursor.max));
+				self.saved_col = Some(self.first_non_blank_col(target_line));
+				MotionKind::LineOffset(target_line as isize - self.cursor_line_number() as isize)
+			}
+			MotionCmd(count,Motion::ToNumber(direction)) => {
+				let cursor = self.cursor.get();
+				let starts = self.numbers().into_iter().map(|(start,_)| start);
+				let target = match direction {
+					Direction::Forward => starts.filter(|start| *start > cursor).nth(count.saturating_sub(1)),
+					Direction::Backward => starts.filter(|start| *start < cursor).rev().nth(count.saturating_sub(1)),
+				};
+				let Some(target) = target else {
+					return MotionKind::Null
+				};
+				MotionKind::On(target)
+			}
+			MotionCmd(count,Motion::ToFold(direction)) => {
+				let cursor_line = self.cursor_line_number();
+				let folds = find_folds(&self.buffer, self.fold_method);
+				let mut targets = match direction {
+					Direction::Forward => folds.iter().map(|(start,_)| *start).filter(|start| *start > cursor_line).collect::<Vec<_>>(),
+					Direction::Backward => folds.iter().map(|(_,end)| *end).filter(|end| *end < cursor_line).collect::<Vec<_>>(),
+				};
+				targets.sort();
+				targets.dedup();
+				let target_line = match direction {
+					Direction::Forward => targets.get(count.saturating_sub(1)).or(targets.last()),
+					Direction::Backward => targets.iter().rev().nth(count.saturating_sub(1)).or(targets.first()),
+				};
+				let Some(&target_line) = target_line else {
+					return MotionKind::Null
+				};
+				self.saved_col = Some(self.first_non_blank_col(target_line));
+				MotionKind::LineOffset(target_line as isize - cursor_line as isize)
+			}
+			MotionCmd(count,Motion::ScrollWindowDown) |
+			MotionCmd(count,Motion::ScrollWindowUp) => {
+				self.scroll_to_cursor();
+				let last_line = self.total_lines().saturating_sub(1);
+				self.window_top = match motion.1 {
+					Motion::ScrollWindowDown => (self.window_top + count).min(last_line),
+					Motion::ScrollWindowUp => self.window_top.saturating_sub(count),
+					_ => unreachable!()
+				};
+				// Drag the cursor along if it fell off of the window
+				let (top,bottom) = self.window_lines();
+				let cursor_line = self.cursor_line_number();
+				let target_line = cursor_line.clamp(top, bottom);
+				if target_line == cursor_line {
+					return MotionKind::Null
+				}
+				self.saved_col = Some(self.cursor_col());
+				MotionKind::LineOffset(target_line as isize - cursor_line as isize)
+			}
 			MotionCmd(count,Motion::ToColumn) => {
-				let start = ClampedUsize::new(self.start_of_line(), self.cursor.max, false);
-				let target_col = count.saturating_sub(1);
-				MotionKind::On(start.ret_add(target_col))
+				let (start,end) = self.this_line();
+				let line = self.slice(start..end).unwrap_or_default().to_string();
+				MotionKind::On(start + self.index_for_display_col(&line, count.saturating_sub(1)))
+			}
+			MotionCmd(_,Motion::HalfOfScreen) => {
+				let (start,end) = self.this_line();
+				let line = self.slice(start..end).unwrap_or_default().to_string();
+				MotionKind::On(start + self.index_for_display_col(&line, SCREEN_COLUMNS / 2))
 			}
 			MotionCmd(count,Motion::RangeInclusive(ref range)) |
 			MotionCmd(count,Motion::Range(ref range)) => {
@@ -2914,6 +4334,8 @@ impl LineBuf {
 				let Some(end_line_no) = self.eval_line_addr(end_addr) else {
 					return MotionKind::Null
 				};
+				// Vim asks before swapping a backwards range, but there is nobody to ask here
+				let (start_line_no, end_line_no) = ordered(start_line_no, end_line_no);
 				MotionKind::LineRange(start_line_no, end_line_no)
 			}
 			MotionCmd(_,Motion::RepeatMotion) | // These two were already handled in exec.rs
@@ -3063,14 +4485,14 @@ impl LineBuf {
 				if self.select_range().is_none() {
 					self.cursor.set(start);
 				} else {
+					// The selection includes its last character, so it ends one before the motion does
+					let end = end.saturating_sub(1).max(start);
 					if start < self.cursor.get() {
-						let start = start + 1;
 						self.cursor.set(start);
 						if let Some(mode) = self.select_mode.as_mut() {
 							mode.set_anchor(SelectAnchor::End);
 						}
 					} else {
-						let end = end.saturating_sub(1);
 						self.cursor.set(end);
 						if let Some(mode) = self.select_mode.as_mut() {
 							mode.set_anchor(SelectAnchor::Start);
@@ -3233,8 +4655,58 @@ impl LineBuf {
 			}
 		}
 	}
+	/// Replace the first match of `regex` on a line with `new`, or every match with the 'g' flag, for ':s' and ':&'.
+	///
+	/// The replacement is expanded separately for each match, so that it can refer to the match's capture groups.
+	fn substitute_line(&mut self, line_no: usize, regex: &Regex, new: &str, flags: SubFlags) {
+		// '$' addresses one past the last line, and line_bounds() would hand the last line out a second time
+		if line_no >= self.total_lines() {
+			return
+		}
+		let Some((start,end)) = self.line_bounds(line_no) else { return };
+		let units = self.units;
+		let syntax = self.sub_syntax;
+		let line = self.slice(start..end).unwrap_or_default();
+		let line_matches = regex.captures_iter(line)
+			.take(if flags.contains(SubFlags::GLOBAL) { usize::MAX } else { 1 })
+			.map(|caps| {
+				let mat = caps.get(0).unwrap();
+				let replacement = match syntax {
+					SubSyntax::Vim => expand_replacement(&caps, new),
+					SubSyntax::Regex => {
+						let mut expanded = String::new();
+						caps.expand(new, &mut expanded);
+						expanded
+					}
+				};
+				(units.count(&line[..mat.start()]), units.count(&line[..mat.end()]), replacement)
+			})
+			.collect::<Vec<_>>();
+		if line_matches.is_empty() {
+			return
+		}
+		for (mat_start,mat_end,replacement) in line_matches.into_iter().rev() {
+			self.replace_sub_match(start + mat_start, start + mat_end, &replacement);
+		}
+		// replace_range() leaves the indices alone, and the next line up is found with them
+		self.update_graphemes();
+	}
+	/// Replace `start..end` with `new` for `:s`, and remember where the replacement went in `sub_spans`.
+	///
+	/// Substitutions work backwards through the buffer, so every span recorded so far comes after this one, and moves along with the text.
+	fn replace_sub_match(&mut self, start: usize, end: usize, new: &str) {
+		self.replace_range(start, end, new);
+		let new_len = self.units.count(new);
+		for span in self.sub_spans.iter_mut() {
+			*span = (span.0 - end + start + new_len, span.1 - end + start + new_len);
+		}
+		self.sub_spans.push((start, start + new_len));
+	}
 	#[allow(clippy::unnecessary_to_owned)]
 	pub fn exec_verb(&mut self, verb: Verb, motion: MotionKind, register: RegisterName) -> Result<(),String> {
+		if self.shell.disabled && verb.spawns_shell() {
+			return Err("shell commands are disabled by '--no-shell'".into())
+		}
 		match verb {
 			Verb::Delete |
 			Verb::Yank |
@@ -3274,7 +4746,7 @@ impl LineBuf {
 				let slice = self.slice(start..end)
 					.unwrap_or_default();
 				let rot13 = rot13(slice);
-				self.buffer.replace_range(start..end, &rot13);
+				self.splice(start..end, &rot13);
 				self.cursor.set(start);
 			}
 			Verb::ReplaceChar(ch) => {
@@ -3395,6 +4867,18 @@ impl LineBuf {
 					self.replace_at(i,new);
 				}
 			}
+			Verb::CoerceCase(case) => {
+				let Some((start,end)) = self.range_from_motion(&motion) else {
+					return Ok(())
+				};
+				let Some(slice) = self.slice(start..end) else {
+					return Ok(())
+				};
+				let coerced = coerce_case(slice, case);
+				self.replace_range(start, end, &coerced);
+				self.update_graphemes();
+				self.cursor.set(start);
+			}
 			Verb::Redo |
 				Verb::Undo => {
 					let (edit_provider,edit_receiver) = match verb {
@@ -3407,6 +4891,9 @@ impl LineBuf {
 					let Some(edit) = edit_provider.pop() else { return Ok(()) };
 					let Edit { pos, cursor_pos, old, old_diff, new, new_diff, merging: _, .. } = edit;
 
+					// Not through splice(), since the undo stacks are still borrowed
+					adjust_line_marks(&mut self.line_marks, &self.buffer, &(pos..pos + new.len()), &old);
+					adjust_scroll_anchor(&mut self.scroll_anchor, &self.buffer, &(pos..pos + new.len()), &old);
 					self.buffer.replace_range(pos..pos + new.len(), &old);
 					let new_cursor_pos = self.cursor.get();
 					let in_insert_mode = !self.cursor.exclusive;
@@ -3484,44 +4971,53 @@ impl LineBuf {
 					}
 				}
 			}
-			Verb::SwapVisualAnchor => {
-				if let Some(range) = self.select_range.clone() {
-					let Some(mode) = self.select_mode.as_mut() else { return Ok(()) };
-					match mode {
-						SelectMode::Char(anchor) => {
-							let SelectRange::OneDim((start,end)) = range else { unreachable!() };
-							let new_cursor_pos = match anchor {
-								SelectAnchor::End => start,
-								SelectAnchor::Start => end,
-							};
-							mode.invert_anchor();
-							self.cursor.set(new_cursor_pos);
-						}
-						SelectMode::Line(anchor) => {
-							let SelectRange::OneDim((start,end)) = range else { unreachable!() };
-							let anchor_pos = match anchor {
-								SelectAnchor::End => end,
-								SelectAnchor::Start => start,
-							};
-							mode.invert_anchor();
-							let cursor_col = self.cursor_col();
-							let new_cursor_pos = {
-								let line_no = self.index_line_number(anchor_pos);
-								let (start,end) = self.line_bounds(line_no).unwrap_or((0,self.cursor.max));
-								let line_len = end.saturating_sub(start);
-								(start + cursor_col).min(line_len)
+			Verb::SwapVisualAnchor |
+			Verb::SwapVisualCorner => {
+				let (Some(range), Some(mut mode)) = (self.select_range.clone(), self.select_mode) else {
+					return Ok(())
+				};
+				match (&mut mode, range) {
+					(SelectMode::Char(anchor), SelectRange::OneDim((start,end))) => {
+						// The cursor goes over to the anchored side
+						let new_cursor_pos = match anchor {
+							SelectAnchor::End => end,
+							SelectAnchor::Start => start,
+						};
+						mode.invert_anchor();
+						self.cursor.set(new_cursor_pos);
+					}
+					(SelectMode::Line(anchor), SelectRange::OneDim((start,end))) => {
+						// Line selections end just past the last newline
+						let anchor_pos = match anchor {
+							SelectAnchor::End => end.saturating_sub(1).max(start),
+							SelectAnchor::Start => start,
+						};
+						mode.invert_anchor();
+						let cursor_col = self.cursor_col();
+						let line_no = self.index_line_number(anchor_pos);
+						self.cursor.set(self.line_col_pos(line_no, cursor_col));
+					}
+					(SelectMode::Block { anchor, anchor_pos }, _) => {
+						let cursor_pos = self.cursor.get();
+						if verb == Verb::SwapVisualCorner {
+							// Trade columns with the anchor, but stay on the same lines
+							let (cursor_line, anchor_line) = (self.index_line_number(cursor_pos), self.index_line_number(*anchor_pos));
+							let (cursor_col, anchor_col) = (self.index_col(cursor_pos), self.index_col(*anchor_pos));
+							self.cursor.set(self.line_col_pos(cursor_line, anchor_col));
+							*anchor_pos = self.line_col_pos(anchor_line, cursor_col);
+						} else {
+							self.cursor.set(*anchor_pos);
+							*anchor_pos = cursor_pos;
+							*anchor = match anchor {
+								SelectAnchor::End => SelectAnchor::Start,
+								SelectAnchor::Start => SelectAnchor::End
 							};
-							self.cursor.set(new_cursor_pos);
-						}
-						SelectMode::Block { anchor: _, anchor_pos } => {
-							let mut cursor_pos = self.cursor.get();
-							std::mem::swap(&mut cursor_pos, anchor_pos);
-							self.cursor.set(cursor_pos);
-
-							mode.invert_anchor();
 						}
 					}
+					_ => return Ok(())
 				}
+				self.select_mode = Some(mode);
+				self.update_select_range();
 			}
 			Verb::JoinLines => {
 				let start = self.start_of_line();
@@ -3552,7 +5048,16 @@ impl LineBuf {
 			}
 			Verb::Insert(string) => {
 				self.push_str(&string);
-				let graphemes = string.graphemes(true).count();
+				let graphemes = self.units.count(&string);
+				self.cursor.add(graphemes);
+			}
+			Verb::InsertRegister => {
+				let Some(content) = register.read_from_register() else {
+					return Ok(())
+				};
+				let content = content.to_string();
+				self.insert_str_at(self.cursor.get(), &content);
+				let graphemes = self.units.count(&content);
 				self.cursor.add(graphemes);
 			}
 			Verb::Indent => {
@@ -3634,9 +5139,9 @@ impl LineBuf {
 				}
 			}
 			Verb::Equalize => {
-				let Ok(program) = env::var("EQUALPRG") else {
-					eprintln!("vicut: '$EQUALPRG' is not set, ignoring '=' call");
-					eprintln!("vicut: The '=' operator requires a path to a formatter program in the '$EQUALPRG' environment variable");
+				let Some(program) = self.shell.equalprg.clone() else {
+					eprintln!("vicut: no formatter program is set, ignoring '=' call");
+					eprintln!("vicut: The '=' operator requires a formatter program, given with '--equalprg' or the '$EQUALPRG' environment variable");
 					return Ok(());
 				};
 				let Some((start, end)) = self.range_from_motion(&motion) else {
@@ -3654,36 +5159,94 @@ impl LineBuf {
 					return Ok(());
 				};
 
-				let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
-				let bytes = Command::new(shell)
-					.arg("-c")
-					.arg(program)
-					.stdin(Stdio::piped())
-					.stdout(Stdio::piped())
-					.spawn()
-					.and_then(|mut child| {
-						child.stdin.as_mut().unwrap().write_all(slice.as_bytes())?;
-						child.wait_with_output()
-					})
-				.map_err(|e| format!("Failed to run command: {e}"))?;
-				let output = String::from_utf8_lossy(&bytes.stdout).to_string();
+				let slice = slice.to_string();
+				let output = self.shell.run(&program, Some(&slice))?;
 
 				self.replace_range(start, end, &output);
 			}
-			Verb::ShellCmd(cmd) => {
-				let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
-				let child = Command::new(shell)
-					.arg("-c")
-					.arg(cmd)
-					.output()
-					.map_err(|e| format!("Failed to spawn child process: {e}"))?;
-
-				if child.status.success() {
-					return Ok(());
-				} else {
-					return Err(format!("Shell command exited with status {}", child.status.code().unwrap_or(-1)));
+			Verb::SetMark(mark) => {
+				self.marks.insert(mark, self.cursor.get());
+			}
+			Verb::Increment(amount) |
+			Verb::IncrementProgressive(amount) => {
+				let progressive = matches!(verb, Verb::IncrementProgressive(_));
+				// Each entry is a line and the columns to look for a number in.
+				// Columns are relative to the start of the line, since editing one line moves the ones after it.
+				let spans: Vec<(usize,usize,usize)> = match motion {
+					MotionKind::Null => {
+						let line_no = self.cursor_line_number();
+						let cursor = self.cursor.get();
+						let Some((_,end)) = self.line_bounds(line_no) else {
+							return Ok(())
+						};
+						if let Some(new_cursor) = self.increment_number_in_line(line_no, cursor, end, amount) {
+							self.cursor.set(new_cursor);
+						}
+						return Ok(())
+					}
+					MotionKind::BlockRange(windows) => {
+						windows.into_iter()
+							.filter_map(|(start,end)| {
+								let line_no = self.index_line_number(start);
+								let (line_start,_) = self.line_bounds(line_no)?;
+								Some((line_no, start - line_start, end - line_start))
+							}).collect()
+					}
+					_ => {
+						let Some((start,end)) = self.range_from_motion(&motion) else {
+							return Ok(())
+						};
+						let start_ln = self.index_line_number(start);
+						let end_ln = self.index_line_number(end.saturating_sub(1).max(start));
+						(start_ln..=end_ln).filter_map(|line_no| {
+							let (line_start,line_end) = self.line_bounds(line_no)?;
+							Some((line_no, start.max(line_start) - line_start, end.min(line_end) - line_start))
+						}).collect()
+					}
+				};
+				let cursor = spans.first().and_then(|(line_no,from,_)| Some(self.line_bounds(*line_no)?.0 + from));
+				let mut step = 1;
+				for (line_no,from,to) in spans {
+					let Some((line_start,_)) = self.line_bounds(line_no) else {
+						continue
+					};
+					let amount = if progressive { amount.saturating_mul(step) } else { amount };
+					if self.increment_number_in_line(line_no, line_start + from, line_start + to, amount).is_some() {
+						step += 1;
+					}
+				}
+				if let Some(cursor) = cursor {
+					self.cursor.set(cursor);
 				}
 			}
+			Verb::Format => {
+				let Some((start, end)) = self.range_from_motion(&motion) else {
+					return Ok(());
+				};
+				let start_ln = self.index_line_number(start);
+				// The range is exclusive, so a range that ends on a line break stops on the line before it
+				let end_ln = self.index_line_number(end.saturating_sub(1).max(start));
+				let Some((start, _)) = self.line_bounds(start_ln) else {
+					return Ok(());
+				};
+				let Some((_, end)) = self.line_bounds(end_ln) else {
+					return Ok(());
+				};
+				let width = self.textwidth();
+				let Some(slice) = self.slice(start..end) else {
+					return Ok(());
+				};
+
+				let output = reflow(slice, width);
+				self.replace_range(start, end, &output);
+				self.update_graphemes();
+				// Like vim, leave the cursor at the start of the last line that was formatted
+				let last_line = output.trim_end_matches('\n').rfind('\n').map_or(0, |pos| pos + 1);
+				self.cursor.set(start + self.units.count(&output[..last_line]));
+			}
+			Verb::ShellCmd(cmd) => {
+				self.shell.run(&cmd, None)?;
+			}
 			Verb::Read(src) => {
 				let insert_line = match motion {
 					MotionKind::Line(n) => n,
@@ -3694,21 +5257,7 @@ impl LineBuf {
 				let needs_newline = self.grapheme_at(insert_pos) != Some("\n");
 
 				let data = match src {
-					ReadSrc::Cmd(sh_cmd) => {
-						let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
-						let child = Command::new(shell)
-							.arg("-c")
-							.arg(sh_cmd)
-							.output()
-							.map_err(|e| format!("Failed to spawn child process for write: {e}"))?;
-
-						if child.status.success() {
-							String::from_utf8(child.stdout)
-								.map_err(|e| format!("Command output was not valid UTF-8: {e}"))?
-						} else {
-							return Err(format!("Shell command exited with status {}", child.status.code().unwrap_or(-1)));
-						}
-					}
+					ReadSrc::Cmd(sh_cmd) => self.shell.run(&sh_cmd, None)?,
 					ReadSrc::File(path) => {
 						std::fs::read_to_string(path)
 							.map_err(|e| format!("Failed to write to file: {e}"))?
@@ -3751,21 +5300,9 @@ impl LineBuf {
 							.map_err(|e| format!("Failed to write to file: {e}"))?;
 					}
 					WriteDest::Cmd(sh_cmd) => {
-						let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
-						let mut child = Command::new(shell)
-							.arg("-c")
-							.arg(sh_cmd)
-							.stdin(Stdio::piped())
-							.spawn()
-							.map_err(|e| format!("Failed to spawn child process for write: {e}"))?;
-
-						child.stdin.as_mut().unwrap().write_all(write_span.as_bytes())
-							.map_err(|e| format!("Failed to pipe input to child process: {e}"))?;
-						let status = child.wait()
-							.map_err(|e| format!("Failed to wait for child process: {e}"))?;
-						if !status.success() {
-							eprintln!("Command exited with non-zero status");
-						}
+						let write_span = write_span.to_string();
+						let output = self.shell.run(&sh_cmd, Some(&write_span))?;
+						crate::print_stdout(&output);
 					}
 				}
 			}
@@ -3784,34 +5321,11 @@ impl LineBuf {
 				// Because of mutable borrowing stuff
 				if let Some(sub) = self.last_substitution.take() {
 					let (ref regex,ref new,flags) = sub;
-					let lines = (start_line..=end_line).rev();
-					for line_no in lines {
-						let Some((start,end)) = self.line_bounds(line_no) else { continue };
-						let line = self.slice(start..end).unwrap();
-						let global = flags.contains(SubFlags::GLOBAL);
-						if global {
-							let line_matches = regex
-								.find_iter(line)
-								.map(|mat| (mat.start(),mat.end()))
-								.collect::<Vec<_>>()
-								.into_iter()
-								.rev();
-							for (mat_start,mat_end) in line_matches {
-								let mat_start = self.find_index_for_byte_pos(mat_start).unwrap();
-								let mat_end = self.find_index_for_byte_pos(mat_end).unwrap();
-								let real_start = start + mat_start;
-								let real_end = start + mat_end;
-								self.replace_range(real_start,real_end, new);
-							}
-						} else {
-							let Some((mat_start,mat_end)) = regex.find(line).map(|mat| (mat.start(),mat.end())) else { continue };
-							let mat_start = self.find_index_for_byte_pos(mat_start).unwrap();
-							let mat_end = self.find_index_for_byte_pos(mat_end).unwrap();
-							let real_start = start + mat_start;
-							let real_end = start + mat_end;
-							self.replace_range(real_start,real_end, new);
-						}
+					self.sub_spans.clear();
+					for line_no in (start_line..=end_line).rev() {
+						self.substitute_line(line_no, regex, new, flags);
 					}
+					self.sub_spans.reverse();
 					// Now we put it back
 					self.last_substitution = Some(sub);
 				}
@@ -3822,37 +5336,24 @@ impl LineBuf {
 					MotionKind::LineRange(s,e) => (s,e),
 					_ => (0,self.total_lines()),
 				};
-				match Regex::new(&old) {
+				let new = match self.sub_syntax {
+					SubSyntax::Vim => {
+						let previous = self.last_substitution.as_ref().map(|(_,previous,_)| previous.as_str());
+						expand_tilde(&new, previous)
+					}
+					SubSyntax::Regex => new
+				};
+				match crate::new_regex(&old) {
 					Ok(regex) => {
 						// We go in reverse here
-						let lines = (start_line..=end_line).rev();
-						for line_no in lines {
-							let Some((start,end)) = self.line_bounds(line_no) else { continue };
-							let line = self.slice(start..end).unwrap_or_default();
-							let global = flags.contains(SubFlags::GLOBAL);
-							if global {
-								let line_matches = regex
-									.find_iter(line)
-									.map(|mat| (mat.start(),mat.end()))
-									.collect::<Vec<_>>()
-									.into_iter()
-									.rev();
-								for (mat_start,mat_end) in line_matches {
-									let mat_start = self.find_index_for_byte_pos(mat_start).unwrap();
-									let mat_end = self.find_index_for_byte_pos(mat_end).unwrap();
-									let real_start = start + mat_start;
-									let real_end = start + mat_end;
-									self.replace_range(real_start,real_end, &new);
-								}
-							} else {
-								let Some((mat_start,mat_end)) = regex.find(line).map(|mat| (mat.start(),mat.end())) else { continue };
-								let mat_start = self.find_index_for_byte_pos(mat_start).unwrap();
-								let mat_end = self.find_index_for_byte_pos(mat_end).unwrap();
-								let real_start = start + mat_start;
-								let real_end = start + mat_end;
-								self.replace_range(real_start,real_end, &new);
+						self.sub_spans.clear();
+						for line_no in (start_line..=end_line).rev() {
+							if crate::deadline_passed() {
+								break
 							}
+							self.substitute_line(line_no, &regex, &new, flags);
 						}
+						self.sub_spans.reverse();
 						self.last_substitution = Some((regex,new,flags));
 					}
 					Err(e) => {
@@ -3861,9 +5362,58 @@ impl LineBuf {
 					}
 				}
 			}
+			Verb::SmartSubstitute(old, new, flags) => {
+				let (start_line,end_line) = match motion {
+					MotionKind::Line(n) => (n,n),
+					MotionKind::LineRange(s,e) => (s,e),
+					_ => (0,self.total_lines()),
+				};
+				let compiled = subvert_variants(&old, &new).and_then(|variants| {
+					let alternation = variants.iter()
+						.map(|(from,_)| regex::escape(from))
+						.collect::<Vec<_>>()
+						.join("|");
+					let pattern = if flags.contains(SubFlags::WHOLE_WORD) {
+						format!(r"\b(?:{alternation})\b")
+					} else {
+						alternation
+					};
+					let regex = crate::new_regex(&pattern).map_err(|e| e.to_string())?;
+					Ok((variants,regex))
+				});
+				// Same as a bad pattern for ':s'
+				let (variants,regex) = match compiled {
+					Ok(compiled) => compiled,
+					Err(e) => {
+						eprintln!("vicut: {e}");
+						std::process::exit(1);
+					}
+				};
+				// We go in reverse here, same as ':s'
+				self.sub_spans.clear();
+				for line_no in (start_line..=end_line).rev() {
+					if crate::deadline_passed() {
+						break
+					}
+					let Some((start,end)) = self.line_bounds(line_no) else { continue };
+					let units = self.units;
+					let line = self.slice(start..end).unwrap_or_default();
+					let line_matches = regex.find_iter(line)
+						.take(if flags.contains(SubFlags::GLOBAL) { usize::MAX } else { 1 })
+						.map(|mat| (units.count(&line[..mat.start()]), units.count(&line[..mat.end()]), mat.as_str().to_string()))
+						.collect::<Vec<_>>();
+					for (mat_start,mat_end,matched) in line_matches.into_iter().rev() {
+						let Some((_,to)) = variants.iter().find(|(from,_)| *from == matched) else { continue };
+						self.replace_sub_match(start + mat_start, start + mat_end, to);
+					}
+				}
+				self.sub_spans.reverse();
+			}
 			Verb::ExMode |
 			Verb::Complete |
 			Verb::Normal(_) |
+			Verb::RecordMacro(_) |
+			Verb::PlayMacro(_) |
 			Verb::EndOfFile |
 			Verb::InsertMode |
 			Verb::NormalMode |
@@ -3904,31 +5454,18 @@ impl LineBuf {
 
 		/*
 		 * Let's evaluate the motion now
-		 * If we got some weird command like 'dvw' we will have to simulate a visual selection to get the range
+		 * If we got some weird command like 'dvw', the motion's wise-ness is forced after it is evaluated
 		 * If motion is None, we will try to use self.select_range
 		 * If self.select_range is None, we will use MotionKind::Null
 		 */
-		let motion_eval = if flags.intersects(CmdFlags::VISUAL | CmdFlags::VISUAL_LINE | CmdFlags::VISUAL_BLOCK) {
-			let motion = motion
-				.clone()
-				.map(|m| self.eval_motion(verb_ref.as_ref(), m))
-				.unwrap_or(MotionKind::Null);
-			let flag_intersection = flags.intersection(CmdFlags::VISUAL | CmdFlags::VISUAL_LINE | CmdFlags::VISUAL_BLOCK);
-			let mode = match flag_intersection {
-				CmdFlags::VISUAL => SelectMode::Char(SelectAnchor::Start),
-				CmdFlags::VISUAL_LINE => SelectMode::Line(SelectAnchor::Start),
-				CmdFlags::VISUAL_BLOCK => self.get_block_select(),
-				_ => unreachable!()
-			};
-			// Start a selection
-			self.start_selecting(mode);
-			// Apply the cursor motion
-			self.apply_motion(motion);
-
-			// Use the selection range created by the motion
-			self.select_range
+		let forced = flags.intersection(CmdFlags::VISUAL | CmdFlags::VISUAL_LINE | CmdFlags::VISUAL_BLOCK);
+		let motion_eval = if !forced.is_empty() {
+			motion
 				.clone()
-				.map(MotionKind::from_select_range)
+				.map(|m| {
+					let kind = self.eval_motion(verb_ref.as_ref(), m.clone());
+					self.force_motion(m, kind, forced)
+				})
 				.unwrap_or(MotionKind::Null)
 		} else {
 			motion
@@ -3956,6 +5493,13 @@ impl LineBuf {
 		}
 
 		if before != after {
+			self.adjust_marks(&before, &after);
+			let is_substitution = verb_ref.as_ref().is_some_and(|verb| {
+				matches!(verb, Verb::Substitute(..) | Verb::RepeatSubstitute | Verb::SmartSubstitute(..))
+			});
+			if !is_substitution {
+				self.sub_spans.clear();
+			}
 			if !is_undo_op {
 				self.handle_edit(before, after, cursor_pos);
 			}
@@ -3972,6 +5516,8 @@ impl LineBuf {
 			self.saved_col = None;
 		}
 
+		self.scroll_to_cursor();
+
 		if is_char_insert {
 			if let Some(edit) = self.undo_stack.last_mut() {
 				edit.start_merge();
@@ -4007,6 +5553,319 @@ pub fn rot13(input: &str) -> String {
 		}).collect()
 }
 
+/// Split an identifier into its words, at underscores, dashes, and changes in case.
+///
+/// A run of capitals is one word, so `HTTPServer` splits into `HTTP` and `Server`.
+fn identifier_words(ident: &str) -> Vec<String> {
+	let mut words: Vec<String> = vec![];
+	let mut current = String::new();
+	let chars = ident.chars().collect::<Vec<_>>();
+	for (i, &ch) in chars.iter().enumerate() {
+		if ch == '_' || ch == '-' {
+			if !current.is_empty() {
+				words.push(std::mem::take(&mut current));
+			}
+			continue
+		}
+		if let Some(&prev) = current.chars().last().as_ref()
+			&& ch.is_uppercase() {
+				let next_is_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+				if !prev.is_uppercase() || next_is_lower {
+					words.push(std::mem::take(&mut current));
+				}
+		}
+		current.push(ch);
+	}
+	if !current.is_empty() {
+		words.push(current);
+	}
+	words
+}
+
+/// Convert every identifier in `text` to the given case. Leading underscores and everything between identifiers are left alone.
+pub fn coerce_case(text: &str, case: Case) -> String {
+	let capitalize = |word: &str| {
+		let mut chars = word.chars();
+		chars.next()
+			.map(|first| first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect::<String>())
+			.unwrap_or_default()
+	};
+	IDENTIFIER.replace_all(text, |caps: &regex::Captures| {
+		let words = identifier_words(&caps[0]);
+		match case {
+			Case::Camel => words.iter()
+				.enumerate()
+				.map(|(i,word)| if i == 0 { word.to_lowercase() } else { capitalize(word) })
+				.collect::<String>(),
+			Case::Pascal => words.iter().map(|word| capitalize(word)).collect::<String>(),
+			Case::Snake => words.iter().map(|word| word.to_lowercase()).collect::<Vec<_>>().join("_"),
+			Case::Upper => words.iter().map(|word| word.to_uppercase()).collect::<Vec<_>>().join("_"),
+			Case::Kebab => words.iter().map(|word| word.to_lowercase()).collect::<Vec<_>>().join("-"),
+			Case::Title => words.iter().map(|word| capitalize(word)).collect::<Vec<_>>().join(" "),
+		}
+	}).into_owned()
+}
+
+/// Count how often each token appears in `text`, where a token is any match of `tokenizer`.
+///
+/// The most frequent tokens come first. Ties keep the order in which the tokens first appeared.
+pub fn token_frequencies(text: &str, tokenizer: &Regex) -> Vec<(String,usize)> {
+	let mut counts: Vec<(String,usize)> = vec![];
+	let mut index: HashMap<&str,usize> = HashMap::new();
+	for token in tokenizer.find_iter(text).map(|mat| mat.as_str()).filter(|token| !token.is_empty()) {
+		match index.get(token) {
+			Some(&i) => counts[i].1 += 1,
+			None => {
+				index.insert(token, counts.len());
+				counts.push((token.to_string(), 1));
+			}
+		}
+	}
+	counts.sort_by_key(|(_,count)| std::cmp::Reverse(*count));
+	counts
+}
+
+/// Expand the brace groups in a `:S` pattern, so `facilit{y,ies}` becomes `facility` and `facilities`.
+///
+/// With more than one group, every combination is produced, with the last group changing fastest.
+pub fn expand_braces(pattern: &str) -> Result<Vec<String>,String> {
+	let Some(open) = pattern.find('{') else {
+		return Ok(vec![pattern.to_string()])
+	};
+	let close = pattern[open..].find('}')
+		.map(|pos| open + pos)
+		.ok_or_else(|| format!("Unclosed brace in '{pattern}'"))?;
+	let (prefix, options, suffix) = (&pattern[..open], &pattern[open + 1..close], &pattern[close + 1..]);
+	let rest = expand_braces(suffix)?;
+	Ok(options.split(',')
+		.flat_map(|option| rest.iter().map(move |tail| format!("{prefix}{option}{tail}")))
+		.collect())
+}
+
+/// A number that `<C-a>` and `<C-x>` can change. Hex is tried first so that the `0` in `0x` is not taken as a decimal.
+static INCREMENTABLE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"0[xX][0-9a-fA-F]+|-?[0-9]+").unwrap());
+
+/// Add `amount` to a number matched by `INCREMENTABLE`, for `<C-a>` and `<C-x>`.
+///
+/// Hex numbers keep their prefix, their width, and the case of their letters, and wrap around like vim's do.
+/// Decimal numbers written with leading zeros keep their width.
+/// Returns `None` if the result does not fit in an `i64`.
+pub fn add_to_number(num: &str, amount: i64) -> Option<String> {
+	if let Some(digits) = num.strip_prefix("0x").or_else(|| num.strip_prefix("0X")) {
+		let prefix = &num[..2];
+		let value = u64::from_str_radix(digits, 16).ok()?.wrapping_add_signed(amount);
+		let width = digits.len();
+		let upper = digits.chars().any(|ch| ch.is_ascii_uppercase());
+		return Some(if upper {
+			format!("{prefix}{value:0width$X}")
+		} else {
+			format!("{prefix}{value:0width$x}")
+		})
+	}
+
+	let value = num.parse::<i64>().ok()?.checked_add(amount)?;
+	let digits = num.trim_start_matches('-');
+	if digits.len() > 1 && digits.starts_with('0') {
+		let width = digits.len();
+		let sign = if value < 0 { "-" } else { "" };
+		Some(format!("{sign}{:0width$}", value.unsigned_abs()))
+	} else {
+		Some(value.to_string())
+	}
+}
+
+/// The indentation and comment or quote leader that `gq` repeats at the start of each line it wraps
+static REFLOW_LEADER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[ \t]*(?:(?://+|#+|>+)[ \t]*)?").unwrap());
+
+/// Rewrap paragraphs of text so that no line is wider than `width` columns, for `gq`.
+///
+/// Blank lines separate paragraphs and are kept. A change of leader also starts a new paragraph,
+/// so a `//` comment is never joined with the code under it. Every wrapped line starts with the leader
+/// of its paragraph's first line, and a word wider than `width` gets a line to itself.
+pub fn reflow(text: &str, width: usize) -> String {
+	let (body, trailing_newline) = match text.strip_suffix('\n') {
+		Some(body) => (body, "\n"),
+		None => (text, "")
+	};
+	let mut lines = vec![];
+	let mut leader = "";
+	let mut words: Vec<&str> = vec![];
+	for line in body.split('\n') {
+		let line_leader = REFLOW_LEADER.find(line).map_or("", |mat| mat.as_str());
+		let content = line[line_leader.len()..].trim();
+		if content.is_empty() || line_leader.trim_end() != leader.trim_end() {
+			wrap_words(leader, &words, width, &mut lines);
+			words.clear();
+		}
+		if content.is_empty() {
+			lines.push(line.trim_end().to_string());
+			continue
+		}
+		if words.is_empty() {
+			leader = line_leader;
+		}
+		words.extend(content.split_whitespace());
+	}
+	wrap_words(leader, &words, width, &mut lines);
+	lines.join("\n") + trailing_newline
+}
+
+/// Fill lines starting with `leader` with as many of `words` as fit in `width` columns
+fn wrap_words(leader: &str, words: &[&str], width: usize, lines: &mut Vec<String>) {
+	let mut line = leader.to_string();
+	let mut line_is_empty = true;
+	for word in words {
+		if !line_is_empty && line.width() + 1 + word.width() > width {
+			lines.push(std::mem::replace(&mut line, leader.to_string()));
+			line_is_empty = true;
+		}
+		if !line_is_empty {
+			line.push(' ');
+		}
+		line.push_str(word);
+		line_is_empty = false;
+	}
+	if !line_is_empty {
+		lines.push(line);
+	}
+}
+
+/// Replace each `~` in the replacement text of `:s` with the replacement text of the previous `:s`, like vim does.
+///
+/// `\~` is left for `expand_replacement()` to turn into a literal `~`, and the rest of the text is passed through untouched.
+pub fn expand_tilde(new: &str, previous: Option<&str>) -> String {
+	let mut expanded = String::new();
+	let mut chars = new.chars();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' => {
+				expanded.push(ch);
+				if let Some(escaped) = chars.next() {
+					expanded.push(escaped);
+				}
+			}
+			'~' => expanded.push_str(previous.unwrap_or_default()),
+			_ => expanded.push(ch)
+		}
+	}
+	expanded
+}
+
+/// A case change requested by `\u`, `\U`, `\l`, or `\L` in the replacement of `:s`
+#[derive(Clone,Copy,Debug,PartialEq)]
+enum LetterCase {
+	Upper,
+	Lower
+}
+
+/// The case changes that are in effect while a replacement is being expanded
+#[derive(Default,Debug)]
+struct CaseMods {
+	/// Set by `\u` or `\l`, and used up by the next character
+	next: Option<LetterCase>,
+	/// Set by `\U` or `\L`, and cleared by `\e` or `\E`
+	rest: Option<LetterCase>
+}
+
+impl CaseMods {
+	fn set(&mut self, modifier: char) {
+		match modifier {
+			'u' => self.next = Some(LetterCase::Upper),
+			'l' => self.next = Some(LetterCase::Lower),
+			'U' => self.rest = Some(LetterCase::Upper),
+			'L' => self.rest = Some(LetterCase::Lower),
+			_ => self.rest = None
+		}
+	}
+	/// Expand `template` into `expanded`, changing the case of each character as it goes
+	fn flush(&mut self, caps: &regex::Captures, template: &mut String, expanded: &mut String) {
+		if template.is_empty() {
+			return
+		}
+		let mut text = String::new();
+		caps.expand(template, &mut text);
+		template.clear();
+		for ch in text.chars() {
+			match self.next.take().or(self.rest) {
+				Some(LetterCase::Upper) => expanded.extend(ch.to_uppercase()),
+				Some(LetterCase::Lower) => expanded.extend(ch.to_lowercase()),
+				None => expanded.push(ch)
+			}
+		}
+	}
+}
+
+/// Expand the replacement text of `:s` for a single match.
+///
+/// Capture groups can be referred to the way vim does it, with `\0` through `\9` and `&` for the whole match,
+/// or with the `regex` crate's expansion syntax: `$1`, `${1}`, `$name`, or `${name}`, where `$$` is a literal `$`.
+/// `\&` and `\~` are a literal `&` and `~`, a doubled backslash is a literal backslash, and any other escape is left alone.
+/// Any `~` should already have been replaced by `expand_tilde()`.
+///
+/// Like in Vim, `\r` splits the line, `\t` is a tab, and `\n` is a NUL byte rather than a newline.
+///
+/// Vim's case modifiers work too: `\u` and `\l` change the case of the next character, and `\U` and `\L` change everything after them until `\e` or `\E`.
+pub fn expand_replacement(caps: &regex::Captures, new: &str) -> String {
+	let mut expanded = String::new();
+	let mut template = String::new();
+	let mut case = CaseMods::default();
+	let mut chars = new.chars().peekable();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'&' => template.push_str("${0}"),
+			'\\' => match chars.next() {
+				Some(digit @ '0'..='9') => write!(template, "${{{digit}}}").unwrap(),
+				Some('\\') => template.push('\\'),
+				Some(literal @ ('&' | '~')) => template.push(literal),
+				Some('r') => template.push('\n'),
+				Some('n') => template.push('\0'),
+				Some('t') => template.push('\t'),
+				Some(modifier @ ('u' | 'U' | 'l' | 'L' | 'e' | 'E')) => {
+					case.flush(caps, &mut template, &mut expanded);
+					case.set(modifier);
+				}
+				Some(other) => {
+					template.push('\\');
+					template.push(other);
+				}
+				None => template.push('\\'),
+			}
+			_ => template.push(ch)
+		}
+	}
+	case.flush(caps, &mut template, &mut expanded);
+	expanded
+}
+
+/// Pair up every spelling that `:S/old/new/` should replace with what it becomes.
+///
+/// Brace groups are expanded in both patterns and paired off in order; a replacement without braces is used for all of them.
+/// Each pair is then given in snake_case, UPPER_CASE, PascalCase, camelCase, and kebab-case, so the replacement keeps the case of whatever it replaces.
+pub fn subvert_variants(old: &str, new: &str) -> Result<Vec<(String,String)>,String> {
+	let olds = expand_braces(old)?;
+	let mut news = expand_braces(new)?;
+	if news.len() == 1 {
+		news = vec![news[0].clone(); olds.len()];
+	}
+	if olds.len() != news.len() {
+		return Err(format!("':S' expected '{new}' to have as many alternatives as '{old}'"))
+	}
+	let mut variants: Vec<(String,String)> = vec![];
+	for (old, new) in olds.iter().zip(news.iter()) {
+		let spellings = std::iter::once((old.clone(), new.clone()))
+			.chain([Case::Snake, Case::Upper, Case::Pascal, Case::Camel, Case::Kebab].into_iter()
+				.map(|case| (coerce_case(old, case), coerce_case(new, case))));
+		for (from, to) in spellings {
+			if !from.is_empty() && !variants.iter().any(|(existing,_)| *existing == from) {
+				variants.push((from,to));
+			}
+		}
+	}
+	// Longer spellings go first, so that 'facilities' is never cut short by 'facilit'
+	variants.sort_by_key(|(from,_)| std::cmp::Reverse(from.len()));
+	Ok(variants)
+}
+
 /// Ensure that the start is always less than or equal to the end
 ///
 /// This is useful for creating ranges where the start and end positions
@@ -4026,3 +5885,116 @@ pub fn ordered_signed(start: isize, end: isize) -> (isize,isize) {
 		(start,end)
 	}
 }
+
+/// Keep each line mark on the same line while the bytes in `range` are replaced with `new`, the way Vim moves its marks.
+///
+/// Lines after the edit are shifted by the number of lines that it added or removed.
+/// Lines that were changed in place keep their mark, and lines that were deleted (or joined into another line) lose it.
+fn adjust_line_marks(line_marks: &mut [VecDeque<usize>], buffer: &str, range: &Range<usize>, new: &str) {
+	if line_marks.iter().all(|marks| marks.is_empty()) || (range.is_empty() && new.is_empty()) {
+		return
+	}
+	let before = &buffer[..range.start];
+	let old = &buffer[range.clone()];
+	let first = before.matches('\n').count();
+	let removed = old.matches('\n').count();
+	let added = new.matches('\n').count();
+	// Replacing whole lines leaves the line after them alone. Otherwise the line that the edit ends on was changed too.
+	let whole_lines = (before.is_empty() || before.ends_with('\n'))
+		&& (old.is_empty() || old.ends_with('\n'))
+		&& (new.is_empty() || new.ends_with('\n'));
+	let (old_end,new_end) = if whole_lines {
+		(first + removed, first + added)
+	} else {
+		(first + removed + 1, first + added + 1)
+	};
+	for marks in line_marks {
+		marks.retain_mut(|line| {
+			if *line < first {
+				true
+			} else if *line >= old_end {
+				*line = *line - old_end + new_end;
+				true
+			} else {
+				// One of the lines that the edit touched, which keeps its mark if the edit left a line in its place
+				*line < new_end
+			}
+		});
+	}
+}
+
+/// Move the scroll anchor for an edit that replaces `range` in `buffer` with `new`. An edit that touches it throws it away.
+fn adjust_scroll_anchor(anchor: &mut Option<(usize,usize)>, buffer: &str, range: &Range<usize>, new: &str) {
+	let Some((pos,line)) = anchor else { return };
+	if range.start >= *pos {
+		return
+	}
+	if range.end > *pos {
+		*anchor = None;
+		return
+	}
+	let old = &buffer[range.clone()];
+	*pos = *pos - old.len() + new.len();
+	*line = *line - old.matches('\n').count() + new.matches('\n').count();
+}
+
+/// A single change between two versions of a text, as produced by `diff_hunks()`
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Hunk {
+	/// The line in the old text where the change begins, counting from zero
+	pub line: usize,
+	/// The lines of the old text that are replaced, without their line endings
+	pub removed: Vec<String>,
+	/// The lines that replace them
+	pub added: Vec<String>,
+}
+
+/// Split `text` into its lines without their line endings, and report whether the last line had one
+fn split_lines(text: &str) -> (Vec<&str>,bool) {
+	match text.strip_suffix('\n') {
+		Some(body) => (body.split('\n').collect(), true),
+		None if text.is_empty() => (vec![], false),
+		None => (text.split('\n').collect(), false)
+	}
+}
+
+/// Find the changes that turn `old` into `new`, line by line.
+///
+/// Hunks don't carry any surrounding context, so each one is exactly one run of changed lines.
+pub fn diff_hunks(old: &str, new: &str) -> Vec<Hunk> {
+	let (old_lines, _) = split_lines(old);
+	let (new_lines, _) = split_lines(new);
+	capture_diff_slices(Algorithm::Myers, &old_lines, &new_lines).into_iter()
+		.filter(|op| op.tag() != DiffTag::Equal)
+		.map(|op| Hunk {
+			line: op.old_range().start,
+			removed: old_lines[op.old_range()].iter().map(|line| line.to_string()).collect(),
+			added: new_lines[op.new_range()].iter().map(|line| line.to_string()).collect(),
+		}).collect()
+}
+
+/// Apply hunks from `diff_hunks()` to `text`, like `patch` would.
+///
+/// Every hunk's removed lines have to match the text exactly, or nothing is applied.
+/// Whether the text ends with a newline is left as it was.
+pub fn apply_hunks(text: &str, hunks: &[Hunk]) -> Result<String,String> {
+	let (lines, trailing_newline) = split_lines(text);
+	let mut lines = lines.into_iter().map(|line| line.to_string()).collect::<Vec<_>>();
+	let mut hunks = hunks.iter().collect::<Vec<_>>();
+	// Going from the bottom up keeps the line numbers of the remaining hunks valid
+	hunks.sort_by_key(|hunk| std::cmp::Reverse(hunk.line));
+	let mut last_start = usize::MAX;
+	for hunk in hunks {
+		let end = hunk.line + hunk.removed.len();
+		if end > last_start || lines.get(hunk.line..end).is_none_or(|found| found != hunk.removed.as_slice()) {
+			return Err(format!("hunk at line {} does not apply", hunk.line + 1))
+		}
+		lines.splice(hunk.line..end, hunk.added.iter().cloned());
+		last_start = hunk.line;
+	}
+	let mut patched = lines.join("\n");
+	if trailing_newline && !lines.is_empty() {
+		patched.push('\n');
+	}
+	Ok(patched)
+}
@@ -9,23 +9,28 @@
 //! 1. Arguments are parsed into a sequence of commands
 //! 2. A `ViCut` instance is created to manage editor state and buffer contents
 //! 3. The commands are applied to the input in sequence, modifying and/or extracting text
-use std::{collections::BTreeMap, env::Args, fmt::{Display, Write}, fs, io::{self, BufRead, Write as IoWrite}, iter::{Peekable, Skip}, path::PathBuf};
+use std::{collections::{BTreeMap, VecDeque}, env::Args, fmt::{Display, Write}, fs, io::{self, BufRead, IsTerminal, Read, Write as IoWrite}, iter::{Peekable, Skip}, path::{Path, PathBuf}, cell::Cell, sync::{atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering}, LazyLock, Mutex}, time::{Duration, Instant}};
 
+#[cfg(jemalloc)]
 extern crate tikv_jemallocator;
 
-#[cfg(target_os = "linux")]
+#[cfg(jemalloc)]
 #[global_allocator]
 /// For linux we use Jemalloc. It is ***significantly*** faster than the default allocator in this case, for some reason.
+/// The `jemalloc` cfg is set by `build.rs`, and is left off for musl targets.
 static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
-use exec::{CompoundVal, Val, ViCut};
+use exec::{CompoundVal, FieldNewlines, InputVars, Val, ViCut};
+use globset::{Glob, GlobMatcher};
 use log::trace;
-use register::{append_register, write_register, RegisterContent};
-use serde_json::{map, Map, Value};
-use rayon::prelude::*;
+use register::{append_register, isolate_registers, write_register, RegisterContent};
+use regex::{Regex, RegexBuilder};
+use serde_json::{value::RawValue, Map, Value};
+use par::ParMap;
+use interrupt::InterruptPolicy;
 use vic::{BinOp, CmdArg, Expr};
 
-use crate::{linebuf::MotionKind, vicmd::{LineAddr, Motion, MotionCmd}};
+use crate::{linebuf::{token_frequencies, BufSettings, FoldMethod, SubSyntax, TextUnit, WORD_TOKEN}, picker::Candidate, shell::{ShellCfg, ShellCwd}, vicmd::{LineAddr, Motion}};
 
 pub mod vicmd;
 pub mod modes;
@@ -34,6 +39,13 @@ pub mod linebuf;
 pub mod keys;
 pub mod register;
 pub mod reader;
+pub mod shell;
+pub mod filetype;
+pub mod build_info;
+pub mod par;
+pub mod interrupt;
+pub mod stats;
+pub mod picker;
 pub mod vic;
 #[cfg(test)]
 pub mod tests;
@@ -57,11 +69,270 @@ pub fn complain_and_exit<T>(err: impl Display) -> T {
 	std::process::exit(1)
 }
 
-pub struct ExecCtx {
-	args: Opts,
+/// The exit code set by a top-level `return` in a vic script.
+static EXIT_CODE: AtomicI32 = AtomicI32::new(0);
+
+/// Set the process exit code. When several buffers set one, the highest code wins.
+///
+/// Codes are clamped to 0-255 first, since that's all that a process can exit with.
+fn set_exit_code(code: isize) {
+	EXIT_CODE.fetch_max(code.clamp(0, 255) as i32, Ordering::Relaxed);
+}
+
+/// The exit code that the process should finish with
+fn exit_code() -> i32 {
+	EXIT_CODE.load(Ordering::Relaxed)
+}
+
+/// Failures from vic's `assert()` and from `--assert`. They are reported all at once when we finish.
+static ASSERT_FAILURES: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+fn record_assert_failure(msg: String) {
+	ASSERT_FAILURES.lock().unwrap().push(msg);
+}
+
+/// The `--cache` file, once it has been loaded. It is written back out when we finish.
+static EDIT_CACHE: Mutex<Option<EditCache>> = Mutex::new(None);
+/// A hash of what each file held when it was read for `-i`, so that changes made by someone else before it is written back aren't lost
+static READ_HASHES: LazyLock<Mutex<BTreeMap<PathBuf,String>>> = LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// What `--cache` remembers about the files that were edited in place.
+///
+/// Each file's entry holds a hash of the options it was edited with, and a hash of what was written to it.
+/// When both still match, running the same edit again would be a waste of time, so the file is skipped.
+struct EditCache {
+	path: PathBuf,
+	script: String,
+	entries: Map<String,Value>,
+}
+
+impl EditCache {
+	fn load(path: &Path, opts: &Opts) -> Result<Self,String> {
+		let entries = match fs::read_to_string(path) {
+			Ok(contents) => serde_json::from_str(&contents)
+				.map_err(|e| format!("vicut: failed to read the cache '{}': {e}", path.display()))?,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Map::new(),
+			Err(e) => return Err(format!("vicut: failed to read the cache '{}': {e}", path.display()))
+		};
+		// Anything that could change the output goes into the hash, so everything except the files themselves does
+		let script = format!("{} {:?}", env!("CARGO_PKG_VERSION"), Opts { files: vec![], ..opts.clone() });
+		Ok(Self { path: path.to_path_buf(), script: content_hash(&script), entries })
+	}
+	fn key(path: &Path) -> String {
+		fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).display().to_string()
+	}
+	/// Whether the file is still what this script made it the last time around
+	fn is_done(&self, path: &Path) -> bool {
+		let Some(Value::Object(entry)) = self.entries.get(&Self::key(path)) else {
+			return false
+		};
+		entry.get("script").and_then(Value::as_str) == Some(&self.script)
+			&& fs::read_to_string(path).is_ok_and(|contents| entry.get("hash").and_then(Value::as_str) == Some(&content_hash(&contents)))
+	}
+	fn record(&mut self, path: &Path, contents: &str) {
+		let mut entry = Map::new();
+		entry.insert("script".into(), Value::String(self.script.clone()));
+		entry.insert("hash".into(), Value::String(content_hash(contents)));
+		self.entries.insert(Self::key(path), Value::Object(entry));
+	}
+	fn save(&self) -> Result<(),String> {
+		let json = serde_json::to_string_pretty(&self.entries).unwrap();
+		fs::write(&self.path, json).map_err(|e| format!("vicut: failed to write the cache '{}': {e}", self.path.display()))
+	}
+}
+
+/// A 64-bit FNV-1a hash, as hex. Unlike `DefaultHasher`, it comes out the same no matter which version of Rust built vicut,
+/// which matters for hashes that are saved to disk.
+fn content_hash(contents: &str) -> String {
+	let hash = contents.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+	format!("{hash:016x}")
+}
+
+/// The most memory that a single compiled regex may use, set by `--regex-size-limit`
+static REGEX_SIZE_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_REGEX_SIZE_LIMIT);
+const DEFAULT_REGEX_SIZE_LIMIT: usize = 10 * (1 << 20);
+
+/// The size of the buffer that output is collected in before it is written to stdout
+const OUTPUT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The most lines of input that `--stream` will hold in memory at once
+const STREAM_CHUNK_LINES: usize = 4096;
+
+thread_local! {
+	/// When the record being evaluated on this thread has to be finished by, set from `--regex-timeout`
+	static RECORD_DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Compile a regex, refusing patterns that would blow past `--regex-size-limit`.
+///
+/// Every user-supplied pattern should go through here instead of `Regex::new()`.
+pub fn new_regex(pattern: &str) -> Result<Regex,regex::Error> {
+	build_regex(pattern, REGEX_SIZE_LIMIT.load(Ordering::Relaxed))
+}
+
+fn build_regex(pattern: &str, size_limit: usize) -> Result<Regex,regex::Error> {
+	RegexBuilder::new(pattern)
+		.size_limit(size_limit)
+		.dfa_size_limit(size_limit)
+		.build()
+}
+
+/// Whether the current record has run past its `--regex-timeout`, or has to stop for `--on-interrupt abort`.
+///
+/// Long running searches check this and stop early, and the record gets thrown out once control gets back to `exec_cmd()`.
+pub fn deadline_passed() -> bool {
+	interrupt::aborting() || RECORD_DEADLINE.get().is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+fn timeout_msg(args: &Opts) -> String {
+	let timeout = args.regex_timeout.unwrap_or_default();
+	format!("evaluation took longer than --regex-timeout ({}s)", timeout.as_secs_f64())
+}
+
+/// Report any assertion failures, save the `--cache` file, and exit with the code that we ended up with
+fn finish() -> ! {
+	if let Some(cache) = EDIT_CACHE.lock().unwrap().as_ref()
+	&& let Err(e) = cache.save() {
+		eprintln!("{e}");
+		set_exit_code(1);
+	}
+	let failures = std::mem::take(&mut *ASSERT_FAILURES.lock().unwrap());
+	if !failures.is_empty() {
+		eprintln!("vicut: {} assertion(s) failed:", failures.len());
+		for failure in &failures {
+			eprintln!("\t{failure}");
+		}
+		set_exit_code(1);
+	}
+	stats::report();
+	interrupt::exit_if_interrupted();
+	if output_closed() {
+		// The same code that a shell reports for a process killed by SIGPIPE
+		std::process::exit(128 + signal_hook::consts::SIGPIPE)
+	}
+	std::process::exit(exit_code())
+}
+
+/// The value of a captured field
+#[derive(Clone,Debug,PartialEq)]
+pub enum FieldVal {
+	Str(String),
+	/// Captured from a visual block selection, with one entry for each line of the block
+	Block(Vec<String>),
+	/// Nothing was captured, so this is the whole buffer, which goes under the `"0"` field name
+	Buffer(String)
+}
+
+impl FieldVal {
+	/// The lines of the field, for `{{name[N]}}` in templates. For a block, these are the pieces it was cut from.
+	pub fn lines(&self) -> Vec<&str> {
+		match self {
+			FieldVal::Str(text) | FieldVal::Buffer(text) => text.lines().collect(),
+			FieldVal::Block(lines) => lines.iter().map(|line| line.as_str()).collect()
+		}
+	}
+	pub fn is_empty(&self) -> bool {
+		match self {
+			FieldVal::Str(text) | FieldVal::Buffer(text) => text.is_empty(),
+			FieldVal::Block(lines) => lines.iter().all(|line| line.is_empty())
+		}
+	}
+	/// The text of the field, with the lines of a block joined by newlines, the way that they looked in the buffer
+	pub fn into_text(self) -> String {
+		match self {
+			FieldVal::Str(text) | FieldVal::Buffer(text) => text,
+			FieldVal::Block(lines) => lines.join("\n")
+		}
+	}
+	/// Trim the whitespace from the field, or from each line of a block
+	pub fn trim(&mut self) {
+		match self {
+			FieldVal::Str(text) | FieldVal::Buffer(text) => *text = text.trim().to_string(),
+			FieldVal::Block(lines) => {
+				for line in lines {
+					*line = line.trim().to_string();
+				}
+			}
+		}
+	}
+}
+
+impl Display for FieldVal {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			FieldVal::Str(text) | FieldVal::Buffer(text) => write!(f, "{text}"),
+			FieldVal::Block(lines) => write!(f, "{}", lines.join("\n"))
+		}
+	}
+}
+
+impl Default for FieldVal {
+	fn default() -> Self {
+		FieldVal::Str(String::new())
+	}
+}
+
+impl From<String> for FieldVal {
+	fn from(value: String) -> Self {
+		FieldVal::Str(value)
+	}
+}
+
+impl From<&str> for FieldVal {
+	fn from(value: &str) -> Self {
+		FieldVal::Str(value.to_string())
+	}
+}
+
+/// Blocks come out as an array of their lines
+impl From<FieldVal> for Value {
+	fn from(value: FieldVal) -> Self {
+		match value {
+			FieldVal::Str(text) | FieldVal::Buffer(text) => Value::String(text),
+			FieldVal::Block(lines) => Value::Array(lines.into_iter().map(Value::String).collect())
+		}
+	}
+}
+
+pub struct ExecCtx<'a> {
+	args: &'a Opts, // Borrowed, so that the parsed commands are shared by every buffer instead of copied into each one
 	field_num: usize,
-	fields: Vec<(String,String)>, // (name, value)
-	fmt_lines: Vec<Vec<(String,String)>>, // Lines to format output from
+	fields: Vec<(String,FieldVal)>, // (name, value)
+	pieces: Vec<(String,FieldVal)>, // What --fields-by split the record into, every -n group starts with these
+	fmt_lines: Vec<Vec<(String,FieldVal)>>, // Lines to format output from
+	global_region: Option<(usize,usize)>, // The lines that a nested -g scope is confined to
+	unwind: Option<Unwind>, // Set by 'return' and exit(), every block stops running until something catches it
+}
+
+impl<'a> ExecCtx<'a> {
+	pub fn new(args: &'a Opts) -> Self {
+		Self {
+			args,
+			field_num: 0,
+			fields: vec![],
+			pieces: vec![],
+			fmt_lines: vec![],
+			global_region: None,
+			unwind: None
+		}
+	}
+	/// Whether nothing was captured into the current `-n` group.
+	///
+	/// Every group after the first starts out with the pieces from `--fields-by`, which don't count on their own.
+	fn group_is_empty(&self) -> bool {
+		self.fields.is_empty() || (!self.fmt_lines.is_empty() && self.fields == self.pieces)
+	}
+}
+
+/// Control flow that leaves every enclosing block at once
+#[derive(Clone,Debug)]
+pub enum Unwind {
+	/// Caught by the enclosing function, or ends the script at the top level
+	Return(Val),
+	/// Caught at the top level, ends the script for the current input
+	Exit(isize),
+	/// Nothing catches this, the current record is reported and thrown out
+	Abort(String)
 }
 
 #[derive(Clone,Debug, PartialEq)]
@@ -72,6 +343,8 @@ pub enum Cmd {
 	GetBufId,
 	SwitchBuf(CmdArg), // Switch to a different buffer
 	Echo(Vec<CmdArg>),
+	EchoErr(Vec<CmdArg>),
+	EchoAppend(Vec<CmdArg>,String), // Append the output to the named variable instead of printing it
 	Motion(CmdArg),
 	Field(CmdArg),
 	Return(CmdArg),
@@ -79,20 +352,36 @@ pub enum Cmd {
 	Pop(CmdArg), 				 // Pop a value from an array or string
 	Yank(CmdArg,char), // The char is the register to yank into
 	NamedField(Name,CmdArg),
+	/// `-c pattern=<REGEX>`, captures each named group of the first match after the cursor as its own field
+	PatternFields(CmdArg),
 	Repeat {
 		body: Vec<Cmd>,
 		count: CmdArg
 	},
+	Normal {
+		range: Option<(CmdArg,CmdArg)>, // 1-based and inclusive, like ':2,10normal'
+		keys: CmdArg
+	},
 	Global{
 		pattern: CmdArg,
 		then_cmds: Vec<Cmd>,
 		else_cmds: Option<Vec<Cmd>>,
-		polarity: bool // Whether to execute on a match, or on no match
+		polarity: bool, // Whether to execute on a match, or on no match
+		limit: Option<CmdArg>, // Only operate on the first N matches
+		context: Option<CmdArg> // Lines around each match that nested -g scopes can see
 	},
 	VarDec {
 		name: String,
 		value: CmdArg
 	},
+	ConstDec {
+		name: String,
+		value: CmdArg
+	},
+	VarDecTuple {
+		names: Vec<String>, // let (a, b) = ..., unpacks an array
+		value: CmdArg
+	},
 	MutateVar {
 		name: String,
 		index: Option<CmdArg>,
@@ -109,6 +398,7 @@ pub enum Cmd {
 		body: Vec<Cmd>
 	},
 	ForBlock {
+		index_var: Option<String>, // Set by 'for (i, item) in ...', counts from 0
 		var_name: String,
 		iterable: CmdArg, // Must be a String or Array
 											// Strings iterate over characters
@@ -118,6 +408,10 @@ pub enum Cmd {
 		cond_blocks: Vec<CondBlock>,
 		else_block: Option<Vec<Cmd>>
 	},
+	MatchBlock {
+		value: CmdArg,
+		arms: Vec<MatchArm>
+	},
 	WhileBlock(CondBlock),
 	UntilBlock(CondBlock),
 }
@@ -128,31 +422,386 @@ pub struct CondBlock {
 	cmds: Vec<Cmd>,
 }
 
+#[derive(Clone,Debug,PartialEq)]
+pub struct MatchArm {
+	patterns: Vec<CmdArg>, // An empty list of patterns matches anything
+	cmds: Vec<Cmd>,
+}
+
+/// The line ending to use in output, set with `--eol`
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Eol {
+	Lf,
+	Crlf
+}
+
+impl Eol {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Eol::Lf => "\n",
+			Eol::Crlf => "\r\n",
+		}
+	}
+}
+
+impl TryFrom<&str> for Eol {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value.trim().to_ascii_lowercase().as_str() {
+			"lf" | "unix" => Ok(Eol::Lf),
+			"crlf" | "dos" | "windows" => Ok(Eol::Crlf),
+			_ => Err(format!("Expected 'lf' or 'crlf' for line endings, found '{value}'"))
+		}
+	}
+}
+
+/// When buffered output is written to stdout, set with `--flush`
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum FlushPolicy {
+	/// Only when the buffer fills up, and when vicut exits
+	Never,
+	/// After each file, or each batch of records with `--stream`
+	Record,
+	/// After every line
+	Line
+}
+
+impl TryFrom<&str> for FlushPolicy {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value.trim().to_ascii_lowercase().as_str() {
+			"never" => Ok(FlushPolicy::Never),
+			"record" => Ok(FlushPolicy::Record),
+			"line" => Ok(FlushPolicy::Line),
+			_ => Err(format!("Expected 'never', 'record', or 'line' for '--flush', found '{value}'"))
+		}
+	}
+}
+
+/// Set once a write to stdout fails because the reader went away, like `head` does once it has all the lines it wants
+static OUTPUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// Whether nothing more can be written to stdout. Nothing new should be started once this is true, since its output would go nowhere.
+pub fn output_closed() -> bool {
+	OUTPUT_CLOSED.load(Ordering::Relaxed)
+}
+
+/// Take note of a broken pipe, and pass the result along
+fn check_pipe<T>(result: io::Result<T>) -> io::Result<T> {
+	if let Err(e) = &result && e.kind() == io::ErrorKind::BrokenPipe {
+		OUTPUT_CLOSED.store(true, Ordering::Relaxed);
+	}
+	result
+}
+
+/// Write straight to stdout, for output that doesn't go through an `OutputWriter`, like `echo`.
+///
+/// Unlike `print!()`, this doesn't panic once stdout has been closed.
+pub fn print_stdout(output: &str) {
+	let mut stdout = io::stdout().lock();
+	check_pipe(stdout.write_all(output.as_bytes()).and_then(|_| stdout.flush())).ok();
+}
+
+/// Buffered output, flushed according to `--flush`.
+///
+/// Every pathway writes to stdout through one of these, so that output is buffered the same way no matter how it was produced.
+struct OutputWriter<W: IoWrite> {
+	out: io::BufWriter<W>,
+	policy: FlushPolicy
+}
+
+impl<W: IoWrite> OutputWriter<W> {
+	fn new(out: W, args: &Opts) -> Self {
+		Self {
+			out: io::BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, out),
+			policy: args.flush_policy()
+		}
+	}
+	/// Called once a file's output, or a batch of records, has been written
+	fn end_record(&mut self) {
+		if self.policy == FlushPolicy::Record {
+			self.out.flush().ok();
+		}
+	}
+}
+
+impl<W: IoWrite> IoWrite for OutputWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let written = check_pipe(self.out.write(buf))?;
+		stats::record_output(written);
+		if self.policy == FlushPolicy::Line && buf[..written].contains(&b'\n') {
+			check_pipe(self.out.flush())?;
+		}
+		Ok(written)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		check_pipe(self.out.flush())
+	}
+}
+
+impl<W: IoWrite> Drop for OutputWriter<W> {
+	fn drop(&mut self) {
+		// Flushed here instead of by the BufWriter, so that a broken pipe is still noticed
+		self.flush().ok();
+	}
+}
+
+/// A locked handle to stdout, buffered according to `--flush`
+///
+/// If the script's prelude sets `write`, the output goes to that file instead. Diagnostics still go to stderr.
+fn stdout_writer(args: &Opts) -> OutputWriter<Box<dyn IoWrite>> {
+	let out: Box<dyn IoWrite> = match args.out_file.as_deref() {
+		Some(path) => Box::new(open_out_file(args, path).unwrap_or_else(complain_and_exit)),
+		// Not locked for the whole run, since `echo` and `:w !cmd` write to stdout from the worker threads
+		None => Box::new(io::stdout())
+	};
+	OutputWriter::new(out, args)
+}
+
+/// Whether two paths lead to the same file, even if they are spelled differently
+fn same_file(a: &Path, b: &Path) -> bool {
+	normalize_path(a) == normalize_path(b)
+}
+
+/// Spell out a path the same way every time, even if the file doesn't exist yet
+fn normalize_path(path: &Path) -> PathBuf {
+	if let Ok(path) = fs::canonicalize(path) {
+		return path
+	}
+	let parent = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+	match (fs::canonicalize(parent), path.file_name()) {
+		(Ok(dir), Some(name)) => dir.join(name),
+		_ => path.to_path_buf()
+	}
+}
+
+/// Open the file set by `write` in the prelude, after making a backup of it for `--backup`
+fn open_out_file(args: &Opts, path: &Path) -> Result<fs::File,String> {
+	if args.backup_files && path.exists() {
+		backup_file(args, path)?;
+	}
+	fs::File::create(path).map_err(|e| format!("couldn't open '{}' for writing: {e}", path.display()))
+}
+
+/// A tabular output format, set with `--format`
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum OutputFormat {
+	/// Comma separated values, quoted as described in RFC 4180
+	Csv,
+	/// Tab separated values, with tabs, newlines, and backslashes escaped by a backslash
+	Tsv
+}
+
+impl TryFrom<&str> for OutputFormat {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value.trim().to_ascii_lowercase().as_str() {
+			"csv" => Ok(OutputFormat::Csv),
+			"tsv" => Ok(OutputFormat::Tsv),
+			_ => Err(format!("Expected 'csv' or 'tsv' for the output format, found '{value}'"))
+		}
+	}
+}
+
+/// Which files in the git working tree to operate on, set with `--git`
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum GitSelection {
+	/// Files that differ from `HEAD`, whether or not the changes are staged
+	Changed,
+	/// Files with changes in the index
+	Staged,
+	/// Every file that git is tracking
+	Tracked
+}
+
+impl GitSelection {
+	/// The git command that lists the selected files, separated by NUL bytes.
+	///
+	/// Paths are relative to the current directory, and deleted files are left out, since there would be nothing to read.
+	fn git_args(&self) -> &'static [&'static str] {
+		match self {
+			GitSelection::Changed => &["diff", "--name-only", "-z", "--relative", "--diff-filter=d", "HEAD"],
+			GitSelection::Staged => &["diff", "--cached", "--name-only", "-z", "--relative", "--diff-filter=d"],
+			GitSelection::Tracked => &["ls-files", "-z"],
+		}
+	}
+}
+
+impl TryFrom<&str> for GitSelection {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value.trim().to_ascii_lowercase().as_str() {
+			"changed" | "modified" => Ok(GitSelection::Changed),
+			"staged" | "cached" => Ok(GitSelection::Staged),
+			"ls-files" | "tracked" => Ok(GitSelection::Tracked),
+			_ => Err(format!("Expected 'changed', 'staged', or 'ls-files' for '--git', found '{value}'"))
+		}
+	}
+}
+
+/// The order that `-g`/`-v` scopes visit matching lines in, set with `--g-order`
+///
+/// Reverse is the default, so that deleting or joining lines doesn't shift the lines that are still to be visited.
+#[derive(Default,Clone,Copy,Debug,PartialEq)]
+pub enum GOrder {
+	Forward,
+	#[default]
+	Reverse
+}
+
+impl TryFrom<&str> for GOrder {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value.trim().to_ascii_lowercase().as_str() {
+			"forward" => Ok(GOrder::Forward),
+			"reverse" => Ok(GOrder::Reverse),
+			_ => Err(format!("Expected 'forward' or 'reverse' for -g order, found '{value}'"))
+		}
+	}
+}
+
+/// How far the numbering of unnamed fields reaches before it starts over, set by `--field-scope`
+#[derive(Default,Clone,Copy,Debug,PartialEq)]
+pub enum FieldScope {
+	/// Every `-n` starts the numbering over
+	#[default]
+	Group,
+	/// Numbering carries on through `-n`, and only starts over for the next record or file
+	Record
+}
+
+impl TryFrom<&str> for FieldScope {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value.trim().to_ascii_lowercase().as_str() {
+			"group" => Ok(FieldScope::Group),
+			"record" => Ok(FieldScope::Record),
+			_ => Err(format!("Expected 'group' or 'record' for --field-scope, found '{value}'"))
+		}
+	}
+}
+
+/// An expectation about how much an in-place edit may change a file, checked by `--verify-roundtrip`
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum RoundtripCheck {
+	/// The line count may change by at most this many lines
+	Lines(usize),
+	/// The byte length may change by at most this many bytes
+	Bytes(usize)
+}
+
+impl RoundtripCheck {
+	/// Parse a comma separated list of checks, like 'lines,bytes:64'
+	pub fn parse_list(value: &str) -> Result<Vec<Self>,String> {
+		value.split(',').map(Self::try_from).collect()
+	}
+	/// Compare the file before and after editing, describing the problem if this check fails
+	pub fn violation(&self, before: &str, after: &str) -> Option<String> {
+		let (unit, before, after, tolerance) = match self {
+			Self::Lines(tolerance) => ("lines", before.lines().count(), after.lines().count(), *tolerance),
+			Self::Bytes(tolerance) => ("bytes", before.len(), after.len(), *tolerance),
+		};
+		(before.abs_diff(after) > tolerance)
+			.then(|| format!("{unit} went from {before} to {after}, but only a change of {tolerance} is allowed"))
+	}
+}
+
+impl TryFrom<&str> for RoundtripCheck {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		let (kind, tolerance) = value.trim().split_once(':').unwrap_or((value.trim(), "0"));
+		let tolerance = tolerance.parse::<usize>()
+			.map_err(|_| format!("Expected a number after '{kind}:' in --verify-roundtrip, found '{tolerance}'"))?;
+		match kind {
+			"lines" => Ok(Self::Lines(tolerance)),
+			"bytes" => Ok(Self::Bytes(tolerance)),
+			_ => Err(format!("Expected 'lines' or 'bytes' for --verify-roundtrip, found '{kind}'"))
+		}
+	}
+}
+
 /// The arguments passed to the program by the user
 #[derive(Default,Clone,Debug)]
 pub struct Opts {
 	delimiter: Option<String>,
 	template: Option<String>,
+	format: Option<OutputFormat>,
 	max_jobs: Option<u32>,
 	backup_extension: Option<String>,
+	shell_timeout: Option<Duration>,
+	shell_cwd: Option<ShellCwd>,
+	shell: Option<String>,
+	equalprg: Option<String>,
+	git: Option<GitSelection>,
+	files_from: Option<PathBuf>,
+	eol: Option<Eol>,
+	flush: Option<FlushPolicy>,
+	on_interrupt: InterruptPolicy,
+	g_order: GOrder,
+	buf_settings: BufSettings,
+	field_newlines: FieldNewlines,
+	field_base: Option<usize>,
+	field_scope: FieldScope,
+	freq: Option<usize>,
+	freq_pattern: Option<Regex>,
+	verify_roundtrip: Vec<RoundtripCheck>,
+	regex_timeout: Option<Duration>,
+	regex_size_limit: Option<usize>,
 
 	edit_inplace: bool,
+	dry_run: bool,
+	patch: bool,
+	recursive: bool,
+	hidden: bool,
+	max_filesize: Option<u64>,
+	text: bool,
 	json: bool,
+	json_input: bool,
+	json_field: Option<String>,
 	trace: bool,
 	linewise: bool,
+	stream: bool,
+	read0: bool,
+	print0: bool,
+	record_sep: Option<Regex>,
+	record_start: Option<Regex>,
+	fields_by: Option<Regex>,
 	trim_fields: bool,
 	keep_mode: bool,
 	backup_files: bool,
+	wait_for_lock: bool,
+	cache: Option<PathBuf>,
+	no_clobber: bool,
 	single_thread: bool,
+	shared_registers: bool,
+	deterministic: bool,
+	stats: bool,
 	global_uses_line_numbers: bool,
+	record_per_match: bool,
 	no_input: bool,
 	silent: bool,
+	capture_line: bool,
+	capture_word: bool,
+	capture_lineno: bool,
+	sandbox: bool,
+	no_shell: bool,
+	pick: bool,
+	strip_bom: bool,
+	dump_state: bool,
+	emit_schema: bool,
+	asserts: Vec<String>,
 
+	// Only set from vic scripts
+	#[cfg_attr(not(feature = "script"), allow(dead_code))]
 	pipe_in: Option<String>,
+	#[cfg_attr(not(feature = "script"), allow(dead_code))]
 	pipe_out: Option<String>,
 	out_file: Option<PathBuf>,
+	out_template: Option<String>,
 
 	cmds: Vec<Cmd>,
+	/// The globs given to `--program`, and the index in `cmds` where each program's commands start
+	programs: Vec<(GlobMatcher,usize)>,
 	files: Vec<PathBuf>
 }
 
@@ -166,15 +815,63 @@ impl Opts {
 				"--json" | "-j" => {
 					new.json = true;
 				}
+				"--json-input" => {
+					new.json_input = true;
+				}
+				"--field" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a field name after '{arg}'"))
+					};
+					new.json_field = Some(next_arg);
+				}
 				"--trace" => {
 					new.trace = true;
 				}
 				"--linewise" => {
 					new.linewise = true;
 				}
+				"--no-input" => {
+					new.no_input = true;
+				}
+				"--stream" => {
+					new.stream = true;
+				}
+				"-0" | "--null" | "--read0" => {
+					new.read0 = true;
+				}
+				"--print0" => {
+					new.print0 = true;
+				}
+				"--record-sep" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a pattern after '{arg}'"))
+					};
+					new.record_sep = Some(parse_record_sep(&next_arg)?);
+				}
+				"--record-start" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a pattern after '{arg}'"))
+					};
+					new.record_start = Some(parse_record_start(&next_arg)?);
+				}
+				"--fields-by" | "--field-sep-regex" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a pattern after '{arg}'"))
+					};
+					new.fields_by = Some(parse_fields_by(&next_arg)?);
+				}
 				"--serial" => {
 					new.single_thread = true;
 				}
+				"--shared-registers" => {
+					new.shared_registers = true;
+				}
+				"--deterministic" => {
+					new.deterministic = true;
+				}
+				"--stats" => {
+					new.stats = true;
+				}
 				"--trim-fields" => {
 					new.trim_fields = true;
 				}
@@ -187,12 +884,92 @@ impl Opts {
 				"--global-uses-line-numbers" => {
 					new.global_uses_line_numbers = true;
 				}
+				"--record-per-match" => {
+					new.record_per_match = true;
+				}
 				"--silent" => {
 					new.silent = true;
 				}
+				"--capture-line" => {
+					new.capture_line = true;
+				}
+				"--capture-word" => {
+					new.capture_word = true;
+				}
+				"--capture-lineno" => {
+					new.capture_lineno = true;
+				}
+				"--sandbox" => {
+					new.sandbox = true;
+				}
+				"--no-shell" => {
+					new.no_shell = true;
+				}
+				"--pick" => {
+					new.pick = true;
+				}
+				"--dump-state" => {
+					new.dump_state = true;
+				}
+				"--emit-schema" => {
+					new.emit_schema = true;
+				}
 				"-i" => {
 					new.edit_inplace = true;
 				}
+				"--out-template" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a file name template after '{arg}'"))
+					};
+					new.out_template = Some(parse_out_template(&next_arg)?);
+				}
+				"--wait" => new.wait_for_lock = true,
+				"--cache" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a file name after '{arg}'"))
+					};
+					new.cache = Some(PathBuf::from(next_arg));
+				}
+				"--no-clobber" => new.no_clobber = true,
+				"--dry-run" => {
+					new.edit_inplace = true;
+					new.dry_run = true;
+				}
+				"--patch" => {
+					new.edit_inplace = true;
+					new.dry_run = true;
+					new.patch = true;
+				}
+				"-R" | "--recursive" => new.recursive = true,
+				"--hidden" => new.hidden = true,
+				"--files-from" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a file name after '{arg}'"))
+					};
+					for file in read_file_list(&next_arg)? {
+						new.handle_filename(file);
+					}
+					new.files_from = Some(PathBuf::from(next_arg));
+				}
+				"--max-filesize" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a size after '{arg}'"))
+					};
+					new.max_filesize = Some(parse_filesize(&next_arg)?);
+				}
+				"--text" => new.text = true,
+				"--format" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'csv' or 'tsv' after '{arg}'"))
+					};
+					new.format = Some(OutputFormat::try_from(next_arg.as_str())?);
+				}
+				"--git" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'changed', 'staged', or 'ls-files' after '{arg}'"))
+					};
+					new.git = Some(GitSelection::try_from(next_arg.as_str())?);
+				}
 				"--template" | "-t" => {
 					let Some(next_arg) = args.next() else {
 						return Err(format!("Expected a format string after '{arg}'"))
@@ -202,6 +979,12 @@ impl Opts {
 					}
 					new.template = Some(next_arg)
 				}
+				"--assert" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a template after '{arg}'"))
+					};
+					new.asserts.push(next_arg)
+				}
 				"--delimiter" | "-d" => {
 					let Some(next_arg) = args.next() else { continue };
 					if next_arg.starts_with('-') {
@@ -209,7 +992,168 @@ impl Opts {
 					}
 					new.delimiter = Some(next_arg)
 				}
+				"--shell-timeout" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of seconds after '{arg}'"))
+					};
+					new.shell_timeout = Some(parse_timeout(&next_arg)?);
+				}
+				"--shell" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a shell after '{arg}'"))
+					};
+					new.shell = Some(parse_shell(&next_arg)?);
+				}
+				"--shell-cwd" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a directory after '{arg}'"))
+					};
+					new.shell_cwd = Some(ShellCwd::from(next_arg.as_str()));
+				}
+				"--equalprg" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a formatter command after '{arg}'"))
+					};
+					new.equalprg = Some(next_arg);
+				}
+				"--eol" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'lf' or 'crlf' after '{arg}'"))
+					};
+					new.eol = Some(Eol::try_from(next_arg.as_str())?);
+				}
+				"--flush" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'never', 'record', or 'line' after '{arg}'"))
+					};
+					new.flush = Some(FlushPolicy::try_from(next_arg.as_str())?);
+				}
+				"--on-interrupt" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'finish' or 'abort' after '{arg}'"))
+					};
+					new.on_interrupt = InterruptPolicy::try_from(next_arg.as_str())?;
+				}
+				"--g-order" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'forward' or 'reverse' after '{arg}'"))
+					};
+					new.g_order = GOrder::try_from(next_arg.as_str())?;
+				}
+				"--regex-timeout" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of seconds after '{arg}'"))
+					};
+					new.regex_timeout = Some(parse_timeout(&next_arg)?);
+				}
+				"--regex-size-limit" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of bytes after '{arg}'"))
+					};
+					new.regex_size_limit = Some(parse_size_limit(&next_arg)?);
+				}
+				"--units" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'bytes', 'chars', or 'graphemes' after '{arg}'"))
+					};
+					new.buf_settings.units = TextUnit::try_from(next_arg.as_str())?;
+				}
+				"--window-height" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of lines after '{arg}'"))
+					};
+					new.buf_settings.window_height = Some(parse_window_height(&next_arg)?);
+				}
+				"--textwidth" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of columns after '{arg}'"))
+					};
+					new.buf_settings.textwidth = Some(parse_textwidth(&next_arg)?);
+				}
+				"--jobs" | "-J" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of threads after '{arg}'"))
+					};
+					new.max_jobs = Some(parse_jobs(&next_arg)?);
+				}
+				"--field-newlines" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'keep', 'strip', or 'escape' after '{arg}'"))
+					};
+					new.field_newlines = FieldNewlines::try_from(next_arg.as_str())?;
+				}
+				"--field-base" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number after '{arg}'"))
+					};
+					let base = next_arg.parse::<usize>().map_err(|_| format!("Expected a number after '{arg}', found '{next_arg}'"))?;
+					// No record holds more than isize::MAX fields, so this keeps every field number within a usize
+					if base > isize::MAX as usize {
+						return Err(format!("Expected a number no larger than {} after '{arg}', found '{next_arg}'", isize::MAX))
+					}
+					new.field_base = Some(base);
+				}
+				"--field-scope" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'group' or 'record' after '{arg}'"))
+					};
+					new.field_scope = FieldScope::try_from(next_arg.as_str())?;
+				}
+				"--fold-method" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'indent' or 'marker' after '{arg}'"))
+					};
+					new.buf_settings.fold_method = FoldMethod::try_from(next_arg.as_str())?;
+				}
+				"--sub-syntax" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'vim' or 'regex' after '{arg}'"))
+					};
+					new.buf_settings.sub_syntax = SubSyntax::try_from(next_arg.as_str())?;
+				}
+				"--kv-separators" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a list of separators after '{arg}'"))
+					};
+					new.buf_settings.kv_separators = Some(parse_kv_separators(&next_arg)?);
+				}
+				"--string-escape" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a character after '{arg}'"))
+					};
+					new.buf_settings.string_escape = Some(parse_string_escape(&next_arg)?);
+				}
+				"--freq" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of tokens after '{arg}'"))
+					};
+					new.freq = Some(parse_freq_count(&next_arg)?);
+				}
+				"--freq-pattern" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a pattern after '{arg}'"))
+					};
+					new.freq_pattern = Some(parse_freq_pattern(&next_arg)?);
+				}
+				"--verify-roundtrip" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a list of checks after '{arg}'"))
+					};
+					new.verify_roundtrip = RoundtripCheck::parse_list(&next_arg)?;
+				}
+				"--bom" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'strip' or 'keep' after '{arg}'"))
+					};
+					new.strip_bom = parse_bom_arg(&next_arg)?;
+				}
 				"-n" | "--next" => new.cmds.push(Cmd::BreakGroup),
+				"--program" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a glob after '{arg}'"))
+					};
+					new.programs.push((parse_program_glob(&next_arg)?, new.cmds.len()));
+				}
 				"-r" | "--repeat" => {
 					let cmd_count = args
 						.next()
@@ -224,6 +1168,9 @@ impl Opts {
 
 					let mut body = vec![];
 					let drain_count = new.cmds.len() - cmd_count;
+					if new.programs.last().is_some_and(|(_,start)| drain_count < *start) {
+						return Err(format!("'{arg}' can't repeat commands from before the last '--program'"))
+					}
 					body.extend(new.cmds.drain(drain_count..));
 					new.cmds.push(Cmd::Repeat{ body, count: CmdArg::Count(repeat_count + 1) });
 				}
@@ -236,7 +1183,9 @@ impl Opts {
 				}
 				"-c" | "--cut" => {
 					let Some(arg) = args.next() else { continue };
-					if arg.starts_with("name=") {
+					if let Some(pattern) = arg.strip_prefix("pattern=") {
+						new.cmds.push(parse_pattern_field(pattern)?);
+					} else if arg.starts_with("name=") {
 						let name = arg.strip_prefix("name=").unwrap().to_string();
 						if name == "0" {
 							// We use '0' as a sentinel value to say "We didn't slice any fields, so this field is the entire buffer"
@@ -257,7 +1206,7 @@ impl Opts {
 				}
 				"-v" | "--not-global" |
 				"-g" | "--global" => {
-					let global = Self::handle_global_arg(arg.as_str(), &mut args);
+					let global = Self::handle_global_arg(arg.as_str(), &mut args)?;
 					new.cmds.push(global);
 				}
 				_ => new.handle_filename(arg)
@@ -279,7 +1228,7 @@ impl Opts {
 	/// ```bash
 	/// vicut -g 'foo' -g 'bar' -c 'd' --else -v 'baz' -c 'y' --end --end
 	/// ```
-	fn handle_global_arg(arg: &str, args: &mut Peekable<Skip<impl Iterator<Item = String>>>) -> Cmd {
+	fn handle_global_arg(arg: &str, args: &mut Peekable<Skip<impl Iterator<Item = String>>>) -> Result<Cmd,String> {
 		let polarity = match arg {
 			"-v" | "--not-global" => false,
 			"-g" | "--global" => true,
@@ -287,13 +1236,17 @@ impl Opts {
 		};
 		let mut then_cmds = vec![];
 		let mut else_cmds = None;
+		let mut limit = None;
+		let mut context = None;
 		let Some(arg) = args.next() else {
-			return Cmd::Global {
+			return Ok(Cmd::Global {
 				pattern: CmdArg::Literal(Val::Str(arg.into())),
 				then_cmds,
 				else_cmds,
-				polarity
-			};
+				polarity,
+				limit,
+				context
+			});
 		};
 		if arg.starts_with('-') {
 			eprintln!("Expected a selection command after '-c', found {arg}");
@@ -344,7 +1297,14 @@ impl Opts {
 				}
 				"-c" | "--cut" => {
 					let Some(arg) = args.next() else { continue };
-					if arg.starts_with("name=") {
+					if let Some(pattern) = arg.strip_prefix("pattern=") {
+						let cmd = parse_pattern_field(pattern).unwrap_or_else(complain_and_exit);
+						if let Some(cmds) = else_cmds.as_mut() {
+							cmds.push(cmd);
+						} else {
+							then_cmds.push(cmd);
+						}
+					} else if arg.starts_with("name=") {
 						let name = arg.strip_prefix("name=").unwrap().to_string();
 						let Some(arg) = args.next() else { continue };
 						if arg.starts_with('-') {
@@ -370,53 +1330,215 @@ impl Opts {
 				}
 				"-g" | "--global" |
 				"-v" | "--not-global" => {
-					let nested = Self::handle_global_arg(&global_arg, args);
+					let nested = Self::handle_global_arg(&global_arg, args)?;
 					if let Some(cmds) = else_cmds.as_mut() {
 						cmds.push(nested);
 					} else {
 						then_cmds.push(nested);
 					}
 				}
+				"--max-matches" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number after '{global_arg}'"))
+					};
+					let max = next_arg.parse::<usize>().map_err(|_| format!("Expected a number after '{global_arg}', found '{next_arg}'"))?;
+					limit = Some(CmdArg::Count(max));
+				}
+				"--context" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number after '{global_arg}'"))
+					};
+					let lines = next_arg.parse::<usize>().map_err(|_| format!("Expected a number after '{global_arg}', found '{next_arg}'"))?;
+					context = Some(CmdArg::Count(lines));
+				}
 				"--else" => {
 					// Now we start working on this
 					else_cmds = Some(vec![]);
 				}
 				"--end" => {
 					// We're done here
-					return Cmd::Global {
+					return Ok(Cmd::Global {
 						pattern: CmdArg::Literal(Val::Str(arg)),
 						then_cmds,
 						else_cmds,
-						polarity
-					};
+						polarity,
+						limit,
+						context
+					});
 				}
 				_ => {
 					eprintln!("Expected command flag in '-g' scope\nDid you forget to close '-g' with '--end'?");
 					std::process::exit(1);
 				}
 			}
-			if args.peek().is_some_and(|arg| !arg.starts_with('-')) { break }
+			if args.peek().is_some_and(|arg| !arg.starts_with('-')) { break }
+		}
+
+		// If we got here, we have run out of arguments
+		// Let's just submit the current -g commands.
+		// no need to be pressed about a missing '--end' when nothing would come after it
+		Ok(Cmd::Global {
+			pattern: CmdArg::Literal(Val::Str(arg)),
+			then_cmds,
+			else_cmds,
+			polarity,
+			limit,
+			context
+		})
+	}
+	pub fn from_script(script: PathBuf) -> Result<Self,String> {
+		let script_content = fs::read_to_string(&script)
+			.map_err(|_| format!("vicut: failed to read script file '{}'",script.display()))?;
+		vic::parse_vic(&script_content)
+			.map_err(|e| format!("vicut: failed to parse script file '{}': {e}",script.display()))
+	}
+	pub fn from_raw(script: &str) -> Result<Self,String> {
+		vic::parse_vic(script)
+			.map_err(|e| format!("vicut: failed to parse script: {e}"))
+	}
+	/// Make sure that nothing we were asked to do escapes the sandbox.
+	///
+	/// Shell commands and `env()` are refused at execution time,
+	/// but anything that would write to a file can be caught up front.
+	fn check_sandbox(&self) -> Result<(),String> {
+		if !self.sandbox {
+			return Ok(())
+		}
+		if self.edit_inplace && !self.dry_run {
+			return Err("vicut: in-place editing is not allowed in sandbox mode".into())
+		}
+		if self.backup_files {
+			return Err("vicut: creating backup files is not allowed in sandbox mode".into())
+		}
+		if let Some(path) = self.out_file.as_ref() {
+			return Err(format!("vicut: writing to '{}' is not allowed in sandbox mode",path.display()))
+		}
+		if self.out_template.is_some() {
+			return Err("vicut: writing output files with '--out-template' is not allowed in sandbox mode".into())
+		}
+		if self.git.is_some() {
+			return Err("vicut: running git for '--git' is not allowed in sandbox mode".into())
+		}
+		Ok(())
+	}
+	/// `--no-input` runs the commands once on an empty buffer, so any input that was asked for would be silently dropped
+	fn check_no_input(&self) -> Result<(),String> {
+		if !self.no_input {
+			return Ok(())
+		}
+		if self.edit_inplace {
+			return Err("vicut: '--no-input' and '-i' can't be used together".into())
+		}
+		if !self.files.is_empty() || self.files_from.is_some() || self.git.is_some() {
+			return Err("vicut: '--no-input' can't be given any input files".into())
+		}
+		Ok(())
+	}
+	/// `--format` decides the whole shape of the output, so the other output formats would be silently ignored
+	fn check_format(&self) -> Result<(),String> {
+		if self.format.is_none() {
+			return Ok(())
+		}
+		if self.template.is_some() {
+			return Err("vicut: '--format' and '--template' can't be used together".into())
+		}
+		if self.json {
+			return Err("vicut: '--format' and '--json' can't be used together".into())
+		}
+		Ok(())
+	}
+	/// Pin down everything that could make two runs over the same input come out differently, for `--deterministic`.
+	fn make_deterministic(&mut self) -> Result<(),String> {
+		if !self.deterministic {
+			return Ok(())
+		}
+		if self.shared_registers {
+			return Err("vicut: '--deterministic' and '--shared-registers' can't be used together".into())
+		}
+		if self.regex_timeout.is_some() || self.shell_timeout.is_some() {
+			return Err("vicut: '--deterministic' can't be used with a timeout, since whether one is hit depends on how busy the machine is".into())
+		}
+		if !self.single_thread {
+			// A single worker takes the records in order, so errors and shell commands happen in the same order on every run
+			self.max_jobs = Some(1);
+		}
+		Ok(())
+	}
+	/// The line ending used for any newlines we add to the output ourselves
+	fn line_ending(&self) -> &'static str {
+		self.eol.map(|eol| eol.as_str()).unwrap_or("\n")
+	}
+	/// How often output is flushed, if `--flush` wasn't given.
+	///
+	/// A terminal sees each line as soon as it is written. Anything else gets whole files, or whole batches with `--stream`.
+	fn flush_policy(&self) -> FlushPolicy {
+		self.flush.unwrap_or_else(|| {
+			if io::stdout().is_terminal() {
+				FlushPolicy::Line
+			} else {
+				FlushPolicy::Record
+			}
+		})
+	}
+	/// What separates one input record from the next in `--linewise` mode. Set to NUL by `--read0`.
+	///
+	/// Only used when there is no `--record-sep` pattern.
+	fn input_separator(&self) -> char {
+		if self.read0 { '\0' } else { '\n' }
+	}
+	/// The pattern that ends each input record in `--linewise` mode
+	fn record_splitter(&self) -> &Regex {
+		static NEWLINE: LazyLock<Regex> = LazyLock::new(|| Regex::new("\n").unwrap());
+		static NUL: LazyLock<Regex> = LazyLock::new(|| Regex::new("\0").unwrap());
+		match &self.record_sep {
+			Some(sep) => sep,
+			None if self.read0 => &NUL,
+			None => &NEWLINE
 		}
-
-		// If we got here, we have run out of arguments
-		// Let's just submit the current -g commands.
-		// no need to be pressed about a missing '--end' when nothing would come after it
-		Cmd::Global {
-			pattern: CmdArg::Literal(Val::Str(arg)),
-			then_cmds,
-			else_cmds,
-			polarity
+	}
+	/// Split the input into the records that `--linewise` works on
+	fn split_input(&self, input: &str) -> Vec<String> {
+		match &self.record_start {
+			Some(start) => split_records_at_starts(input, start),
+			None => split_records(input, self.record_splitter())
 		}
 	}
-	pub fn from_script(script: PathBuf) -> Result<Self,String> {
-		let script_content = fs::read_to_string(&script)
-			.map_err(|_| format!("vicut: failed to read script file '{}'",script.display()))?;
-		vic::parse_vic(&script_content)
-			.map_err(|e| format!("vicut: failed to parse script file '{}': {e}",script.display()))
+	/// What ends each record in the output. Set to NUL by `--print0`.
+	///
+	/// Newlines are converted by `--eol` later on, so this is always a bare `\n` otherwise.
+	fn record_separator(&self) -> &'static str {
+		if self.print0 { "\0" } else { "\n" }
 	}
-	pub fn from_raw(script: &str) -> Result<Self,String> {
-		vic::parse_vic(script)
-			.map_err(|e| format!("vicut: failed to parse script: {e}"))
+	/// The name of the `num`th unnamed field, counting from one. `--field-base` decides what the first one is called.
+	fn field_name(&self, num: usize) -> String {
+		(num - 1 + self.field_base.unwrap_or(1)).to_string()
+	}
+	/// What to write after a complete chunk of output.
+	///
+	/// With `--print0`, every record is already terminated, so an extra NUL would only show up as an empty record.
+	/// A table from `--format` already ends with a line ending, and a blank line after it would be read as an empty row.
+	fn output_terminator(&self, output: &str) -> &'static str {
+		match self.print0 {
+			true if output.is_empty() || output.ends_with('\0') => "",
+			true => "\0",
+			false if self.format.is_some() && (output.is_empty() || output.ends_with('\n')) => "",
+			false => self.line_ending()
+		}
+	}
+	/// The commands to run on a file, from the first `--program` whose glob matches its path.
+	///
+	/// Stdin, and files that no program matches, get the commands that came before the first `--program`.
+	fn program_for(&self, path: Option<&Path>) -> &[Cmd] {
+		let default_end = self.programs.first().map_or(self.cmds.len(), |(_,start)| *start);
+		let Some(path) = path else {
+			return &self.cmds[..default_end]
+		};
+		let Some(idx) = self.programs.iter().position(|(glob,_)| glob.is_match(path)) else {
+			return &self.cmds[..default_end]
+		};
+		let start = self.programs[idx].1;
+		let end = self.programs.get(idx + 1).map_or(self.cmds.len(), |(_,start)| *start);
+		&self.cmds[start..end]
 	}
 	fn validate_filename(filename: &str) -> Result<(),String> {
 		let path = PathBuf::from(filename.trim().to_string());
@@ -439,8 +1561,12 @@ impl Opts {
 	/// 3. The path given refers to a file that we are allowed to read.
 	///
 	/// We check all three separately instead of just the last one, so that we can give better error messages
+	///
+	/// Directories are let through here, since `-R` may come after them. They are walked or rejected in `run()`.
+	/// `-` is let through as well, since it stands for stdin.
 	fn handle_filename(&mut self, filename: String) {
-		if let Err(e) = Self::validate_filename(&filename) {
+		let path = Path::new(filename.trim());
+		if !is_stdin(path) && !path.is_dir() && let Err(e) = Self::validate_filename(&filename) {
 			eprintln!("{e}");
 			std::process::exit(1);
 		}
@@ -451,6 +1577,176 @@ impl Opts {
 	}
 }
 
+/// Parse the argument given to `--shell-timeout` or `--regex-timeout`, which is a number of seconds
+pub fn parse_timeout(secs: &str) -> Result<Duration,String> {
+	secs.trim()
+		.parse::<f64>()
+		.ok()
+		.and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+		.ok_or_else(|| format!("Expected a number of seconds for the timeout, found '{secs}'"))
+}
+
+/// Parse the glob given to `--program`
+pub fn parse_program_glob(glob: &str) -> Result<GlobMatcher,String> {
+	Glob::new(glob)
+		.map(|glob| glob.compile_matcher())
+		.map_err(|e| format!("Invalid glob '{glob}' for --program: {e}"))
+}
+
+/// Parse the argument given to `--window-height`, which is a number of lines
+pub fn parse_window_height(lines: &str) -> Result<usize,String> {
+	lines.trim()
+		.parse::<usize>()
+		.ok()
+		.filter(|lines| *lines > 0)
+		.ok_or_else(|| format!("Expected a positive number of lines for the window height, found '{lines}'"))
+}
+
+/// Parse the argument given to `--textwidth`, which is a number of columns
+pub fn parse_textwidth(columns: &str) -> Result<usize,String> {
+	columns.trim()
+		.parse::<usize>()
+		.ok()
+		.filter(|columns| *columns > 0)
+		.ok_or_else(|| format!("Expected a positive number of columns for the text width, found '{columns}'"))
+}
+
+/// Parse the argument given to `--jobs`, which is a number of threads
+pub fn parse_jobs(jobs: &str) -> Result<u32,String> {
+	jobs.trim()
+		.parse::<u32>()
+		.ok()
+		.filter(|jobs| *jobs > 0)
+		.ok_or_else(|| format!("Expected a positive number of threads for '--jobs', found '{jobs}'"))
+}
+
+/// Parse the argument given to `--kv-separators`, which is a whitespace separated list of separators
+pub fn parse_kv_separators(separators: &str) -> Result<Vec<String>,String> {
+	let separators = separators.split_whitespace()
+		.map(|sep| sep.to_string())
+		.collect::<Vec<_>>();
+	if separators.is_empty() {
+		return Err("Expected at least one key/value separator".into())
+	}
+	Ok(separators)
+}
+
+/// Parse the argument given to `--string-escape`, which is a single character
+pub fn parse_string_escape(escape: &str) -> Result<char,String> {
+	let mut chars = escape.chars();
+	match (chars.next(), chars.next()) {
+		(Some(ch), None) => Ok(ch),
+		_ => Err(format!("Expected a single character for the string escape, found '{escape}'"))
+	}
+}
+
+/// Parse the argument given to `--max-filesize`, which is a number of bytes with an optional 'K', 'M', or 'G' suffix
+pub fn parse_filesize(size: &str) -> Result<u64,String> {
+	let err = || format!("Expected a size like '512', '64K', '10M', or '1G', found '{size}'");
+	let size = size.trim();
+	let (digits, scale) = match size.char_indices().last() {
+		Some((i, 'k' | 'K')) => (&size[..i], 1 << 10),
+		Some((i, 'm' | 'M')) => (&size[..i], 1 << 20),
+		Some((i, 'g' | 'G')) => (&size[..i], 1 << 30),
+		_ => (size, 1)
+	};
+	digits.parse::<u64>()
+		.ok()
+		.and_then(|num| num.checked_mul(scale))
+		.ok_or_else(err)
+}
+
+/// Parse the argument given to `--freq`, which is the number of tokens to list
+pub fn parse_freq_count(count: &str) -> Result<usize,String> {
+	count.trim()
+		.parse::<usize>()
+		.ok()
+		.filter(|count| *count > 0)
+		.ok_or_else(|| format!("Expected a positive number of tokens for the frequency report, found '{count}'"))
+}
+
+/// Parse the argument given to `--freq-pattern`, which is the regex that matches a single token
+pub fn parse_freq_pattern(pattern: &str) -> Result<Regex,String> {
+	new_regex(pattern).map_err(|e| format!("Invalid regex for the frequency report: {e}"))
+}
+
+/// Parse the argument given to `--record-sep`, which is the regex that ends each record
+pub fn parse_record_sep(pattern: &str) -> Result<Regex,String> {
+	let regex = new_regex(pattern).map_err(|e| format!("Invalid regex for the record separator: {e}"))?;
+	if regex.is_match("") {
+		return Err(format!("The record separator '{pattern}' can't match an empty string"))
+	}
+	Ok(regex)
+}
+
+/// Parse the argument given to `--record-start`, which is the regex that a record's first line matches
+pub fn parse_record_start(pattern: &str) -> Result<Regex,String> {
+	let regex = new_regex(pattern).map_err(|e| format!("Invalid regex for the record start: {e}"))?;
+	if regex.is_match("") {
+		return Err(format!("The record start '{pattern}' can't match an empty string"))
+	}
+	Ok(regex)
+}
+
+/// Parse the argument given to `--fields-by`, which is the regex that separates the fields of each record
+pub fn parse_fields_by(pattern: &str) -> Result<Regex,String> {
+	let regex = new_regex(pattern).map_err(|e| format!("Invalid regex for the field separator: {e}"))?;
+	if regex.is_match("") {
+		return Err(format!("The field separator '{pattern}' can't match an empty string"))
+	}
+	Ok(regex)
+}
+
+/// Parse the pattern given to `-c pattern=`. It is compiled once here, instead of once for every record.
+pub fn parse_pattern_field(pattern: &str) -> Result<Cmd,String> {
+	let regex = new_regex(pattern).map_err(|e| format!("Invalid regex for '-c pattern=': {e}"))?;
+	if regex.capture_names().flatten().next().is_none() {
+		return Err(format!("The pattern '{pattern}' has no named groups to capture, e.g. '(?P<name>\\w+)'"))
+	}
+	Ok(Cmd::PatternFields(CmdArg::Literal(Val::Regex(regex))))
+}
+
+/// Parse the argument given to `--regex-size-limit`, which is a number of bytes
+/// The placeholders that `--out-template` fills in from each input file's path
+const OUT_TEMPLATE_PLACEHOLDERS: [&str;3] = ["name", "stem", "ext"];
+
+pub fn parse_out_template(template: &str) -> Result<String,String> {
+	let mut rest = template;
+	while let Some(start) = rest.find('{') {
+		let Some(len) = rest[start..].find('}') else {
+			return Err(format!("Unclosed '{{' in '--out-template' '{template}'"))
+		};
+		let placeholder = &rest[start + 1..start + len];
+		if !OUT_TEMPLATE_PLACEHOLDERS.contains(&placeholder) {
+			return Err(format!("Unknown placeholder '{{{placeholder}}}' in '--out-template', expected one of {{name}}, {{stem}}, or {{ext}}"))
+		}
+		rest = &rest[start + len + 1..];
+	}
+	Ok(template.to_string())
+}
+
+pub fn parse_shell(shell: &str) -> Result<String,String> {
+	if shell.trim().is_empty() {
+		return Err("Expected a shell for '--shell', found an empty string".into())
+	}
+	Ok(shell.to_string())
+}
+
+pub fn parse_size_limit(bytes: &str) -> Result<usize,String> {
+	bytes.trim()
+		.parse::<usize>()
+		.map_err(|_| format!("Expected a number of bytes for the regex size limit, found '{bytes}'"))
+}
+
+/// Parse the argument given to `--bom`. Returns whether or not the BOM should be stripped.
+pub fn parse_bom_arg(arg: &str) -> Result<bool,String> {
+	match arg.trim() {
+		"strip" => Ok(true),
+		"keep" => Ok(false),
+		_ => Err(format!("Expected 'strip' or 'keep' for BOM handling, found '{arg}'"))
+	}
+}
+
 /// "Get some help" - Michael Jordan
 /// Prints out the help info for `vicut`
 fn get_help() -> String {
@@ -473,6 +1769,11 @@ fn get_help() -> String {
 	writeln!(help, "\t-d, --delimiter <STR>").ok();
 	writeln!(help, "\t\tProvide a delimiter to place between fields in the output. No effect when used with --json.").ok();
 	writeln!(help).ok();
+	writeln!(help, "\t--format <csv|tsv>").ok();
+	writeln!(help, "\t\tOutput the records as a CSV or TSV table, with a header row made from the field names.").ok();
+	writeln!(help, "\t\tCSV fields are quoted as described in RFC 4180. In TSV, tabs, newlines, and backslashes are escaped with a backslash.").ok();
+	writeln!(help, "\t\tWith --stream, the columns are the fields found in the first batch of records. Can't be used with --json or --template.").ok();
+	writeln!(help).ok();
 	writeln!(help, "\t--keep-mode").ok();
 	writeln!(help, "\t\tThe internal editor will not return to normal mode after each command.").ok();
 	writeln!(help).ok();
@@ -484,18 +1785,109 @@ fn get_help() -> String {
 	writeln!(help, "\t\tEach line in the input is treated as it's own separate buffer.").ok();
 	writeln!(help, "\t\tThis operation is multi-threaded.").ok();
 	writeln!(help).ok();
+	writeln!(help, "\t--stream").ok();
+	writeln!(help, "\t\tLike --linewise, but lines from stdin are processed as they arrive, and output is written as it is produced.").ok();
+	writeln!(help, "\t\tOnly a bounded number of lines are held in memory at once. With --json, each record is printed as its own line of JSON.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--json-input").ok();
+	writeln!(help, "\t\tRead the input as JSON Lines, and run the commands on the value of the key given by --field in each record.").ok();
+	writeln!(help, "\t\tEach record is printed back out as a line of JSON. The edited buffer replaces the field's value,").ok();
+	writeln!(help, "\t\tor if any fields were captured, they are added to the record instead. Implies --linewise.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--field <NAME>").ok();
+	writeln!(help, "\t\tThe key whose value is edited with --json-input.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t-0, --null, --read0").ok();
+	writeln!(help, "\t\tWith --linewise or --stream, split the input into records at NUL bytes instead of newlines, like 'xargs -0'.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--record-sep <REGEX>").ok();
+	writeln!(help, "\t\tWith --linewise or --stream, split the input into records at each match of REGEX instead of at newlines,").ok();
+	writeln!(help, "\t\te.g. '\\n\\n+' for paragraphs. Each record keeps the separator that ended it.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--record-start <REGEX>").ok();
+	writeln!(help, "\t\tStart a new record at every line that matches REGEX, so that records can span several lines,").ok();
+	writeln!(help, "\t\te.g. '^\\d{{4}}-\\d\\d-\\d\\d' for log entries that begin with a date. Implies --linewise.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--fields-by <REGEX>").ok();
+	writeln!(help, "\t\tSplit each record at every match of REGEX before running any commands, like awk's -F.").ok();
+	writeln!(help, "\t\tThe pieces become fields '1' through 'N', and fields captured with -c are numbered after them.").ok();
+	writeln!(help, "\t\tCapturing with '-c name=<N>' replaces field N. Scripts can read the pieces from the 'fields' array.").ok();
+	writeln!(help, "\t\tEach group made by -n starts out with the pieces.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--print0").ok();
+	writeln!(help, "\t\tEnd each output record with a NUL byte instead of a newline, for 'xargs -0' and friends.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--no-input").ok();
+	writeln!(help, "\t\tDon't read from stdin or any files. Commands are run on an empty buffer.").ok();
+	writeln!(help, "\t\tUseful for scripts that generate all of their output themselves. Can't be given files or -i.").ok();
+	writeln!(help).ok();
 	writeln!(help, "\t--serial").ok();
 	writeln!(help, "\t\tWhen used with --linewise, operates on each line sequentially instead of using multi-threading.").ok();
 	writeln!(help, "\t\tNote that the order of lines is maintained regardless of whether or not multi-threading is used.").ok();
 	writeln!(help).ok();
-	writeln!(help, "\t--jobs").ok();
-	writeln!(help, "\t\tWhen used with --linewise, limits the number of threads that the program can use.").ok();
+	writeln!(help, "\t--shared-registers").ok();
+	writeln!(help, "\t\tWhen files or --linewise records are processed in parallel, each one normally starts with empty registers.").ok();
+	writeln!(help, "\t\tWith this flag, every thread reads and writes the same set of registers instead, one access at a time.").ok();
+	writeln!(help, "\t\tWhich record sees which register contents then depends on the order that threads happen to run in.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--deterministic").ok();
+	writeln!(help, "\t\tMake every run over the same input produce the same bytes, for comparing output in CI.").ok();
+	writeln!(help, "\t\tRecords are handed to a single worker in order, so errors and shell commands always happen in the same order.").ok();
+	writeln!(help, "\t\tRegisters stay separate for each record, like they are by default, unless --serial is given.").ok();
+	writeln!(help, "\t\tCan't be combined with --shared-registers, --regex-timeout, or --shell-timeout.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t-J, --jobs <N>").ok();
+	writeln!(help, "\t\tLimit the number of threads used to work on records with --linewise, or on files when several are given.").ok();
 	writeln!(help).ok();
 	writeln!(help, "\t--trim-fields").ok();
 	writeln!(help, "\t\tTrim leading and trailing whitespace from captured fields.").ok();
 	writeln!(help).ok();
 	writeln!(help, "\t-i").ok();
 	writeln!(help, "\t\tEdit given files in-place.").ok();
+	writeln!(help, "\t\tEach file is locked with flock while it is read and written. If another process already holds a lock, vicut stops before editing anything.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--out-template <TEMPLATE>").ok();
+	writeln!(help, "\t\tWrite each input file's output to its own file instead of stdout. The path is TEMPLATE with '{{name}}', '{{stem}}', and '{{ext}}'").ok();
+	writeln!(help, "\t\treplaced by the input file's name, its name without the extension, and its extension, e.g. '{{stem}}.out.{{ext}}'.").ok();
+	writeln!(help, "\t\tRelative paths are placed next to the input file, the same as backups.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--cache <FILE>").ok();
+	writeln!(help, "\t\tWith -i, remember what each file looked like after it was edited in FILE, and skip the files that haven't changed").ok();
+	writeln!(help, "\t\tsince the last run with the same commands and options.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--wait").ok();
+	writeln!(help, "\t\tWith -i, wait for files that are locked by another process instead of stopping.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--no-clobber").ok();
+	writeln!(help, "\t\tWith -i, skip files that are locked by another process, with a warning, and edit the rest.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t-R, --recursive").ok();
+	writeln!(help, "\t\tRead every file under the directories given as arguments. Files matched by .gitignore or .ignore files are skipped,").ok();
+	writeln!(help, "\t\tas are hidden files and directories, the same way ripgrep skips them.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--hidden").ok();
+	writeln!(help, "\t\tInclude hidden files and directories when walking directories with -R.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--files-from <FILE>").ok();
+	writeln!(help, "\t\tRead the list of files to operate on from FILE, one per line, or from stdin if FILE is '-'.").ok();
+	writeln!(help, "\t\tIn the list of files, '-' reads the input from stdin, at that position in the list.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--max-filesize <SIZE>").ok();
+	writeln!(help, "\t\tSkip files larger than SIZE, with a warning. SIZE is a number of bytes, and may end in K, M, or G.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--text").ok();
+	writeln!(help, "\t\tRead files that look like binary files. Otherwise, files with a NUL byte near the start are skipped, with a warning.").ok();
+	writeln!(help, "\t\tImplied by --read0.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--git <changed|staged|ls-files>").ok();
+	writeln!(help, "\t\tAdd files from the git working tree to the list of files: the ones that differ from HEAD, the ones with staged changes,").ok();
+	writeln!(help, "\t\tor every tracked file. Only files under the current directory are included.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--dry-run").ok();
+	writeln!(help, "\t\tLike -i, but print a unified diff of what would change in each file instead of writing to it.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--patch").ok();
+	writeln!(help, "\t\tLike --dry-run, but the diffs are written for 'git apply' and 'patch -p1', with paths relative to the current directory.").ok();
 	writeln!(help).ok();
 	writeln!(help, "\t--backup").ok();
 	writeln!(help, "\t\tIf editing files in-place, create a backup first.").ok();
@@ -503,9 +1895,145 @@ fn get_help() -> String {
 	writeln!(help, "\t--backup-extension").ok();
 	writeln!(help, "\t\tIf --backup is set, use the given file extension. Default is '.bak'").ok();
 	writeln!(help).ok();
+	writeln!(help, "\t--capture-line").ok();
+	writeln!(help, "\t--capture-word").ok();
+	writeln!(help, "\t--capture-lineno").ok();
+	writeln!(help, "\t\tAutomatically add the cursor's current line, word, or line number to each record").ok();
+	writeln!(help, "\t\tas the named fields 'line', 'word', and 'lineno'.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--shell-timeout <SECS>").ok();
+	writeln!(help, "\t\tKill shell commands run by ':!', ':r !', ':w !', and '=' if they take longer than SECS seconds.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--regex-timeout <SECS>").ok();
+	writeln!(help, "\t\tGive up on a record if evaluating it takes longer than SECS seconds. The record is reported and skipped,").ok();
+	writeln!(help, "\t\tfiles being edited in place are left untouched, and vicut exits with a non-zero code.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--regex-size-limit <BYTES>").ok();
+	writeln!(help, "\t\tRefuse to compile any regex that would take up more than BYTES bytes of memory. Defaults to 10MiB.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--shell <CMD>").ok();
+	writeln!(help, "\t\tRun ':!', ':r !', ':w !', and '=' with CMD instead of '$SHELL'. CMD can include arguments, which go before '-c', e.g. 'bash -eu'.").ok();
+	writeln!(help, "\t\tThe shell_args() function in vic scripts runs a program directly from a list of arguments, without any shell at all.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--shell-cwd <DIR|filedir>").ok();
+	writeln!(help, "\t\tRun shell commands in DIR instead of the current working directory. 'filedir' runs them in the directory of the file").ok();
+	writeln!(help, "\t\tthat each record came from, so that relative paths mean the same thing in every file. Records from stdin use the current directory.").ok();
+	writeln!(help, "\t\tScripts can also get that directory from the '$filedir' variable. Use './filedir' for a directory that is actually named 'filedir'.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--eol <lf|crlf>").ok();
+	writeln!(help, "\t\tConvert all line endings in the output to the given style. This includes files edited in-place.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--flush <never|record|line>").ok();
+	writeln!(help, "\t\tWhen output is written out. 'line' writes every line as soon as it is ready, 'record' writes after each file, or each batch of records with '--stream',").ok();
+	writeln!(help, "\t\tand 'never' waits until the output buffer is full. Defaults to 'line' for a terminal, and 'record' otherwise.").ok();
+	writeln!(help, "\t\tWith 'never', output from 'echo' can show up ahead of records that were produced before it.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--on-interrupt <finish|abort>").ok();
+	writeln!(help, "\t\tWhat to do with the records and files that are in progress when SIGINT or SIGTERM arrives. Nothing new is started either way.").ok();
+	writeln!(help, "\t\t'finish' lets them complete, and 'abort' stops them and leaves them out. Default is 'finish'.").ok();
+	writeln!(help, "\t\tOutput is cut off at the first record that didn't finish, files cut short aren't edited in place, and the --cache is saved.").ok();
+	writeln!(help, "\t\tvicut then prints a summary like 'vicut: interrupted: signal=SIGINT completed=120 total=500' and exits with 128 plus the signal number.").ok();
+	writeln!(help, "\t\tA second signal exits right away.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--stats").ok();
+	writeln!(help, "\t\tPrint a summary of the run to stderr as one line of JSON once vicut is done: how many records and files went in,").ok();
+	writeln!(help, "\t\thow many records and fields came out, bytes read and written, and the elapsed time in all and for each file.").ok();
+	writeln!(help, "\t\tWith --linewise, the number of records and the time spent on them is also broken down by thread.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--g-order <forward|reverse>").ok();
+	writeln!(help, "\t\tThe order that '-g'/'-v' scopes visit matching lines in. Default is 'reverse'. Matching lines are tracked as the scope edits the buffer, so matches that are deleted are skipped.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--units <bytes|chars|graphemes>").ok();
+	writeln!(help, "\t\tWhat a single position in the buffer refers to, for counted motions like '5l' and for column numbers.").ok();
+	writeln!(help, "\t\tDefaults to graphemes. Use bytes or chars for fixed-width data.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--window-height <LINES>").ok();
+	writeln!(help, "\t\tThe height of the virtual window used by 'H', 'M', 'L', Ctrl-E, and Ctrl-Y. Defaults to 24.").ok();
+	writeln!(help, "\t\tThe window starts at the top of the buffer, and scrolls as little as possible to keep the cursor in view.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--textwidth <COLUMNS>").ok();
+	writeln!(help, "\t\tThe width that 'gq' wraps lines to. Defaults to 79.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--field-newlines <keep|strip|escape>").ok();
+	writeln!(help, "\t\tWhat to do with the line endings in captured fields. 'keep' leaves them alone, 'strip' drops the one at the end").ok();
+	writeln!(help, "\t\tthat linewise motions like 'V' pick up, and 'escape' also writes any others as '\\n',").ok();
+	writeln!(help, "\t\twith backslashes doubled so that it can be undone. Defaults to keep.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--field-base <N>").ok();
+	writeln!(help, "\t\tThe number that unnamed fields start counting from, including the pieces from --fields-by. Defaults to 1.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--field-scope <group|record>").ok();
+	writeln!(help, "\t\tWhen the numbering of unnamed fields starts over. 'group' starts over after every '-n', and 'record'").ok();
+	writeln!(help, "\t\tkeeps counting through '-n' until the next record, or the next file. Defaults to group.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--fold-method <indent|marker>").ok();
+	writeln!(help, "\t\tHow folds are found for 'zj', 'zk', 'iz', and 'az'. 'indent' folds are blocks of lines indented past the line above them,").ok();
+	writeln!(help, "\t\tand 'marker' folds go from a line containing '{{{{{{' to a line containing '}}}}}}'. Defaults to indent.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--sub-syntax <vim|regex>").ok();
+	writeln!(help, "\t\tHow the replacement text of ':s' is read. 'vim' understands '\\0' through '\\9', '&' for the whole match, and '~' for the previous replacement,").ok();
+	writeln!(help, "\t\talong with the regex crate's '$1' and '${{name}}'. 'regex' only understands the regex crate's syntax. Defaults to vim.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--kv-separators <SEPS>").ok();
+	writeln!(help, "\t\tA whitespace separated list of the separators that split a key from its value for 'iv' and 'av'.").ok();
+	writeln!(help, "\t\tDefaults to '=> = :'.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--string-escape <CHAR>").ok();
+	writeln!(help, "\t\tThe character that escapes a quote inside of a string for 'iq' and 'aq'. Defaults to '\\'.").ok();
+	writeln!(help, "\t\tWhen it is the quote itself, as in SQL, a doubled quote is escaped.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--freq <N>").ok();
+	writeln!(help, "\t\tInstead of the usual output, list the N most frequent tokens in each buffer, after the commands have run.").ok();
+	writeln!(help, "\t\tEach record holds the fields 'count' and 'token'.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--freq-pattern <REGEX>").ok();
+	writeln!(help, "\t\tThe pattern that matches a single token for '--freq'. Defaults to '\\w+'.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--verify-roundtrip <CHECKS>").ok();
+	writeln!(help, "\t\tBefore writing an in-place edit, make sure that it didn't change the file more than expected.").ok();
+	writeln!(help, "\t\tCHECKS is a comma separated list of 'lines' or 'bytes', optionally followed by how much they may change,").ok();
+	writeln!(help, "\t\te.g. 'lines,bytes:64'. Files that fail a check are left untouched, and vicut exits with a non-zero code.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--bom <strip|keep>").ok();
+	writeln!(help, "\t\tStrip a leading UTF-8 byte order mark from the output, or keep it as-is. Default is 'keep'.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--equalprg <CMD>").ok();
+	writeln!(help, "\t\tUse CMD as the formatter program for the '=' operator. Overrides '$EQUALPRG'.").ok();
+	writeln!(help, "\t\tIf neither are set, a default is picked from the file extension, e.g. 'jq .' for .json files.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--sandbox").ok();
+	writeln!(help, "\t\tDisallow anything that reaches outside of the buffer: shell commands, '=', ':r', ':w',").ok();
+	writeln!(help, "\t\tin-place edits, and the env() function. Useful for running untrusted scripts.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--no-shell").ok();
+	writeln!(help, "\t\tMake ':!', ':r !', ':w !', and '=' fail instead of spawning a shell. Unlike --sandbox, files can still be read,").ok();
+	writeln!(help, "\t\twritten, and edited in place. A script can't turn this back off.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--record-per-match").ok();
+	writeln!(help, "\t\tEach line matched by -g/-v gets its own record, as if -n was given after its commands.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--pick").ok();
+	writeln!(help, "\t\tWhen a -g/-v pattern matches more than one line, choose which matches to operate on").ok();
+	writeln!(help, "\t\tusing an interactive, filterable picker. Without a terminal, every match is used.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--build-info, --version --verbose").ok();
+	writeln!(help, "\t\tPrint the version, git commit, target, enabled features, and dependency versions as JSON").ok();
+	writeln!(help).ok();
 	writeln!(help, "\t--trace").ok();
 	writeln!(help, "\t\tPrint debug trace of command execution").ok();
 	writeln!(help).ok();
+	writeln!(help, "\t--assert <TEMPLATE>").ok();
+	writeln!(help, "\t\tCheck every record against a template. '{{{{name}}}}' requires a non-empty field called 'name',").ok();
+	writeln!(help, "\t\tand '{{{{name:REGEX}}}}' requires the whole field to match REGEX. Failures are summarized at the end,").ok();
+	writeln!(help, "\t\tand make vicut exit with a non-zero code. Can be given more than once.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--emit-schema").ok();
+	writeln!(help, "\t\tInstead of reading any input, print a JSON Schema describing the records that the given commands produce.").ok();
+	writeln!(help, "\t\tFields that are only captured under -g/-v, a loop, or a condition are not marked as required.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--dump-state").ok();
+	writeln!(help, "\t\tOnce the commands are done with a buffer, print every variable and named register to stderr").ok();
+	writeln!(help).ok();
 	writeln!(help).ok();
 	writeln!(help, "\x1b[1;4mCOMMANDS:\x1b[0m").ok();
 	writeln!(help, "\t-c, --cut [name=<NAME>] <VIM_COMMAND>").ok();
@@ -521,6 +2049,13 @@ fn get_help() -> String {
 	writeln!(help, "\t--end").ok();
 	writeln!(help, "\t\tEnds a '-g'/'-v' subscope, allowing you to continue writing commands in the non-conditional outer scope").ok();
 	writeln!(help).ok();
+	writeln!(help, "\t--max-matches <N>").ok();
+	writeln!(help, "\t\tOnly execute a '-g'/'-v' subscope on the first N matching lines. The rest of the matches are skipped.").ok();
+	writeln!(help).ok();
+	writeln!(help, "\t--context <N>").ok();
+	writeln!(help, "\t\tA '-g'/'-v' nested inside of another one only looks at the outer scope's matching line.").ok();
+	writeln!(help, "\t\tGiving '--context' to the outer scope lets nested scopes see N lines above and below each match as well.").ok();
+	writeln!(help).ok();
 	writeln!(help, "\t-m, --move <VIM_COMMAND>").ok();
 	writeln!(help, "\t\tLogically identical to -c/--cut, except it does not capture a field.").ok();
 	writeln!(help).ok();
@@ -530,6 +2065,11 @@ fn get_help() -> String {
 	writeln!(help, "\t-n, --next").ok();
 	writeln!(help, "\t\tStart a new field group. Each field group becomes one output record.").ok();
 	writeln!(help).ok();
+	writeln!(help, "\t--program <GLOB>").ok();
+	writeln!(help, "\t\tThe commands that follow only run on files whose path matches GLOB, up to the next '--program'.").ok();
+	writeln!(help, "\t\tEach file runs the first program that matches it. Stdin, and files that no program matches,").ok();
+	writeln!(help, "\t\trun the commands given before the first '--program'.").ok();
+	writeln!(help).ok();
 	writeln!(help).ok();
 	writeln!(help, "\x1b[1;4mNOTES:\x1b[0m").ok();
 	writeln!(help, "\t* Commands are executed left to right.").ok();
@@ -582,19 +2122,96 @@ fn init_logger(trace: bool) {
 /// Format the stuff we extracted according to user specification
 ///
 /// `lines` is a two-dimensional vector of tuples, each representing a key/value pair for extract fields.
-fn format_output(args: &Opts, lines: Vec<Vec<(String,String)>>) -> String {
-	if args.json {
+fn format_output(args: &Opts, lines: Vec<Vec<(String,FieldVal)>>) -> String {
+	let output = if args.json {
 		Ok(format_output_json(lines))
+	} else if let Some(format) = args.format && !only_whole_buffers(&lines) {
+		Ok(format_output_table(format, lines, &mut vec![]))
 	} else if let Some(template) = args.template.as_deref() {
-		format_output_template(template, lines)
+		format_output_template(template, args.record_separator(), lines)
 	} else {
-		let delimiter = args.delimiter.as_deref().unwrap_or(" ");
-		Ok(format_output_standard(delimiter, lines))
-	}.unwrap_or_else(complain_and_exit)
+		Ok(format_output_standard(args, lines))
+	}.unwrap_or_else(complain_and_exit);
+	finalize_output(args, output)
+}
+
+/// Apply `--eol` and `--bom` to a chunk of output.
+///
+/// Everything we print or write back to a file passes through here, so that the settings are applied uniformly.
+fn finalize_output(args: &Opts, mut output: String) -> String {
+	if args.strip_bom && let Some(stripped) = output.strip_prefix('\u{feff}') {
+		output = stripped.to_string();
+	}
+	match args.eol {
+		Some(Eol::Lf) => output.replace("\r\n", "\n"),
+		Some(Eol::Crlf) => output.replace("\r\n", "\n").replace('\n', "\r\n"),
+		None => output
+	}
+}
+
+/// Format the output as JSON Lines, one compact object per record
+///
+/// Used by `--stream`, since a single JSON array can't be written out a piece at a time.
+fn format_output_json_lines(args: &Opts, lines: Vec<Vec<(String,FieldVal)>>) -> String {
+	let mut output = String::new();
+	for fields in lines.into_iter().filter(|fields| !fields.is_empty()) {
+		let mut obj = fields.into_iter()
+			.map(|(name,field)| (name, Value::from(field)))
+			.collect::<Map<_,_>>();
+		obj.sort_keys();
+		write!(output, "{}{}", Value::Object(obj), args.record_separator()).ok();
+	}
+	output
+}
+
+/// Format the output as a CSV or TSV table
+///
+/// The columns are every field name found in the records, in the order that they first appear, and they are listed in a header row.
+/// If `columns` already has some names in it, they are used as they are and no header is written,
+/// which lets `--stream` write one table a piece at a time. Fields that aren't among them are left out.
+/// Records that are missing one of the fields leave its cell empty.
+fn format_output_table(format: OutputFormat, lines: Vec<Vec<(String,FieldVal)>>, columns: &mut Vec<String>) -> String {
+	let escape = |cell: &str| match format {
+		OutputFormat::Csv if cell.contains([',', '"', '\n', '\r']) => format!("\"{}\"", cell.replace('"', "\"\"")),
+		OutputFormat::Csv => cell.to_string(),
+		OutputFormat::Tsv => cell.replace('\\', "\\\\")
+			.replace('\t', "\\t")
+			.replace('\n', "\\n")
+			.replace('\r', "\\r"),
+	};
+	let (sep, eol) = match format {
+		OutputFormat::Csv => (",", "\r\n"),
+		OutputFormat::Tsv => ("\t", "\n"),
+	};
+	let mut output = String::new();
+	if columns.is_empty() {
+		for (name,_) in lines.iter().flatten() {
+			if !columns.contains(name) {
+				columns.push(name.clone());
+			}
+		}
+		if columns.is_empty() {
+			return output
+		}
+		let header = columns.iter().map(|name| escape(name)).collect::<Vec<_>>().join(sep);
+		write!(output, "{header}{eol}").ok();
+	}
+	for fields in lines.iter().filter(|fields| !fields.is_empty()) {
+		let row = columns.iter()
+			.map(|name| {
+				fields.iter()
+					.find(|(field_name,_)| field_name == name)
+					.map(|(_,field)| escape(&field.to_string()))
+					.unwrap_or_default()
+			}).collect::<Vec<_>>()
+			.join(sep);
+		write!(output, "{row}{eol}").ok();
+	}
+	output
 }
 
 /// Format the output as JSON
-fn format_output_json(lines: Vec<Vec<(String,String)>>) -> String {
+fn format_output_json(lines: Vec<Vec<(String,FieldVal)>>) -> String {
 	if lines.is_empty() || lines.iter().all(|line| line.is_empty()) {
 		return String::new();
 	}
@@ -603,8 +2220,9 @@ fn format_output_json(lines: Vec<Vec<(String,String)>>) -> String {
 		.map(|fields| {
 			let mut obj = Map::new();
 			for (name,field) in fields {
-				obj.insert(name, Value::String(field));
+				obj.insert(name, Value::from(field));
 			}
+			obj.sort_keys();
 			Value::Object(obj)
 		}).collect();
 
@@ -612,7 +2230,7 @@ fn format_output_json(lines: Vec<Vec<(String,String)>>) -> String {
 	serde_json::to_string_pretty(&json).unwrap()
 }
 
-type Files = Vec<(PathBuf, Vec<Vec<(String,String)>>)>; // YEESH
+type Files = Vec<(PathBuf, Vec<Vec<(String,FieldVal)>>)>; // YEESH
 fn format_output_json_files(files: Files) -> String {
 	let mut array = vec![];
 	for (path, content) in files {
@@ -625,30 +2243,41 @@ fn format_output_json_files(files: Files) -> String {
 			.map(|fields| {
 				let mut obj = Map::new();
 				for (name,field) in fields {
-					obj.insert(name, Value::String(field));
+					obj.insert(name, Value::from(field));
 				}
 				Value::Object(obj)
 			}).collect();
 		obj.insert("__content__".into(), Value::Array(array_content));
 		array.push(Value::Object(obj));
 	}
-	let json = Value::Array(array);
+	let mut json = Value::Array(array);
+	json.sort_all_objects();
 	serde_json::to_string_pretty(&json).unwrap()
 }
 
 /// Check to see if we didn't explicitly extract any fields
 ///
-/// Checks for the `"0"` field name, which is a sentinel value that says "We didn't get any `-c` commands"
-/// This can be depended on, since `"0"` is a reserved field name that cannot be set by user input.
-fn no_fields_extracted(lines: &[Vec<(String,String)>]) -> bool {
-	lines.len() == 1 && lines.first().is_some_and(|record| record.len() == 1 && record.first().is_some_and(|field| field.0 == "0"))
+/// Checks for a lone `FieldVal::Buffer`, which stands in for the fields when none were captured.
+/// Going by the `"0"` field name instead would mistake a field numbered from `--field-base 0` for it.
+fn no_fields_extracted(lines: &[Vec<(String,FieldVal)>]) -> bool {
+	lines.len() == 1 && lines.first().is_some_and(|record| record.len() == 1 && record.first().is_some_and(|field| matches!(field.1, FieldVal::Buffer(_))))
+}
+
+/// Whether every record is just its whole buffer, because no fields were extracted from any of them.
+///
+/// A table of those would have a single column named `"0"`, so `--format` prints the buffers as they are instead.
+fn only_whole_buffers(lines: &[Vec<(String,FieldVal)>]) -> bool {
+	!lines.is_empty() && lines.iter().all(|record| no_fields_extracted(std::slice::from_ref(record)))
 }
 
 /// Perform standard output formatting.
 ///
 /// If we didn't extract any fields, we do our best to preserve the formatting of the original input
 /// If we did extract some fields, we print each record one at a time, and each field will be separated by `delimiter`
-fn format_output_standard(delimiter: &str, mut lines: Vec<Vec<(String,String)>>) -> String {
+/// Each record ends with `record_sep`, which replaces any newline or NUL that the record already ended with.
+fn format_output_standard(args: &Opts, mut lines: Vec<Vec<(String,FieldVal)>>) -> String {
+	let delimiter = args.delimiter.as_deref().unwrap_or(" ");
+	let record_sep = args.record_separator();
 	// Let's check to see if we are outputting the whole buffer
 	if no_fields_extracted(&lines)  {
 		// We performed len checks in no_fields_extracted(), so unwrap is safe
@@ -658,13 +2287,14 @@ fn format_output_standard(delimiter: &str, mut lines: Vec<Vec<(String,String)>>)
 			.pop()
 			.unwrap()
 			.1
+			.into_text()
 	} else {
 		let mut fields = vec![];
 		let mut records = vec![];
 		let mut output = String::new();
 		for line in lines {
 			for field in line {
-				fields.push(field.1);
+				fields.push(field.1.into_text());
 			}
 			// Join the fields by the delimiter
 			// Also clear fields for the next line
@@ -673,11 +2303,8 @@ fn format_output_standard(delimiter: &str, mut lines: Vec<Vec<(String,String)>>)
 			records.push(record);
 		}
 		for record in records {
-			if record.ends_with('\n') {
-				write!(output, "{record}").ok();
-			} else {
-				writeln!(output,"{record}").ok();
-			}
+			let record = record.strip_suffix(['\n','\0']).unwrap_or(&record);
+			write!(output, "{record}{record_sep}").ok();
 		}
 		output
 	}
@@ -687,7 +2314,7 @@ fn format_output_standard(delimiter: &str, mut lines: Vec<Vec<(String,String)>>)
 ///
 /// We use a state machine here to interpolate the fields
 /// The loop looks for patterns like {{1}} or {{foo}} to interpolate on
-fn format_output_template(template: &str, lines: Vec<Vec<(String,String)>>) -> Result<String,String> {
+fn format_output_template(template: &str, record_sep: &str, lines: Vec<Vec<(String,FieldVal)>>) -> Result<String,String> {
 	let mut field_name = String::new();
 	let mut output = String::new();
 	let mut cur_line = String::new();
@@ -719,13 +2346,10 @@ fn format_output_template(template: &str, lines: Vec<Vec<(String,String)>>) -> R
 						}
 					}
 					if closed {
-						let result = line
-							.iter()
-							.find(|(name,_)| name == &field_name)
-							.map(|(_,field)| field);
+						let result = template_field(&line, &field_name)?;
 
 						if let Some(field) = result {
-							cur_line.push_str(field);
+							cur_line.push_str(&field);
 						} else {
 							let mut e = String::new();
 							writeln!(e,"Did not find a field called '{field_name}' for output template").ok();
@@ -733,59 +2357,512 @@ fn format_output_template(template: &str, lines: Vec<Vec<(String,String)>>) -> R
 							for (name,_) in line {
 								writeln!(e,"\t{name}").ok();
 							}
-							return Err(e)
+							return Err(e)
+						}
+					} else {
+						cur_line.extend(field_name.drain(..));
+					}
+					field_name.clear();
+				}
+				_ => cur_line.push(ch)
+			}
+		}
+		if !cur_line.is_empty() {
+			write!(output, "{}{record_sep}", std::mem::take(&mut cur_line)).ok();
+		}
+	}
+	Ok(output)
+}
+
+/// Look up the value of a `{{...}}` placeholder in an output template.
+///
+/// `{{name[N]}}` picks out line `N` of a field, counting from zero, which is mostly useful for the pieces of a visual block.
+/// A field that is really called `name[N]` still wins, though.
+fn template_field(record: &[(String,FieldVal)], placeholder: &str) -> Result<Option<String>,String> {
+	let find = |name: &str| record.iter().find(|(field_name,_)| field_name == name).map(|(_,field)| field);
+	if let Some(field) = find(placeholder) {
+		return Ok(Some(field.to_string()))
+	}
+	let Some((name, idx)) = placeholder.strip_suffix(']')
+		.and_then(|rest| rest.rsplit_once('['))
+		.and_then(|(name, idx)| Some((name, idx.parse::<usize>().ok()?))) else {
+		return Ok(None)
+	};
+	let Some(field) = find(name) else {
+		return Ok(None)
+	};
+	let lines = field.lines();
+	match lines.get(idx) {
+		Some(line) => Ok(Some(line.to_string())),
+		None => Err(format!("Field '{name}' only has {} line(s), so there is no line {idx} for output template\n", lines.len()))
+	}
+}
+
+/// Describe the records that the command program would produce, as a JSON Schema.
+///
+/// This only looks at the commands, so fields under a -g/-v scope, a loop, or a condition are listed but not required.
+/// Each `-n` group gets a schema of its own under `anyOf`, unless the places where records are split can't be known ahead of time.
+fn record_schema(args: &Opts) -> Value {
+	let mut fields = SchemaFields::default();
+	collect_schema_fields(&args.cmds, false, &mut 0, &mut fields, args);
+	if fields.fields.is_empty() {
+		// With no fields, the whole buffer comes out as field '0'
+		fields.fields.push(SchemaField { name: "0".into(), required: true, is_block: false, group: 0 });
+	}
+
+	let mut groups: Vec<Vec<SchemaField>> = vec![];
+	if fields.unknown_groups {
+		// Any of the fields could end up in any record
+		groups.push(fields.fields.into_iter().map(|field| SchemaField { required: false, ..field }).collect());
+	} else {
+		let mut fields = fields.fields;
+		fields.sort_by_key(|field| field.group);
+		// Groups that capture nothing don't make a record
+		groups.extend(fields.chunk_by(|a, b| a.group == b.group).map(|group| group.to_vec()));
+	}
+	let mut records = groups.into_iter()
+		.map(|group| group_schema(group, args))
+		.collect::<Vec<_>>();
+	let items = if records.len() == 1 {
+		records.pop().unwrap()
+	} else {
+		serde_json::json!({ "anyOf": records })
+	};
+
+	let mut schema = Map::new();
+	schema.insert("$schema".into(), Value::String("https://json-schema.org/draft/2020-12/schema".into()));
+	schema.insert("type".into(), Value::String("array".into()));
+	schema.insert("items".into(), items);
+	Value::Object(schema)
+}
+
+/// The schema for the records made by one `-n` group
+fn group_schema(mut fields: Vec<SchemaField>, args: &Opts) -> Value {
+	let context_fields = [
+		(args.capture_lineno, "lineno"),
+		(args.capture_line, "line"),
+		(args.capture_word, "word"),
+	];
+	for (enabled, name) in context_fields {
+		if enabled {
+			fields.push(SchemaField { name: name.to_string(), required: true, is_block: false, group: 0 });
+		}
+	}
+
+	let mut properties = Map::new();
+	let mut required = vec![];
+	for SchemaField { name, required: is_required, is_block, .. } in fields {
+		if is_required && !required.contains(&Value::String(name.clone())) {
+			required.push(Value::String(name.clone()));
+		}
+		let mut field = Map::new();
+		if is_block {
+			// Visual blocks come out as an array of their lines
+			field.insert("type".into(), Value::String("array".into()));
+			field.insert("items".into(), serde_json::json!({ "type": "string" }));
+		} else {
+			field.insert("type".into(), Value::String("string".into()));
+		}
+		properties.insert(name, Value::Object(field));
+	}
+	let mut record = Map::new();
+	record.insert("type".into(), Value::String("object".into()));
+	record.insert("properties".into(), Value::Object(properties));
+	record.insert("required".into(), Value::Array(required));
+	// Fields named after line numbers can't be known ahead of time
+	record.insert("additionalProperties".into(), Value::Bool(args.global_uses_line_numbers));
+	Value::Object(record)
+}
+
+/// A field that the command program captures, as seen by `--emit-schema`
+#[derive(Clone,Debug)]
+struct SchemaField {
+	name: String,
+	required: bool,
+	/// A literal motion that selects a visual block
+	is_block: bool,
+	/// Which `-n` group the field is captured in
+	group: usize
+}
+
+#[derive(Default,Debug)]
+struct SchemaFields {
+	fields: Vec<SchemaField>,
+	/// The `-n` group that the next field goes in
+	group: usize,
+	/// Set when records are split up somewhere that can't be known ahead of time, like a `-n` under a condition
+	unknown_groups: bool
+}
+
+impl SchemaFields {
+	fn push(&mut self, name: String, required: bool, is_block: bool) {
+		self.fields.push(SchemaField { name, required, is_block, group: self.group });
+	}
+}
+
+/// Walk the commands in the order they would run, collecting the names of the fields they capture.
+///
+/// `conditional` is true for commands that might not run at all.
+fn collect_schema_fields(cmds: &[Cmd], conditional: bool, field_num: &mut usize, fields: &mut SchemaFields, args: &Opts) {
+	let selects_block = |motion: &CmdArg| matches!(motion, CmdArg::Literal(Val::Str(motion)) if motion.to_lowercase().contains("<c-v>"));
+	for cmd in cmds {
+		match cmd {
+			Cmd::Field(motion) => {
+				*field_num += 1;
+				fields.push(args.field_name(*field_num), !conditional, selects_block(motion));
+			}
+			Cmd::NamedField(name, motion) => {
+				*field_num += 1;
+				fields.push(name.clone(), !conditional, selects_block(motion));
+			}
+			Cmd::PatternFields(CmdArg::Literal(Val::Regex(regex))) => {
+				// Nothing is captured when the pattern doesn't match, so none of these are guaranteed
+				for name in regex.capture_names().flatten() {
+					*field_num += 1;
+					fields.push(name.to_string(), false, false);
+				}
+			}
+			Cmd::BreakGroup => {
+				if args.field_scope == FieldScope::Group {
+					*field_num = 0;
+				}
+				if conditional {
+					fields.unknown_groups = true;
+				} else {
+					fields.group += 1;
+				}
+			}
+			Cmd::Repeat { body, count } => {
+				match count {
+					CmdArg::Count(count) => {
+						for _ in 0..*count {
+							collect_schema_fields(body, conditional, field_num, fields, args);
+						}
+					}
+					_ => collect_schema_fields(body, true, field_num, fields, args)
+				}
+			}
+			Cmd::Global { then_cmds, else_cmds, .. } => {
+				if args.global_uses_line_numbers {
+					// Field names come from the matched line numbers
+					continue
+				}
+				let mut then_num = *field_num;
+				if args.record_per_match {
+					// Every match starts a record of its own
+					fields.unknown_groups = true;
+					if args.field_scope == FieldScope::Group {
+						then_num = 0;
+					}
+				}
+				collect_schema_fields(then_cmds, true, &mut then_num, fields, args);
+				if let Some(else_cmds) = else_cmds {
+					collect_schema_fields(else_cmds, true, &mut field_num.clone(), fields, args);
+				}
+			}
+			Cmd::IfBlock { cond_blocks, else_block } => {
+				for block in cond_blocks {
+					collect_schema_fields(&block.cmds, true, &mut field_num.clone(), fields, args);
+				}
+				if let Some(else_block) = else_block {
+					collect_schema_fields(else_block, true, &mut field_num.clone(), fields, args);
+				}
+			}
+			Cmd::MatchBlock { arms, .. } => {
+				for arm in arms {
+					collect_schema_fields(&arm.cmds, true, &mut field_num.clone(), fields, args);
+				}
+			}
+			Cmd::ForBlock { body, .. } => collect_schema_fields(body, true, &mut field_num.clone(), fields, args),
+			Cmd::WhileBlock(block) |
+			Cmd::UntilBlock(block) => collect_schema_fields(&block.cmds, true, &mut field_num.clone(), fields, args),
+			_ => {}
+		}
+	}
+}
+
+/// Check each record against the `--assert` templates, and record a failure for every expectation it misses.
+///
+/// Each `{{name}}` placeholder requires a non-empty field called 'name', and `{{name:REGEX}}` requires that the whole field matches REGEX.
+/// Text outside of the placeholders is ignored.
+fn check_record_asserts(asserts: &[String], records: &[Vec<(String,FieldVal)>], source: &str) -> Result<(),String> {
+	let mut expectations = vec![];
+	for template in asserts {
+		for placeholder in template_placeholders(template) {
+			let (name, pattern) = match placeholder.split_once(':') {
+				Some((name, pattern)) => {
+					let regex = new_regex(&format!("^(?:{pattern})$"))
+						.map_err(|e| format!("Invalid regex in --assert '{template}': {e}"))?;
+					(name.to_string(), Some((pattern.to_string(), regex)))
+				}
+				None => (placeholder, None)
+			};
+			expectations.push((name, pattern));
+		}
+	}
+	for (i, record) in records.iter().enumerate() {
+		for (name, pattern) in &expectations {
+			let field = record.iter().find(|(field_name,_)| field_name == name).map(|(_,field)| field.to_string());
+			let failure = match (field, pattern) {
+				(None, _) => format!("field '{name}' is missing"),
+				(Some(field), None) if field.is_empty() => format!("field '{name}' is empty"),
+				(Some(field), Some((pattern, regex))) if !regex.is_match(&field) => format!("field '{name}' does not match /{pattern}/: {field:?}"),
+				_ => continue
+			};
+			record_assert_failure(format!("{source}: record {}: {failure}", i + 1));
+		}
+	}
+	Ok(())
+}
+
+/// Pull the contents of each `{{...}}` out of a template.
+///
+/// Backslashes escape the next character, but are left in the placeholder's contents for the regex to interpret.
+fn template_placeholders(template: &str) -> Vec<String> {
+	let mut placeholders = vec![];
+	let mut chars = template.chars().peekable();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' => { chars.next(); }
+			'{' if chars.peek() == Some(&'{') => {
+				chars.next();
+				let mut placeholder = String::new();
+				while let Some(ch) = chars.next() {
+					match ch {
+						'\\' => {
+							// Kept as-is, so that regexes like '\d+' survive
+							placeholder.push(ch);
+							if let Some(esc_ch) = chars.next() {
+								placeholder.push(esc_ch)
+							}
 						}
-					} else {
-						cur_line.extend(field_name.drain(..));
+						'}' if chars.peek() == Some(&'}') => {
+							chars.next();
+							placeholders.push(std::mem::take(&mut placeholder));
+							break
+						}
+						_ => placeholder.push(ch)
 					}
-					field_name.clear();
 				}
-				_ => cur_line.push(ch)
 			}
+			_ => {}
 		}
-		if !cur_line.is_empty() {
-			writeln!(output,"{}",std::mem::take(&mut cur_line)).ok();
+	}
+	placeholders
+}
+
+/// The records captured from one record or file
+type Captures = Vec<Vec<(String,FieldVal)>>;
+
+/// Run `execute()` on one record or file, unless SIGINT or SIGTERM says that it shouldn't start, or shouldn't finish.
+///
+/// `None` means that it was left undone, so it has to be left out of the output.
+fn execute_unit(args: &Opts, input: String, filename: Option<PathBuf>) -> Option<Result<Captures,String>> {
+	if interrupt::interrupted() || output_closed() {
+		return None
+	}
+	let start = Instant::now();
+	let input_len = input.len();
+	let path = filename.clone().filter(|_| stats::enabled());
+	match execute(args, input, filename) {
+		Err(_) if interrupt::aborting() => None,
+		result => {
+			if let Ok(records) = &result {
+				stats::record_unit(path.as_deref(), input_len, records, start);
+			}
+			Some(result)
 		}
 	}
-	Ok(output)
+}
+
+/// Execute the user's commands on a single buffer of input, or on one field of it with `--json-input`.
+fn execute(args: &Opts, input: String, filename: Option<PathBuf>) -> Result<Vec<Vec<(String,FieldVal)>>,String> {
+	let cmds = args.program_for(filename.as_deref());
+	match &args.json_field {
+		Some(field) if args.json_input => execute_json_record(args, cmds, field, input, filename),
+		_ => execute_buffer(args, cmds, input, filename)
+	}
+}
+
+/// Execute the commands on the value of `field` in a JSON record, for `--json-input`.
+///
+/// Each result is the record written back out as a single line of JSON.
+/// If nothing was captured, the edited buffer replaces the field's value. Otherwise the captured fields are added to the record,
+/// and every set of captured fields (e.g. from `-n` or `record_per_match`) gets its own copy of the record.
+/// Strings are edited as they are, and any other kind of value is edited as its JSON text.
+/// Records without the field are passed through untouched.
+/// Keys stay in the order they were in, and values that weren't changed are written exactly as they were.
+fn execute_json_record(args: &Opts, cmds: &[Cmd], field: &str, input: String, filename: Option<PathBuf>) -> Result<Vec<Vec<(String,FieldVal)>>,String> {
+	let input = input.trim_end_matches('\0');
+	if input.trim().is_empty() {
+		return Ok(vec![])
+	}
+	let record = match serde_json::from_str(input) {
+		Ok(Value::Object(record)) => record,
+		Ok(_) => return Err(format!("expected a JSON object, found '{}'", input.trim())),
+		Err(e) => return Err(format!("invalid JSON record '{}': {e}", input.trim())),
+	};
+	let value = match record.get(field) {
+		Some(Value::String(value)) => value.clone(),
+		Some(value) => value.to_string(),
+		None => return Ok(vec![vec![("0".into(), FieldVal::Buffer(input.trim().into()))]])
+	};
+	// The text of each value, as it was written
+	let raw: BTreeMap<String,&RawValue> = serde_json::from_str(input)
+		.map_err(|e| format!("invalid JSON record '{}': {e}", input.trim()))?;
+	let output = execute_buffer(args, cmds, value, filename)?;
+	Ok(output.into_iter()
+		.map(|fields| {
+			let changes = if no_fields_extracted(std::slice::from_ref(&fields)) {
+				let edited = fields.into_iter().next().unwrap().1.into_text();
+				vec![(field.to_string(), edited_json_value(&record[field], edited))]
+			} else {
+				fields.into_iter().map(|(name,value)| (name, Value::from(value))).collect()
+			};
+			vec![("0".into(), FieldVal::Buffer(write_json_record(&record, &raw, changes)))]
+		})
+		.collect())
+}
+
+/// An edited number or boolean keeps its JSON type, as long as what it was edited into still reads as one
+fn edited_json_value(original: &Value, edited: String) -> Value {
+	match (original, serde_json::from_str(edited.trim())) {
+		(Value::Number(_), Ok(Value::Number(num))) => Value::Number(num),
+		(Value::Bool(_), Ok(Value::Bool(yn))) => Value::Bool(yn),
+		_ => Value::String(edited)
+	}
+}
+
+/// Write a JSON Lines record back out, with `changes` replacing the values it had, or added to the end of it.
+///
+/// Everything else is copied from `raw` as it was, so numbers like `2.50` don't come out as `2.5`.
+fn write_json_record(record: &Map<String,Value>, raw: &BTreeMap<String,&RawValue>, mut changes: Vec<(String,Value)>) -> String {
+	let mut members = vec![];
+	for key in record.keys() {
+		let value = match changes.iter().position(|(name,_)| name == key) {
+			Some(idx) => changes.remove(idx).1.to_string(),
+			None => raw[key].get().to_string()
+		};
+		members.push(format!("{}:{value}", Value::String(key.clone())));
+	}
+	for (name, value) in changes {
+		members.push(format!("{}:{value}", Value::String(name)));
+	}
+	format!("{{{}}}", members.join(","))
 }
 
 /// Execute the user's commands.
 ///
 /// Here we are going to initialize a new instance of `ViCut` to manage state for editing this input
-/// Next we loop over `args.cmds` and execute each one in sequence.
-fn execute(args: &Opts, input: String, filename: Option<PathBuf>) -> Result<Vec<Vec<(String,String)>>,String> {
-	let fields: Vec<(String,String)> = vec![];
-	let fmt_lines: Vec<Vec<(String,String)>> = vec![];
-
+/// Next we loop over `cmds` and execute each one in sequence.
+fn execute_buffer(args: &Opts, cmds: &[Cmd], input: String, filename: Option<PathBuf>) -> Result<Vec<Vec<(String,FieldVal)>>,String> {
+	// A record split off at a NUL keeps it, but unlike a newline the buffer would treat it as part of the text
+	let input = match input.strip_suffix('\0') {
+		Some(record) if args.read0 && args.record_sep.is_none() && (args.linewise || args.stream) => record.to_string(),
+		_ => input
+	};
+	let filetype = filetype::detect(filename.as_deref(), &input);
+	// Kept around in case the record gets thrown out
+	let original = args.edit_inplace.then(|| input.clone());
 	let mut vicut = ViCut::new(input, 0)?;
+	vicut.sandbox = args.sandbox;
+	vicut.set_settings(args.buf_settings.clone());
+	vicut.set_field_newlines(args.field_newlines);
+	// --equalprg wins over $EQUALPRG, which wins over the per-filetype default
+	let equalprg = args.equalprg.clone()
+		.or_else(|| std::env::var("EQUALPRG").ok())
+		.or_else(|| filetype.and_then(shell::default_equalprg).map(|prg| prg.to_string()));
+	vicut.set_shell_cfg(ShellCfg {
+		timeout: args.shell_timeout,
+		cwd: args.shell_cwd.as_ref().and_then(|cwd| cwd.resolve(filename.as_deref())),
+		equalprg,
+		disabled: args.no_shell,
+		shell: args.shell.clone(),
+	});
 	let basename = filename.clone()
 		.map(|s| s.file_name().unwrap_or_default().to_string_lossy().to_string())
 		.unwrap_or_else(|| String::from("stdin"));
+	let filedir = filename.as_deref().map_or_else(|| PathBuf::from("."), shell::file_dir);
 	let filepath = filename.map(|s| s.to_string_lossy().to_string()).unwrap_or(String::from("stdin"));
-	vicut.set_var("filename".into(), Val::Str(basename))?;
-	vicut.set_var("filepath".into(), Val::Str(filepath))?;
-
+	vicut.set_input_vars(InputVars {
+		filename: basename,
+		filepath: filepath.clone(),
+		filedir: filedir.to_string_lossy().to_string(),
+		filetype: filetype.unwrap_or_default().to_string(),
+	});
+	RECORD_DEADLINE.set(args.regex_timeout.map(|timeout| Instant::now() + timeout));
 
-	let field_num = 0;
-	let mut ctx = ExecCtx {
-		args: args.clone(),
-		field_num,
-		fields,
-		fmt_lines
-	};
-	for cmd in &args.cmds {
+	let mut ctx = ExecCtx::new(args);
+	if let Some(sep) = args.fields_by.as_ref() {
+		let pieces = split_fields(&vicut.current_buffer().buffer, sep, args);
+		vicut.set_builtin_var("fields".into(), Val::Arr(pieces.iter().cloned().map(Val::Str).collect()));
+		ctx.field_num = pieces.len();
+		ctx.pieces = pieces.into_iter()
+			.enumerate()
+			.map(|(i,piece)| (args.field_name(i + 1), piece.into()))
+			.collect();
+		ctx.fields = ctx.pieces.clone();
+	}
+	for cmd in cmds {
 		exec_cmd(
 			cmd,
 			&mut vicut,
 			&mut ctx
 		);
+		match ctx.unwind.take() {
+			// A top-level 'return' ends the script.
+			// Numbers become the exit code, anything else becomes the output
+			Some(Unwind::Return(val)) => match val {
+				Val::Null => break,
+				Val::Num(code) => {
+					set_exit_code(code);
+					break
+				}
+				val => return Ok(vec![vec![("0".into(), FieldVal::Buffer(val.to_string()))]])
+			}
+			Some(Unwind::Exit(code)) => {
+				set_exit_code(code);
+				break
+			}
+			Some(abort @ Unwind::Abort(_)) => {
+				ctx.unwind = Some(abort);
+				break
+			}
+			None => {}
+		}
 		if !ctx.args.keep_mode {
 			vicut.set_normal_mode();
 		}
 	}
 
-	if !ctx.fields.is_empty() {
+	if ctx.unwind.is_none() && deadline_passed() {
+		ctx.unwind = Some(Unwind::Abort(timeout_msg(args)));
+	}
+	RECORD_DEADLINE.set(None);
+	if let Some(Unwind::Abort(msg)) = ctx.unwind.take() {
+		if interrupt::aborting() {
+			return Err("interrupted".into())
+		}
+		eprintln!("vicut: {filepath}: {msg}, skipping this record");
+		set_exit_code(1);
+		// If we are editing in place, leave the file the way we found it
+		return Ok(original.map(|input| vec![vec![("0".into(), FieldVal::Buffer(input))]]).unwrap_or_default())
+	}
+
+	if ctx.args.dump_state {
+		eprint!("{}", vicut.dump_state());
+	}
+
+	if let Some(top) = args.freq {
+		let tokenizer = args.freq_pattern.as_ref().unwrap_or(&WORD_TOKEN);
+		return Ok(token_frequencies(&vicut.current_buffer().buffer, tokenizer).into_iter()
+			.take(top)
+			.map(|(token,count)| vec![("count".into(), count.to_string().into()), ("token".into(), token.into())])
+			.collect())
+	}
+
+	if !ctx.group_is_empty() {
+		capture_context_fields(&mut vicut, &mut ctx);
 		ctx.fmt_lines.push(std::mem::take(&mut ctx.fields));
 	}
 
@@ -796,7 +2873,7 @@ fn execute(args: &Opts, input: String, filename: Option<PathBuf>) -> Result<Vec<
 	// Let's figure out if we want to print the whole buffer
 	let no_fields = ctx.fmt_lines.is_empty(); // No fields were extracted
 	let has_files = !ctx.args.files.is_empty(); // We have files to edit
-	let has_pattern_search = ctx.args.cmds.iter().any(|cmd| {
+	let has_pattern_search = cmds.iter().any(|cmd| {
 		if let Cmd::Global { then_cmds, .. } = cmd {
 			then_cmds.iter().any(|cmd| matches!(cmd, Cmd::Field(_) | Cmd::NamedField(_, _)))
 		} else {
@@ -815,49 +2892,140 @@ fn execute(args: &Opts, input: String, filename: Option<PathBuf>) -> Result<Vec<
 
 	if should_print_entire_buffer {
 		let big_line = vicut.current_buffer().buffer.clone();
-		ctx.fmt_lines.push(vec![("0".into(),big_line)]);
+		ctx.fmt_lines.push(vec![("0".into(),FieldVal::Buffer(big_line))]);
 	}
 
 	if ctx.args.trim_fields {
 		trim_fields(&mut ctx.fmt_lines);
 	}
 
+	if !ctx.args.asserts.is_empty() {
+		let source = vicut.get_var("filepath").map(|path| path.to_string()).unwrap_or_default();
+		check_record_asserts(&ctx.args.asserts, &ctx.fmt_lines, &source)?;
+	}
+
 	Ok(ctx.fmt_lines)
 }
 
+/// Append the context fields requested with `--capture-line`, `--capture-word`, and `--capture-lineno`
+///
+/// These are read from the cursor's position at the moment the record is closed,
+/// so in a `-g` block they describe the matched line.
+fn capture_context_fields(vicut: &mut ViCut, ctx: &mut ExecCtx) {
+	let args = ctx.args;
+	if !(args.capture_line || args.capture_word || args.capture_lineno) {
+		return
+	}
+	if args.capture_lineno {
+		let line_no = vicut.current_buffer().cursor_line_number() + 1;
+		ctx.fields.push(("lineno".into(), line_no.to_string().into()));
+	}
+	if args.capture_line {
+		let (start,end) = vicut.current_buffer().this_line();
+		let line = vicut.current_buffer()
+			.slice(start..end)
+			.map(|line| line.trim_end_matches('\n').to_string())
+			.unwrap_or_default();
+		ctx.fields.push(("line".into(), line.into()));
+	}
+	if args.capture_word {
+		let word = vicut.get_builtin_var("word").unwrap_or(Val::Str(String::new()));
+		ctx.fields.push(("word".into(), word.to_string().into()));
+	}
+}
+
+/// Split a record into the fields for `--fields-by`, like awk does for `$1` through `$NF`.
+///
+/// The separator that ended the record is left out of the last field.
+/// Like a regex `FS` in awk, a separator at the start or end of the record leaves an empty field there.
+fn split_fields(record: &str, sep: &Regex, args: &Opts) -> Vec<String> {
+	let record = match args.record_sep.as_ref() {
+		Some(record_sep) => record_sep.find_iter(record)
+			.last()
+			.filter(|mat| mat.end() == record.len())
+			.map_or(record, |mat| &record[..mat.start()]),
+		None => {
+			let record = record.strip_suffix(args.input_separator()).unwrap_or(record);
+			record.strip_suffix('\r').unwrap_or(record)
+		}
+	};
+	sep.split(record).map(|piece| piece.to_string()).collect()
+}
+
 /// Trim the fields 🧑‍🌾
-fn trim_fields(lines: &mut Vec<Vec<(String,String)>>) {
+fn trim_fields(lines: &mut Vec<Vec<(String,FieldVal)>>) {
 	for line in lines {
 		for (_, field) in line {
-			*field = field.trim().to_string()
+			field.trim()
 		}
 	}
 }
 
-/// Split a string slice into it's lines.
+/// Split a string slice into the records that `--linewise` operates on, each ending with a match of `sep`.
 ///
 /// We use this instead of `String::lines()` because that method does not include the newline itself
 /// in each line. The newline characters are vital to `LineBuf`'s navigation logic.
-fn get_lines(value: &str) -> Vec<String> {
-	let mut cur_line = String::new();
+/// Every record keeps the separator that ended it, so joining the records back together gives back the input.
+fn split_records(value: &str, sep: &Regex) -> Vec<String> {
 	let mut lines = vec![];
-	let mut chars = value.chars();
+	let mut start = 0;
+	for sep in sep.find_iter(value) {
+		lines.push(value[start..sep.end()].to_string());
+		start = sep.end();
+	}
 
-	while let Some(ch) = chars.next() {
-		match ch {
-			'\n' => {
-				cur_line.push(ch);
-				lines.push(std::mem::take(&mut cur_line))
-			}
-			_ => cur_line.push(ch)
+	if start < value.len() {
+		lines.push(value[start..].to_string())
+	}
+
+	lines
+}
+
+/// Split a string slice into records that each begin with a line matching `start`, for `--record-start`.
+///
+/// Anything before the first matching line is a record of its own, so joining the records back together gives back the input.
+fn split_records_at_starts(value: &str, start: &Regex) -> Vec<String> {
+	let mut records: Vec<String> = vec![];
+	for line in value.split_inclusive('\n') {
+		match records.last_mut() {
+			Some(record) if !is_record_start(start, line) => record.push_str(line),
+			_ => records.push(line.to_string())
 		}
 	}
+	records
+}
+
+/// Whether `line` begins a new record for `--record-start`
+fn is_record_start(start: &Regex, line: &str) -> bool {
+	let line = line.strip_suffix('\n').unwrap_or(line);
+	start.is_match(line.strip_suffix('\r').unwrap_or(line))
+}
+
+/// Evaluate the arguments given to `echo` and friends, and join them with spaces
+fn echo_output(args: &[CmdArg], vicut: &mut ViCut, ctx: &mut ExecCtx) -> String {
+	let mut display_args = vec![];
+	for arg in args {
+		let value = vicut.eval_cmd_arg(arg,ctx).unwrap_or_else(complain_and_exit);
 
-	if !cur_line.is_empty() {
-		lines.push(std::mem::take(&mut cur_line))
+		display_args.push(value.to_string());
 	}
+	display_args.join(" ")
+}
 
-	lines
+/// Evaluate one end of the range given to `normal!()`, which must be a positive line number
+fn eval_line_number(vicut: &mut ViCut, arg: &CmdArg, ctx: &mut ExecCtx) -> Result<usize,String> {
+	match vicut.eval_cmd_arg(arg, ctx)? {
+		Val::Num(n) if n > 0 => Ok(n as usize),
+		val => Err(format!("Expected a line number in normal!(), found {val}"))
+	}
+}
+
+/// Expose the current iteration of a `repeat` or `for` loop as `$i` (starting from 1) and `$total`
+///
+/// They go in the loop's own scope, so a constant with the same name outside of the loop is only hidden inside of it.
+fn set_iteration_vars(vicut: &mut ViCut, i: usize, total: usize) {
+	vicut.set_local_var("i".into(), Val::Num(i as isize + 1));
+	vicut.set_local_var("total".into(), Val::Num(total as isize));
 }
 
 /// Execute a single `Cmd`
@@ -866,6 +3034,10 @@ fn exec_cmd(
 	vicut: &mut ViCut,
 	ctx: &mut ExecCtx,
 ) -> Option<Val>{
+	if deadline_passed() {
+		ctx.unwind = Some(Unwind::Abort(timeout_msg(ctx.args)));
+		return None
+	}
 	match cmd {
 		Cmd::SwitchBuf(id) => {
 			let Val::Num(id) = vicut.eval_cmd_arg(id,ctx).unwrap_or_else(complain_and_exit) else {
@@ -971,6 +3143,7 @@ fn exec_cmd(
 			// Evaluate the argument and return it
 			// This is the only branch that returns a value
 			let value = vicut.eval_cmd_arg(arg, ctx).unwrap_or_else(complain_and_exit);
+			ctx.unwind = Some(Unwind::Return(value.clone()));
 			return Some(value)
 		}
 		Cmd::FuncDef { name, args, body } => {
@@ -985,24 +3158,37 @@ fn exec_cmd(
 			vicut.eval_function(name.to_string(), func_args, ctx).unwrap_or_else(complain_and_exit);
 		}
 		Cmd::Echo(args) => {
-			if args.is_empty() {
-				println!();
-				return None
-			}
-			let mut display_args = vec![];
-			for arg in args {
-				let value = vicut.eval_cmd_arg(arg,ctx).unwrap_or_else(complain_and_exit);
-
-				display_args.push(value.to_string());
+			let output = echo_output(args, vicut, ctx);
+			print_stdout(&format!("{output}\n"));
+		}
+		Cmd::EchoErr(args) => {
+			let output = echo_output(args, vicut, ctx);
+			eprintln!("{output}");
+		}
+		Cmd::EchoAppend(args, var_name) => {
+			let output = echo_output(args, vicut, ctx);
+			match vicut.get_var_mut(var_name) {
+				Some(Val::Str(s)) => {
+					// Each echo is a line, same as if it had been printed
+					if !s.is_empty() {
+						s.push('\n');
+					}
+					s.push_str(&output);
+				}
+				Some(Val::Arr(arr)) => arr.push(Val::Str(output)),
+				Some(val) => {
+					let err = format!("Cannot append echo output to variable '{var_name}' of type {}", val.display_type());
+					complain_and_exit(err)
+				}
+				None => vicut.set_var(var_name.clone(), Val::Str(output)).unwrap_or_else(complain_and_exit),
 			}
-			let output = display_args.join(" ");
-			println!("{output}");
 		}
 		// -r <N> <R>
 		Cmd::Repeat{ body, count } => {
 			let n_repeats = vicut.eval_count(count).unwrap_or_else(complain_and_exit);
 			vicut.descend(); // new scope
-			for _ in 0..n_repeats {
+			for i in 0..n_repeats {
+				set_iteration_vars(vicut, i, n_repeats);
 
 				for r_cmd in body {
 					// We use recursion so that we can nest repeats easily
@@ -1011,15 +3197,17 @@ fn exec_cmd(
 						vicut,
 						ctx
 					);
+					if ctx.unwind.is_some() { break }
 				}
 				if !ctx.args.keep_mode {
 					vicut.set_normal_mode();
 				}
+				if ctx.unwind.is_some() { break }
 			}
 			vicut.ascend(); // leave scope
 		}
 		// -g/-v <PATTERN> <COMMANDS> [--else <COMMANDS>]
-		Cmd::Global { pattern, then_cmds, else_cmds, polarity } => {
+		Cmd::Global { pattern, then_cmds, else_cmds, polarity, limit, context } => {
 			let pattern = match pattern {
 				CmdArg::Literal(pattern) => pattern.clone(),
 				CmdArg::Var(var) => {
@@ -1034,17 +3222,52 @@ fn exec_cmd(
 				}
 				_ => unreachable!()
 			};
-			let motion = match polarity {
-				false  => Motion::NotGlobal(Box::new(Motion::LineRange(LineAddr::Number(1), LineAddr::Last)), pattern),
-				true => Motion::Global(Box::new(Motion::LineRange(LineAddr::Number(1), LineAddr::Last)), pattern)
+			// Used to fill in $match for each matching line
+			let regex = match &pattern {
+				Val::Regex(regex) => regex.clone(),
+				_ => new_regex(&pattern.to_string()).unwrap_or_else(complain_and_exit)
+			};
+			// A nested -g only looks at the lines around the outer scope's match
+			let range = match ctx.global_region {
+				Some((start,end)) => Motion::LineRange(LineAddr::Number(start + 1), LineAddr::Number(end + 1)),
+				None => Motion::LineRange(LineAddr::Number(1), LineAddr::Last)
+			};
+			let context = match context {
+				Some(context) => vicut.eval_count(context).unwrap_or_else(complain_and_exit),
+				None => 0
 			};
 
-			// Here we ask ViCut's editor directly to evaluate the Global motion for us.
-			// LineBuf::eval_motion() *always* returns MotionKind::Lines() for Motion::Global/NotGlobal.
-			let MotionKind::Lines(lines) = vicut.current_buffer().eval_motion(None, MotionCmd(1,motion)) else { unreachable!() };
-			if !lines.is_empty() {
+			let limit = limit.as_ref().map(|limit| vicut.eval_count(limit).unwrap_or_else(complain_and_exit));
+			// The scan stops at the first N matches in the buffer, which are still visited in the usual order.
+			// Even with a limit of zero, it looks for one, so that we know whether to take the else branch.
+			let mut lines = vicut.current_buffer().global_lines(&range, &pattern, *polarity, limit.map(|limit| limit.max(1)));
+			if deadline_passed() {
+				ctx.unwind = Some(Unwind::Abort(timeout_msg(ctx.args)));
+				return None
+			}
+			// Picking nothing should not send us down the else branch
+			let matched = !lines.is_empty();
+			if limit == Some(0) {
+				lines.clear();
+			}
+			if ctx.args.g_order == GOrder::Forward {
+				lines.sort();
+			}
+			if ctx.args.pick && lines.len() > 1 {
+				let mut candidates = lines.iter()
+					.filter_map(|&line| {
+						let (start,end) = vicut.current_buffer().line_bounds(line)?;
+						let text = vicut.current_buffer().slice(start..end)?.to_string();
+						Some(Candidate { line, text })
+					}).collect::<Vec<_>>();
+				candidates.sort_by_key(|cand| cand.line);
+				let picked = picker::pick_lines(candidates);
+				lines.retain(|line| picked.contains(line));
+			}
+			if matched {
 				// Positive branch
-				for line in lines {
+				let mut lines = VecDeque::from(lines);
+				while let Some(line) = lines.pop_front() {
 					let mut line_no = line;
 					let field_num = if ctx.args.global_uses_line_numbers {
 						// If we are using line numbers, we need to set the field number to the line number
@@ -1052,12 +3275,31 @@ fn exec_cmd(
 					} else {
 						&mut ctx.field_num.clone()
 					};
-					let Some((start,_)) = vicut.current_buffer().line_bounds(line) else { continue };
+					let Some((start,end)) = vicut.current_buffer().line_bounds(line) else { continue };
+					let matched_line = vicut.current_buffer().slice(start..end)
+						.unwrap_or_default()
+						.trim_end_matches(['\n','\r'])
+						.to_string();
+					// For -v, there is nothing that matched
+					let matched_str = regex.find(&matched_line)
+						.map(|mat| mat.as_str().to_string())
+						.unwrap_or_default();
 					// Set the cursor on the start of the line
 					vicut.current_buffer().cursor.set(start);
 					// Execute our commands
 
+					if ctx.args.record_per_match && !ctx.fields.is_empty() {
+						// Don't let fields from before this match bleed into its record
+						exec_cmd(&Cmd::BreakGroup, vicut, ctx);
+					}
+
+					// The buffer keeps the remaining lines in step with its edits, like the marks that Vim's `:g` puts on them
+					let buf_idx = vicut.mark_lines(std::mem::take(&mut lines));
+					let outer_region = ctx.global_region.replace((line.saturating_sub(context), line + context));
 					vicut.descend(); // new scope
+					vicut.set_var("matched_line".into(), Val::Str(matched_line)).unwrap_or_else(complain_and_exit);
+					vicut.set_var("matched_lineno".into(), Val::Num(line as isize + 1)).unwrap_or_else(complain_and_exit);
+					vicut.set_var("match".into(), Val::Str(matched_str)).unwrap_or_else(complain_and_exit);
 					for cmd in then_cmds {
 						exec_cmd(
 							cmd,
@@ -1067,8 +3309,15 @@ fn exec_cmd(
 						if !ctx.args.keep_mode {
 							vicut.set_normal_mode();
 						}
+						if ctx.unwind.is_some() { break }
 					}
 					vicut.ascend(); // leave scope
+					ctx.global_region = outer_region;
+					lines = vicut.take_line_marks(buf_idx);
+					if ctx.args.record_per_match {
+						exec_cmd(&Cmd::BreakGroup, vicut, ctx);
+					}
+					if ctx.unwind.is_some() { break }
 				}
 			} else if let Some(else_cmds) = else_cmds {
 				// Negative branch
@@ -1082,10 +3331,44 @@ fn exec_cmd(
 					if !ctx.args.keep_mode {
 						vicut.set_normal_mode();
 					}
+					if ctx.unwind.is_some() { break }
 				}
 				vicut.ascend();
 			}
 		}
+		// normal!(<START>, <END>, <VIM_CMDS>)
+		Cmd::Normal { range, keys } => {
+			let keys = vicut.eval_cmd_arg(keys,ctx).unwrap_or_else(complain_and_exit).to_string();
+			let total = vicut.current_buffer().total_lines();
+			let (start,end) = match range {
+				Some((start,end)) => {
+					let start = eval_line_number(vicut, start, ctx).unwrap_or_else(complain_and_exit);
+					let end = eval_line_number(vicut, end, ctx).unwrap_or_else(complain_and_exit);
+					(start.min(end), start.max(end).min(total))
+				}
+				None => {
+					let line = vicut.current_buffer().cursor_line_number() + 1;
+					(line,line)
+				}
+			};
+			// Lines are tracked through edits, so that deleting or adding lines doesn't shift the range
+			let mut lines = (start.saturating_sub(1)..end).collect::<VecDeque<_>>();
+			while let Some(line) = lines.pop_front() {
+				let Some((line_start,_)) = vicut.current_buffer().line_bounds(line) else { continue };
+				vicut.current_buffer().cursor.set(line_start);
+				let buf_idx = vicut.mark_lines(std::mem::take(&mut lines));
+				if let Err(e) = vicut.move_cursor(&keys) {
+					eprintln!("vicut: {e}");
+				}
+				// Like Vim, an unfinished command is abandoned at the end of each line
+				vicut.set_normal_mode();
+				lines = vicut.take_line_marks(buf_idx);
+				if deadline_passed() {
+					ctx.unwind = Some(Unwind::Abort(timeout_msg(ctx.args)));
+					break
+				}
+			}
+		}
 		// -m <VIM_CMDS>
 		Cmd::Motion(motion) => {
 			let motion = vicut.eval_cmd_arg(motion,ctx).unwrap_or_else(complain_and_exit).to_string();
@@ -1099,7 +3382,7 @@ fn exec_cmd(
 			ctx.field_num += 1;
 			match vicut.read_field(&motion) {
 				Ok(field) => {
-					let name = format!("{}",ctx.field_num);
+					let name = ctx.args.field_name(ctx.field_num);
 					ctx.fields.push((name,field))
 				}
 				Err(e) => {
@@ -1113,12 +3396,31 @@ fn exec_cmd(
 			let motion = vicut.eval_cmd_arg(motion,ctx).unwrap_or_else(complain_and_exit).to_string();
 			ctx.field_num += 1;
 			match vicut.read_field(&motion) {
+				// With --fields-by, capturing into the name of a field that the record was split into replaces it
+				Ok(field) if ctx.args.fields_by.is_some() => {
+					match ctx.fields.iter_mut().find(|(existing,_)| existing == name) {
+						Some((_,existing)) => *existing = field,
+						None => ctx.fields.push((name.clone(),field))
+					}
+				}
 				Ok(field) => ctx.fields.push((name.clone(),field)),
 				Err(e) => {
 					eprintln!("vicut: {e}");
 				}
 			}
 		}
+		// -c pattern=<REGEX>
+		Cmd::PatternFields(pattern) => {
+			let pattern = vicut.eval_cmd_arg(pattern,ctx).unwrap_or_else(complain_and_exit);
+			let regex = match pattern {
+				Val::Regex(regex) => regex,
+				pattern => new_regex(&pattern.to_string()).unwrap_or_else(complain_and_exit)
+			};
+			for (name,field) in vicut.read_pattern_fields(&regex) {
+				ctx.field_num += 1;
+				ctx.fields.push((name,field.into()));
+			}
+		}
 		// -n
 		Cmd::BreakGroup => {
 			if ctx.args.trace {
@@ -1129,15 +3431,37 @@ fn exec_cmd(
 					trace!("\t{name}: {content}");
 				}
 			}
-			ctx.field_num = 0;
-			if !ctx.fields.is_empty() {
+			if ctx.args.field_scope == FieldScope::Group {
+				ctx.field_num = ctx.pieces.len();
+			}
+			capture_context_fields(vicut, ctx);
+			if !ctx.group_is_empty() {
 				ctx.fmt_lines.push(std::mem::take(&mut ctx.fields));
 			}
+			ctx.fields = ctx.pieces.clone();
 		}
 		Cmd::VarDec { name, value } => {
 			let value = vicut.eval_cmd_arg(value,ctx).unwrap_or_else(complain_and_exit);
 			vicut.set_var(name.clone(), value.clone()).unwrap_or_else(complain_and_exit);
 		}
+		Cmd::ConstDec { name, value } => {
+			let value = vicut.eval_cmd_arg(value,ctx).unwrap_or_else(complain_and_exit);
+			vicut.set_const(name.clone(), value).unwrap_or_else(complain_and_exit);
+		}
+		Cmd::VarDecTuple { names, value } => {
+			let value = vicut.eval_cmd_arg(value,ctx).unwrap_or_else(complain_and_exit);
+			let Val::Arr(values) = value else {
+				eprintln!("vicut: cannot destructure {} into ({})", value.display_type(), names.join(", "));
+				std::process::exit(1);
+			};
+			if values.len() != names.len() {
+				eprintln!("vicut: cannot destructure {} values into ({})", values.len(), names.join(", "));
+				std::process::exit(1);
+			}
+			for (name, value) in names.iter().zip(values) {
+				vicut.set_var(name.clone(), value).unwrap_or_else(complain_and_exit);
+			}
+		}
 		Cmd::MutateVar { name, index, op, value } => {
 			let value = vicut.eval_cmd_arg(value,ctx).unwrap_or_else(complain_and_exit);
 			if let Some(index) = index {
@@ -1146,8 +3470,7 @@ fn exec_cmd(
 					eprintln!("vicut: expected number for index");
 					std::process::exit(1);
 				};
-				let index = index as usize;
-				vicut.set_index_var(name.to_string(), index, value);
+				vicut.set_index_var(name.to_string(), index, value).unwrap_or_else(complain_and_exit);
 			} else {
 				vicut.mutate_var(name.clone(), op.clone(), value.clone()).unwrap_or_else(complain_and_exit);
 			}
@@ -1169,6 +3492,7 @@ fn exec_cmd(
 						if !ctx.args.keep_mode {
 							vicut.set_normal_mode();
 						}
+						if ctx.unwind.is_some() { break }
 					}
 					vicut.ascend(); // leave scope
 					break;
@@ -1187,19 +3511,52 @@ fn exec_cmd(
 						if !ctx.args.keep_mode {
 							vicut.set_normal_mode();
 						}
+						if ctx.unwind.is_some() { break }
+					}
+					vicut.ascend(); // leave scope
+				}
+			}
+		}
+		Cmd::MatchBlock { value, arms } => {
+			let value = vicut.eval_cmd_arg(value,ctx).unwrap_or_else(complain_and_exit);
+			for arm in arms {
+				let MatchArm { patterns, cmds } = arm;
+				let mut is_match = patterns.is_empty();
+				for pattern in patterns {
+					// Val's PartialEq takes care of matching strings against regexes
+					let pattern = vicut.eval_cmd_arg(pattern,ctx).unwrap_or_else(complain_and_exit);
+					if pattern == value {
+						is_match = true;
+						break
+					}
+				}
+				if is_match {
+					vicut.descend(); // new scope
+					for cmd in cmds {
+						exec_cmd(
+							cmd,
+							vicut,
+							ctx
+						);
+						if !ctx.args.keep_mode {
+							vicut.set_normal_mode();
+						}
+						if ctx.unwind.is_some() { break }
 					}
 					vicut.ascend(); // leave scope
+					break;
 				}
 			}
 		}
-		Cmd::ForBlock { var_name, iterable, body } => {
+		Cmd::ForBlock { index_var, var_name, iterable, body } => {
 			let val = vicut.eval_cmd_arg(iterable,ctx).unwrap_or_else(complain_and_exit);
 			let val_iter = CompoundVal::try_from(val).unwrap_or_else(complain_and_exit);
 			let iter = val_iter.into_iter().collect::<Vec<_>>();
 			if iter.is_empty() {
 				return None;
 			}
-			'main: for item in iter {
+			let total = iter.len();
+			'main: for (i,item) in iter.into_iter().enumerate() {
 				if cmd == &Cmd::LoopBreak {
 					break;
 				}
@@ -1207,6 +3564,11 @@ fn exec_cmd(
 					continue;
 				}
 				vicut.descend(); // new scope
+				// Set these first, so that a loop variable named 'i' wins
+				set_iteration_vars(vicut, i, total);
+				if let Some(index_var) = index_var {
+					vicut.set_var(index_var.clone(), Val::Num(i as isize)).unwrap_or_else(complain_and_exit);
+				}
 				vicut.set_var(var_name.clone(), item).unwrap_or_else(complain_and_exit);
 				for cmd in body {
 					if cmd == &Cmd::LoopBreak {
@@ -1223,58 +3585,398 @@ fn exec_cmd(
 					if !ctx.args.keep_mode {
 						vicut.set_normal_mode();
 					}
+					if ctx.unwind.is_some() { break }
+				}
+				vicut.ascend(); // leave scope
+				if ctx.unwind.is_some() { break }
+			}
+		}
+		Cmd::WhileBlock(cond_block) => {
+			let CondBlock { cond, cmds } = cond_block;
+			'main: while cond.is_truthy(vicut,ctx) {
+				vicut.descend(); // new scope
+				for cmd in cmds {
+					if cmd == &Cmd::LoopBreak {
+						break 'main;
+					}
+					if cmd == &Cmd::LoopContinue {
+						continue 'main;
+					}
+					exec_cmd(
+						cmd,
+						vicut,
+						ctx
+					);
+					if !ctx.args.keep_mode {
+						vicut.set_normal_mode();
+					}
+					if ctx.unwind.is_some() { break }
+				}
+				vicut.ascend(); // leave scope
+				if ctx.unwind.is_some() { break }
+			}
+		}
+		Cmd::UntilBlock(cond_block) => {
+			let CondBlock { cond, cmds } = cond_block;
+			'main: while !cond.is_truthy(vicut,ctx) {
+				vicut.descend(); // new scope
+				for cmd in cmds {
+					if cmd == &Cmd::LoopBreak {
+						break 'main;
+					}
+					if cmd == &Cmd::LoopContinue {
+						continue 'main;
+					}
+					exec_cmd(
+						cmd,
+						vicut,
+						ctx
+					);
+					if !ctx.args.keep_mode {
+						vicut.set_normal_mode();
+					}
+					if ctx.unwind.is_some() { break }
 				}
 				vicut.ascend(); // leave scope
+				if ctx.unwind.is_some() { break }
 			}
 		}
-		Cmd::WhileBlock(cond_block) => {
-			let CondBlock { cond, cmds } = cond_block;
-			'main: while cond.is_truthy(vicut,ctx) {
-				vicut.descend(); // new scope
-				for cmd in cmds {
-					if cmd == &Cmd::LoopBreak {
-						break 'main;
-					}
-					if cmd == &Cmd::LoopContinue {
-						continue 'main;
-					}
-					exec_cmd(
-						cmd,
-						vicut,
-						ctx
-					);
-					if !ctx.args.keep_mode {
-						vicut.set_normal_mode();
-					}
-				}
-				vicut.ascend(); // leave scope
-			}
+	}
+	None
+}
+
+/// Run the `--verify-roundtrip` checks on an in-place edit, before it gets written.
+///
+/// The original contents are read back from the file, since it hasn't been touched yet.
+fn verify_roundtrip(args: &Opts, path: &Path, output: &str) -> Result<(),String> {
+	if args.verify_roundtrip.is_empty() {
+		return Ok(())
+	}
+	let before = fs::read_to_string(path).map_err(|e| e.to_string())?;
+	let violations = args.verify_roundtrip.iter()
+		.filter_map(|check| check.violation(&before, output))
+		.collect::<Vec<_>>();
+	if violations.is_empty() {
+		Ok(())
+	} else {
+		Err(violations.join("; "))
+	}
+}
+
+/// The file name that stands for stdin, like `cat` and friends use it
+pub const STDIN_PATH: &str = "-";
+
+/// Whether a path from the list of files is `-`, meaning stdin
+pub fn is_stdin(path: &Path) -> bool {
+	path.as_os_str() == STDIN_PATH
+}
+
+/// Read the input for one entry in the list of files, which may be stdin
+fn read_input(path: &Path) -> io::Result<String> {
+	if is_stdin(path) {
+		let mut input = String::new();
+		interrupt::Interruptible(io::stdin().lock()).read_to_string(&mut input)?;
+		Ok(input)
+	} else {
+		fs::read_to_string(path)
+	}
+}
+
+/// Read a file that vicut is going to work on.
+///
+/// When it is about to be edited in place, it is read under its lock, and what it held is remembered by `write_in_place()`.
+fn read_file(args: &Opts, path: &Path) -> Result<String,String> {
+	if !args.edit_inplace || args.dry_run || is_stdin(path) {
+		return read_input(path).map_err(|e| format!("vicut: failed to read '{}': {e}", path.display()))
+	}
+	let _lock = lock_file(args, path)?;
+	let contents = read_input(path).map_err(|e| format!("vicut: failed to read '{}': {e}", path.display()))?;
+	READ_HASHES.lock().unwrap().insert(path.to_path_buf(), content_hash(&contents));
+	Ok(contents)
+}
+
+/// Read the list of files for `--files-from`, one per line, from a file or from stdin.
+///
+/// Blank lines are ignored.
+pub fn read_file_list(source: &str) -> Result<Vec<String>,String> {
+	let list = read_input(Path::new(source))
+		.map_err(|e| format!("vicut: failed to read the list of files from '{source}': {e}"))?;
+	Ok(list.lines()
+		.map(|line| line.trim_end_matches('\r'))
+		.filter(|line| !line.trim().is_empty())
+		.map(str::to_string)
+		.collect())
+}
+
+/// How much of a file is searched for a NUL byte to decide that it is binary. Git and ripgrep look at the same amount.
+const BINARY_SNIFF_LEN: u64 = 8192;
+
+/// Why a file given as input should be skipped, if it should be.
+///
+/// Files over `--max-filesize` are skipped, and so are binary files, unless `--text` or `--read0` say that NUL bytes are expected.
+/// Editing a binary file in place as text would mangle it.
+/// Files that can't be read at all are let through, so that they fail later with the usual error.
+fn skip_reason(args: &Opts, path: &Path) -> Option<String> {
+	if is_stdin(path) {
+		return None
+	}
+	if let Some(max) = args.max_filesize
+	&& let Ok(meta) = fs::metadata(path)
+	&& meta.len() > max {
+		return Some(format!("it is larger than {max} bytes"))
+	}
+	if args.text || args.read0 {
+		return None
+	}
+	let mut head = vec![];
+	let file = fs::File::open(path).ok()?;
+	file.take(BINARY_SNIFF_LEN).read_to_end(&mut head).ok()?;
+	head.contains(&0).then(|| "it looks like a binary file".to_string())
+}
+
+/// Copy `path` to a backup next to it, using the extension from `--backup-extension`
+fn backup_file(args: &Opts, path: &Path) -> Result<(),String> {
+	let extension = args.backup_extension.as_deref().unwrap_or("bak");
+	let backup_path = path.with_extension(format!(
+			"{}.{extension}",
+			path.extension()
+			.and_then(|ext| ext.to_str())
+			.unwrap_or("")
+	));
+
+	fs::copy(path, &backup_path).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Write the result of an in-place edit back to its file, after making a backup of it for `--backup`.
+///
+/// The file is locked while it is written. If it changed since `read_file()` read it, it is left alone.
+fn write_in_place(args: &Opts, path: &Path, output: &str) {
+	let _lock = match lock_file(args, path) {
+		Ok(lock) => lock,
+		Err(e) => {
+			eprintln!("vicut: refusing to write '{}': {e}", path.display());
+			set_exit_code(1);
+			return
+		}
+	};
+	let read_hash = READ_HASHES.lock().unwrap().remove(path);
+	if let Some(hash) = read_hash
+	&& fs::read_to_string(path).map(|contents| content_hash(&contents)).ok() != Some(hash) {
+		eprintln!("vicut: refusing to write '{}': it was changed by something else while vicut was working on it", path.display());
+		set_exit_code(1);
+		return
+	}
+	if args.backup_files {
+		backup_file(args, path).unwrap_or_else(complain_and_exit);
+	}
+	fs::write(path, output).unwrap_or_else(complain_and_exit);
+	stats::record_output(output.len());
+	if let Some(cache) = EDIT_CACHE.lock().unwrap().as_mut() {
+		cache.record(path, output);
+	}
+}
+
+/// Where `--out-template` sends the output for the file at `path`.
+///
+/// Relative paths are placed next to the input file, like backups are.
+fn out_template_path(template: &str, path: &Path) -> PathBuf {
+	let part = |part: Option<&std::ffi::OsStr>| part.map(|part| part.to_string_lossy().to_string()).unwrap_or_default();
+	let out = template
+		.replace("{name}", &part(path.file_name()))
+		.replace("{stem}", &part(path.file_stem()))
+		.replace("{ext}", &part(path.extension()));
+	match path.parent() {
+		Some(dir) => dir.join(out),
+		None => PathBuf::from(out)
+	}
+}
+
+/// Make sure that `--out-template` gives every input file an output of its own, and never overwrites an input
+fn check_out_template(template: &str, files: &[PathBuf]) -> Result<(),String> {
+	let inputs = files.iter().map(|path| normalize_path(path)).collect::<Vec<_>>();
+	let mut outputs: Vec<(PathBuf,&Path)> = vec![];
+	for path in files {
+		let out_path = normalize_path(&out_template_path(template, path));
+		if inputs.contains(&out_path) {
+			return Err(format!("vicut: '--out-template' would overwrite the input file '{}'", out_path.display()))
+		}
+		if let Some((_,other)) = outputs.iter().find(|(existing,_)| *existing == out_path) {
+			return Err(format!("vicut: '--out-template' writes both '{}' and '{}' to '{}'", other.display(), path.display(), out_path.display()))
 		}
-		Cmd::UntilBlock(cond_block) => {
-			let CondBlock { cond, cmds } = cond_block;
-			'main: while !cond.is_truthy(vicut,ctx) {
-				vicut.descend(); // new scope
-				for cmd in cmds {
-					if cmd == &Cmd::LoopBreak {
-						break 'main;
-					}
-					if cmd == &Cmd::LoopContinue {
-						continue 'main;
-					}
-					exec_cmd(
-						cmd,
-						vicut,
-						ctx
-					);
-					if !ctx.args.keep_mode {
-						vicut.set_normal_mode();
-					}
-				}
-				vicut.ascend(); // leave scope
+		outputs.push((out_path, path));
+	}
+	Ok(())
+}
+
+/// Write the output for the file at `path` to the file picked by `--out-template`, instead of to stdout
+fn write_out_template(template: &str, path: &Path, output: &str) {
+	let out_path = out_template_path(template, path);
+	if same_file(&out_path, path) {
+		eprintln!("vicut: refusing to write '{}': '--out-template' would overwrite the input file", path.display());
+		set_exit_code(1);
+		return
+	}
+	if let Err(e) = fs::write(&out_path, output) {
+		eprintln!("vicut: failed to write '{}': {e}", out_path.display());
+		set_exit_code(1);
+		return
+	}
+	stats::record_output(output.len());
+}
+
+/// Take an advisory lock on a file that is about to be edited in place, for `-i`.
+///
+/// The lock is let go of when the returned file is dropped, so only one file is locked at a time.
+/// If someone else holds the lock, `--wait` waits for it, and otherwise this fails.
+fn lock_file(args: &Opts, path: &Path) -> Result<Option<fs::File>,String> {
+	if !cfg!(unix) {
+		// Locks are mandatory on Windows, so holding one would keep vicut from writing to the file itself
+		return Ok(None)
+	}
+	let file = fs::File::open(path)
+		.map_err(|e| format!("vicut: failed to open '{}': {e}", path.display()))?;
+	if args.wait_for_lock {
+		file.lock().map_err(|e| format!("vicut: failed to lock '{}': {e}", path.display()))?;
+	} else if !try_lock_file(&file, path)? {
+		return Err(format!("vicut: '{}' is locked by another process", path.display()))
+	}
+	Ok(Some(file))
+}
+
+/// Lock a file if nobody else has, returning whether it worked
+fn try_lock_file(file: &fs::File, path: &Path) -> Result<bool,String> {
+	match file.try_lock() {
+		Ok(()) => Ok(true),
+		Err(fs::TryLockError::WouldBlock) => Ok(false),
+		Err(fs::TryLockError::Error(e)) => Err(format!("vicut: failed to lock '{}': {e}", path.display()))
+	}
+}
+
+/// Make sure that none of the files that are about to be edited in place are locked, before any of them are edited.
+///
+/// Each lock is only tried and let go of again, since holding all of them at once could run out of file descriptors.
+/// `--no-clobber` drops locked files from the list, and otherwise a locked file stops vicut before it edits anything.
+/// With `--wait`, there's nothing to check, since every file is waited for when its turn comes.
+fn check_locks(opts: &mut Opts) -> Result<(),String> {
+	if opts.wait_for_lock || !cfg!(unix) {
+		return Ok(())
+	}
+	let mut paths = opts.files.clone();
+	paths.sort();
+	paths.dedup();
+	for path in paths {
+		let file = fs::File::open(&path)
+			.map_err(|e| format!("vicut: failed to open '{}': {e}", path.display()))?;
+		if try_lock_file(&file, &path)? {
+			continue
+		}
+		if opts.no_clobber {
+			eprintln!("vicut: skipping '{}': it is locked by another process", path.display());
+			set_exit_code(1);
+			opts.files.retain(|file| *file != path);
+		} else {
+			return Err(format!("vicut: '{}' is locked by another process, use '--wait' to wait for it or '--no-clobber' to skip it", path.display()))
+		}
+	}
+	Ok(())
+}
+
+/// Replace the directories in the list of files with the files underneath them, for `-R`.
+///
+/// Ignore files and hidden files are handled the way ripgrep handles them: `.gitignore` is honored inside of git repositories,
+/// `.ignore` is honored everywhere, and hidden entries are skipped unless `--hidden` is given.
+/// Files that were named explicitly are kept even if an ignore file would exclude them.
+fn walk_files(files: &[PathBuf], hidden: bool) -> Vec<PathBuf> {
+	let mut walked = vec![];
+	for path in files {
+		if !path.is_dir() {
+			walked.push(path.clone());
+			continue
+		}
+		let walker = ignore::WalkBuilder::new(path)
+			.hidden(!hidden)
+			.sort_by_file_name(|a, b| a.cmp(b))
+			// ripgrep would descend into .git with --hidden, but nothing in there should ever be edited in place
+			.filter_entry(|entry| entry.file_name() != ".git")
+			.build();
+		for entry in walker {
+			match entry {
+				Ok(entry) if entry.file_type().is_some_and(|ty| ty.is_file()) => walked.push(entry.into_path()),
+				Ok(_) => {}
+				// Like ripgrep, an unreadable directory is worth a warning, but not worth giving up on the rest
+				Err(e) => eprintln!("vicut: {e}")
 			}
 		}
 	}
-	None
+	itertools::Itertools::unique(walked.into_iter()).collect()
+}
+
+/// Ask git for the files picked out by `--git`
+fn git_files(selection: GitSelection) -> Result<Vec<PathBuf>,String> {
+	let git = |args: &[&str]| std::process::Command::new("git")
+		.args(args)
+		.output()
+		.map_err(|e| format!("vicut: failed to run git: {e}"));
+	// Outside of a repository, 'git diff' falls back to comparing two paths, and its complaints make no sense here
+	if !git(&["rev-parse", "--is-inside-work-tree"])?.status.success() {
+		return Err("vicut: '--git' has to be used inside of a git working tree".into())
+	}
+	let git_args = selection.git_args();
+	let output = git(git_args)?;
+	if !output.status.success() {
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		let reason = stderr.lines().next().unwrap_or_default();
+		return Err(format!("vicut: 'git {}' failed: {reason}", git_args.join(" ")))
+	}
+	Ok(output.stdout
+		.split(|byte| *byte == 0)
+		.filter(|path| !path.is_empty())
+		.map(|path| PathBuf::from(String::from_utf8_lossy(path).into_owned()))
+		// ls-files still lists files that were deleted from the working tree
+		.filter(|path| path.is_file())
+		.collect())
+}
+
+/// Render the changes an in-place edit would make as a unified diff, for `--dry-run` and `--patch`. Unchanged files produce nothing.
+///
+/// A patch starts with a `diff --git` line, which `git apply` and most review tools expect to find.
+fn dry_run_diff(path: &Path, before: &str, after: &str, patch: bool) -> String {
+	if before == after {
+		return String::new()
+	}
+	let path = if patch { patch_path(path) } else { path.display().to_string() };
+	let diff = similar::TextDiff::from_lines(before, after)
+		.unified_diff()
+		.context_radius(3)
+		.header(&format!("a/{path}"), &format!("b/{path}"))
+		.to_string();
+	if patch {
+		format!("diff --git a/{path} b/{path}\n{diff}")
+	} else {
+		diff
+	}
+}
+
+/// The path to show in a `--patch`, relative to the current directory when possible, since that is where it will be applied from.
+fn patch_path(path: &Path) -> String {
+	let relative = std::env::current_dir().ok()
+		.and_then(|cwd| path.strip_prefix(cwd).ok())
+		.unwrap_or(path);
+	let relative = relative.strip_prefix(".").unwrap_or(relative);
+	relative.display().to_string()
+}
+
+/// Print what an in-place edit would have done to `path`, instead of doing it.
+///
+/// The original contents are read back from the file, since it hasn't been touched.
+fn print_dry_run_diff(out: &mut impl IoWrite, args: &Opts, path: &Path, output: &str) {
+	match fs::read_to_string(path) {
+		Ok(before) => { write!(out, "{}", dry_run_diff(path, &before, output, args.patch)).ok(); }
+		Err(e) => {
+			eprintln!("vicut: {}: {e}", path.display());
+			set_exit_code(1);
+		}
+	}
 }
 
 /// Multi-thread the execution of file input.
@@ -1283,33 +3985,37 @@ fn exec_cmd(
 /// 1. Create a `work` vector containing a tuple of the file's path, and it's contents.
 /// 2. Call `execute()` on each file's contents
 /// 3. Decide how to handle output depending on whether args.edit_inplace is set.
-fn execute_multi_thread_files(mut stdout: io::StdoutLock, args: &Opts) {
-	let work: Vec<(PathBuf, String)> = args.files.par_iter()
-		.fold(Vec::new, |mut acc,file| {
-			let contents = fs::read_to_string(file).unwrap_or_else(complain_and_exit);
-			acc.push((file.clone(), contents.to_string()));
-			acc
-		}).reduce(Vec::new, |mut a, mut b| {
-			a.append(&mut b);
-			a
+fn execute_multi_thread_files(mut stdout: OutputWriter<impl IoWrite>, args: &Opts) {
+	let work: Vec<(PathBuf, String)> = args.files.clone()
+		.par_map(|file| {
+			let contents = read_file(args, &file).unwrap_or_else(complain_and_exit);
+			(file, contents)
 		});
 
 	// Process each file's content
-	let results = work.into_par_iter()
-		.map(|(path, content)| {
-			let processed = match execute(args, content, Some(path.clone())) {
-				Ok(content) => content,
-				Err(e) => {
+	let results = work
+		.par_map(|(path, content)| {
+			isolate_registers(args.shared_registers);
+			let processed = match execute_unit(args, content, Some(path.clone())) {
+				Some(Ok(content)) => Some(content),
+				Some(Err(e)) => {
 					eprintln!("vicut: error in file '{}': {e}",path.display());
 					std::process::exit(1)
 				}
+				None => None
 			};
 			(path, processed)
-		}).collect::<Vec<_>>();
+		});
+	// After an interrupt, stop at the first file that didn't finish, so the output is the start of a full run's
+	let total = results.len();
+	let results: Vec<_> = results.into_iter()
+		.map_while(|(path, processed)| Some((path, processed?)))
+		.collect();
+	interrupt::record_progress(results.len(), Some(total));
 
 	// Write back to file
-	if args.json  && args.files.len() > 1 {
-		let json = format_output_json_files(results);
+	if args.json && args.files.len() > 1 && args.out_template.is_none() {
+		let json = finalize_output(args, format_output_json_files(results));
 		write!(stdout, "{json}").ok();
 		return
 	}
@@ -1317,25 +4023,29 @@ fn execute_multi_thread_files(mut stdout: io::StdoutLock, args: &Opts) {
 		let output = format_output(args, contents);
 
 		if args.edit_inplace {
-			if args.backup_files {
-				let extension = args.backup_extension.as_deref().unwrap_or("bak");
-				let backup_path = path.with_extension(format!(
-						"{}.{extension}",
-						path.extension()
-						.and_then(|ext| ext.to_str())
-						.unwrap_or("")
-				));
-
-				fs::copy(&path, &backup_path).unwrap_or_else(complain_and_exit);
-			}
-			fs::write(&path, output).unwrap_or_else(complain_and_exit);
+			if let Err(e) = verify_roundtrip(args, &path, &output) {
+				eprintln!("vicut: refusing to write '{}': {e}", path.display());
+				set_exit_code(1);
+				continue
+			}
+			if args.dry_run {
+				print_dry_run_diff(&mut stdout, args, &path, &output);
+				continue
+			}
+			write_in_place(args, &path, &output);
+		} else if let Some(template) = &args.out_template {
+			write_out_template(template, &path, &output);
 		} else if args.files.len() > 1 {
 			if !output.is_empty() {
-				writeln!(stdout, "--- {}\n{}",path.display(), output).ok();
+				write!(stdout, "--- {}{eol}{}{eol}", path.display(), output, eol = args.line_ending()).ok();
 			}
 		} else {
 			write!(stdout, "{output}").ok();
 		}
+		stdout.end_record();
+		if output_closed() {
+			break
+		}
 	}
 }
 
@@ -1358,48 +4068,72 @@ fn execute_multi_thread_files(mut stdout: io::StdoutLock, args: &Opts) {
 ///
 /// Errors during reading, transformation, or writing will abort the program with a diagnostic.
 /// Backup files are created if `--backup-files` is enabled.
-fn execute_multi_thread_files_linewise(mut stdout: io::StdoutLock, args: &Opts) {
-
-	let work: Vec<(PathBuf, usize, String)> = args.files.par_iter()
-		.fold(Vec::new, |mut acc,file| {
-			let contents = fs::read_to_string(file).unwrap_or_else(complain_and_exit);
-			for (line_no,line) in get_lines(&contents).into_iter().enumerate() {
-				acc.push((file.clone(), line_no, line.to_string()));
-			}
-			acc
-		}).reduce(Vec::new, |mut a, mut b| {
-			a.append(&mut b);
-			a
-		});
+fn execute_multi_thread_files_linewise(mut stdout: OutputWriter<impl IoWrite>, args: &Opts) {
+
+	let work: Vec<(PathBuf, usize, String)> = args.files.clone()
+		.par_map(|file| {
+			let contents = read_file(args, &file).unwrap_or_else(complain_and_exit);
+			args.split_input(&contents).into_iter()
+				.enumerate()
+				.map(|(line_no,line)| (file.clone(), line_no, line.to_string()))
+				.collect::<Vec<_>>()
+		}).into_iter()
+		.flatten()
+		.collect();
 
 	// Process each line's content
-	let results = work.into_par_iter()
-		.map(|(path, line_no, line)| {
-			let processed = match execute(args, line, Some(path.clone())) {
-				Ok(line) => line,
-				Err(e) => {
+	let results = work
+		.par_map(|(path, line_no, line)| {
+			isolate_registers(args.shared_registers);
+			let processed = match execute_unit(args, line, Some(path.clone())) {
+				Some(Ok(line)) => Some(line),
+				Some(Err(e)) => {
 					eprintln!("vicut: error in file '{}', line {}: {e}",path.display(),line_no);
 					std::process::exit(1)
 				}
+				None => None
 			};
 			(path, line_no, processed)
-		}).collect::<Vec<_>>();
+		});
+	// After an interrupt, only the records before the first one that didn't finish are written out.
+	// The file that it belongs to is left alone when editing in place, since it would come out cut short.
+	let total = results.len();
+	let unfinished = results.iter().position(|(_,_,processed)| processed.is_none());
+	let partial_file = unfinished.map(|i| results[i].0.clone());
+	let results: Vec<_> = results.into_iter()
+		.take(unfinished.unwrap_or(total))
+		.map(|(path, line_no, processed)| (path, line_no, processed.unwrap_or_default()))
+		.collect();
+	interrupt::record_progress(results.len(), Some(total));
 
 	// Separate content by file
 	let mut per_file: BTreeMap<PathBuf, Vec<(usize,String)>> = BTreeMap::new();
+	let mut per_file_records: BTreeMap<PathBuf, Vec<_>> = BTreeMap::new();
 	for (path, line_no, processed) in results {
+		if args.format.is_some() {
+			// A table needs all of a file's records at once, for its header
+			per_file_records.entry(path)
+				.or_default()
+				.push((line_no,processed));
+			continue
+		}
 		let output = format_output(args, processed);
 
 		per_file.entry(path)
 			.or_default()
 			.push((line_no,output));
 	}
-	if args.json  && args.files.len() > 1 {
+	for (path, mut lines) in per_file_records {
+		lines.sort_by_key(|(line_no,_)| *line_no);
+		let records = lines.into_iter().flat_map(|(_,records)| records).collect();
+		per_file.insert(path, vec![(0, format_output(args, records))]);
+	}
+	if args.json && args.files.len() > 1 && args.out_template.is_none() {
 		let results = per_file.into_iter()
-			.map(|(path, lines)| (path, lines.into_iter().map(|(num,line)| vec![(num.to_string(),line)]).collect::<Vec<_>>()))
+			.map(|(path, lines)| (path, lines.into_iter().map(|(num,line)| vec![(num.to_string(),line.into())]).collect::<Vec<_>>()))
 			.collect::<Vec<_>>(); // two vec collects, holy cringe
 														// it'll come out in the wash
-		let json = format_output_json_files(results);
+		let json = finalize_output(args, format_output_json_files(results));
 		write!(stdout, "{json}").ok();
 		return
 	}
@@ -1412,25 +4146,37 @@ fn execute_multi_thread_files_linewise(mut stdout: io::StdoutLock, args: &Opts)
 			.join("");
 
 		if args.edit_inplace {
-			if args.backup_files {
-				let extension = args.backup_extension.as_deref().unwrap_or("bak");
-				let backup_path = path.with_extension(format!(
-						"{}.{extension}",
-						path.extension()
-						.and_then(|ext| ext.to_str())
-						.unwrap_or("")
-				));
-
-				fs::copy(&path, &backup_path).unwrap_or_else(complain_and_exit);
-			}
-			fs::write(&path, output_final).unwrap_or_else(complain_and_exit);
+			if partial_file.as_ref() == Some(&path) {
+				eprintln!("vicut: leaving '{}' untouched, since it was interrupted", path.display());
+				continue
+			}
+			if let Err(e) = verify_roundtrip(args, &path, &output_final) {
+				eprintln!("vicut: refusing to write '{}': {e}", path.display());
+				set_exit_code(1);
+				continue
+			}
+			if args.dry_run {
+				print_dry_run_diff(&mut stdout, args, &path, &output_final);
+				continue
+			}
+			write_in_place(args, &path, &output_final);
+		} else if let Some(template) = &args.out_template {
+			if partial_file.as_ref() == Some(&path) {
+				eprintln!("vicut: not writing the output for '{}', since it was interrupted", path.display());
+				continue
+			}
+			write_out_template(template, &path, &output_final);
 		} else if args.files.len() > 1 {
 			if !output_final.is_empty() {
-				writeln!(stdout, "--- {}\n{}",path.display(), output_final).ok();
+				write!(stdout, "--- {}{eol}{}{eol}", path.display(), output_final, eol = args.line_ending()).ok();
 			}
 		} else {
 			write!(stdout, "{output_final}").ok();
 		}
+		stdout.end_record();
+		if output_closed() {
+			break
+		}
 	}
 }
 
@@ -1441,133 +4187,308 @@ fn execute_multi_thread_files_linewise(mut stdout: io::StdoutLock, args: &Opts)
 fn execute_linewise(mut stream: Box<dyn BufRead>, args: &Opts) -> String {
 	let mut input = String::new();
 	stream.read_to_string(&mut input).unwrap_or_else(complain_and_exit);
-	let lines = get_lines(&input);
+	let lines = args.split_input(&input);
 	// Pair each line with its original index
 	let mut lines: Vec<_> = lines
-		.into_par_iter()
+		.into_iter()
 		.enumerate()
-		.map(|(i, line)| {
-			let output = match execute(args, line, None) {
-				Ok(line) => line,
-				Err(e) => {
+		.collect::<Vec<_>>()
+		.par_map(|(i, line)| {
+			isolate_registers(args.shared_registers);
+			let output = match execute_unit(args, line, None) {
+				Some(Ok(line)) => Some(line),
+				Some(Err(e)) => {
 					eprintln!("vicut: {e}");
 					std::process::exit(1)
 				}
+				None => None
 			};
 			(i, output)
-		})
-	.collect();
+		});
 	lines.sort_by_key(|(i,_)| *i);
-	let mut fmt_lines = vec![];
-	for (_,mut line) in lines {
-		fmt_lines.append(&mut line);
-	}
+	let total = lines.len();
+	// After an interrupt, stop at the first record that didn't finish
+	let completed: Vec<_> = lines.into_iter().map_while(|(_,line)| line).collect();
+	interrupt::record_progress(completed.len(), Some(total));
+	let fmt_lines = completed.into_iter().flatten().collect();
 	format_output(args, fmt_lines)
 }
 
+/// Reads the records for `--stream` one at a time, as they arrive.
+///
+/// Single character separators are read straight out of the input.
+/// A `--record-sep` pattern could keep matching as more input comes in, like `\n\n+` does,
+/// so a record is only handed out once there is more input after its separator, or the input has ended.
+/// With `--record-start`, a record is only complete once the line that starts the next one has arrived.
+struct RecordReader<'a, R> {
+	stream: io::BufReader<R>,
+	sep: Option<&'a Regex>,
+	start: Option<&'a Regex>,
+	byte: u8,
+	pending: String,
+}
+
+impl<'a, R: Read> RecordReader<'a, R> {
+	fn new(input: R, args: &'a Opts) -> Self {
+		Self {
+			stream: io::BufReader::new(input),
+			sep: args.record_sep.as_ref(),
+			start: args.record_start.as_ref(),
+			byte: args.input_separator() as u8,
+			pending: String::new(),
+		}
+	}
+	fn next_record(&mut self) -> Result<Option<String>,String> {
+		if let Some(start) = self.start {
+			loop {
+				let mut line = String::new();
+				if self.stream.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+					return Ok((!self.pending.is_empty()).then(|| std::mem::take(&mut self.pending)))
+				}
+				if !self.pending.is_empty() && is_record_start(start, &line) {
+					return Ok(Some(std::mem::replace(&mut self.pending, line)))
+				}
+				self.pending.push_str(&line);
+			}
+		}
+		let Some(sep) = self.sep else {
+			let mut record = vec![];
+			if self.stream.read_until(self.byte, &mut record).map_err(|e| e.to_string())? == 0 {
+				return Ok(None)
+			}
+			return String::from_utf8(record).map(Some).map_err(|e| e.to_string())
+		};
+		loop {
+			if let Some(end) = sep.find(&self.pending).map(|sep| sep.end()).filter(|end| *end < self.pending.len()) {
+				let rest = self.pending.split_off(end);
+				return Ok(Some(std::mem::replace(&mut self.pending, rest)))
+			}
+			if self.stream.read_line(&mut self.pending).map_err(|e| e.to_string())? == 0 {
+				return Ok((!self.pending.is_empty()).then(|| std::mem::take(&mut self.pending)))
+			}
+		}
+	}
+	/// Whether the next record can be read without waiting on the input
+	fn has_buffered(&self) -> bool {
+		match self.sep.or(self.start) {
+			Some(_) => !self.stream.buffer().is_empty(),
+			None => self.stream.buffer().contains(&self.byte)
+		}
+	}
+}
+
+/// The pathway for when the `--stream` flag is set
+///
+/// Works like `execute_linewise()`, but never holds more than `STREAM_CHUNK_LINES` lines of input at once.
+/// Lines are gathered into a chunk for as long as more of them are already buffered,
+/// so a slow producer like `tail -f` still gets each line's output right away.
+/// Each chunk is executed, possibly in parallel, and written out before the next one is read.
+fn execute_stream(input: impl Read, out: &mut impl IoWrite, args: &Opts) -> Result<(),String> {
+	let mut records = RecordReader::new(input, args);
+	// With --format, the first chunk decides the table's columns, since its header is written before the next chunk is read.
+	// Fields that only show up in later chunks are left out.
+	let mut columns = vec![];
+	let flush_each_chunk = args.flush_policy() == FlushPolicy::Record;
+	let mut completed = 0;
+	loop {
+		let mut chunk = vec![];
+		while chunk.len() < STREAM_CHUNK_LINES {
+			let Some(record) = records.next_record()? else {
+				break
+			};
+			chunk.push(record);
+			// Don't wait on the input just to fill up the chunk
+			if !records.has_buffered() {
+				break
+			}
+		}
+		if chunk.is_empty() {
+			return Ok(())
+		}
+
+		let results = if args.single_thread {
+			chunk.into_iter().map(|line| execute_unit(args, line, None)).collect::<Vec<_>>()
+		} else {
+			chunk.par_map(|line| {
+				isolate_registers(args.shared_registers);
+				execute_unit(args, line, None)
+			})
+		};
+		let mut fmt_lines = vec![];
+		// After an interrupt, stop at the first record that didn't finish
+		for result in results.into_iter().map_while(|result| result) {
+			fmt_lines.append(&mut result?);
+			completed += 1;
+		}
+		interrupt::record_progress(completed, None);
+		let mut output = if args.json {
+			finalize_output(args, format_output_json_lines(args, fmt_lines))
+		} else if let Some(format) = args.format && !only_whole_buffers(&fmt_lines) {
+			finalize_output(args, format_output_table(format, fmt_lines, &mut columns))
+		} else {
+			format_output(args, fmt_lines)
+		};
+		if !output.is_empty() && !output.ends_with(['\n','\0']) {
+			let terminator = args.output_terminator(&output);
+			output.push_str(terminator);
+		}
+		let written = out.write_all(output.as_bytes())
+			.and_then(|_| if flush_each_chunk { out.flush() } else { Ok(()) });
+		match written {
+			// The reader has all it wants, so there's no point in reading any further
+			Err(_) if output_closed() => return Ok(()),
+			Err(e) => return Err(e.to_string()),
+			Ok(()) => {}
+		}
+		if interrupt::interrupted() || output_closed() {
+			return Ok(())
+		}
+	}
+}
+
+/// The pathway for when the `--stream` flag is set
+fn exec_stream(args: &Opts) {
+	let run = || {
+		let mut stdout = stdout_writer(args);
+		execute_stream(interrupt::Interruptible(io::stdin().lock()), &mut stdout, args)
+	};
+	let result = match args.max_jobs {
+		Some(num) => par::with_max_jobs(num, run).and_then(|result| result),
+		None => run()
+	};
+	if let Err(e) = result {
+		eprintln!("vicut: {e}");
+		set_exit_code(1);
+	}
+}
+
 /// The pathway for when the `--linewise` flag is set
 ///
 /// Each route in this function operates on individual lines from the input
 fn exec_linewise(args: &Opts) {
 	if args.single_thread {
-		let mut stdout = io::stdout().lock();
+		let mut stdout = stdout_writer(args);
 
 		// We need to initialize stream in each branch, since Box<dyn BufReader> does not implement send/sync
 		// So using it in pool.install() doesn't work. We have to initialize it in the closure there.
 		let mut lines = vec![];
 		let mut json_data = vec![];
+		let mut completed = 0;
 		if !args.files.is_empty() {
 			for path in &args.files {
-				let input = fs::read_to_string(path).unwrap_or_else(complain_and_exit);
-				for line in get_lines(&input) {
-					match execute(args,line, Some(path.clone())) {
-						Ok(mut new_line) => {
+				let input = read_file(args, path).unwrap_or_else(complain_and_exit);
+				let mut cut_short = false;
+				for line in args.split_input(&input) {
+					match execute_unit(args,line, Some(path.clone())) {
+						Some(Ok(mut new_line)) => {
 							lines.append(&mut new_line);
+							completed += 1;
 						}
-						Err(e) => {
+						Some(Err(e)) => {
 							eprintln!("vicut: {e}");
 							return;
 						}
+						None => {
+							cut_short = true;
+							break
+						}
 					}
 				}
-				if args.json {
+				interrupt::record_progress(completed, None);
+				if cut_short && args.edit_inplace {
+					eprintln!("vicut: leaving '{}' untouched, since it was interrupted", path.display());
+					break
+				}
+				if cut_short && args.out_template.is_some() {
+					eprintln!("vicut: not writing the output for '{}', since it was interrupted", path.display());
+					break
+				}
+				if args.json && args.out_template.is_none() {
 					json_data.push((path.clone(), std::mem::take(&mut lines)));
+					if cut_short {
+						break
+					}
 					continue
 				}
-				let mut output = format_output(args, std::mem::take(&mut lines));
+				let output = format_output(args, std::mem::take(&mut lines));
 				if args.edit_inplace {
-					if args.backup_files {
-						let extension = args.backup_extension.as_deref().unwrap_or("bak");
-						let backup_path = path.with_extension(format!(
-								"{}.{extension}",
-								path.extension()
-								.and_then(|ext| ext.to_str())
-								.unwrap_or("")
-						));
-
-						fs::copy(path, &backup_path).unwrap_or_else(complain_and_exit);
+					if let Err(e) = verify_roundtrip(args, path, &output) {
+						eprintln!("vicut: refusing to write '{}': {e}", path.display());
+						set_exit_code(1);
+						continue
+					}
+					if args.dry_run {
+						print_dry_run_diff(&mut stdout, args, path, &output);
+						continue
 					}
-					fs::write(path, std::mem::take(&mut output)).unwrap_or_else(complain_and_exit);
+					write_in_place(args, path, &output);
+				} else if let Some(template) = &args.out_template {
+					write_out_template(template, path, &output);
 				} else {
 					if args.files.len() > 1 {
-						writeln!(stdout,"--- {}", path.display()).ok();
+						write!(stdout, "--- {}{}", path.display(), args.line_ending()).ok();
 					}
-					writeln!(stdout, "{output}").ok();
+					write!(stdout, "{output}{}", args.output_terminator(&output)).ok();
+					stdout.end_record();
+				}
+				if cut_short || output_closed() {
+					break
 				}
 			}
-			if !args.json {
+			if !args.json || args.out_template.is_some() {
 				// If we are not outputting JSON, we can just return here
 				return;
 			}
-			let json = format_output_json_files(json_data);
+			let json = finalize_output(args, format_output_json_files(json_data));
 			write!(stdout, "{json}").ok();
 		} else {
-			let mut stream: Box<dyn BufRead> = Box::new(io::BufReader::new(io::stdin()));
+			let mut stream: Box<dyn BufRead> = Box::new(io::BufReader::new(interrupt::Interruptible(io::stdin())));
 			let mut input = String::new();
 			stream.read_to_string(&mut input).unwrap_or_else(complain_and_exit);
-			for line in get_lines(&input) {
-				match execute(args,line, None) {
-					Ok(mut new_line) => {
+			let records = args.split_input(&input);
+			let total = records.len();
+			for line in records {
+				match execute_unit(args,line, None) {
+					Some(Ok(mut new_line)) => {
 						lines.append(&mut new_line);
+						completed += 1;
 					}
-					Err(e) => {
+					Some(Err(e)) => {
 						eprintln!("vicut: {e}");
 						return;
 					}
+					None => break
 				}
 			}
+			interrupt::record_progress(completed, Some(total));
 		}
 		let output = format_output(args, lines);
-		writeln!(stdout, "{output}").ok();
+		write!(stdout, "{output}{}", args.output_terminator(&output)).ok();
 
 	} else if let Some(num) = args.max_jobs {
-		let pool = rayon::ThreadPoolBuilder::new()
-			.num_threads(num as usize)
-			.build()
-			.unwrap_or_else(complain_and_exit);
-		pool.install(|| {
-			let mut stdout = io::stdout().lock();
+		par::with_max_jobs(num, || {
+			let mut stdout = stdout_writer(args);
 			let output = if !args.files.is_empty() {
 				execute_multi_thread_files_linewise(stdout, args);
 				// Output has already been handled
-				std::process::exit(0);
+				finish();
 			} else {
-				let stream: Box<dyn BufRead> = Box::new(io::BufReader::new(io::stdin()));
+				let stream: Box<dyn BufRead> = Box::new(io::BufReader::new(interrupt::Interruptible(io::stdin())));
 				execute_linewise(stream, args)
 			};
-			writeln!(stdout, "{output}").ok();
-		});
+			write!(stdout, "{output}{}", args.output_terminator(&output)).ok();
+		}).unwrap_or_else(complain_and_exit);
 	} else {
-		let mut stdout = io::stdout().lock();
+		let mut stdout = stdout_writer(args);
 		let output = if !args.files.is_empty() {
 			execute_multi_thread_files_linewise(stdout, args);
 			// Output has already been handled
-			std::process::exit(0);
+			finish();
 		} else {
-			let stream: Box<dyn BufRead> = Box::new(io::BufReader::new(io::stdin()));
+			let stream: Box<dyn BufRead> = Box::new(io::BufReader::new(interrupt::Interruptible(io::stdin())));
 			execute_linewise(stream, args)
 		};
-		writeln!(stdout, "{output}").ok();
+		write!(stdout, "{output}{}", args.output_terminator(&output)).ok();
 	}
 
 }
@@ -1578,54 +4499,57 @@ fn exec_linewise(args: &Opts) {
 fn exec_files(args: &Opts) {
 	let mut json_data = vec![];
 	if args.single_thread {
-		let mut stdout = io::stdout().lock();
+		let mut stdout = stdout_writer(args);
+		let mut completed = 0;
 		for path in &args.files {
-			let content = fs::read_to_string(path).unwrap_or_else(complain_and_exit);
-			match execute(args,content, Some(path.clone())) {
+			let content = read_file(args, path).unwrap_or_else(complain_and_exit);
+			let Some(result) = execute_unit(args,content, Some(path.clone())) else {
+				break
+			};
+			completed += 1;
+			match result {
 				Ok(output) => {
-					if args.json {
+					if args.json && args.out_template.is_none() {
 						json_data.push((path.clone(), output));
 						continue
 					}
-					let mut output = format_output(args, output);
+					let output = format_output(args, output);
 					if args.edit_inplace {
-						if args.backup_files {
-							let extension = args.backup_extension.as_deref().unwrap_or("bak");
-							let backup_path = path.with_extension(format!(
-									"{}.{extension}",
-									path.extension()
-									.and_then(|ext| ext.to_str())
-									.unwrap_or("")
-							));
-
-							fs::copy(path, &backup_path).unwrap_or_else(complain_and_exit);
+						if let Err(e) = verify_roundtrip(args, path, &output) {
+							eprintln!("vicut: refusing to write '{}': {e}", path.display());
+							set_exit_code(1);
+							continue
+						}
+						if args.dry_run {
+							print_dry_run_diff(&mut stdout, args, path, &output);
+							continue
 						}
-						fs::write(path, std::mem::take(&mut output)).unwrap_or_else(complain_and_exit);
+						write_in_place(args, path, &output);
+					} else if let Some(template) = &args.out_template {
+						write_out_template(template, path, &output);
 					} else {
 						if args.files.len() > 1 {
-							writeln!(stdout,"--- {}", path.display()).ok();
+							write!(stdout, "--- {}{}", path.display(), args.line_ending()).ok();
 						}
-						writeln!(stdout,"{output}").ok();
+						write!(stdout, "{output}{}", args.output_terminator(&output)).ok();
+						stdout.end_record();
 					}
 				}
 				Err(e) => eprintln!("vicut: {e}"),
 			};
 		}
-		if args.json {
-			let json = format_output_json_files(json_data);
+		interrupt::record_progress(completed, Some(args.files.len()));
+		if args.json && args.out_template.is_none() {
+			let json = finalize_output(args, format_output_json_files(json_data));
 			write!(stdout, "{json}").ok();
 		}
 	} else if let Some(num) = args.max_jobs {
-		let pool = rayon::ThreadPoolBuilder::new()
-			.num_threads(num as usize)
-			.build()
-			.unwrap_or_else(complain_and_exit);
-		pool.install(|| {
-			let stdout = io::stdout().lock();
+		par::with_max_jobs(num, || {
+			let stdout = stdout_writer(args);
 			execute_multi_thread_files(stdout, args);
-		});
+		}).unwrap_or_else(complain_and_exit);
 	} else {
-		let stdout = io::stdout().lock();
+		let stdout = stdout_writer(args);
 		execute_multi_thread_files(stdout, args);
 	}
 
@@ -1635,9 +4559,9 @@ fn exec_files(args: &Opts) {
 ///
 /// Simplest of the three routes.
 fn exec_stdin(args: &Opts) {
-	let mut stdout = io::stdout().lock();
+	let mut stdout = stdout_writer(args);
 	let mut lines = vec![];
-	let mut stream: Box<dyn BufRead> = Box::new(io::BufReader::new(io::stdin()));
+	let mut stream: Box<dyn BufRead> = Box::new(io::BufReader::new(interrupt::Interruptible(io::stdin())));
 	let mut input = String::new();
 	match stream.read_to_string(&mut input) {
 		Ok(_) => {}
@@ -1646,14 +4570,24 @@ fn exec_stdin(args: &Opts) {
 			return;
 		}
 	}
-	match execute(args,input, None) {
-		Ok(mut output) => {
+	let empty_input = input.is_empty();
+	match execute_unit(args,input, None) {
+		Some(Ok(mut output)) => {
 			lines.append(&mut output);
 		}
-		Err(e) => eprintln!("vicut: {e}"),
+		Some(Err(e)) => eprintln!("vicut: {e}"),
+		None => {
+			interrupt::record_progress(0, Some(1));
+			return
+		}
 	};
+	interrupt::record_progress(1, Some(1));
 	let output = format_output(args, lines);
-	writeln!(stdout,"{output}").ok();
+	if empty_input && output.is_empty() {
+		// Nothing in, nothing out. Don't print a stray newline.
+		return
+	}
+	write!(stdout, "{output}{}", args.output_terminator(&output)).ok();
 
 }
 
@@ -1684,7 +4618,15 @@ fn print_help_or_version() {
 		std::process::exit(0);
 	}
 	if std::env::args().any(|arg| arg == "--help" || arg == "-h") {
-		print!("{}",get_help());
+		print_stdout(&get_help());
+		std::process::exit(0);
+	}
+	let verbose = std::env::args().any(|arg| arg == "--verbose");
+	if std::env::args().any(|arg| arg == "--build-info")
+	|| (verbose && std::env::args().any(|arg| arg == "--version")) {
+		let mut info = build_info::as_json();
+		info.sort_all_objects();
+		println!("{}", serde_json::to_string_pretty(&info).unwrap());
 		std::process::exit(0);
 	}
 	if std::env::args().any(|arg| arg == "--version") {
@@ -1694,6 +4636,179 @@ fn print_help_or_version() {
 
 }
 
+/// Pick an execution pathway for the parsed options, and run it.
+fn run(mut opts: Opts) {
+	init_logger(opts.trace);
+
+	if !par::is_parallel() {
+		// Built without the 'parallel' feature, so the multi-threaded pathways would just be slower serial ones
+		opts.single_thread = true;
+	}
+	if opts.pick {
+		// Only one picker can own the terminal at a time
+		opts.single_thread = true;
+	}
+	opts.check_no_input().unwrap_or_else(complain_and_exit);
+	opts.check_format().unwrap_or_else(complain_and_exit);
+	opts.make_deterministic().unwrap_or_else(complain_and_exit);
+	interrupt::install(opts.on_interrupt).unwrap_or_else(complain_and_exit);
+	if opts.stats {
+		stats::enable(opts.linewise || opts.stream);
+	}
+
+	if let Some(limit) = opts.regex_size_limit {
+		REGEX_SIZE_LIMIT.store(limit, Ordering::Relaxed);
+	}
+
+	if let Some(dir) = opts.files.iter().find(|path| path.is_dir()) {
+		if !opts.recursive {
+			complain_and_exit::<()>(format!("vicut: '{}' is a directory, use -R to read the files under it", dir.display()));
+		}
+		opts.files = walk_files(&opts.files, opts.hidden);
+		if opts.files.is_empty() {
+			// Everything was ignored, which is not the same as being asked to read stdin
+			finish();
+		}
+	}
+
+	if let Some(list) = opts.files_from.as_deref() {
+		if is_stdin(list) && opts.files.iter().any(|path| is_stdin(path)) {
+			complain_and_exit::<()>("vicut: '-' can't be in the list of files when the list itself is read from stdin");
+		}
+		if opts.files.is_empty() {
+			// An empty list is not the same as being asked to read stdin
+			finish();
+		}
+	}
+	if opts.edit_inplace && opts.files.iter().any(|path| is_stdin(path)) {
+		complain_and_exit::<()>("vicut: '-' reads from stdin, which can't be edited in place");
+	}
+	if opts.out_template.is_some() {
+		if opts.edit_inplace {
+			complain_and_exit::<()>("vicut: '--out-template' and '-i' can't be used together");
+		}
+		if opts.files.iter().any(|path| is_stdin(path)) {
+			complain_and_exit::<()>("vicut: '-' reads from stdin, which has no name for '--out-template' to use");
+		}
+	}
+
+	if let Some(selection) = opts.git {
+		let files = git_files(selection).unwrap_or_else(complain_and_exit);
+		if files.is_empty() && opts.files.is_empty() {
+			// Nothing was selected, which is not the same as being asked to read stdin
+			finish();
+		}
+		opts.files.extend(files);
+	}
+
+	if !opts.files.is_empty() {
+		let files = std::mem::take(&mut opts.files);
+		opts.files = files.into_iter()
+			.filter(|path| match skip_reason(&opts, path) {
+				Some(reason) => {
+					eprintln!("vicut: skipping '{}': {reason}", path.display());
+					false
+				}
+				None => true
+			})
+			.collect();
+		if opts.files.is_empty() {
+			// Every file was skipped, which is not the same as being asked to read stdin
+			finish();
+		}
+	}
+
+	if opts.wait_for_lock && opts.no_clobber {
+		complain_and_exit::<()>("vicut: '--wait' and '--no-clobber' can't be used together");
+	}
+	if opts.edit_inplace && !opts.dry_run && !opts.files.is_empty() {
+		check_locks(&mut opts).unwrap_or_else(complain_and_exit);
+		if opts.files.is_empty() {
+			// Every file was locked, and there is nothing left to edit
+			finish();
+		}
+	}
+
+	if let Some(path) = opts.cache.as_ref().filter(|_| opts.edit_inplace && !opts.dry_run) {
+		let cache = EditCache::load(path, &opts).unwrap_or_else(complain_and_exit);
+		let files = std::mem::take(&mut opts.files);
+		opts.files = files.into_iter()
+			.filter(|path| !cache.is_done(path))
+			.collect();
+		*EDIT_CACHE.lock().unwrap() = Some(cache);
+		if opts.files.is_empty() {
+			// Everything is up to date
+			finish();
+		}
+	}
+
+	if opts.json_input {
+		if opts.json_field.is_none() {
+			complain_and_exit::<()>("vicut: '--json-input' needs a '--field' to operate on");
+		}
+		if opts.json || opts.format.is_some() || opts.template.is_some() || opts.freq.is_some() {
+			complain_and_exit::<()>("vicut: '--json-input' prints each record as JSON, so it can't be combined with another output format");
+		}
+		// JSON Lines are one record per line
+		opts.linewise = true;
+	} else if opts.json_field.is_some() {
+		complain_and_exit::<()>("vicut: '--field' only has a meaning with '--json-input'");
+	}
+
+	if opts.record_start.is_some() {
+		if opts.record_sep.is_some() || opts.read0 {
+			complain_and_exit::<()>("vicut: '--record-start' splits the input into lines, so it can't be combined with '--record-sep' or '--read0'");
+		}
+		// Each record is worked on by itself, like the lines of --linewise
+		opts.linewise = true;
+	}
+
+	if opts.freq.is_some() && opts.edit_inplace {
+		complain_and_exit::<()>("vicut: '--freq' cannot be used while editing files in place");
+	}
+
+	if opts.emit_schema {
+		let mut schema = record_schema(&opts);
+		schema.sort_all_objects();
+		print_stdout(&format!("{}\n", serde_json::to_string_pretty(&schema).unwrap()));
+		return
+	}
+
+	if opts.out_template.is_some() && opts.files.is_empty() {
+		complain_and_exit::<()>("vicut: '--out-template' needs input files to name its output after");
+	}
+	if let Some(template) = opts.out_template.as_deref() {
+		check_out_template(template, &opts.files).unwrap_or_else(complain_and_exit);
+	}
+
+	if let Some(out_file) = opts.out_file.as_deref()
+	&& opts.files.iter().any(|path| same_file(path, out_file)) {
+		// The output file is truncated before any input is read
+		complain_and_exit::<()>(format!("vicut: can't write the output to '{}', since it is also an input file", out_file.display()));
+	}
+
+	if opts.no_input {
+		if let Some(output) = execute_unit(&opts, String::new(), None) {
+			let output = output.unwrap_or_else(complain_and_exit);
+			let mut stdout = stdout_writer(&opts);
+			let output = format_output(&opts, output);
+			if !output.is_empty() {
+				write!(stdout, "{output}{}", opts.output_terminator(&output)).ok();
+			}
+		}
+	} else if opts.stream && opts.files.is_empty() {
+		exec_stream(&opts);
+	} else if opts.linewise || opts.stream {
+		exec_linewise(&opts);
+	} else if !opts.files.is_empty() {
+		exec_files(&opts);
+	} else {
+		exec_stdin(&opts);
+	}
+
+	finish();
+}
+
 fn main_script() {
 	// Is it a script file? or an in-line script?
 	let maybe_script = std::env::args().nth(1).unwrap();
@@ -1706,22 +4821,9 @@ fn main_script() {
 		let script_path = PathBuf::from(maybe_script);
 		Opts::from_script(script_path).unwrap_or_else(complain_and_exit)
 	};
+	opts.check_sandbox().unwrap_or_else(complain_and_exit);
 
-
-	init_logger(opts.trace);
-
-	if opts.no_input {
-		let output = execute(&opts, String::new(), None).unwrap_or_else(complain_and_exit);
-		let mut stdout = io::stdout().lock();
-		let output = format_output(&opts, output);
-		write!(stdout, "{output}").ok();
-	} else if opts.linewise {
-		exec_linewise(&opts);
-	} else if !opts.files.is_empty() {
-		exec_files(&opts);
-	} else {
-		exec_stdin(&opts);
-	}
+	run(opts);
 }
 
 #[allow(unreachable_code)]
@@ -1740,13 +4842,13 @@ fn main() {
 	args.find(|arg| arg == "--script"); // let's find the --script flag
 	let script = args.next(); // If we found it, the next arg is the script name
 
-	let opts = if let Some(script) = script {
+	let mut opts = if let Some(script) = script {
 		let script = PathBuf::from(script);
 		Opts::from_script(script).unwrap_or_else(complain_and_exit)
 	} else {
 		// Let's see if we got a literal in-line script instead then
 		let mut flags = std::env::args().take_while(|arg| arg != "--");
-		let use_inline = flags.all(|arg| !arg.starts_with('-'));
+		let use_inline = flags.all(|arg| !arg.starts_with('-') || arg == STDIN_PATH);
 
 		if use_inline {
 			// We know that there's at least one argument, so we can safely unwrap
@@ -1763,7 +4865,7 @@ fn main() {
 			};
 			// Now let's grab the file names
 			for arg in args {
-				if let Err(e) = Opts::validate_filename(&arg) {
+				if !is_stdin(Path::new(&arg)) && !Path::new(&arg).is_dir() && let Err(e) = Opts::validate_filename(&arg) {
 					eprintln!("vicut: {e}");
 					std::process::exit(1);
 				}
@@ -1776,19 +4878,14 @@ fn main() {
 			Opts::parse().unwrap_or_else(complain_and_exit)
 		}
 	};
-
-	init_logger(opts.trace);
-
-	if opts.no_input {
-		let output = execute(&opts, String::new(), None).unwrap_or_else(complain_and_exit);
-		let mut stdout = io::stdout().lock();
-		let output = format_output(&opts, output);
-		write!(stdout, "{output}").ok();
-	} else if opts.linewise {
-		exec_linewise(&opts);
-	} else if !opts.files.is_empty() {
-		exec_files(&opts);
-	} else {
-		exec_stdin(&opts);
+	if std::env::args().any(|arg| arg == "--sandbox") {
+		// Scripts can't lift themselves out of the sandbox
+		opts.sandbox = true;
 	}
+	if std::env::args().any(|arg| arg == "--no-shell") {
+		opts.no_shell = true;
+	}
+	opts.check_sandbox().unwrap_or_else(complain_and_exit);
+
+	run(opts);
 }
@@ -3,7 +3,7 @@ use std::{iter::Peekable, path::PathBuf, str::Chars};
 use bitflags::bitflags;
 use itertools::Itertools;
 
-use crate::{exec::Val, modes::{common_cmds, ModeReport, ViMode}, vicmd::{Anchor, CmdFlags, LineAddr, Motion, MotionCmd, ReadSrc, RegisterName, Verb, VerbCmd, ViCmd, WriteDest}};
+use crate::{exec::Val, modes::{common_cmds, ModeReport, ViMode}, vicmd::{Anchor, CmdFlags, Direction, LineAddr, Motion, MotionCmd, ReadSrc, RegisterName, Verb, VerbCmd, ViCmd, WriteDest}};
 
 bitflags! {
 	#[derive(Debug,Clone,Copy,PartialEq,Eq)]
@@ -16,6 +16,7 @@ bitflags! {
 		const PRINT_RESULT     = 1 << 5; // p
 		const PRINT_NUMBERED   = 1 << 6; // #
 		const PRINT_LEFT_ALIGN = 1 << 7; // l
+		const WHOLE_WORD       = 1 << 8; // w (only for ':S')
 	}
 }
 
@@ -161,48 +162,79 @@ fn parse_ex_cmd(raw: &str, select_range: Option<(usize,usize)>) -> Result<Option
 	}))
 }
 
+/// Parse the range in front of an ex command, like `%`, `5`, `.,$`, `'a,'b`, or `/foo/+1;/bar/-1`.
+///
+/// A missing address is the current line, so `,5` is `.,5`. With `;` instead of `,`, the second address is found starting from the first one.
+/// When more than two addresses are given, only the last two are used, like in Vim.
 fn parse_ex_address(chars: &mut Peekable<Chars<'_>>) -> Result<Option<Motion>,Option<String>> {
 	if chars.peek() == Some(&'%') {
 		chars.next();
 		return Ok(Some(Motion::LineRange(LineAddr::Number(1),LineAddr::Last)))
 	}
 	let mut chars_clone = chars.clone();
-	let Some(start) = parse_one_addr(&mut chars_clone)? else { return Ok(None) };
-	if let Some(&',') = chars_clone.peek() {
-		chars_clone.next();
-		let Some(end) = parse_one_addr(&mut chars_clone)? else { return Ok(Some(Motion::Line(start))) };
-		*chars = chars_clone;
-		Ok(Some(Motion::LineRange(start, end)))
-	} else {
-		*chars = chars_clone;
-		Ok(Some(Motion::Line(start)))
+	let first = parse_one_addr(&mut chars_clone)?;
+	let mut range: Option<(LineAddr,LineAddr)> = None;
+	let mut prev = first.clone();
+	while let Some(sep) = chars_clone.next_if(|ch| *ch == ',' || *ch == ';') {
+		let start = prev.take().unwrap_or(LineAddr::Current);
+		let mut end = parse_one_addr(&mut chars_clone)?.unwrap_or(LineAddr::Current);
+		if sep == ';' {
+			end = LineAddr::From(Box::new(start.clone()), Box::new(end));
+		}
+		prev = Some(end.clone());
+		range = Some((start,end));
 	}
+	let motion = match (range,first) {
+		(Some((start,end)),_) => Motion::LineRange(start, end),
+		(None,Some(addr)) => Motion::Line(addr),
+		(None,None) => return Ok(None)
+	};
+	*chars = chars_clone;
+	Ok(Some(motion))
 }
 
+/// Parse one address: a line number, `.`, `$`, a mark, or a search, followed by any number of `+N` or `-N` offsets and further searches.
+///
+/// Offsets on their own are relative to the current line, and a number right after an address is added to it, so `.5` is `.+5`.
 fn parse_one_addr(chars: &mut Peekable<Chars<'_>>) -> Result<Option<LineAddr>,Option<String>> {
-	let Some(first) = chars.next() else { return Ok(None) };
+	let mut addr = parse_addr_base(chars)?;
+	loop {
+		match (chars.peek(), addr.take()) {
+			(Some('+' | '-'), prev) => {
+				let offset = parse_addr_offset(chars)?;
+				addr = Some(match prev {
+					Some(prev) => LineAddr::Relative(Box::new(prev), offset),
+					None => LineAddr::Offset(offset)
+				});
+			}
+			(Some('0'..='9'), Some(prev)) => {
+				let digits = chars.peeking_take_while(|c| c.is_ascii_digit()).collect::<String>();
+				let offset = digits.parse::<isize>().map_err(|_| None)?;
+				addr = Some(LineAddr::Relative(Box::new(prev), offset));
+			}
+			(Some('/' | '?'), Some(prev)) => {
+				let search = parse_addr_base(chars)?.ok_or(None)?;
+				addr = Some(LineAddr::From(Box::new(prev), Box::new(search)));
+			}
+			(_, prev) => return Ok(prev)
+		}
+	}
+}
+
+/// Parse the part of an address that comes before any offsets. Nothing is consumed if there isn't one.
+fn parse_addr_base(chars: &mut Peekable<Chars<'_>>) -> Result<Option<LineAddr>,Option<String>> {
+	let Some(&first) = chars.peek() else { return Ok(None) };
 	match first {
 		'0'..='9' => {
-			let mut digits = String::new();
-			digits.push(first);
-			digits.extend(chars.peeking_take_while(|c| c.is_ascii_digit()));
+			let digits = chars.peeking_take_while(|c| c.is_ascii_digit()).collect::<String>();
 
 			let number = digits.parse::<usize>()
 				.map_err(|_| None)?;
 
 			Ok(Some(LineAddr::Number(number)))
 		}
-		'+' | '-' => {
-			let mut digits = String::new();
-			digits.push(first);
-			digits.extend(chars.peeking_take_while(|c| c.is_ascii_digit()));
-
-			let number = digits.parse::<isize>()
-				.map_err(|_| None)?;
-
-			Ok(Some(LineAddr::Offset(number)))
-		}
 		'/' | '?' => {
+			chars.next();
 			let mut pattern = String::new();
 			while let Some(ch) = chars.next() {
 				match ch {
@@ -221,14 +253,54 @@ fn parse_one_addr(chars: &mut Peekable<Chars<'_>>) -> Result<Option<LineAddr>,Op
 				'?' => Ok(Some(LineAddr::PatternRev(pattern))),
 				_ => unreachable!()
 			}
-
 		}
-		'.' => Ok(Some(LineAddr::Current)),
-		'$' => Ok(Some(LineAddr::Last)),
+		'\'' => {
+			chars.next();
+			match chars.next() {
+				Some(mark) if mark.is_ascii_lowercase() || mark == '<' || mark == '>' => Ok(Some(LineAddr::Mark(mark))),
+				Some(mark) => Err(Some(format!("Invalid mark '{mark}' in ex range"))),
+				None => Err(Some("Expected a mark after ' in ex range".into()))
+			}
+		}
+		'\\' => {
+			let mut lookahead = chars.clone();
+			lookahead.next();
+			let addr = match lookahead.next() {
+				Some('/') => LineAddr::LastSearch(Direction::Forward),
+				Some('?') => LineAddr::LastSearch(Direction::Backward),
+				Some('&') => LineAddr::LastSubstitute,
+				_ => return Ok(None)
+			};
+			*chars = lookahead;
+			Ok(Some(addr))
+		}
+		'.' => {
+			chars.next();
+			Ok(Some(LineAddr::Current))
+		}
+		'$' => {
+			chars.next();
+			Ok(Some(LineAddr::Last))
+		}
 		_ => Ok(None)
 	}
 }
 
+/// Parse a run of offsets like `+2`, `-`, or `++-3`, which add up. A sign without a number is one line.
+fn parse_addr_offset(chars: &mut Peekable<Chars<'_>>) -> Result<isize,Option<String>> {
+	let mut total: isize = 0;
+	while let Some(sign) = chars.next_if(|ch| *ch == '+' || *ch == '-') {
+		let digits = chars.peeking_take_while(|c| c.is_ascii_digit()).collect::<String>();
+		let amount = if digits.is_empty() {
+			1
+		} else {
+			digits.parse::<isize>().map_err(|_| None)?
+		};
+		total += if sign == '-' { -amount } else { amount };
+	}
+	Ok(total)
+}
+
 /// Unescape shell command arguments
 fn unescape_shell_cmd(cmd: &str) -> String {
 	// The pest grammar uses double quotes for vicut commands
@@ -273,6 +345,7 @@ fn parse_ex_command(chars: &mut Peekable<Chars<'_>>) -> Result<Option<Verb>,Opti
 			Ok(Some(Verb::ShellCmd(cmd)))
 		}
 		"normal!" => parse_normal(chars),
+		"S" | "Subvert" => parse_subvert(chars),
 		_ if "delete".starts_with(&cmd_name) => Ok(Some(Verb::Delete)),
 		_ if "yank".starts_with(&cmd_name) => Ok(Some(Verb::Yank)),
 		_ if "put".starts_with(&cmd_name) => Ok(Some(Verb::Put(Anchor::After))),
@@ -384,6 +457,29 @@ fn parse_substitute(chars: &mut Peekable<Chars<'_>>) -> Result<Option<Verb>,Opti
 	Ok(Some(Verb::Substitute(old_pat, new_pat, flags)))
 }
 
+/// Parse ':S', the smart substitute from vim-abolish. Patterns are literal text with brace groups, not regexes.
+fn parse_subvert(chars: &mut Peekable<Chars<'_>>) -> Result<Option<Verb>,Option<String>> {
+	chars.peeking_take_while(|c| c.is_whitespace()).for_each(drop); // Ignore whitespace
+
+	let Some(delimiter) = chars.next() else {
+		return Err(Some("Expected a pattern after ':S'".into()))
+	};
+	if delimiter.is_alphanumeric() {
+		return Err(None)
+	}
+	let old_pat = parse_pattern(chars, delimiter)?;
+	let new_pat = parse_pattern(chars, delimiter)?;
+	let mut flags = SubFlags::empty();
+	for ch in chars.by_ref() {
+		match ch {
+			'g' => flags |= SubFlags::GLOBAL,
+			'w' => flags |= SubFlags::WHOLE_WORD,
+			_ => return Err(None)
+		}
+	}
+	Ok(Some(Verb::SmartSubstitute(old_pat, new_pat, flags)))
+}
+
 fn parse_pattern(chars: &mut Peekable<Chars<'_>>, delimiter: char) -> Result<String,Option<String>> {
 	let mut pat = String::new();
 	let mut closed = false;
@@ -1,12 +1,22 @@
-use crate::vicmd::{Direction, Motion, MotionCmd, To, Verb, VerbCmd, ViCmd, Word};
+use crate::vicmd::{Direction, Motion, MotionCmd, RegisterName, To, Verb, VerbCmd, ViCmd, Word};
 use crate::keys::{KeyEvent as E, KeyCode as K, ModKeys as M};
 
 use super::{common_cmds, CmdReplay, ModeReport, ViMode};
 
+/// How far along a `<c-r>` is
+#[derive(Debug)]
+enum PendingRegister {
+	/// Waiting for the name of the register
+	Name,
+	/// `<c-r>=`, reading the expression up to Enter
+	Expr(String)
+}
+
 #[derive(Default,Debug)]
 pub struct ViInsert {
 	cmds: Vec<ViCmd>,
 	pending_cmd: ViCmd,
+	pending_register: Option<PendingRegister>,
 	repeat_count: u16
 }
 
@@ -39,10 +49,52 @@ impl ViInsert {
 	pub fn take_cmd(&mut self) -> ViCmd {
 		std::mem::take(&mut self.pending_cmd)
 	}
+	/// Handle the keys that follow `<c-r>`, which name the register to insert.
+	///
+	/// `<c-r>=` reads an expression up to Enter instead, and the register is filled with its result when the command runs.
+	fn handle_register_key(&mut self, pending: PendingRegister, key: E) -> Option<ViCmd> {
+		match (pending, key) {
+			(PendingRegister::Name, E(K::Char('='), M::NONE)) => {
+				self.pending_register = Some(PendingRegister::Expr(String::new()));
+				None
+			}
+			(PendingRegister::Name, E(K::Char(ch @ ('a'..='z' | 'A'..='Z')), M::NONE)) => self.insert_register(ch, String::new()),
+			// Like Vim, anything else cancels it
+			(PendingRegister::Name, _) => None,
+			(PendingRegister::Expr(expr), E(K::Enter, M::NONE) | E(K::Char('\r'), M::NONE)) => {
+				// Written the same way as '"=' in normal mode, which is where the expression is read from
+				self.insert_register('=', format!("\"={expr}\r"))
+			}
+			(PendingRegister::Expr(_), E(K::Esc, M::NONE)) => None,
+			(PendingRegister::Expr(mut expr), E(K::Backspace, M::NONE)) => {
+				expr.pop();
+				self.pending_register = Some(PendingRegister::Expr(expr));
+				None
+			}
+			(PendingRegister::Expr(mut expr), E(K::Char(ch), M::NONE)) => {
+				expr.push(ch);
+				self.pending_register = Some(PendingRegister::Expr(expr));
+				None
+			}
+			(pending, _) => {
+				self.pending_register = Some(pending);
+				None
+			}
+		}
+	}
+	fn insert_register(&mut self, name: char, raw_seq: String) -> Option<ViCmd> {
+		self.pending_cmd.register = RegisterName::new(Some(name), None);
+		self.pending_cmd.raw_seq = raw_seq;
+		self.pending_cmd.set_verb(VerbCmd(1,Verb::InsertRegister));
+		self.register_and_return()
+	}
 }
 
 impl ViMode for ViInsert {
 	fn handle_key(&mut self, key: E) -> Option<ViCmd> {
+		if let Some(pending) = self.pending_register.take() {
+			return self.handle_register_key(pending, key)
+		}
 		match key {
 			// Carriage return -> newline
 			E(K::Enter, M::NONE) |
@@ -61,6 +113,10 @@ impl ViMode for ViInsert {
 				self.pending_cmd.set_motion(MotionCmd(1, Motion::WordMotion(To::Start, Word::Normal, Direction::Backward)));
 				self.register_and_return()
 			}
+			E(K::Char('R'), M::CTRL) => {
+				self.pending_register = Some(PendingRegister::Name);
+				None
+			}
 			E(K::Char('H'), M::CTRL) |
 			E(K::Backspace, M::NONE) => {
 				self.pending_cmd.set_verb(VerbCmd(1,Verb::Delete));
@@ -90,6 +90,12 @@ pub trait ViMode {
 	}
 }
 
+/// Whether `seq` is in the middle of an expression for the `"=` register, which Enter finishes.
+pub fn is_pending_expr(seq: &str) -> bool {
+	let seq = seq.trim_start_matches(|ch: char| ch.is_ascii_digit());
+	seq.strip_prefix("\"=").is_some_and(|expr| !expr.contains('\r'))
+}
+
 pub fn common_cmds(key: E) -> Option<ViCmd> {
 	let mut pending_cmd = ViCmd::new();
 	match key {
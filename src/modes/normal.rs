@@ -1,9 +1,9 @@
 use std::{iter::Peekable, str::Chars};
 
-use crate::vicmd::{Anchor, Bound, CmdFlags, Dest, Direction, Motion, MotionCmd, RegisterName, TextObj, To, Verb, VerbCmd, ViCmd, Word};
+use crate::vicmd::{Anchor, Bound, Case, CmdFlags, Dest, Direction, Motion, MotionCmd, RegisterName, TextObj, To, Verb, VerbCmd, ViCmd, Word};
 use crate::keys::{KeyEvent as E, KeyCode as K, ModKeys as M};
 
-use super::{common_cmds, CmdReplay, CmdState, ModeReport, ViMode};
+use super::{common_cmds, is_pending_expr, CmdReplay, CmdState, ModeReport, ViMode};
 
 const CTRL_V: char = '\x16'; // Control-V
 
@@ -94,6 +94,10 @@ impl ViNormal {
 			match reg_name {
 				'a'..='z' |
 				'A'..='Z' => { /* proceed */ }
+				// "=<expr><CR>, the expression is evaluated when the command runs
+				'=' => if !chars_clone.by_ref().any(|ch| ch == '\r') {
+					return None // Pending expression
+				}
 				_ => return self.quit_parse()
 			}
 
@@ -127,7 +131,7 @@ impl ViNormal {
 								return Some(
 									ViCmd {
 										register,
-										verb: Some(VerbCmd(1, Verb::VisualModeSelectLast)),
+										verb: Some(VerbCmd(count, Verb::VisualModeSelectLast)),
 										motion: None,
 										raw_seq: self.take_cmd(),
 										flags: self.flags(),
@@ -154,6 +158,11 @@ impl ViNormal {
 								chars = chars_clone;
 								break 'verb_parse Some(VerbCmd(count, Verb::Rot13));
 							}
+							'q' => {
+								chars_clone.next();
+								chars = chars_clone;
+								break 'verb_parse Some(VerbCmd(count, Verb::Format));
+							}
 							_ => break 'verb_parse None
 						}
 					} else {
@@ -264,6 +273,51 @@ impl ViNormal {
 						}
 					)
 				}
+				'm' => {
+					let mark = chars_clone.next()?;
+					if !mark.is_ascii_lowercase() {
+						return self.quit_parse()
+					}
+					return Some(
+						ViCmd {
+							register,
+							verb: Some(VerbCmd(1, Verb::SetMark(mark))),
+							motion: None,
+							raw_seq: self.take_cmd(),
+							flags: self.flags()
+						}
+					)
+				}
+				'q' => {
+					let reg = chars_clone.next()?;
+					if !reg.is_ascii_alphabetic() {
+						return self.quit_parse()
+					}
+					return Some(
+						ViCmd {
+							register,
+							verb: Some(VerbCmd(1, Verb::RecordMacro(reg))),
+							motion: None,
+							raw_seq: self.take_cmd(),
+							flags: self.flags()
+						}
+					)
+				}
+				'@' => {
+					let reg = chars_clone.next()?;
+					if !reg.is_ascii_alphabetic() && reg != '@' {
+						return self.quit_parse()
+					}
+					return Some(
+						ViCmd {
+							register,
+							verb: Some(VerbCmd(count, Verb::PlayMacro(reg))),
+							motion: None,
+							raw_seq: self.take_cmd(),
+							flags: self.flags()
+						}
+					)
+				}
 				'r' => {
 					let ch = chars_clone.next()?;
 					return Some(
@@ -428,6 +482,16 @@ impl ViNormal {
 					break 'verb_parse Some(VerbCmd(count, Verb::Delete))
 				}
 				'c' => {
+					// 'cr' followed by a case, like 'crs', coerces identifiers instead
+					if chars_clone.peek() == Some(&'r') {
+						chars_clone.next();
+						let key = chars_clone.next()?;
+						let Some(case) = Case::from_key(key) else {
+							return self.quit_parse()
+						};
+						chars = chars_clone;
+						break 'verb_parse Some(VerbCmd(count, Verb::CoerceCase(case)))
+					}
 					chars = chars_clone;
 					break 'verb_parse Some(VerbCmd(count, Verb::Change))
 				}
@@ -472,6 +536,21 @@ impl ViNormal {
 			}
 		};
 
+		// 'v', 'V', or Ctrl-V between an operator and its motion force the motion to be charwise, linewise, or blockwise
+		let mut flags = self.flags();
+		if verb.is_some() {
+			let forced = match chars.peek() {
+				Some('v') => CmdFlags::VISUAL,
+				Some('V') => CmdFlags::VISUAL_LINE,
+				Some(&CTRL_V) => CmdFlags::VISUAL_BLOCK,
+				_ => CmdFlags::empty()
+			};
+			if !forced.is_empty() {
+				chars.next();
+				flags |= forced;
+			}
+		}
+
 		let motion = 'motion_parse: {
 			let mut chars_clone = chars.clone();
 			let count = self.parse_count(&mut chars_clone).unwrap_or(1);
@@ -487,11 +566,15 @@ impl ViNormal {
 				('c', Some(VerbCmd(_,Verb::Change))) |
 				('y', Some(VerbCmd(_,Verb::Yank))) |
 				('=', Some(VerbCmd(_,Verb::Equalize))) |
+				('q', Some(VerbCmd(_,Verb::Format))) |
 				('u', Some(VerbCmd(_,Verb::ToLower))) |
 				('U', Some(VerbCmd(_,Verb::ToUpper))) |
 				('~', Some(VerbCmd(_,Verb::ToggleCaseRange))) |
 				('>', Some(VerbCmd(_,Verb::Indent))) |
 				('<', Some(VerbCmd(_,Verb::Dedent))) => break 'motion_parse Some(MotionCmd(count, Motion::WholeLineExclusive)),
+				(key, Some(VerbCmd(_,Verb::CoerceCase(case)))) if Case::from_key(key) == Some(*case) => {
+					break 'motion_parse Some(MotionCmd(count, Motion::WholeLineExclusive))
+				}
 				_ => { /* Nothing weird, so let's continue */ }
 			}
 			match ch {
@@ -510,7 +593,11 @@ impl ViNormal {
 						'j' => break 'motion_parse Some(MotionCmd(count, Motion::ScreenLineDown)),
 						'_' => break 'motion_parse Some(MotionCmd(count, Motion::EndOfLastWord)),
 						'0' => break 'motion_parse Some(MotionCmd(count, Motion::BeginningOfScreenLine)),
+						'$' => break 'motion_parse Some(MotionCmd(count, Motion::EndOfScreenLine)),
+						'm' => break 'motion_parse Some(MotionCmd(count, Motion::HalfOfScreen)),
 						'^' => break 'motion_parse Some(MotionCmd(count, Motion::FirstGraphicalOnScreenLine)),
+						';' => break 'motion_parse Some(MotionCmd(count, Motion::ToSubChange(Direction::Backward))),
+						',' => break 'motion_parse Some(MotionCmd(count, Motion::ToSubChange(Direction::Forward))),
 						_ => return self.quit_parse()
 					}
 				}
@@ -520,42 +607,32 @@ impl ViNormal {
 					};
 					match ch {
 						'(' | ')' => break 'motion_parse Some(MotionCmd(count, Motion::ToParen(Direction::Forward))),
+						'n' => break 'motion_parse Some(MotionCmd(count, Motion::ToNumber(Direction::Forward))),
 						'{' | '}' => break 'motion_parse Some(MotionCmd(count, Motion::ToBrace(Direction::Forward))),
 						_ => return self.quit_parse()
 					}
 				}
+				'z' => {
+					let Some(ch) = chars_clone.next() else {
+						break 'motion_parse None
+					};
+					match ch {
+						'j' => break 'motion_parse Some(MotionCmd(count, Motion::ToFold(Direction::Forward))),
+						'k' => break 'motion_parse Some(MotionCmd(count, Motion::ToFold(Direction::Backward))),
+						_ => return self.quit_parse()
+					}
+				}
 				'[' => {
 					let Some(ch) = chars_clone.peek() else {
 						break 'motion_parse None
 					};
 					match ch {
 						'(' | ')' => break 'motion_parse Some(MotionCmd(count, Motion::ToParen(Direction::Backward))),
+						'n' => break 'motion_parse Some(MotionCmd(count, Motion::ToNumber(Direction::Backward))),
 						'{' | '}' => break 'motion_parse Some(MotionCmd(count, Motion::ToBrace(Direction::Backward))),
 						_ => return self.quit_parse()
 					}
 				}
-				'v' => {
-					// We got 'v' after a verb
-					// Instead of normal operations, we will calculate the span based on how visual mode would see it
-					if self.flags().intersects(CmdFlags::VISUAL | CmdFlags::VISUAL_LINE | CmdFlags::VISUAL_BLOCK) {
-						// We can't have more than one of these
-						return self.quit_parse();
-					}
-					self.pending_flags |= CmdFlags::VISUAL;
-					break 'motion_parse None
-				}
-				'V' => {
-					// We got 'V' after a verb
-					// Instead of normal operations, we will calculate the span based on how visual line mode would see it
-					if self.flags().intersects(CmdFlags::VISUAL | CmdFlags::VISUAL_LINE | CmdFlags::VISUAL_BLOCK) {
-						// We can't have more than one of these
-						// I know vim can technically do this, but it doesn't really make sense to allow it
-						// since even in vim only the first one given is used
-						return self.quit_parse();
-					}
-					self.pending_flags |= CmdFlags::VISUAL;
-					break 'motion_parse None
-				}
 				'f' => {
 					let Some(ch) = chars_clone.peek() else {
 						break 'motion_parse None
@@ -563,6 +640,19 @@ impl ViNormal {
 
 					break 'motion_parse Some(MotionCmd(count, Motion::CharSearch(Direction::Forward, Dest::On, *ch)))
 				}
+				'`' | '\'' => {
+					let Some(mark) = chars_clone.peek() else {
+						break 'motion_parse None
+					};
+					if !mark.is_ascii_lowercase() {
+						return self.quit_parse()
+					}
+
+					match ch {
+						'`' => break 'motion_parse Some(MotionCmd(count, Motion::ToMark(*mark))),
+						_ => break 'motion_parse Some(MotionCmd(count, Motion::ToMarkLine(*mark))),
+					}
+				}
 				'F' => {
 					let Some(ch) = chars_clone.peek() else {
 						break 'motion_parse None
@@ -588,6 +678,9 @@ impl ViNormal {
 				'N' => break 'motion_parse Some(MotionCmd(count, Motion::PrevMatch)),
 				'%' => break 'motion_parse Some(MotionCmd(count, Motion::ToDelimMatch)),
 				'G' => break 'motion_parse Some(MotionCmd(count, Motion::EndOfBuffer)),
+				'H' => break 'motion_parse Some(MotionCmd(count, Motion::WindowTop)),
+				'M' => break 'motion_parse Some(MotionCmd(count, Motion::WindowMiddle)),
+				'L' => break 'motion_parse Some(MotionCmd(count, Motion::WindowBottom)),
 				';' => break 'motion_parse Some(MotionCmd(count, Motion::RepeatMotion)),
 				',' => break 'motion_parse Some(MotionCmd(count, Motion::RepeatMotionRev)),
 				'|' => break 'motion_parse Some(MotionCmd(count, Motion::ToColumn)),
@@ -629,7 +722,21 @@ impl ViNormal {
 						'{' | '}' | 'B' => TextObj::Brace(bound),
 						'[' | ']' => TextObj::Bracket(bound),
 						'<' | '>' => TextObj::Angle(bound),
-						_ => return self.quit_parse()
+						't' => TextObj::Tag(bound),
+						'z' => TextObj::Fold(bound),
+						'i' => TextObj::Indent(bound),
+						'a' => TextObj::Arg(bound),
+						'n' => TextObj::Number(bound),
+						'u' => TextObj::Url(bound),
+						'f' => TextObj::Path(bound),
+						'e' => TextObj::Email(bound),
+						'v' => TextObj::KeyValue(bound),
+						'c' => TextObj::CodeFence(bound),
+						'h' => TextObj::Section(bound),
+						'l' => TextObj::ListItem(bound),
+						'q' => TextObj::StringLiteral(bound),
+						'r' => TextObj::SubChange(bound),
+						ch => TextObj::Custom(ch,bound)
 					};
 					break 'motion_parse Some(MotionCmd(count, Motion::TextObj(obj)))
 				}
@@ -648,7 +755,7 @@ impl ViNormal {
 						verb,
 						motion,
 						raw_seq: std::mem::take(&mut self.pending_seq),
-						flags: self.flags()
+						flags
 					}
 				)
 			}
@@ -685,6 +792,10 @@ impl ViNormal {
 			match reg_name {
 				'a'..='z' |
 				'A'..='Z' => { /* proceed */ }
+				// "=<expr><CR>, the expression is evaluated when the command runs
+				'=' => if !chars_clone.by_ref().any(|ch| ch == '\r') {
+					return None // Pending expression
+				}
 				_ => return self.quit_parse()
 			}
 
@@ -718,7 +829,7 @@ impl ViNormal {
 								return Some(
 									ViCmd {
 										register,
-										verb: Some(VerbCmd(1, Verb::VisualModeSelectLast)),
+										verb: Some(VerbCmd(count, Verb::VisualModeSelectLast)),
 										motion: None,
 										raw_seq: self.take_cmd(),
 										flags: self.flags(),
@@ -745,6 +856,11 @@ impl ViNormal {
 								chars = chars_clone;
 								break 'verb_parse Some(VerbCmd(count, Verb::Rot13));
 							}
+							'q' => {
+								chars_clone.next();
+								chars = chars_clone;
+								break 'verb_parse Some(VerbCmd(count, Verb::Format));
+							}
 							_ => break 'verb_parse None
 						}
 					} else {
@@ -822,6 +938,51 @@ impl ViNormal {
 					chars = chars_clone;
 					break 'verb_parse Some(VerbCmd(count, Verb::Dedent));
 				}
+				'm' => {
+					let mark = chars_clone.next()?;
+					if !mark.is_ascii_lowercase() {
+						return self.quit_parse()
+					}
+					return Some(
+						ViCmd {
+							register,
+							verb: Some(VerbCmd(1, Verb::SetMark(mark))),
+							motion: None,
+							raw_seq: self.take_cmd(),
+							flags: self.flags()
+						}
+					)
+				}
+				'q' => {
+					let reg = chars_clone.next()?;
+					if !reg.is_ascii_alphabetic() {
+						return self.quit_parse()
+					}
+					return Some(
+						ViCmd {
+							register,
+							verb: Some(VerbCmd(1, Verb::RecordMacro(reg))),
+							motion: None,
+							raw_seq: self.take_cmd(),
+							flags: self.flags()
+						}
+					)
+				}
+				'@' => {
+					let reg = chars_clone.next()?;
+					if !reg.is_ascii_alphabetic() && reg != '@' {
+						return self.quit_parse()
+					}
+					return Some(
+						ViCmd {
+							register,
+							verb: Some(VerbCmd(count, Verb::PlayMacro(reg))),
+							motion: None,
+							raw_seq: self.take_cmd(),
+							flags: self.flags()
+						}
+					)
+				}
 				'r' => {
 					let ch = chars_clone.next()?;
 					return Some(
@@ -975,6 +1136,16 @@ impl ViNormal {
 					break 'verb_parse Some(VerbCmd(count, Verb::Delete))
 				}
 				'c' => {
+					// 'cr' followed by a case, like 'crs', coerces identifiers instead
+					if chars_clone.peek() == Some(&'r') {
+						chars_clone.next();
+						let key = chars_clone.next()?;
+						let Some(case) = Case::from_key(key) else {
+							return self.quit_parse()
+						};
+						chars = chars_clone;
+						break 'verb_parse Some(VerbCmd(count, Verb::CoerceCase(case)))
+					}
 					chars = chars_clone;
 					break 'verb_parse Some(VerbCmd(count, Verb::Change))
 				}
@@ -1019,6 +1190,21 @@ impl ViNormal {
 			}
 		};
 
+		// 'v', 'V', or Ctrl-V between an operator and its motion force the motion to be charwise, linewise, or blockwise
+		let mut flags = self.flags();
+		if verb.is_some() {
+			let forced = match chars.peek() {
+				Some('v') => CmdFlags::VISUAL,
+				Some('V') => CmdFlags::VISUAL_LINE,
+				Some(&CTRL_V) => CmdFlags::VISUAL_BLOCK,
+				_ => CmdFlags::empty()
+			};
+			if !forced.is_empty() {
+				chars.next();
+				flags |= forced;
+			}
+		}
+
 		let motion = 'motion_parse: {
 			let mut chars_clone = chars.clone();
 			let count = self.parse_count(&mut chars_clone).unwrap_or(1);
@@ -1034,11 +1220,15 @@ impl ViNormal {
 				('c', Some(VerbCmd(_,Verb::Change))) |
 				('y', Some(VerbCmd(_,Verb::Yank))) |
 				('=', Some(VerbCmd(_,Verb::Equalize))) |
+				('q', Some(VerbCmd(_,Verb::Format))) |
 				('u', Some(VerbCmd(_,Verb::ToLower))) |
 				('U', Some(VerbCmd(_,Verb::ToUpper))) |
 				('~', Some(VerbCmd(_,Verb::ToggleCaseRange))) |
 				('>', Some(VerbCmd(_,Verb::Indent))) |
 				('<', Some(VerbCmd(_,Verb::Dedent))) => break 'motion_parse Some(MotionCmd(count, Motion::WholeLine)),
+				(key, Some(VerbCmd(_,Verb::CoerceCase(case)))) if Case::from_key(key) == Some(*case) => {
+					break 'motion_parse Some(MotionCmd(count, Motion::WholeLine))
+				}
 				('W', Some(VerbCmd(_, Verb::Change))) => {
 					// Same with 'W'
 					break 'motion_parse Some(MotionCmd(count, Motion::WordMotion(To::End, Word::Big, Direction::Forward)));
@@ -1061,7 +1251,11 @@ impl ViNormal {
 						'j' => break 'motion_parse Some(MotionCmd(count, Motion::ScreenLineDown)),
 						'_' => break 'motion_parse Some(MotionCmd(count, Motion::EndOfLastWord)),
 						'0' => break 'motion_parse Some(MotionCmd(count, Motion::BeginningOfScreenLine)),
+						'$' => break 'motion_parse Some(MotionCmd(count, Motion::EndOfScreenLine)),
+						'm' => break 'motion_parse Some(MotionCmd(count, Motion::HalfOfScreen)),
 						'^' => break 'motion_parse Some(MotionCmd(count, Motion::FirstGraphicalOnScreenLine)),
+						';' => break 'motion_parse Some(MotionCmd(count, Motion::ToSubChange(Direction::Backward))),
+						',' => break 'motion_parse Some(MotionCmd(count, Motion::ToSubChange(Direction::Forward))),
 						_ => return self.quit_parse()
 					}
 				}
@@ -1071,42 +1265,32 @@ impl ViNormal {
 					};
 					match ch {
 						')' => break 'motion_parse Some(MotionCmd(count, Motion::ToParen(Direction::Forward))),
+						'n' => break 'motion_parse Some(MotionCmd(count, Motion::ToNumber(Direction::Forward))),
 						'}' => break 'motion_parse Some(MotionCmd(count, Motion::ToBrace(Direction::Forward))),
 						_ => return self.quit_parse()
 					}
 				}
+				'z' => {
+					let Some(ch) = chars_clone.next() else {
+						break 'motion_parse None
+					};
+					match ch {
+						'j' => break 'motion_parse Some(MotionCmd(count, Motion::ToFold(Direction::Forward))),
+						'k' => break 'motion_parse Some(MotionCmd(count, Motion::ToFold(Direction::Backward))),
+						_ => return self.quit_parse()
+					}
+				}
 				'[' => {
 					let Some(ch) = chars_clone.peek() else {
 						break 'motion_parse None
 					};
 					match ch {
 						'(' => break 'motion_parse Some(MotionCmd(count, Motion::ToParen(Direction::Backward))),
+						'n' => break 'motion_parse Some(MotionCmd(count, Motion::ToNumber(Direction::Backward))),
 						'{' => break 'motion_parse Some(MotionCmd(count, Motion::ToBrace(Direction::Backward))),
 						_ => return self.quit_parse()
 					}
 				}
-				'v' => {
-					// We got 'v' after a verb
-					// Instead of normal operations, we will calculate the span based on how visual mode would see it
-					if self.flags().intersects(CmdFlags::VISUAL | CmdFlags::VISUAL_LINE | CmdFlags::VISUAL_BLOCK) {
-						// We can't have more than one of these
-						return self.quit_parse();
-					}
-					self.pending_flags |= CmdFlags::VISUAL;
-					break 'motion_parse None
-				}
-				'V' => {
-					// We got 'V' after a verb
-					// Instead of normal operations, we will calculate the span based on how visual line mode would see it
-					if self.flags().intersects(CmdFlags::VISUAL | CmdFlags::VISUAL_LINE | CmdFlags::VISUAL_BLOCK) {
-						// We can't have more than one of these
-						// I know vim can technically do this, but it doesn't really make sense to allow it
-						// since even in vim only the first one given is used
-						return self.quit_parse();
-					}
-					self.pending_flags |= CmdFlags::VISUAL;
-					break 'motion_parse None
-				}
 				'f' => {
 					let Some(ch) = chars_clone.peek() else {
 						break 'motion_parse None
@@ -1114,6 +1298,19 @@ impl ViNormal {
 
 					break 'motion_parse Some(MotionCmd(count, Motion::CharSearch(Direction::Forward, Dest::On, *ch)))
 				}
+				'`' | '\'' => {
+					let Some(mark) = chars_clone.peek() else {
+						break 'motion_parse None
+					};
+					if !mark.is_ascii_lowercase() {
+						return self.quit_parse()
+					}
+
+					match ch {
+						'`' => break 'motion_parse Some(MotionCmd(count, Motion::ToMark(*mark))),
+						_ => break 'motion_parse Some(MotionCmd(count, Motion::ToMarkLine(*mark))),
+					}
+				}
 				'F' => {
 					let Some(ch) = chars_clone.peek() else {
 						break 'motion_parse None
@@ -1137,6 +1334,9 @@ impl ViNormal {
 				}
 				'%' => break 'motion_parse Some(MotionCmd(count, Motion::ToDelimMatch)),
 				'G' => break 'motion_parse Some(MotionCmd(count, Motion::EndOfBuffer)),
+				'H' => break 'motion_parse Some(MotionCmd(count, Motion::WindowTop)),
+				'M' => break 'motion_parse Some(MotionCmd(count, Motion::WindowMiddle)),
+				'L' => break 'motion_parse Some(MotionCmd(count, Motion::WindowBottom)),
 				';' => break 'motion_parse Some(MotionCmd(count, Motion::RepeatMotion)),
 				',' => break 'motion_parse Some(MotionCmd(count, Motion::RepeatMotionRev)),
 				'|' => break 'motion_parse Some(MotionCmd(count, Motion::ToColumn)),
@@ -1207,7 +1407,21 @@ impl ViNormal {
 						'{' | '}' | 'B' => TextObj::Brace(bound),
 						'[' | ']' => TextObj::Bracket(bound),
 						'<' | '>' => TextObj::Angle(bound),
-						_ => return self.quit_parse()
+						't' => TextObj::Tag(bound),
+						'z' => TextObj::Fold(bound),
+						'i' => TextObj::Indent(bound),
+						'a' => TextObj::Arg(bound),
+						'n' => TextObj::Number(bound),
+						'u' => TextObj::Url(bound),
+						'f' => TextObj::Path(bound),
+						'e' => TextObj::Email(bound),
+						'v' => TextObj::KeyValue(bound),
+						'c' => TextObj::CodeFence(bound),
+						'h' => TextObj::Section(bound),
+						'l' => TextObj::ListItem(bound),
+						'q' => TextObj::StringLiteral(bound),
+						'r' => TextObj::SubChange(bound),
+						ch => TextObj::Custom(ch,bound)
 					};
 					break 'motion_parse Some(MotionCmd(count, Motion::TextObj(obj)))
 				}
@@ -1226,7 +1440,7 @@ impl ViNormal {
 						verb,
 						motion,
 						raw_seq: std::mem::take(&mut self.pending_seq),
-						flags: self.flags()
+						flags
 					}
 				)
 			}
@@ -1245,6 +1459,7 @@ impl ViMode for ViNormal {
 	fn handle_key(&mut self, key: E) -> Option<ViCmd> {
 		let mut cmd = match key {
 			E(K::Char(ch), M::NONE) => self.try_parse(ch),
+			E(K::Enter, M::NONE) if is_pending_expr(&self.pending_seq) => self.try_parse('\r'),
 			E(K::Char('M'), M::CTRL) |
 			E(K::Enter, M::NONE) => {
 				self.try_parse_multiple("j^")
@@ -1262,6 +1477,23 @@ impl ViMode for ViNormal {
 					flags: self.flags()
 				})
 			}
+			E(K::Char(ch @ ('E' | 'Y')), M::CTRL) => {
+				let mut chars = self.pending_seq.chars().peekable();
+				let count = self.parse_count(&mut chars).unwrap_or(1);
+				let motion = match ch {
+					'E' => Motion::ScrollWindowDown,
+					_ => Motion::ScrollWindowUp
+				};
+				Some(
+					ViCmd {
+						register: RegisterName::default(),
+						verb: None,
+						motion: Some(MotionCmd(count, motion)),
+						raw_seq: self.take_cmd(),
+						flags: self.flags()
+					}
+				)
+			}
 			E(K::Char('R'), M::CTRL) => {
 				let mut chars = self.pending_seq.chars().peekable();
 				let count = self.parse_count(&mut chars).unwrap_or(1);
@@ -1275,6 +1507,20 @@ impl ViMode for ViNormal {
 					}
 				)
 			}
+			E(K::Char(ch @ ('A' | 'X')), M::CTRL) => {
+				let mut chars = self.pending_seq.chars().peekable();
+				let count = self.parse_count(&mut chars).unwrap_or(1) as i64;
+				let amount = if ch == 'A' { count } else { -count };
+				Some(
+					ViCmd {
+						register: RegisterName::default(),
+						verb: Some(VerbCmd(1,Verb::Increment(amount))),
+						motion: None,
+						raw_seq: self.take_cmd(),
+						flags: self.flags()
+					}
+				)
+			}
 			E(K::Esc, M::NONE) => {
 				self.clear_cmd();
 				None
@@ -1,9 +1,9 @@
 use std::{iter::Peekable, str::Chars};
 
-use crate::vicmd::{Anchor, Bound, CmdFlags, Dest, Direction, Motion, MotionCmd, RegisterName, TextObj, To, Verb, VerbCmd, ViCmd, Word};
+use crate::vicmd::{Anchor, Bound, Case, CmdFlags, Dest, Direction, Motion, MotionCmd, RegisterName, TextObj, To, Verb, VerbCmd, ViCmd, Word};
 use crate::keys::{KeyEvent as E, KeyCode as K, ModKeys as M};
 
-use super::{common_cmds, CmdReplay, CmdState, ModeReport, ViMode};
+use super::{common_cmds, is_pending_expr, CmdReplay, CmdState, ModeReport, ViMode};
 
 #[derive(Default,Debug)]
 pub struct ViVisual {
@@ -77,6 +77,10 @@ impl ViVisual {
 			match reg_name {
 				'a'..='z' |
 				'A'..='Z' => { /* proceed */ }
+				// "=<expr><CR>, the expression is evaluated when the command runs
+				'=' => if !chars_clone.by_ref().any(|ch| ch == '\r') {
+					return None // Pending expression
+				}
 				_ => return self.quit_parse()
 			}
 
@@ -117,6 +121,34 @@ impl ViVisual {
 									}
 								)
 							}
+							'q' => {
+								return Some(
+									ViCmd {
+										register,
+										verb: Some(VerbCmd(1, Verb::Format)),
+										motion: Some(MotionCmd(1, Motion::WholeLine)),
+										raw_seq: self.take_cmd(),
+										flags: CmdFlags::empty()
+									}
+								)
+							}
+							'r' => {
+								// 'c' changes the selection right away, so case coercion is 'gr' here instead of 'cr'
+								chars_clone.next();
+								let key = chars_clone.next()?;
+								let Some(case) = Case::from_key(key) else {
+									return self.quit_parse()
+								};
+								return Some(
+									ViCmd {
+										register,
+										verb: Some(VerbCmd(1, Verb::CoerceCase(case))),
+										motion: None,
+										raw_seq: self.take_cmd(),
+										flags: CmdFlags::empty()
+									}
+								)
+							}
 							_ => break 'verb_parse None
 						}
 					} else {
@@ -299,12 +331,16 @@ impl ViVisual {
 						}
 					)
 				}
-				'O' |
-				'o' => {
+				'o' | 'O' => {
+					// 'O' only differs in block mode, where it stays on the same line
+					let verb = match ch {
+						'O' => Verb::SwapVisualCorner,
+						_ => Verb::SwapVisualAnchor
+					};
 					return Some(
 						ViCmd { 
 							register,
-							verb: Some(VerbCmd(count, Verb::SwapVisualAnchor)),
+							verb: Some(VerbCmd(count, verb)),
 							motion: None,
 							raw_seq: self.take_cmd(), 
 							flags: CmdFlags::empty()
@@ -395,6 +431,11 @@ impl ViVisual {
 							'E' => break 'motion_parse Some(MotionCmd(count, Motion::WordMotion(To::End, Word::Big, Direction::Backward))),
 							'k' => break 'motion_parse Some(MotionCmd(count, Motion::ScreenLineUp)),
 							'j' => break 'motion_parse Some(MotionCmd(count, Motion::ScreenLineDown)),
+							'0' => break 'motion_parse Some(MotionCmd(count, Motion::BeginningOfScreenLine)),
+							'$' => break 'motion_parse Some(MotionCmd(count, Motion::EndOfScreenLine)),
+							'm' => break 'motion_parse Some(MotionCmd(count, Motion::HalfOfScreen)),
+							';' => break 'motion_parse Some(MotionCmd(count, Motion::ToSubChange(Direction::Backward))),
+							',' => break 'motion_parse Some(MotionCmd(count, Motion::ToSubChange(Direction::Forward))),
 							_ => return self.quit_parse()
 						}
 					} else {
@@ -407,16 +448,28 @@ impl ViVisual {
 					};
 					match ch {
 						')' => break 'motion_parse Some(MotionCmd(count, Motion::ToParen(Direction::Forward))),
+						'n' => break 'motion_parse Some(MotionCmd(count, Motion::ToNumber(Direction::Forward))),
 						'}' => break 'motion_parse Some(MotionCmd(count, Motion::ToBrace(Direction::Forward))),
 						_ => return self.quit_parse()
 					}
 				}
+				'z' => {
+					let Some(ch) = chars_clone.next() else {
+						break 'motion_parse None
+					};
+					match ch {
+						'j' => break 'motion_parse Some(MotionCmd(count, Motion::ToFold(Direction::Forward))),
+						'k' => break 'motion_parse Some(MotionCmd(count, Motion::ToFold(Direction::Backward))),
+						_ => return self.quit_parse()
+					}
+				}
 				'[' => {
 					let Some(ch) = chars_clone.peek() else {
 						break 'motion_parse None
 					};
 					match ch {
 						'(' => break 'motion_parse Some(MotionCmd(count, Motion::ToParen(Direction::Backward))),
+						'n' => break 'motion_parse Some(MotionCmd(count, Motion::ToNumber(Direction::Backward))),
 						'{' => break 'motion_parse Some(MotionCmd(count, Motion::ToBrace(Direction::Backward))),
 						_ => return self.quit_parse()
 					}
@@ -429,6 +482,19 @@ impl ViVisual {
 
 					break 'motion_parse Some(MotionCmd(count, Motion::CharSearch(Direction::Forward, Dest::On, *ch)))
 				}
+				'`' | '\'' => {
+					let Some(mark) = chars_clone.peek() else {
+						break 'motion_parse None
+					};
+					if !mark.is_ascii_lowercase() {
+						return self.quit_parse()
+					}
+
+					match ch {
+						'`' => break 'motion_parse Some(MotionCmd(count, Motion::ToMark(*mark))),
+						_ => break 'motion_parse Some(MotionCmd(count, Motion::ToMarkLine(*mark))),
+					}
+				}
 				'F' => {
 					let Some(ch) = chars_clone.peek() else {
 						break 'motion_parse None
@@ -451,6 +517,9 @@ impl ViVisual {
 					break 'motion_parse Some(MotionCmd(count, Motion::CharSearch(Direction::Backward, Dest::Before, *ch)))
 				}
 				'G' => break 'motion_parse Some(MotionCmd(count, Motion::EndOfBuffer)),
+				'H' => break 'motion_parse Some(MotionCmd(count, Motion::WindowTop)),
+				'M' => break 'motion_parse Some(MotionCmd(count, Motion::WindowMiddle)),
+				'L' => break 'motion_parse Some(MotionCmd(count, Motion::WindowBottom)),
 				'n' => break 'motion_parse Some(MotionCmd(count, Motion::NextMatch)),
 				'N' => break 'motion_parse Some(MotionCmd(count, Motion::PrevMatch)),
 				';' => break 'motion_parse Some(MotionCmd(count, Motion::RepeatMotion)),
@@ -493,7 +562,21 @@ impl ViVisual {
 						'{' | '}' | 'B' => TextObj::Brace(bound),
 						'[' | ']' => TextObj::Bracket(bound),
 						'<' | '>' => TextObj::Angle(bound),
-						_ => return self.quit_parse()
+						't' => TextObj::Tag(bound),
+						'z' => TextObj::Fold(bound),
+						'i' => TextObj::Indent(bound),
+						'a' => TextObj::Arg(bound),
+						'n' => TextObj::Number(bound),
+						'u' => TextObj::Url(bound),
+						'f' => TextObj::Path(bound),
+						'e' => TextObj::Email(bound),
+						'v' => TextObj::KeyValue(bound),
+						'c' => TextObj::CodeFence(bound),
+						'h' => TextObj::Section(bound),
+						'l' => TextObj::ListItem(bound),
+						'q' => TextObj::StringLiteral(bound),
+						'r' => TextObj::SubChange(bound),
+						ch => TextObj::Custom(ch,bound)
 					};
 					break 'motion_parse Some(MotionCmd(count, Motion::TextObj(obj)))
 				}
@@ -531,6 +614,7 @@ impl ViMode for ViVisual {
 	fn handle_key(&mut self, key: E) -> Option<ViCmd> {
 		let mut cmd = match key {
 			E(K::Char(ch), M::NONE) => self.try_parse(ch),
+			E(K::Enter, M::NONE) if is_pending_expr(&self.pending_seq) => self.try_parse('\r'),
 			E(K::Backspace, M::NONE) => {
 				Some(ViCmd {
 					register: Default::default(),
@@ -553,6 +637,24 @@ impl ViMode for ViVisual {
 					}
 				)
 			}
+			E(K::Char(ch @ ('A' | 'X')), M::CTRL) => {
+				let mut chars = self.pending_seq.chars().peekable();
+				let count = self.parse_count(&mut chars).unwrap_or(1) as i64;
+				let amount = if ch == 'A' { count } else { -count };
+				let verb = match chars.next() {
+					Some('g') => Verb::IncrementProgressive(amount),
+					_ => Verb::Increment(amount)
+				};
+				Some(
+					ViCmd {
+						register: RegisterName::default(),
+						verb: Some(VerbCmd(1,verb)),
+						motion: None,
+						raw_seq: self.take_cmd(),
+						flags: CmdFlags::empty()
+					}
+				)
+			}
 			E(K::Esc, M::NONE) => {
 				Some(
 					ViCmd {
@@ -0,0 +1,74 @@
+//! The parallel execution backend.
+//!
+//! With the `parallel` feature enabled, work is spread across threads using `rayon`.
+//! Without it, the same interface runs everything serially on the current thread,
+//! so the execution pathways in `main.rs` don't have to care which one they're using.
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Mapping over a collection of work items, possibly in parallel.
+///
+/// The output is always in the same order as the input.
+pub trait ParMap<T: Send> {
+	fn par_map<U, F>(self, f: F) -> Vec<U>
+	where
+		U: Send,
+		F: Fn(T) -> U + Sync + Send;
+}
+
+impl<T: Send> ParMap<T> for Vec<T> {
+	#[cfg(feature = "parallel")]
+	fn par_map<U, F>(self, f: F) -> Vec<U>
+	where
+		U: Send,
+		F: Fn(T) -> U + Sync + Send
+	{
+		self.into_par_iter().map(f).collect()
+	}
+	#[cfg(not(feature = "parallel"))]
+	fn par_map<U, F>(self, f: F) -> Vec<U>
+	where
+		U: Send,
+		F: Fn(T) -> U + Sync + Send
+	{
+		self.into_iter().map(f).collect()
+	}
+}
+
+/// Whether this build can actually run work on multiple threads
+pub const fn is_parallel() -> bool {
+	cfg!(feature = "parallel")
+}
+
+/// Run `f` with at most `jobs` threads available to `ParMap::par_map()`.
+///
+/// Without the `parallel` feature, `f` is just called directly.
+#[cfg(feature = "parallel")]
+pub fn with_max_jobs<R: Send>(jobs: u32, f: impl FnOnce() -> R + Send) -> Result<R,String> {
+	let pool = rayon::ThreadPoolBuilder::new()
+		.num_threads(jobs as usize)
+		.build()
+		.map_err(|e| format!("Failed to build thread pool: {e}"))?;
+	Ok(pool.install(f))
+}
+
+/// Run `f` with at most `jobs` threads available to `ParMap::par_map()`.
+///
+/// Without the `parallel` feature, `f` is just called directly.
+#[cfg(not(feature = "parallel"))]
+pub fn with_max_jobs<R: Send>(_jobs: u32, f: impl FnOnce() -> R + Send) -> Result<R,String> {
+	Ok(f())
+}
+
+/// The index of the worker thread that this is running on, or `None` if it isn't one
+#[cfg(feature = "parallel")]
+pub fn thread_index() -> Option<usize> {
+	rayon::current_thread_index()
+}
+
+/// The index of the worker thread that this is running on, or `None` if it isn't one
+#[cfg(not(feature = "parallel"))]
+pub fn thread_index() -> Option<usize> {
+	None
+}
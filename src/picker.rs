@@ -0,0 +1,108 @@
+//! A small interactive picker, used by `--pick` to choose which `-g` matches to operate on.
+//!
+//! stdin is usually busy being our input, so the picker talks to the terminal directly through `/dev/tty`.
+//! If there is no terminal to talk to, every candidate is picked.
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+
+/// A line that can be picked, and the text that is shown for it
+pub struct Candidate {
+	pub line: usize,
+	pub text: String,
+}
+
+/// Whether a query fuzzy matches some text.
+///
+/// Every character of the query has to show up in the text, in order. Case is ignored.
+pub fn fuzzy_match(query: &str, text: &str) -> bool {
+	let mut text = text.chars().flat_map(char::to_lowercase);
+	query.chars()
+		.flat_map(char::to_lowercase)
+		.filter(|ch| !ch.is_whitespace())
+		.all(|q_ch| text.any(|t_ch| t_ch == q_ch))
+}
+
+/// Parse a selection like `1 3-5` into indices of the shown candidates.
+///
+/// Numbers are 1-based, as displayed by the picker.
+pub fn parse_selection(input: &str, shown: usize) -> Result<Vec<usize>,String> {
+	let mut picked = vec![];
+	for word in input.split(|ch: char| ch.is_whitespace() || ch == ',').filter(|word| !word.is_empty()) {
+		let (start,end) = match word.split_once('-') {
+			Some((start,end)) => (start,end),
+			None => (word,word)
+		};
+		let parse = |num: &str| num.parse::<usize>()
+			.ok()
+			.filter(|num| (1..=shown).contains(num))
+			.ok_or_else(|| format!("'{word}' is not a valid selection"));
+		let (start,end) = (parse(start)?, parse(end)?);
+		for num in start.min(end)..=start.max(end) {
+			if !picked.contains(&(num - 1)) {
+				picked.push(num - 1);
+			}
+		}
+	}
+	Ok(picked)
+}
+
+/// Let the user pick from `candidates`, and return the line numbers of the ones they chose.
+///
+/// Falls back on returning every candidate if we aren't attached to a terminal.
+pub fn pick_lines(candidates: Vec<Candidate>) -> Vec<usize> {
+	if candidates.len() < 2 || !io::stderr().is_terminal() {
+		return candidates.into_iter().map(|cand| cand.line).collect()
+	}
+	let Ok(tty) = OpenOptions::new().read(true).write(true).open("/dev/tty") else {
+		return candidates.into_iter().map(|cand| cand.line).collect()
+	};
+	match run_picker(tty, &candidates) {
+		Ok(picked) => picked,
+		Err(e) => {
+			eprintln!("vicut: picker failed, using all matches: {e}");
+			candidates.into_iter().map(|cand| cand.line).collect()
+		}
+	}
+}
+
+fn run_picker(tty: File, candidates: &[Candidate]) -> io::Result<Vec<usize>> {
+	let mut out = tty.try_clone()?;
+	let mut input = BufReader::new(tty);
+	let mut query = String::new();
+	loop {
+		let shown = candidates.iter()
+			.filter(|cand| fuzzy_match(&query, &cand.text))
+			.collect::<Vec<_>>();
+
+		writeln!(out)?;
+		for (i,cand) in shown.iter().enumerate() {
+			writeln!(out, "\x1b[1m{:>3}\x1b[0m  {}:\t{}", i + 1, cand.line + 1, cand.text.trim_end())?;
+		}
+		if !query.is_empty() {
+			writeln!(out, "({} of {} matches for '{query}')", shown.len(), candidates.len())?;
+		}
+		write!(out, "pick [numbers | /filter | enter = all shown | q = none]> ")?;
+		out.flush()?;
+
+		let mut line = String::new();
+		if input.read_line(&mut line)? == 0 {
+			// EOF on the terminal, treat it like accepting everything shown
+			return Ok(shown.iter().map(|cand| cand.line).collect())
+		}
+		let line = line.trim();
+		if line.is_empty() {
+			return Ok(shown.iter().map(|cand| cand.line).collect())
+		}
+		if line == "q" {
+			return Ok(vec![])
+		}
+		if let Some(new_query) = line.strip_prefix('/') {
+			query = new_query.to_string();
+			continue
+		}
+		match parse_selection(line, shown.len()) {
+			Ok(picked) => return Ok(picked.into_iter().map(|i| shown[i].line).collect()),
+			Err(e) => writeln!(out, "{e}")?,
+		}
+	}
+}
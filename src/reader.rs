@@ -20,7 +20,8 @@ pub trait KeyReader {
 #[derive(Default,Debug)]
 pub struct RawReader {
 	pub bytes: VecDeque<u8>,
-	pub is_escaped: bool // The last byte was a backslash or not
+	pub is_escaped: bool, // The last byte was a backslash or not
+	pub recording: Option<Vec<u8>> // The bytes read since `q{reg}` started recording a macro
 }
 
 impl RawReader {
@@ -52,16 +53,33 @@ impl RawReader {
 			self.bytes.push_front(*byte);
 		}
 	}
+	pub fn start_recording(&mut self) {
+		self.recording = Some(vec![]);
+	}
+	pub fn stop_recording(&mut self) -> Option<Vec<u8>> {
+		self.recording.take()
+	}
+	pub fn is_recording(&self) -> bool {
+		self.recording.is_some()
+	}
+	/// Take the next byte, adding it to the macro being recorded, if there is one
+	fn pop_byte(&mut self) -> Option<u8> {
+		let byte = self.bytes.pop_front()?;
+		if let Some(recording) = self.recording.as_mut() {
+			recording.push(byte);
+		}
+		Some(byte)
+	}
 
 	/// Parse an escape sequence
 	pub fn parse_esc_seq(&mut self) -> Option<KeyEvent> {
 		let mut seq = vec![0x1b];
-		let b1 = self.bytes.pop_front()?;
+		let b1 = self.pop_byte()?;
 		seq.push(b1);
 
 		match b1 {
 			b'[' => {
-				let b2 = self.bytes.pop_front()?;
+				let b2 = self.pop_byte()?;
 				seq.push(b2);
 
 				match b2 {
@@ -74,7 +92,7 @@ impl RawReader {
 
 						while let Some(&b) = self.bytes.front() {
 							seq.push(b);
-							self.bytes.pop_front();
+							self.pop_byte();
 
 							if b == b'~' || b == b';' {
 								break;
@@ -112,7 +130,7 @@ impl RawReader {
 			}
 
 			b'O' => {
-				let b2 = self.bytes.pop_front()?;
+				let b2 = self.pop_byte()?;
 				seq.push(b2);
 
 				let key = match b2 {
@@ -134,8 +152,10 @@ impl RawReader {
 	/// This is where aliases like `<esc>` and `<CR>` are parsed into KeyEvents
 	pub fn parse_byte_alias(&mut self) -> Option<KeyEvent> {
 		let mut buf = vec![];
-		let mut byte_iter = self.bytes.iter().copied();
-		for b in byte_iter.by_ref() {
+		// How many bytes the alias takes up, counting the closing '>'
+		let mut len = 0;
+		for &b in self.bytes.iter() {
+			len += 1;
 			match b {
 				b'>' => break,
 				_ => buf.push(b)
@@ -204,7 +224,9 @@ impl RawReader {
 			_ => None
 		};
 		if result.is_some() {
-			self.bytes = byte_iter.collect();
+			for _ in 0..len {
+				self.pop_byte();
+			}
 		}
 		result
 	}
@@ -212,13 +234,16 @@ impl RawReader {
 
 impl KeyReader for RawReader {
 	/// Read a single KeyEvent from the internal byte deque.
+	///
+	/// While a macro is being recorded, the bytes that make up the key are saved by `pop_byte()` as they were written,
+	/// so that aliases like `<esc>` are replayed the same way.
 	fn read_key(&mut self) -> Option<KeyEvent> {
 		use core::str;
 
 		let mut collected = Vec::with_capacity(4);
 
 		loop {
-			let byte = self.bytes.pop_front()?;
+			let byte = self.pop_byte()?;
 
 			// Check for byte aliases like '<esc>' and '<c-w>'
 			if byte == b'<' && !self.is_escaped {
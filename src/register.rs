@@ -1,28 +1,56 @@
 //! This module contains logic for emulation of Vim's registers feature.
 //!
 //! It contains the `Registers` struct, which is held in a thread local, global variable.
-use std::{cell::RefCell, fmt::Display};
+//! With `--shared-registers`, every thread uses a single mutex-protected `Registers` instead.
+use std::{cell::{Cell, RefCell}, fmt::Display, sync::{Mutex, PoisonError}};
 
 thread_local! {
 	/// The global state for all registers.
 	///
 	/// This variable is thread local, so it can be freely mutated.
-	pub static REGISTERS: RefCell<Registers> = const { RefCell::new(Registers::new()) };
+	static REGISTERS: RefCell<Registers> = const { RefCell::new(Registers::new()) };
+	/// Whether this thread uses `SHARED_REGISTERS` instead of its own, for `--shared-registers`
+	static USE_SHARED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The registers that all threads read and write with `--shared-registers`
+static SHARED_REGISTERS: Mutex<Registers> = Mutex::new(Registers::new());
+
+/// Run `f` on the registers that this thread should be using
+pub fn with_registers<T>(f: impl FnOnce(&mut Registers) -> T) -> T {
+	if USE_SHARED.get() {
+		f(&mut SHARED_REGISTERS.lock().unwrap_or_else(PoisonError::into_inner))
+	} else {
+		REGISTERS.with_borrow_mut(f)
+	}
+}
+
+/// Get this thread ready for the next piece of parallel work.
+///
+/// Worker threads are reused, so without this, whatever one record left in a register
+/// would show up in whichever record happened to run on the same thread next.
+/// If `shared` is false, the thread starts over with empty registers.
+/// If it is true, the thread uses the registers shared by every thread instead.
+pub fn isolate_registers(shared: bool) {
+	USE_SHARED.set(shared);
+	if !shared {
+		REGISTERS.set(Registers::new());
+	}
 }
 
 /// Attempt to read from the register corresponding to the given character
 pub fn read_register(ch: Option<char>) -> Option<RegisterContent> {
-	REGISTERS.with_borrow(|regs| regs.get_reg(ch).map(|r| r.content().clone()))
+	with_registers(|regs| regs.get_reg(ch).map(|r| r.content().clone()))
 }
 
 /// Attempt to write to the register corresponding to the given character
 pub fn write_register(ch: Option<char>, buf: RegisterContent) {
-	REGISTERS.with_borrow_mut(|regs| if let Some(r) = regs.get_reg_mut(ch) { r.write(buf); })
+	with_registers(|regs| if let Some(r) = regs.get_reg_mut(ch) { r.write(buf); })
 }
 
 /// Attempt to append text to the register corresponding to the given character
 pub fn append_register(ch: Option<char>, buf: RegisterContent) {
-	REGISTERS.with_borrow_mut(|regs| if let Some(r) = regs.get_reg_mut(ch) { r.append(buf) })
+	with_registers(|regs| if let Some(r) = regs.get_reg_mut(ch) { r.append(buf) })
 }
 
 #[derive(Default,Debug)]
@@ -54,6 +82,7 @@ pub struct Registers {
 	x: Register,
 	y: Register,
 	z: Register,
+	expr: Register, // "=, holds the result of the last expression
 }
 
 impl Registers {
@@ -89,6 +118,7 @@ impl Registers {
 			x: Register::new(),
 			y: Register::new(),
 			z: Register::new(),
+			expr: Register::new(),
 		}
 	}
 	/// Get a register by name. Read only.
@@ -123,6 +153,7 @@ impl Registers {
 			'x' => Some(&self.x),
 			'y' => Some(&self.y),
 			'z' => Some(&self.z),
+			'=' => Some(&self.expr),
 			_ => None
 		}
 	}
@@ -158,6 +189,7 @@ impl Registers {
 			'x' => Some(&mut self.x),
 			'y' => Some(&mut self.y),
 			'z' => Some(&mut self.z),
+			'=' => Some(&mut self.expr),
 			_ => None
 		}
 	}
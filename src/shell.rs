@@ -0,0 +1,197 @@
+//! This module contains the logic for running shell commands on behalf of the editor.
+//!
+//! Verbs like `:!cmd`, `:r !cmd`, `:w !cmd`, and `=` all go through `ShellCfg::run()`,
+//! and `shell_args()` in vic scripts goes through `ShellCfg::run_args()`,
+//! so that stderr capture, timeouts, and the working directory are handled in one place.
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often we check up on a child process that has a timeout
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Settings for spawning shell commands
+#[derive(Default,Clone,Debug)]
+pub struct ShellCfg {
+	/// Kill the child process if it runs for longer than this
+	pub timeout: Option<Duration>,
+	/// Run the child process in this directory instead of the current one
+	pub cwd: Option<PathBuf>,
+	/// The formatter program used by the `=` operator
+	pub equalprg: Option<String>,
+	/// Set by `--no-shell`. Verbs that would spawn a shell fail instead.
+	pub disabled: bool,
+	/// The shell given with `--shell`, used instead of `$SHELL`
+	pub shell: Option<String>,
+}
+
+/// Where shell commands are run, set with `--shell-cwd`
+#[derive(Clone,Debug,PartialEq)]
+pub enum ShellCwd {
+	/// The same directory for every record
+	Dir(PathBuf),
+	/// The directory of the file that each record came from
+	FileDir
+}
+
+impl From<&str> for ShellCwd {
+	fn from(value: &str) -> Self {
+		match value {
+			"filedir" => ShellCwd::FileDir,
+			_ => ShellCwd::Dir(PathBuf::from(value))
+		}
+	}
+}
+
+impl ShellCwd {
+	/// The directory to run shell commands in for a record from `filename`.
+	///
+	/// `None` means the current working directory, which is where records from stdin go with `filedir`.
+	pub fn resolve(&self, filename: Option<&Path>) -> Option<PathBuf> {
+		match self {
+			ShellCwd::Dir(dir) => Some(dir.clone()),
+			ShellCwd::FileDir => filename.map(file_dir)
+		}
+	}
+}
+
+/// The directory that the file at `path` is in, as used for `$filedir`
+pub fn file_dir(path: &Path) -> PathBuf {
+	match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+		_ => PathBuf::from(".")
+	}
+}
+
+/// Formatters to fall back on for `=` when neither `--equalprg` nor `$EQUALPRG` are set.
+///
+/// Keyed by filetype, as detected by `filetype::detect()`.
+const DEFAULT_EQUALPRGS: [(&str,&str);7] = [
+	("json", "jq ."),
+	("rust", "rustfmt"),
+	("go", "gofmt"),
+	("c", "clang-format"),
+	("cpp", "clang-format"),
+	("xml", "xmllint --format -"),
+	("python", "black -q -"),
+];
+
+/// Get the default formatter program for the given filetype, if we know one
+pub fn default_equalprg(filetype: &str) -> Option<&'static str> {
+	DEFAULT_EQUALPRGS.iter()
+		.find(|(ft,_)| *ft == filetype)
+		.map(|(_,prg)| *prg)
+}
+
+impl ShellCfg {
+	/// The shell that commands are run with, and any arguments that go before `-c`.
+	///
+	/// `--shell` wins over `$SHELL`, which wins over `sh`. Only `--shell` can have arguments,
+	/// `$SHELL` is the path to a program, and that path may have spaces in it.
+	fn shell_program(&self) -> Vec<String> {
+		if let Some(shell) = self.shell.as_ref().filter(|shell| !shell.trim().is_empty()) {
+			return shell.split_whitespace().map(|word| word.to_string()).collect()
+		}
+		let shell = std::env::var("SHELL").ok()
+			.filter(|shell| !shell.trim().is_empty())
+			.unwrap_or_else(|| "sh".to_string());
+		vec![shell]
+	}
+	/// Run `cmd` with the shell's `-c`, optionally piping `input` into it.
+	///
+	/// Returns the child's stdout. If the child exits with a non-zero status, or does not finish
+	/// before the timeout, an error containing its stderr is returned instead.
+	/// Anything written to stderr by a successful child is passed along to our own stderr.
+	pub fn run(&self, cmd: &str, input: Option<&str>) -> Result<String,String> {
+		let shell = self.shell_program();
+		let mut command = Command::new(&shell[0]);
+		command.args(&shell[1..])
+			.arg("-c")
+			.arg(cmd);
+		self.run_command(command, cmd, input)
+	}
+	/// Run `argv[0]` with the rest of `argv` as its arguments, without a shell in between.
+	///
+	/// Nothing in `argv` is split, expanded, or unquoted, so values taken from the buffer can be passed along safely.
+	/// Otherwise this works just like `run()`.
+	pub fn run_args(&self, argv: &[String], input: Option<&str>) -> Result<String,String> {
+		let Some((program, args)) = argv.split_first() else {
+			return Err("Expected a program to run".into())
+		};
+		let mut command = Command::new(program);
+		command.args(args);
+		self.run_command(command, &argv.join(" "), input)
+	}
+	fn run_command(&self, mut command: Command, cmd: &str, input: Option<&str>) -> Result<String,String> {
+		command.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped());
+		if let Some(cwd) = self.cwd.as_ref() {
+			command.current_dir(cwd);
+		}
+		let mut child = command.spawn()
+			.map_err(|e| format!("Failed to spawn child process for '{cmd}': {e}"))?;
+
+		// Everything is done on separate threads so that a chatty child can't deadlock us
+		let stdin_thread = input.map(|input| {
+			let mut stdin = child.stdin.take().unwrap();
+			let input = input.to_string();
+			thread::spawn(move || { stdin.write_all(input.as_bytes()).ok(); })
+		});
+		let mut stdout = child.stdout.take().unwrap();
+		let stdout_thread = thread::spawn(move || {
+			let mut buf = vec![];
+			stdout.read_to_end(&mut buf).ok();
+			buf
+		});
+		let mut stderr = child.stderr.take().unwrap();
+		let stderr_thread = thread::spawn(move || {
+			let mut buf = vec![];
+			stderr.read_to_end(&mut buf).ok();
+			buf
+		});
+
+		let status = match self.timeout {
+			Some(timeout) => {
+				let deadline = Instant::now() + timeout;
+				loop {
+					if let Some(status) = child.try_wait().map_err(|e| format!("Failed to wait for child process: {e}"))? {
+						break status
+					}
+					if Instant::now() >= deadline {
+						child.kill().ok();
+						child.wait().ok();
+						return Err(format!("Shell command '{cmd}' timed out after {}s", timeout.as_secs_f64()))
+					}
+					thread::sleep(POLL_INTERVAL);
+				}
+			}
+			None => child.wait().map_err(|e| format!("Failed to wait for child process: {e}"))?
+		};
+
+		if let Some(handle) = stdin_thread {
+			handle.join().ok();
+		}
+		let stdout = stdout_thread.join().unwrap_or_default();
+		let stderr = stderr_thread.join().unwrap_or_default();
+		let stderr = String::from_utf8_lossy(&stderr).to_string();
+
+		if !status.success() {
+			let code = status.code().map(|c| c.to_string()).unwrap_or("unknown".into());
+			let mut err = format!("Shell command '{cmd}' exited with status {code}");
+			if !stderr.trim().is_empty() {
+				err = format!("{err}: {}", stderr.trim());
+			}
+			return Err(err)
+		}
+
+		if !stderr.is_empty() {
+			eprint!("{stderr}");
+		}
+
+		String::from_utf8(stdout)
+			.map_err(|e| format!("Output of '{cmd}' was not valid UTF-8: {e}"))
+	}
+}
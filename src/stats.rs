@@ -0,0 +1,144 @@
+//! Counters for `--stats`.
+//!
+//! Every record or file that goes through `execute_unit()` is counted here, along with the bytes that are written out.
+//! When vicut finishes, the totals are printed to stderr as a single line of JSON, so that they stay out of the way of the output itself.
+
+use std::{collections::BTreeMap, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, LazyLock, Mutex}, time::{Duration, Instant}};
+
+use serde_json::{json, Value};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STATS: LazyLock<Mutex<Stats>> = LazyLock::new(|| Mutex::new(Stats::new(false)));
+
+/// When a file's first record started, and when its last one finished
+#[derive(Clone,Copy,Debug)]
+struct Span {
+	start: Instant,
+	end: Instant
+}
+
+/// How much work one thread did
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct ThreadStats {
+	pub records: usize,
+	pub busy: Duration
+}
+
+#[derive(Debug)]
+pub struct Stats {
+	started: Instant,
+	per_thread: bool,
+	/// Records or files that were executed
+	pub inputs: usize,
+	/// Records that came out of them
+	pub records: usize,
+	pub fields: usize,
+	pub bytes_in: usize,
+	pub bytes_out: usize,
+	files: BTreeMap<PathBuf, Span>,
+	/// Keyed by the worker thread's index. `None` is the main thread.
+	pub threads: BTreeMap<Option<usize>, ThreadStats>
+}
+
+impl Stats {
+	/// `per_thread` breaks the timings down by thread, which only means something for `--linewise`
+	pub fn new(per_thread: bool) -> Self {
+		Self {
+			started: Instant::now(),
+			per_thread,
+			inputs: 0,
+			records: 0,
+			fields: 0,
+			bytes_in: 0,
+			bytes_out: 0,
+			files: BTreeMap::new(),
+			threads: BTreeMap::new()
+		}
+	}
+	/// Count one record or file that started at `start`, and produced `output`
+	pub fn record_unit(&mut self, filename: Option<&Path>, input: usize, output: &[Vec<(String,crate::FieldVal)>], start: Instant, thread: Option<usize>) {
+		let end = Instant::now();
+		self.inputs += 1;
+		self.records += output.len();
+		self.fields += output.iter().map(|record| record.len()).sum::<usize>();
+		self.bytes_in += input;
+		if let Some(path) = filename {
+			self.files.entry(path.to_path_buf())
+				.and_modify(|span| {
+					span.start = span.start.min(start);
+					span.end = span.end.max(end);
+				})
+				.or_insert(Span { start, end });
+		}
+		let thread = self.threads.entry(thread).or_default();
+		thread.records += 1;
+		thread.busy += end - start;
+	}
+	pub fn to_json(&self) -> Value {
+		let fields_per_record = if self.records == 0 {
+			0.0
+		} else {
+			self.fields as f64 / self.records as f64
+		};
+		let mut stats = json!({
+			"inputs": self.inputs,
+			"records": self.records,
+			"fields": self.fields,
+			"fields_per_record": fields_per_record,
+			"bytes_in": self.bytes_in,
+			"bytes_out": self.bytes_out,
+			"elapsed_secs": self.started.elapsed().as_secs_f64(),
+		});
+		if !self.files.is_empty() {
+			stats["files"] = self.files.iter()
+				.map(|(path, span)| json!({
+					"path": path.display().to_string(),
+					"elapsed_secs": (span.end - span.start).as_secs_f64()
+				}))
+				.collect();
+		}
+		if self.per_thread {
+			stats["threads"] = self.threads.iter()
+				.map(|(thread, stats)| json!({
+					"thread": thread.map_or_else(|| "main".to_string(), |i| i.to_string()),
+					"records": stats.records,
+					"busy_secs": stats.busy.as_secs_f64()
+				}))
+				.collect();
+		}
+		stats
+	}
+}
+
+/// Start counting. Nothing is recorded unless this is called.
+pub fn enable(per_thread: bool) {
+	*STATS.lock().unwrap() = Stats::new(per_thread);
+	ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+	ENABLED.load(Ordering::Relaxed)
+}
+
+/// Count one record or file that went through `execute_unit()`
+pub fn record_unit(filename: Option<&Path>, input: usize, output: &[Vec<(String,crate::FieldVal)>], start: Instant) {
+	if enabled() {
+		STATS.lock().unwrap().record_unit(filename, input, output, start, crate::par::thread_index());
+	}
+}
+
+/// Count bytes written to stdout, or to a file edited in place
+pub fn record_output(bytes: usize) {
+	if enabled() {
+		STATS.lock().unwrap().bytes_out += bytes;
+	}
+}
+
+/// Print the summary to stderr, if `--stats` was given
+pub fn report() {
+	if enabled() {
+		let mut stats = STATS.lock().unwrap().to_json();
+		stats.sort_all_objects();
+		eprintln!("{stats}");
+	}
+}
@@ -1,4 +1,4 @@
-use crate::tests::{normal_cmd, LOREM_IPSUM};
+use crate::tests::{normal_cmd, vicut_integration, LOREM_IPSUM};
 use pretty_assertions::assert_eq;
 
 
@@ -281,3 +281,224 @@ fn editor_sentence_operations() {
 }
 
 //"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum. Curabitur pretium tincidunt lacus. Nulla gravida orci a odio. Nullam varius, turpis et commodo pharetra."
+
+#[test]
+fn editor_display_column_motions() {
+	// The tab stretches from column 2 to column 8
+	assert_eq!(normal_cmd("9|", "a\tb c", 0).1, 2);
+	assert_eq!(normal_cmd("5|", "a\tb c", 0).1, 1);
+	// Wide characters take up two columns each
+	assert_eq!(normal_cmd("5|", "日本語abc", 0).1, 2);
+	assert_eq!(normal_cmd("7|", "日本語abc", 0).1, 3);
+	assert_eq!(normal_cmd("99|", "日本語abc", 0).1, 5);
+	assert_eq!(normal_cmd("d4|", "abcdef", 5), ("abcf".into(), 3));
+
+	assert_eq!(normal_cmd("g0", "foo bar\nbiz buzz", 12).1, 8);
+	// There is no wrapping, so g$ is the same as $
+	assert_eq!(normal_cmd("g$", "foo bar\nbiz buzz", 9).1, normal_cmd("$", "foo bar\nbiz buzz", 9).1);
+	assert_eq!(normal_cmd("gm", &format!("{}Y", "x".repeat(40)), 0).1, 40);
+	assert_eq!(normal_cmd("gm", "\tshort", 0).1, 5);
+}
+
+#[test]
+fn editor_window_motions() {
+	let buf = (1..=50).map(|n| format!("  {n:02}")).collect::<Vec<_>>().join("\n");
+	let line_start = |n: usize| (n - 1) * 5;
+	// The default window is 24 lines tall, and starts at the top
+	assert_eq!(normal_cmd("H", &buf, line_start(10)).1, line_start(1) + 2);
+	assert_eq!(normal_cmd("3H", &buf, line_start(10)).1, line_start(3) + 2);
+	assert_eq!(normal_cmd("M", &buf, 0).1, line_start(12) + 2);
+	assert_eq!(normal_cmd("L", &buf, 0).1, line_start(24) + 2);
+	assert_eq!(normal_cmd("2L", &buf, 0).1, line_start(23) + 2);
+	// The window has followed the cursor down to the bottom
+	assert_eq!(normal_cmd("H", &buf, line_start(50)).1, line_start(27) + 2);
+	// Ctrl-E and Ctrl-Y only move the cursor if it falls out of the window
+	assert_eq!(normal_cmd("5\x05", &buf, line_start(10)).1, line_start(10));
+	assert_eq!(normal_cmd("5\x05", &buf, line_start(2) + 3).1, line_start(6) + 3);
+	assert_eq!(normal_cmd("5\x19", &buf, line_start(50)).1, line_start(45));
+}
+
+#[test]
+fn editor_fold_objects() {
+	let yaml = "server:\n  host: localhost\n  tls:\n    cert: a.pem\n    key: a.key\n\n  port: 80\nclient:\n  retries: 3";
+	let cert = yaml.find("cert").unwrap();
+	assert_eq!(
+		normal_cmd("diz", yaml, cert).0,
+		"server:\n  host: localhost\n  tls:\n\n  port: 80\nclient:\n  retries: 3"
+	);
+	assert_eq!(
+		normal_cmd("daz", yaml, cert).0,
+		"server:\n  host: localhost\n\n  port: 80\nclient:\n  retries: 3"
+	);
+	assert_eq!(normal_cmd("d2iz", yaml, cert).0, "server:\nclient:\n  retries: 3");
+	// zj goes to the start of the next fold, zk to the end of the previous one
+	assert_eq!(normal_cmd("zj", yaml, 0).1, yaml.find("host").unwrap());
+	assert_eq!(normal_cmd("2zj", yaml, 0).1, cert);
+	assert_eq!(normal_cmd("zk", yaml, yaml.find("retries").unwrap()).1, yaml.find("port").unwrap());
+}
+
+#[test]
+fn editor_textobj_indent() {
+	let python = "def main():\n    x = 1\n    if x:\n        print(x)\n\n    return x\n\nmain()";
+	let print = python.find("print").unwrap();
+	assert_eq!(
+		normal_cmd("dii", python, print).0,
+		"def main():\n    x = 1\n    if x:\n\n    return x\n\nmain()"
+	);
+	assert_eq!(
+		normal_cmd("dai", python, print).0,
+		"def main():\n    x = 1\n\n    return x\n\nmain()"
+	);
+	// Blank lines inside of the block are included, the ones after it are not
+	assert_eq!(normal_cmd("dii", python, python.find("x = 1").unwrap()).0, "def main():\n\nmain()");
+	assert_eq!(normal_cmd("d2ii", python, print).0, "def main():\n\nmain()");
+}
+
+#[test]
+fn editor_textobj_argument() {
+	let call = r#"foo(a, bar(b, c), "x, y", d)"#;
+	let on = |s: &str| call.find(s).unwrap();
+	assert_eq!(normal_cmd("dia", call, on("bar")).0, r#"foo(a, , "x, y", d)"#);
+	assert_eq!(normal_cmd("daa", call, on("bar")).0, r#"foo(a, "x, y", d)"#);
+	// Quoted commas don't split arguments
+	assert_eq!(normal_cmd("daa", call, on("x")).0, "foo(a, bar(b, c), d)");
+	// The last argument takes the comma before it
+	assert_eq!(normal_cmd("daa", call, on("d)")).0, r#"foo(a, bar(b, c), "x, y")"#);
+	assert_eq!(normal_cmd("daa", call, on("c)")).0, r#"foo(a, bar(b), "x, y", d)"#);
+	assert_eq!(normal_cmd("d2aa", call, on("c)")).0, r#"foo(a, "x, y", d)"#);
+	// Apostrophes don't start quotes
+	assert_eq!(normal_cmd("daa", "[don't, stop, 'a,b']", 8).0, "[don't, 'a,b']");
+	assert_eq!(normal_cmd("daa", "f(only)", 2).0, "f()");
+	assert_eq!(normal_cmd("dia", "no brackets, here", 4).0, "no brackets, here");
+}
+
+#[test]
+fn editor_textobj_number() {
+	let line = "foo 12 bar -0x1F 3.5 end\nnext 7";
+	let on = |s: &str| line.find(s).unwrap();
+	assert_eq!(normal_cmd("din", line, on("12")).0, "foo  bar -0x1F 3.5 end\nnext 7");
+	assert_eq!(normal_cmd("dan", line, on("12")).0, "foo bar -0x1F 3.5 end\nnext 7");
+	// The number after the cursor on the same line, counts pick later ones
+	assert_eq!(normal_cmd("din", line, 0).0, "foo  bar -0x1F 3.5 end\nnext 7");
+	assert_eq!(normal_cmd("d2in", line, 0).0, "foo 12 bar  3.5 end\nnext 7");
+	assert_eq!(normal_cmd("din", line, on("end")).0, line);
+	// With nothing after it, `an` takes the whitespace before
+	assert_eq!(normal_cmd("dan", line, on("7")).0, "foo 12 bar -0x1F 3.5 end\nnext");
+	assert_eq!(normal_cmd("]n", line, 0).1, on("12"));
+	assert_eq!(normal_cmd("3]n", line, 0).1, on("3.5"));
+	assert_eq!(normal_cmd("]n", line, on("end")).1, on("7"));
+	assert_eq!(normal_cmd("2[n", line, on("end")).1, on("-0x1F"));
+	assert_eq!(normal_cmd("d]n", line, on("bar")).0, "foo 12 -0x1F 3.5 end\nnext 7");
+}
+
+#[test]
+fn editor_textobj_url_path_email() {
+	let line = "see https://example.com/a?b=1, or ~/src/main.rs. mail bob.smith+x@mail.example.org now";
+	let on = |s: &str| line.find(s).unwrap();
+	// Trailing punctuation belongs to the sentence, not the URL
+	assert_eq!(normal_cmd("diu", line, 0).0, "see , or ~/src/main.rs. mail bob.smith+x@mail.example.org now");
+	assert_eq!(normal_cmd("diu", line, on("example")).0, "see , or ~/src/main.rs. mail bob.smith+x@mail.example.org now");
+	assert_eq!(normal_cmd("daf", line, on("or")).0, "see https://example.com/a?b=1, or. mail bob.smith+x@mail.example.org now");
+	// The tail of a URL isn't a path
+	assert_eq!(normal_cmd("dif", line, 0).0, "see https://example.com/a?b=1, or . mail bob.smith+x@mail.example.org now");
+	assert_eq!(normal_cmd("d2if", "a/b c/d ../e", 0).0, "a/b  ../e");
+	assert_eq!(normal_cmd("die", line, 0).0, "see https://example.com/a?b=1, or ~/src/main.rs. mail  now");
+	assert_eq!(normal_cmd("dae", line, on("smith")).0, "see https://example.com/a?b=1, or ~/src/main.rs. mail now");
+	assert_eq!(normal_cmd("die", line, on("now")).0, line);
+	assert_eq!(normal_cmd("diu", "visit www.example.com.", 0).0, "visit .");
+}
+
+#[test]
+fn editor_textobj_tag() {
+	let html = "<div class=\"a>b\"><p>one <b>two</b></p><br><!-- <p> --><p>three</p></div>";
+	let on = |s: &str| html.find(s).unwrap();
+	assert_eq!(normal_cmd("dit", html, on("two")).0, "<div class=\"a>b\"><p>one <b></b></p><br><!-- <p> --><p>three</p></div>");
+	assert_eq!(normal_cmd("dat", html, on("two")).0, "<div class=\"a>b\"><p>one </p><br><!-- <p> --><p>three</p></div>");
+	// A count goes further out, and '<br>' never closes so it doesn't count
+	assert_eq!(normal_cmd("d2it", html, on("two")).0, "<div class=\"a>b\"><p></p><br><!-- <p> --><p>three</p></div>");
+	assert_eq!(normal_cmd("d3it", html, on("two")).0, "<div class=\"a>b\"></div>");
+	assert_eq!(normal_cmd("d4it", html, on("two")).0, html);
+	// On a tag counts as inside of its element, and the tags in a comment are skipped
+	assert_eq!(normal_cmd("dit", html, on("<br>")).0, "<div class=\"a>b\"></div>");
+	assert_eq!(normal_cmd("dit", html, on("three")).0, "<div class=\"a>b\"><p>one <b>two</b></p><br><!-- <p> --><p></p></div>");
+	// Outside of any element, the next one is used
+	assert_eq!(normal_cmd("dat", "x <A>\n  hi\n</a> y", 0).0, "x  y");
+	assert_eq!(normal_cmd("dit", "no tags here", 0).0, "no tags here");
+}
+
+#[test]
+fn editor_textobj_tag_capture() {
+	vicut_integration(
+		"<li><a href=\"/x\">first</a></li>\n",
+		&[
+			"--delimiter", "|",
+			"-m", "fr",
+			"-c", "vit",
+			"-c", "v2it",
+		],
+		"first|<a href=\"/x\">first</a>",
+	);
+}
+
+#[test]
+fn editor_textobj_key_value() {
+	assert_eq!(normal_cmd("div", "name = \"old value\";", 0).0, "name = ;");
+	assert_eq!(normal_cmd("dav", "  url: https://x.org", 0).0, "  ");
+	assert_eq!(normal_cmd("div", "\"a\" => 1,", 0).0, "\"a\" => ,");
+	// Only the first separator splits the pair
+	assert_eq!(normal_cmd("div", "PATH=/bin:/usr/bin", 0).0, "PATH=");
+	assert_eq!(normal_cmd("div", "a=1\nb=2", 4).0, "a=1\nb=");
+	assert_eq!(normal_cmd("div", "no separator", 0).0, "no separator");
+	assert_eq!(normal_cmd("div", "key=", 0).0, "key=");
+}
+
+#[test]
+fn editor_textobj_markdown() {
+	let doc = "# Title\nintro\n## Install\n- one\n  more one\n- two\n  1. nested\n\n```sh\n# not a heading\n```\n## Usage\ntext";
+	let on = |s: &str| doc.find(s).unwrap();
+	assert_eq!(
+		normal_cmd("dic", doc, on("# not")).0,
+		"# Title\nintro\n## Install\n- one\n  more one\n- two\n  1. nested\n\n```sh\n```\n## Usage\ntext"
+	);
+	assert_eq!(
+		normal_cmd("dac", doc, on("```")).0,
+		"# Title\nintro\n## Install\n- one\n  more one\n- two\n  1. nested\n\n## Usage\ntext"
+	);
+	// Headings inside of code blocks don't start sections
+	assert_eq!(normal_cmd("dah", doc, on("# not")).0, "# Title\nintro\n## Usage\ntext");
+	assert_eq!(normal_cmd("dih", doc, on("Usage")).0, "# Title\nintro\n## Install\n- one\n  more one\n- two\n  1. nested\n\n```sh\n# not a heading\n```\n## Usage\n");
+	assert_eq!(normal_cmd("d2ah", doc, on("text")).0, "");
+	assert_eq!(
+		normal_cmd("dil", doc, on("more")).0,
+		"# Title\nintro\n## Install\n- \n- two\n  1. nested\n\n```sh\n# not a heading\n```\n## Usage\ntext"
+	);
+	assert_eq!(
+		normal_cmd("d2al", doc, on("nested")).0,
+		"# Title\nintro\n## Install\n- one\n  more one\n\n```sh\n# not a heading\n```\n## Usage\ntext"
+	);
+	assert_eq!(normal_cmd("dil", doc, on("intro")).0, doc);
+}
+
+#[test]
+fn editor_textobj_string() {
+	let code = "x = \"a \\\"b\\\" c\" + f(\"multi\nline\")";
+	assert_eq!(normal_cmd("diq", code, 0).0, "x = \"\" + f(\"multi\nline\")");
+	assert_eq!(normal_cmd("daq", code, 0).0, "x = + f(\"multi\nline\")");
+	assert_eq!(normal_cmd("d2iq", code, 0).0, "x = \"a \\\"b\\\" c\" + f(\"\")");
+	// The cursor can be on any line of the string
+	assert_eq!(normal_cmd("diq", code, code.find("line").unwrap()).0, "x = \"a \\\"b\\\" c\" + f(\"\")");
+	// An escaped quote doesn't end the string, even with the cursor right after it
+	assert_eq!(normal_cmd("diq", code, code.find("b").unwrap() + 1).0, "x = \"\" + f(\"multi\nline\")");
+	assert_eq!(normal_cmd("diq", "cat <<EOF\nbody\nEOF", 0).0, "cat <<EOF\nEOF");
+	assert_eq!(normal_cmd("diq", "no strings", 0).0, "no strings");
+}
+
+#[test]
+fn editor_coerce_case() {
+	let code = "let fooBar = baz_qux;\nnext_line";
+	assert_eq!(normal_cmd("crs$", code, 0).0, "let foo_bar = baz_qux;\nnext_line");
+	assert_eq!(normal_cmd("crciw", code, code.find("baz").unwrap()).0, "let fooBar = bazQux;\nnext_line");
+	assert_eq!(normal_cmd("crkk", code, 0).0, "let foo-bar = baz-qux;\nnext_line");
+	assert_eq!(normal_cmd("cr_j", code, 0).0, "let foo_bar = baz_qux;\nnext_line");
+	assert_eq!(normal_cmd("crUiw", code, 4).0, "let FOO_BAR = baz_qux;\nnext_line");
+}
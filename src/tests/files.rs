@@ -42,3 +42,42 @@ fn file_all_comments() {
 		MAIN_ALL_COMMENTS.trim_end(),
 	);
 }
+
+/// Not a correctness test, run it with `cargo test --release -- --ignored --nocapture bench_shared_script`.
+///
+/// Compares running one parsed script over many buffers against parsing it, or copying it, for each buffer.
+#[test]
+#[ignore]
+fn bench_shared_script() {
+	use std::time::Instant;
+	let script = (0..200)
+		.map(|i| format!("if $filename == \"never{i}\" {{ cut \"e\" }}\n"))
+		.chain(["cut \"e\"\n".to_string()])
+		.collect::<String>();
+	let inputs = (0..2000).map(|i| format!("word{i} rest")).collect::<Vec<_>>();
+
+	let start = Instant::now();
+	for input in &inputs {
+		let opts = crate::Opts::from_raw(&script).unwrap();
+		crate::execute(&opts, input.clone(), None).unwrap();
+	}
+	let reparsed = start.elapsed();
+
+	let opts = crate::Opts::from_raw(&script).unwrap();
+	let start = Instant::now();
+	for input in &inputs {
+		crate::execute(&opts.clone(), input.clone(), None).unwrap();
+	}
+	let copied = start.elapsed();
+
+	let start = Instant::now();
+	for input in &inputs {
+		let fields = crate::execute(&opts, input.clone(), None).unwrap();
+		assert_eq!(fields[0][0].1.to_string(), input.split(' ').next().unwrap());
+	}
+	let shared = start.elapsed();
+
+	println!("reparsed: {reparsed:?}, copied: {copied:?}, shared: {shared:?}");
+	assert!(shared < reparsed);
+	assert!(shared < copied);
+}
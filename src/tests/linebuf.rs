@@ -1,4 +1,5 @@
-use crate::linebuf::LineBuf;
+use crate::linebuf::{add_to_number, apply_hunks, coerce_case, diff_hunks, expand_braces, expand_replacement, expand_tilde, find_code_fences, find_folds, find_list_items, find_sections, find_strings, reflow, subvert_variants, token_frequencies, WORD_TOKEN, FoldMethod, Hunk, LineBuf};
+use crate::{exec::Val, vicmd::{Case, LineAddr, Motion}};
 
 #[test]
 fn linebuf_empty_linebuf() {
@@ -169,3 +170,199 @@ fn linebuf_cursor_motion() {
 		);
 	}
 }
+
+#[test]
+fn linebuf_line_marks() {
+	let marked = |text: &str, lines: &[usize], edit: &dyn Fn(&mut LineBuf)| {
+		let mut buf = LineBuf::new().with_initial(text.into(), 0);
+		buf.line_marks.push(lines.iter().copied().collect());
+		edit(&mut buf);
+		buf.line_marks.pop().unwrap()
+	};
+	// Lines after an insertion move down
+	assert_eq!(marked("a\nb\nc\n", &[0, 2], &|buf| buf.insert_str_at(2, "new\n")), [0, 3]);
+	// Deleted lines are dropped, and the rest move up
+	assert_eq!(marked("a\nb\nc\nd\n", &[1, 2, 3], &|buf| { buf.drain(2, 4); }), [1, 2]);
+	// Lines that were changed in place keep their position
+	assert_eq!(marked("a\nb\nc\n", &[1, 2], &|buf| buf.replace_range(2, 3, "B!")), [1, 2]);
+	// A line joined into the one before it is dropped
+	assert_eq!(marked("a\nb\nc\n", &[0, 1, 2], &|buf| buf.replace_range(1, 2, " ")), [0, 1]);
+	// The edit itself says which line went, even when the lines all look the same
+	assert_eq!(marked("x\nx\nx\n", &[1, 2], &|buf| { buf.drain(0, 2); }), [0, 1]);
+}
+
+#[test]
+fn linebuf_global_lines() {
+	let mut buf = LineBuf::new().with_initial("a1\nb\na2\na3\nb".into(), 0);
+	let all = Motion::LineRange(LineAddr::Number(1), LineAddr::Last);
+	let pattern = Val::Str("a".into());
+	assert_eq!(buf.global_lines(&all, &pattern, true, None), [3, 2, 0]);
+	assert_eq!(buf.global_lines(&all, &pattern, false, None), [4, 1]);
+	// The scan stops at the first N matches from the top
+	assert_eq!(buf.global_lines(&all, &pattern, true, Some(2)), [2, 0]);
+}
+
+#[test]
+fn linebuf_find_folds() {
+	let yaml = "server:\n  host: localhost\n  tls:\n    cert: a.pem\n    key: a.key\n\n  port: 80\nclient:\n  retries: 3";
+	assert_eq!(find_folds(yaml, FoldMethod::Indent), [(1,6), (3,4), (8,8)]);
+
+	let markers = "a\n{{{\nb\n{{{\nc\n}}}\n}}}\nd";
+	assert_eq!(find_folds(markers, FoldMethod::Marker), [(1,6), (3,5)]);
+	// Unbalanced markers are ignored
+	assert_eq!(find_folds("}}}\n{{{\na", FoldMethod::Marker), []);
+}
+
+#[test]
+fn linebuf_find_markdown_blocks() {
+	let doc = "# Title\nintro\n## Install\n- one\n  more one\n- two\n  1. nested\n  2. other\n\n```sh\n# not a heading\n- not an item\n```\n## Usage\ntext";
+	assert_eq!(find_code_fences(doc), [(9,12)]);
+	assert_eq!(find_sections(doc), [(0,14), (2,12), (13,14)]);
+	assert_eq!(find_list_items(doc), [(3,4), (5,7), (6,6), (7,7)]);
+	// Closing fences have to match the opening one, and unclosed blocks run to the end
+	assert_eq!(find_code_fences("````\n```\n````\n~~~\na"), [(0,2), (3,4)]);
+	assert_eq!(find_list_items("-not\n1.also not\n10) yes"), [(2,2)]);
+}
+
+#[test]
+fn linebuf_find_strings() {
+	let inner = |text: &str, escape: char| find_strings(text, escape)
+		.into_iter()
+		.map(|(_,inner)| text[inner].to_string())
+		.collect::<Vec<_>>();
+	assert_eq!(inner(r#"a "b \"c\" d" 'e' `f`"#, '\\'), [r#"b \"c\" d"#, "e", "f"]);
+	assert_eq!(inner(r#"\"not "yes" \\"#, '\\'), ["yes"]);
+	assert_eq!(inner(r#""a\\" "b""#, '\\'), [r"a\\", "b"]);
+	assert_eq!(inner("\"multi\nline\"", '\\'), ["multi\nline"]);
+	assert_eq!(inner("'''it's \"quoted\"\n'''", '\\'), ["it's \"quoted\"\n"]);
+	// Apostrophes don't start strings, and neither does an unterminated quote
+	assert_eq!(inner("don't \"go\" or 'come", '\\'), ["go"]);
+	assert_eq!(inner("'it''s' 'x'", '\''), ["it''s", "x"]);
+	assert_eq!(inner("cat <<-'EOF' >f\n\tbody \"x\n\tEOF\nnext \"y\"", '\\'), ["\tbody \"x\n", "y"]);
+	assert_eq!(inner("x << 2", '\\'), Vec::<String>::new());
+}
+
+#[test]
+fn linebuf_coerce_case() {
+	let text = "fooBar baz_qux HTTPServer _private-name x2y, user-ID";
+	assert_eq!(coerce_case(text, Case::Snake), "foo_bar baz_qux http_server _private_name x2y, user_id");
+	assert_eq!(coerce_case(text, Case::Camel), "fooBar bazQux httpServer _privateName x2y, userId");
+	assert_eq!(coerce_case(text, Case::Pascal), "FooBar BazQux HttpServer _PrivateName X2y, UserId");
+	assert_eq!(coerce_case(text, Case::Upper), "FOO_BAR BAZ_QUX HTTP_SERVER _PRIVATE_NAME X2Y, USER_ID");
+	assert_eq!(coerce_case(text, Case::Kebab), "foo-bar baz-qux http-server _private-name x2y, user-id");
+	assert_eq!(coerce_case("first_name", Case::Title), "First Name");
+}
+
+#[test]
+fn linebuf_subvert_variants() {
+	assert_eq!(expand_braces("facilit{y,ies}").unwrap(), ["facility", "facilities"]);
+	assert_eq!(expand_braces("{a,b}_{c,d}").unwrap(), ["a_c", "a_d", "b_c", "b_d"]);
+	assert!(expand_braces("a{b").is_err());
+
+	let variants = subvert_variants("child{,ren}", "adult{,s}").unwrap();
+	let lookup = |from: &str| variants.iter().find(|(old,_)| old == from).map(|(_,new)| new.as_str());
+	assert_eq!(lookup("children"), Some("adults"));
+	assert_eq!(lookup("Child"), Some("Adult"));
+	assert_eq!(lookup("CHILDREN"), Some("ADULTS"));
+	let variants = subvert_variants("box_office", "ticket_window").unwrap();
+	let lookup = |from: &str| variants.iter().find(|(old,_)| old == from).map(|(_,new)| new.as_str());
+	assert_eq!(lookup("boxOffice"), Some("ticketWindow"));
+	assert_eq!(lookup("box-office"), Some("ticket-window"));
+	assert!(subvert_variants("x{a,b}", "y{a,b,c}").is_err());
+}
+
+#[test]
+fn linebuf_token_frequencies() {
+	let counts = token_frequencies("b a b c a b", &WORD_TOKEN);
+	assert_eq!(counts, vec![("b".to_string(), 3), ("a".to_string(), 2), ("c".to_string(), 1)]);
+	let tokenizer = regex::Regex::new(r"\S+").unwrap();
+	assert_eq!(token_frequencies("x.y x.y z", &tokenizer), vec![("x.y".to_string(), 2), ("z".to_string(), 1)]);
+	assert!(token_frequencies("", &WORD_TOKEN).is_empty());
+}
+
+#[test]
+fn linebuf_diff_hunks() {
+	let old = "one\ntwo\nthree\nfour\n";
+	let new = "one\nTWO\nthree\nfour\nfive\n";
+	let hunks = diff_hunks(old, new);
+	assert_eq!(hunks, vec![
+		Hunk { line: 1, removed: vec!["two".into()], added: vec!["TWO".into()] },
+		Hunk { line: 4, removed: vec![], added: vec!["five".into()] },
+	]);
+	assert_eq!(apply_hunks(old, &hunks).unwrap(), new);
+	assert_eq!(apply_hunks(old, &hunks[1..]).unwrap(), "one\ntwo\nthree\nfour\nfive\n");
+	assert_eq!(apply_hunks("a\nb", &diff_hunks("a\nb", "a\nc")).unwrap(), "a\nc");
+	assert!(diff_hunks(old, old).is_empty());
+	assert!(apply_hunks("one\nthree\n", &hunks).is_err());
+}
+
+#[test]
+fn linebuf_expand_replacement() {
+	let regex = regex::Regex::new(r"(?P<key>\w+)=(?P<val>\w+)").unwrap();
+	let caps = regex.captures("foo=bar").unwrap();
+	assert_eq!(expand_replacement(&caps, r"\2:\1"), "bar:foo");
+	assert_eq!(expand_replacement(&caps, "${val}:$key"), "bar:foo");
+	assert_eq!(expand_replacement(&caps, r"<\0> $$1 \\ \q"), r"<foo=bar> $1 \ \q");
+	assert_eq!(expand_replacement(&caps, r"\9$nope"), "");
+}
+
+#[test]
+fn linebuf_expand_replacement_vim_specials() {
+	let regex = regex::Regex::new(r"(\w+)=(\w+)").unwrap();
+	let caps = regex.captures("foo=bar").unwrap();
+	assert_eq!(expand_replacement(&caps, "[&]"), "[foo=bar]");
+	assert_eq!(expand_replacement(&caps, r"\& \~ \\&"), r"& ~ \foo=bar");
+	assert_eq!(expand_replacement(&caps, r"\1\r\2\t\n\\r"), "foo\nbar\t\0\\r");
+
+	assert_eq!(expand_tilde("<~>", Some(r"\1")), r"<\1>");
+	assert_eq!(expand_tilde(r"\~~", Some("x")), r"\~x");
+	assert_eq!(expand_tilde("~", None), "");
+}
+
+#[test]
+fn linebuf_expand_replacement_case_modifiers() {
+	let regex = regex::Regex::new(r"(\w+) (\w+)").unwrap();
+	let caps = regex.captures("hello WORLD").unwrap();
+	assert_eq!(expand_replacement(&caps, r"\u\1 \l\2"), "Hello wORLD");
+	assert_eq!(expand_replacement(&caps, r"\U\1\e \L$2\E!"), "HELLO world!");
+	assert_eq!(expand_replacement(&caps, r"\U&"), "HELLO WORLD");
+	// '\u' inside '\L' only changes the one character
+	assert_eq!(expand_replacement(&caps, r"\L\u\2"), "World");
+	// a doubled backslash keeps the letter after it
+	assert_eq!(expand_replacement(&caps, r"\\u\1"), r"\uhello");
+}
+
+#[test]
+fn linebuf_reflow() {
+	assert_eq!(reflow("aa bb cc\ndd ee\n", 5), "aa bb\ncc dd\nee\n");
+	assert_eq!(reflow("aa bb\n\n  cc dd", 80), "aa bb\n\n  cc dd");
+	assert_eq!(reflow("// aa bb cc\n// dd\nlet x = 1;", 10), "// aa bb\n// cc dd\nlet x = 1;");
+	assert_eq!(reflow("> quoted\n> text", 80), "> quoted text");
+	assert_eq!(reflow("tiny verylongword end", 4), "tiny\nverylongword\nend");
+}
+
+#[test]
+fn linebuf_add_to_number() {
+	assert_eq!(add_to_number("9", 5).as_deref(), Some("14"));
+	assert_eq!(add_to_number("-1", 3).as_deref(), Some("2"));
+	assert_eq!(add_to_number("0", -1).as_deref(), Some("-1"));
+	assert_eq!(add_to_number("007", 1).as_deref(), Some("008"));
+	assert_eq!(add_to_number("-007", 10).as_deref(), Some("003"));
+	assert_eq!(add_to_number("0x0f", 1).as_deref(), Some("0x10"));
+	assert_eq!(add_to_number("0XFF", 1).as_deref(), Some("0X100"));
+	assert_eq!(add_to_number("0x00", -1).as_deref(), Some("0xffffffffffffffff"));
+	assert_eq!(add_to_number(&i64::MAX.to_string(), 1), None);
+}
+
+#[test]
+fn linebuf_adjust_marks() {
+	let mut buf = LineBuf::new().with_initial("one two three".into(), 0);
+	buf.marks.extend([('a', 0), ('b', 5), ('c', 10)]);
+	buf.adjust_marks("one two three", "one 2 three");
+	assert_eq!(buf.marks[&'a'], 0);
+	assert_eq!(buf.marks[&'b'], 4);
+	assert_eq!(buf.marks[&'c'], 8);
+	buf.adjust_marks("one 2 three", "zero one 2 three");
+	assert_eq!(buf.marks[&'a'], 5);
+	assert_eq!(buf.marks[&'c'], 13);
+}
@@ -16,6 +16,9 @@ pub mod editor;
 pub mod files;
 pub mod pattern_match;
 pub mod wiki_examples;
+pub mod opts;
+pub mod vic;
+pub mod picker;
 
 fn vicut_integration(input: &str, args: &[&str], expected: &str) {
 	let output = call_main(args, input).unwrap();
@@ -71,7 +74,7 @@ pub fn call_main(args: &[&str], input: &str) -> Result<String,String> {
 	} else {
 		// Let's see if we got a literal in-line script instead then
 		let mut flags = args.iter().take_while(|arg| **arg != "--");
-		let use_inline = flags.all(|arg| !arg.starts_with('-'));
+		let use_inline = flags.all(|arg| !arg.starts_with('-') || *arg == crate::STDIN_PATH);
 
 		if use_inline {
 			// We know that there's at least one argument, so we can safely unwrap
@@ -111,11 +114,20 @@ pub fn call_main(args: &[&str], input: &str) -> Result<String,String> {
 			})
 		}
 	};
+	// JSON Lines are one record per line, and --record-start works on records the same way. run() sets this too
+	let args = Opts { linewise: args.linewise || args.json_input || args.record_start.is_some(), ..args };
 
 	use std::{io::{self, BufRead, Cursor}, path::PathBuf};
 
-use crate::{execute, execute_linewise, format_output, get_help, get_lines, Opts};
-	if args.linewise {
+use crate::{execute, execute_linewise, execute_stream, format_output, get_help, Opts};
+	if args.no_input {
+		let output = execute(&args, String::new(), None).map_err(|e| format!("vicut: {e}"))?;
+		Ok(format_output(&args, output))
+	} else if args.stream {
+		let mut output = vec![];
+		execute_stream(Cursor::new(input.to_string()), &mut output, &args).map_err(|e| format!("vicut: {e}"))?;
+		Ok(String::from_utf8(output).unwrap())
+	} else if args.linewise {
 		if args.single_thread {
 			// We need to initialize stream in each branch, since Box<dyn BufReader> does not implement send/sync
 			// So using it in pool.install() doesn't work. We have to initialize it in the closure there.
@@ -124,7 +136,7 @@ use crate::{execute, execute_linewise, format_output, get_help, get_lines, Opts}
 			let mut input = String::new();
 			stream.read_to_string(&mut input).unwrap();
 			let mut lines = vec![];
-			for line in get_lines(&input) {
+			for line in args.split_input(&input) {
 				match execute(&args,line,None) {
 					Ok(mut new_line) => {
 						lines.append(&mut new_line);
@@ -137,17 +149,10 @@ use crate::{execute, execute_linewise, format_output, get_help, get_lines, Opts}
 			let output = format_output(&args, lines);
 			Ok(output)
 		} else if let Some(num) = args.max_jobs {
-			let pool = rayon::ThreadPoolBuilder::new()
-				.num_threads(num as usize)
-				.build()
-				.unwrap_or_else(|e| {
-					eprintln!("vicut: Failed to build thread pool: {e}");
-					std::process::exit(1)
-				});
-			Ok(pool.install(|| {
+			crate::par::with_max_jobs(num, || {
 				let stream: Box<dyn BufRead> = Box::new(io::BufReader::new(Cursor::new(input.to_string())));
 				execute_linewise(stream, &args)
-			}))
+			}).map_err(|e| format!("vicut: {e}"))
 		} else {
 			let stream: Box<dyn BufRead> = Box::new(io::BufReader::new(Cursor::new(input.to_string())));
 			Ok(execute_linewise(stream, &args))
@@ -185,15 +190,63 @@ impl Opts {
 				"--json" | "-j" => {
 					new.json = true;
 				}
+				"--json-input" => {
+					new.json_input = true;
+				}
+				"--field" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a field name after '{arg}'"))
+					};
+					new.json_field = Some(next_arg);
+				}
 				"--trace" => {
 					new.trace = true;
 				}
 				"--linewise" => {
 					new.linewise = true;
 				}
+				"--stream" => {
+					new.stream = true;
+				}
+				"-0" | "--null" | "--read0" => {
+					new.read0 = true;
+				}
+				"--print0" => {
+					new.print0 = true;
+				}
+				"--record-sep" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a pattern after '{arg}'"))
+					};
+					new.record_sep = Some(crate::parse_record_sep(&next_arg)?);
+				}
+				"--record-start" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a pattern after '{arg}'"))
+					};
+					new.record_start = Some(crate::parse_record_start(&next_arg)?);
+				}
+				"--fields-by" | "--field-sep-regex" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a pattern after '{arg}'"))
+					};
+					new.fields_by = Some(crate::parse_fields_by(&next_arg)?);
+				}
+				"--no-input" => {
+					new.no_input = true;
+				}
 				"--serial" => {
 					new.single_thread = true;
 				}
+				"--shared-registers" => {
+					new.shared_registers = true;
+				}
+				"--deterministic" => {
+					new.deterministic = true;
+				}
+				"--stats" => {
+					new.stats = true;
+				}
 				"--trim-fields" => {
 					new.trim_fields = true;
 				}
@@ -206,9 +259,244 @@ impl Opts {
 				"--global-uses-line-numbers" => {
 					new.global_uses_line_numbers = true;
 				}
+				"--record-per-match" => {
+					new.record_per_match = true;
+				}
+				"--capture-line" => {
+					new.capture_line = true;
+				}
+				"--capture-word" => {
+					new.capture_word = true;
+				}
+				"--capture-lineno" => {
+					new.capture_lineno = true;
+				}
+				"--sandbox" => {
+					new.sandbox = true;
+				}
+				"--no-shell" => {
+					new.no_shell = true;
+				}
+				"--pick" => {
+					new.pick = true;
+				}
+				"--dump-state" => {
+					new.dump_state = true;
+				}
+				"--emit-schema" => {
+					new.emit_schema = true;
+				}
+				"--shell-timeout" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of seconds after '{arg}'"))
+					};
+					new.shell_timeout = Some(crate::parse_timeout(&next_arg)?);
+				}
+				"--regex-timeout" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of seconds after '{arg}'"))
+					};
+					new.regex_timeout = Some(crate::parse_timeout(&next_arg)?);
+				}
+				"--regex-size-limit" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of bytes after '{arg}'"))
+					};
+					new.regex_size_limit = Some(crate::parse_size_limit(&next_arg)?);
+				}
+				"--shell" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a shell after '{arg}'"))
+					};
+					new.shell = Some(crate::parse_shell(&next_arg)?);
+				}
+				"--shell-cwd" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a directory after '{arg}'"))
+					};
+					new.shell_cwd = Some(crate::shell::ShellCwd::from(next_arg.as_str()));
+				}
+				"--equalprg" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a formatter command after '{arg}'"))
+					};
+					new.equalprg = Some(next_arg);
+				}
+				"--eol" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'lf' or 'crlf' after '{arg}'"))
+					};
+					new.eol = Some(crate::Eol::try_from(next_arg.as_str())?);
+				}
+				"--flush" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'never', 'record', or 'line' after '{arg}'"))
+					};
+					new.flush = Some(crate::FlushPolicy::try_from(next_arg.as_str())?);
+				}
+				"--on-interrupt" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'finish' or 'abort' after '{arg}'"))
+					};
+					new.on_interrupt = crate::interrupt::InterruptPolicy::try_from(next_arg.as_str())?;
+				}
+				"--g-order" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'forward' or 'reverse' after '{arg}'"))
+					};
+					new.g_order = crate::GOrder::try_from(next_arg.as_str())?;
+				}
+				"--units" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'bytes', 'chars', or 'graphemes' after '{arg}'"))
+					};
+					new.buf_settings.units = crate::linebuf::TextUnit::try_from(next_arg.as_str())?;
+				}
+				"--window-height" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of lines after '{arg}'"))
+					};
+					new.buf_settings.window_height = Some(crate::parse_window_height(&next_arg)?);
+				}
+				"--textwidth" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of columns after '{arg}'"))
+					};
+					new.buf_settings.textwidth = Some(crate::parse_textwidth(&next_arg)?);
+				}
+				"--jobs" | "-J" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of threads after '{arg}'"))
+					};
+					new.max_jobs = Some(crate::parse_jobs(&next_arg)?);
+				}
+				"--field-newlines" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'keep', 'strip', or 'escape' after '{arg}'"))
+					};
+					new.field_newlines = crate::exec::FieldNewlines::try_from(next_arg.as_str())?;
+				}
+				"--field-base" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number after '{arg}'"))
+					};
+					let base = next_arg.parse::<usize>().map_err(|_| format!("Expected a number after '{arg}', found '{next_arg}'"))?;
+					// No record holds more than isize::MAX fields, so this keeps every field number within a usize
+					if base > isize::MAX as usize {
+						return Err(format!("Expected a number no larger than {} after '{arg}', found '{next_arg}'", isize::MAX))
+					}
+					new.field_base = Some(base);
+				}
+				"--field-scope" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'group' or 'record' after '{arg}'"))
+					};
+					new.field_scope = crate::FieldScope::try_from(next_arg.as_str())?;
+				}
+				"--fold-method" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'indent' or 'marker' after '{arg}'"))
+					};
+					new.buf_settings.fold_method = crate::linebuf::FoldMethod::try_from(next_arg.as_str())?;
+				}
+				"--sub-syntax" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'vim' or 'regex' after '{arg}'"))
+					};
+					new.buf_settings.sub_syntax = crate::linebuf::SubSyntax::try_from(next_arg.as_str())?;
+				}
+				"--kv-separators" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a list of separators after '{arg}'"))
+					};
+					new.buf_settings.kv_separators = Some(crate::parse_kv_separators(&next_arg)?);
+				}
+				"--string-escape" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a character after '{arg}'"))
+					};
+					new.buf_settings.string_escape = Some(crate::parse_string_escape(&next_arg)?);
+				}
+				"--freq" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number of tokens after '{arg}'"))
+					};
+					new.freq = Some(crate::parse_freq_count(&next_arg)?);
+				}
+				"--freq-pattern" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a pattern after '{arg}'"))
+					};
+					new.freq_pattern = Some(crate::parse_freq_pattern(&next_arg)?);
+				}
+				"--verify-roundtrip" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a list of checks after '{arg}'"))
+					};
+					new.verify_roundtrip = crate::RoundtripCheck::parse_list(&next_arg)?;
+				}
+				"--bom" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'strip' or 'keep' after '{arg}'"))
+					};
+					new.strip_bom = crate::parse_bom_arg(&next_arg)?;
+				}
 				"-i" => {
 					new.edit_inplace = true;
 				}
+				"--out-template" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a file name template after '{arg}'"))
+					};
+					new.out_template = Some(crate::parse_out_template(&next_arg)?);
+				}
+				"--wait" => new.wait_for_lock = true,
+				"--cache" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a file name after '{arg}'"))
+					};
+					new.cache = Some(std::path::PathBuf::from(next_arg));
+				}
+				"--no-clobber" => new.no_clobber = true,
+				"--files-from" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a file name after '{arg}'"))
+					};
+					for file in crate::read_file_list(&next_arg)? {
+						new.handle_filename(file);
+					}
+					new.files_from = Some(std::path::PathBuf::from(next_arg));
+				}
+				"--dry-run" => {
+					new.edit_inplace = true;
+					new.dry_run = true;
+				}
+				"--patch" => {
+					new.edit_inplace = true;
+					new.dry_run = true;
+					new.patch = true;
+				}
+				"-R" | "--recursive" => new.recursive = true,
+				"--hidden" => new.hidden = true,
+				"--max-filesize" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a size after '{arg}'"))
+					};
+					new.max_filesize = Some(crate::parse_filesize(&next_arg)?);
+				}
+				"--text" => new.text = true,
+				"--format" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'csv' or 'tsv' after '{arg}'"))
+					};
+					new.format = Some(crate::OutputFormat::try_from(next_arg.as_str())?);
+				}
+				"--git" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected 'changed', 'staged', or 'ls-files' after '{arg}'"))
+					};
+					new.git = Some(crate::GitSelection::try_from(next_arg.as_str())?);
+				}
 				"--template" | "-t" => {
 					let Some(next_arg) = args.next() else {
 						return Err(format!("Expected a format string after '{arg}'"))
@@ -218,6 +506,12 @@ impl Opts {
 					}
 					new.template = Some(next_arg.to_string());
 				}
+				"--assert" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a template after '{arg}'"))
+					};
+					new.asserts.push(next_arg.to_string());
+				}
 				"--delimiter" | "-d" => {
 					let Some(next_arg) = args.next() else { continue };
 					if next_arg.starts_with('-') {
@@ -226,6 +520,12 @@ impl Opts {
 					new.delimiter = Some(next_arg.to_string());
 				}
 				"-n" | "--next" => new.cmds.push(Cmd::BreakGroup),
+				"--program" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a glob after '{arg}'"))
+					};
+					new.programs.push((crate::parse_program_glob(&next_arg)?, new.cmds.len()));
+				}
 				"-r" | "--repeat" => {
 					let cmd_count = args
 						.next()
@@ -240,6 +540,9 @@ impl Opts {
 
 					let mut body = vec![];
 					let drain_count = new.cmds.len().saturating_sub(cmd_count);
+					if new.programs.last().is_some_and(|(_,start)| drain_count < *start) {
+						return Err(format!("'{arg}' can't repeat commands from before the last '--program'"))
+					}
 					body.extend(new.cmds.drain(drain_count..));
 					dbg!(&body);
 					dbg!(&repeat_count);
@@ -254,7 +557,9 @@ impl Opts {
 				}
 				"-c" | "--cut" => {
 					let Some(arg) = args.next() else { continue };
-					if arg.starts_with("name=") {
+					if let Some(pattern) = arg.strip_prefix("pattern=") {
+						new.cmds.push(crate::parse_pattern_field(pattern)?);
+					} else if arg.starts_with("name=") {
 						let name = arg.strip_prefix("name=").unwrap().to_string();
 						if name == "0" {
 							// We use '0' as a sentinel value to say "We didn't slice any fields, so this field is the entire buffer"
@@ -275,7 +580,7 @@ impl Opts {
 				}
 				"-v" | "--not-global" |
 				"-g" | "--global" => {
-					let global = Self::handle_global_arg(&arg, &mut args);
+					let global = Self::handle_global_arg(&arg, &mut args)?;
 					new.cmds.push(global);
 				}
 				_ => new.handle_filename(arg.to_string())
@@ -283,7 +588,7 @@ impl Opts {
 		}
 		Ok(new)
 	}
-	fn handle_global_arg_raw(arg: &str, args: &mut Peekable<Skip<std::slice::Iter<'_, &str>>>) -> Cmd {
+	fn handle_global_arg_raw(arg: &str, args: &mut Peekable<Skip<std::slice::Iter<'_, &str>>>) -> Result<Cmd,String> {
 		let polarity = match arg {
 			"-v" | "--not-global" => false,
 			"-g" | "--global" => true,
@@ -291,13 +596,17 @@ impl Opts {
 		};
 		let mut then_cmds = vec![];
 		let mut else_cmds = None;
+		let mut limit = None;
+		let mut context = None;
 		let Some(arg) = args.next() else {
-			return Cmd::Global {
+			return Ok(Cmd::Global {
 				pattern: CmdArg::Literal(Val::Str(arg.to_string())),
 				then_cmds,
 				else_cmds,
-				polarity
-			};
+				polarity,
+				limit,
+				context
+			});
 		};
 		if arg.starts_with('-') {
 			eprintln!("Expected a selection command after '-c', found {arg}");
@@ -349,7 +658,14 @@ impl Opts {
 				}
 				"-c" | "--cut" => {
 					let Some(arg) = args.next() else { continue };
-					if arg.starts_with("name=") {
+					if let Some(pattern) = arg.strip_prefix("pattern=") {
+						let cmd = crate::parse_pattern_field(pattern).unwrap_or_else(crate::complain_and_exit);
+						if let Some(cmds) = else_cmds.as_mut() {
+							cmds.push(cmd);
+						} else {
+							then_cmds.push(cmd);
+						}
+					} else if arg.starts_with("name=") {
 						let name = arg.strip_prefix("name=").unwrap().to_string();
 						let Some(arg) = args.next() else { continue };
 						if arg.starts_with('-') {
@@ -375,25 +691,41 @@ impl Opts {
 				}
 				"-g" | "--global" |
 				"-v" | "--not-global" => {
-					let nested = Self::handle_global_arg_raw(global_arg, args);
+					let nested = Self::handle_global_arg_raw(global_arg, args)?;
 					if let Some(cmds) = else_cmds.as_mut() {
 						cmds.push(nested);
 					} else {
 						then_cmds.push(nested);
 					}
 				}
+				"--max-matches" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number after '{global_arg}'"))
+					};
+					let max = next_arg.parse::<usize>().map_err(|_| format!("Expected a number after '{global_arg}', found '{next_arg}'"))?;
+					limit = Some(CmdArg::Count(max));
+				}
+				"--context" => {
+					let Some(next_arg) = args.next() else {
+						return Err(format!("Expected a number after '{global_arg}'"))
+					};
+					let lines = next_arg.parse::<usize>().map_err(|_| format!("Expected a number after '{global_arg}', found '{next_arg}'"))?;
+					context = Some(CmdArg::Count(lines));
+				}
 				"--else" => {
 					// Now we start working on this
 					else_cmds = Some(vec![]);
 				}
 				"--end" => {
 					// We're done here
-					return Cmd::Global {
+					return Ok(Cmd::Global {
 						pattern: CmdArg::Literal(Val::Str(arg.to_string())),
 						then_cmds,
 						else_cmds,
-						polarity
-					};
+						polarity,
+						limit,
+						context
+					});
 				}
 				_ => {
 					eprintln!("Expected command flag in '-g' scope\nDid you forget to close '-g' with '--end'?");
@@ -406,11 +738,13 @@ impl Opts {
 		// If we got here, we have run out of arguments
 		// Let's just submit the current -g commands.
 		// no need to be pressed about a missing '--end' when nothing would come after it
-		Cmd::Global {
+		Ok(Cmd::Global {
 			pattern: CmdArg::Literal(Val::Str(arg.to_string())),
 			then_cmds,
 			else_cmds,
-			polarity
-		}
+			polarity,
+			limit,
+			context
+		})
 	}
 }
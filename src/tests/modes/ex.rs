@@ -95,3 +95,278 @@ fn ex_global_normal_nested() {
 		"Wow!\nBar\nBiz\nWow!\nBuzz\nWow!\nBaz",
 	);
 }
+
+#[test]
+fn ex_smart_substitute() {
+	vicut_integration(
+		"The facility and its Facilities\nFACILITY_ID, facilityName\nfacility",
+		&[
+			"-m", ":%S/facilit{y,ies}/building{,s}/g",
+		],
+		"The building and its Buildings\nBUILDING_ID, buildingName\nbuilding",
+	);
+	// Only the first match without 'g', and only whole words with 'w'
+	vicut_integration(
+		"cat catalog Cat",
+		&[
+			"-m", ":S/cat/dog/",
+		],
+		"dog catalog Cat",
+	);
+	vicut_integration(
+		"cat catalog Cat",
+		&[
+			"-m", ":S/cat/dog/gw",
+		],
+		"dog catalog Dog",
+	);
+}
+
+#[test]
+fn ex_substitute_backrefs() {
+	vicut_integration(
+		"foo=bar baz=qux\nnaïve café=1",
+		&[
+			"-m", r":%s/(\w+)=(\w+)/\2=\1/g",
+		],
+		"bar=foo qux=baz\nnaïve 1=café",
+	);
+	// The regex crate's own syntax works too, with '${' escaped from variable interpolation, and ':s' repeats with the same replacement
+	vicut_integration(
+		"a=b\nc=d",
+		&[
+			"-m", r":s/(\w)=(\w)/\${2}=$1/<CR>j:s<CR>",
+		],
+		"b=a\nd=c",
+	);
+	vicut_integration(
+		"cost 5",
+		&[
+			"-m", r":s/\d/$$\0 \\/",
+		],
+		r"cost $5 \",
+	);
+}
+
+#[test]
+fn ex_substitute_vim_specials() {
+	// '&' is the whole match, and '~' is the previous replacement, which has its own '&' expanded again
+	vicut_integration(
+		"a=b\nc=d",
+		&[
+			"-m", r":s/\w/[&]/g<CR>j:s/=/~\&/<CR>",
+		],
+		"[a]=[b]\nc[=]&d",
+	);
+	// '\r' splits the line, '\t' is a tab, and '\n' is a NUL byte, like in Vim
+	vicut_integration(
+		"a=b\nc=d",
+		&[
+			"-m", r":%s/=/\r/<CR>",
+		],
+		"a\nb\nc\nd",
+	);
+	vicut_integration(
+		"a=b",
+		&[
+			"-m", r":s/=/\t\n/<CR>",
+		],
+		"a\t\0b",
+	);
+	// --sub-syntax regex leaves everything but the regex crate's syntax alone
+	vicut_integration(
+		"a=b",
+		&[
+			"--sub-syntax", "regex",
+			"-m", r":s/(\w)=(\w)/\1&~$2/<CR>",
+		],
+		r"\1&~b",
+	);
+}
+
+#[test]
+fn ex_substitute_spans() {
+	// 'ir' is the next replacement, 'ar' runs from the first one to the last
+	vicut_integration(
+		"a=1\nskip\nb=22\n",
+		&[
+			"--delimiter", "|",
+			"-m", r":%s/(\w+)=(\w+)/\2=\1/<CR>gg",
+			"-c", "vir",
+			"-m", "0",
+			"-c", "v2ir",
+		],
+		"1=a|22=b",
+	);
+	vicut_integration(
+		"x a=1\nskip\nb=22 y\n",
+		&[
+			"-m", r":%s/(\w+)=(\w+)/\2=\1/<CR>G",
+			"-m", "dar",
+		],
+		"x  y",
+	);
+	// 'g;' counts back from the last replacement, and 'g,' counts from the first
+	vicut_integration(
+		"x foo foo\nfoo\n",
+		&[
+			"--delimiter", "|",
+			"-m", r":%s/foo/bar/g<CR>",
+			"-m", "g;", "-c", "ve",
+			"-m", "2g;", "-c", "ve",
+			"-m", "g,", "-c", "vir",
+		],
+		"bar|bar|bar",
+	);
+	vicut_integration(
+		"x foo foo\nfoo\n",
+		&[
+			"-m", r":%s/foo/bar/g<CR>",
+			"-m", "2g,", "-c", "vE",
+			"-m", "3g,", "-c", "vE",
+		],
+		"bar bar",
+	);
+	// Any other edit forgets them
+	vicut_integration(
+		"foo bar\n",
+		&[
+			"-m", r":s/foo/baz/<CR>wx",
+			"-m", "0dir",
+		],
+		"baz ar",
+	);
+}
+
+#[test]
+fn ex_substitute_case_modifiers() {
+	vicut_integration(
+		"first_name=jane\nlast_name=DOE",
+		&[
+			"-m", r":%s/(\w+)=(\w+)/\U\1\e=\u\L\2/<CR>",
+		],
+		"FIRST_NAME=Jane\nLAST_NAME=Doe",
+	);
+}
+
+#[test]
+fn ex_range_pattern_offsets() {
+	vicut_integration(
+		"a\nb\nc\nd\ne\nf\n",
+		&[
+			"-m", ":/c/+1d",
+		],
+		"a\nb\nc\ne\nf"
+	);
+	vicut_integration(
+		"a\nb\nc\nd\ne\nf\n",
+		&[
+			"-m", ":/b/,/d/-1d",
+		],
+		"a\nd\ne\nf"
+	);
+	vicut_integration(
+		"a\nb\nc\nd\ne\nf\n",
+		&[
+			"-m", ":/b//d/d",
+		],
+		"a\nb\nc\ne\nf"
+	);
+}
+
+#[test]
+fn ex_range_semicolon() {
+	vicut_integration(
+		"a\nb\nc\nd\ne\nf\n",
+		&[
+			"-m", ":2;+2d",
+		],
+		"a\ne\nf"
+	);
+	vicut_integration(
+		"a\nb\nc\nd\ne\nf\n",
+		&[
+			"-m", ":/e/-2;++d",
+		],
+		"a\nb\nf"
+	);
+	vicut_integration(
+		"a\nb\nc\nd\ne\nf\n",
+		&[
+			"-m", ":,3d",
+		],
+		"d\ne\nf"
+	);
+}
+
+#[test]
+fn ex_range_marks() {
+	vicut_integration(
+		"a\nb\nc\nd\ne\nf\n",
+		&[
+			"-m", "jjjmakkkjmb:'b,'ad",
+		],
+		"a\ne\nf"
+	);
+	vicut_integration(
+		"a\nb\nc\nd\ne\nf\n",
+		&[
+			"-m", "jVjy:'<,'>d",
+		],
+		"a\nd\ne\nf"
+	);
+	vicut_integration(
+		"a\nb\nc\nd\ne\nf\n",
+		&[
+			"-m", "wvjy:'<,'>d",
+		],
+		"a\nd\ne\nf"
+	);
+}
+
+#[test]
+fn ex_range_last_patterns() {
+	vicut_integration(
+		"c1\nb\nc2\nd\nc3\n",
+		&[
+			"-m", r":1s/c/X/<CR>:\&d",
+		],
+		"X1\nb\nd\nc3"
+	);
+	vicut_integration(
+		"a\nb\nc\nd\ne\nf\n",
+		&[
+			"-m", r":/d/<CR>gg:\/d",
+		],
+		"a\nb\nc\ne\nf"
+	);
+}
+
+#[test]
+fn ex_range_backwards() {
+	// Backwards ranges are swapped around
+	let input = "l1\nl2\nl3\nl4";
+	vicut_integration(input, &[ "-m", ":3,1d", ], "l4");
+	vicut_integration(input, &[ "-m", ":3;1d", ], "l4");
+	vicut_integration(input, &[ "-m", ":/l3/,/l1/d", ], "l4");
+	vicut_integration(input, &[ "-m", ":3,1s/l/L/<CR>", ], "L1\nL2\nL3\nl4");
+	vicut_integration(input, &[ "-m", ":3,1g/l/d<CR>", ], "l4");
+}
+
+#[test]
+fn ex_range_last_line() {
+	vicut_integration(
+		"a\nb\nc",
+		&[
+			"-m", ":$d",
+		],
+		"a\nb"
+	);
+	vicut_integration(
+		"a\nb\nc\nd\ne",
+		&[
+			"-m", ":2,$-1d",
+		],
+		"a\ne"
+	);
+}
@@ -146,3 +146,28 @@ fn insert_empty_buffer() {
 		"hello world"
 	);
 }
+
+#[test]
+fn insert_register() {
+	vicut_integration(
+		"ab",
+		&[ "-m", "a<c-r>=2 * 3<CR>x<esc>", ],
+		"a6xb",
+	);
+	vicut_integration(
+		"ab",
+		&[ "-m", "i<c-r>=1+<BS>2<CR><esc>", ],
+		"12ab",
+	);
+	// <esc> gives up on the expression, but stays in insert mode
+	vicut_integration(
+		"ab",
+		&[ "-m", "i<c-r>=1<esc>z<esc>", ],
+		"zab",
+	);
+	vicut_integration(
+		"ab",
+		&[ "-m", "\"aylA<c-r>a<esc>", ],
+		"aba",
+	);
+}
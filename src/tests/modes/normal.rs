@@ -700,3 +700,191 @@ Duis aute irure dolor in reprehenderit in voluptate
 Curabitur pretium tincidunt lacus. Nulla gravida orci a odio. Nullam varius, turpis et commodo pharetra.",
 	);
 }
+
+#[test]
+fn coerce_case_needs_a_case() {
+	vicut_integration(
+		"foo_bar baz",
+		&[
+			"-m", "crxcrc$",
+		],
+		"fooBar baz",
+	);
+}
+
+#[test]
+fn normal_format_lines() {
+	vicut_integration(
+		"aa bb cc dd\nee ff\ngg",
+		&[ "--textwidth", "6", "-m", "gqj", ],
+		"aa bb\ncc dd\nee ff\ngg",
+	);
+	vicut_integration(
+		"aa bb cc dd\nee ff",
+		&[ "--textwidth", "6", "-m", "gqqx", ],
+		"aa bb\nc dd\nee ff",
+	);
+	vicut_integration(
+		"# aa bb cc\n# dd\nee",
+		&[ "--textwidth", "8", "-m", "Vjgq", ],
+		"# aa bb\n# cc dd\nee",
+	);
+}
+
+#[test]
+fn normal_marks() {
+	vicut_integration(
+		"one two three four",
+		&[ "-m", "wwmaB", "-m", "d`a", ],
+		"one three four",
+	);
+	vicut_integration(
+		"a\n  b\nc\nd",
+		&[ "-m", "jmaG", "-m", "d'a", ],
+		"a",
+	);
+	vicut_integration(
+		"a\n  b\nc\nd",
+		&[ "-m", "jmajj'aix<esc>", ],
+		"a\n  xb\nc\nd",
+	);
+	// The mark follows its text when an edit happens before it
+	vicut_integration(
+		"one two",
+		&[ "-m", "wma0", "-m", "ix<esc>", "-m", "`aiy<esc>", ],
+		"xone ytwo",
+	);
+	// Jumping to a mark that was never set does nothing
+	vicut_integration(
+		"one two",
+		&[ "-m", "wd`b", ],
+		"one two",
+	);
+}
+
+#[test]
+fn normal_macros() {
+	vicut_integration(
+		"a\nb\nc\nd",
+		&[ "-m", "qaI-<esc>jq", "-m", "2@a", ],
+		"-a\n-b\n-c\nd",
+	);
+	vicut_integration(
+		"a\nb\nc\nd",
+		&[ "-m", "qaI-<esc>jq@a@@", ],
+		"-a\n-b\n-c\nd",
+	);
+	vicut_integration(
+		"one two three",
+		&[ "-m", "qqdwq", "-m", "@q", ],
+		"three",
+	);
+	// An uppercase register appends to the macro
+	vicut_integration(
+		"a\nb\nc",
+		&[ "-m", "qaI-<esc>q", "-m", "qAjq", "-m", "@a", ],
+		"-a\n-b\nc",
+	);
+	// A macro can be yanked text, too
+	vicut_integration(
+		"x~\nabc",
+		&[ "-m", "\"ayEj", "-m", "@a", ],
+		"x~\nBc",
+	);
+	// A macro that plays itself gives up instead of running forever
+	let mut vicut = crate::exec::ViCut::new("x".into(), 0).unwrap();
+	assert!(vicut.move_cursor("qaI-<esc>@aq@a").is_err());
+}
+
+#[test]
+fn normal_expr_register() {
+	vicut_integration(
+		"count 3",
+		&[ "-m", "w\"=2 * 3<CR>P", ],
+		"count 63",
+	);
+	vicut_integration(
+		"ab",
+		&[ "-m", "\"=\"hi\"<CR>P", ],
+		"hiab",
+	);
+	// An empty expression puts the last result again
+	vicut_integration(
+		"ab",
+		&[ "-m", "\"=1 + 1<CR>p", "-m", "0\"=<CR>P", ],
+		"2a2b",
+	);
+	assert!(crate::modes::is_pending_expr("2\"=1 + "));
+	assert!(!crate::modes::is_pending_expr("\"=1\r"));
+	assert!(!crate::modes::is_pending_expr("\"a"));
+}
+
+#[test]
+fn normal_increment() {
+	vicut_integration(
+		"x 9 y",
+		&[ "-m", "5<c-a>", ],
+		"x 14 y",
+	);
+	vicut_integration(
+		"a -1 b",
+		&[ "-m", "3<c-a>", ],
+		"a 2 b",
+	);
+	vicut_integration(
+		"mask 0x0f",
+		&[ "-m", "<c-x>", ],
+		"mask 0x0e",
+	);
+	vicut_integration(
+		"v007",
+		&[ "-m", "<c-a>", ],
+		"v008",
+	);
+	// The cursor lands on the last digit, and '.' repeats the change
+	vicut_integration(
+		"9 1",
+		&[ "-m", "<c-a>.ix<esc>", ],
+		"1x1 1",
+	);
+	vicut_integration(
+		"a1 b2",
+		&[ "-m", "$<c-x>", ],
+		"a1 b1",
+	);
+}
+
+#[test]
+fn normal_forced_motions() {
+	let input = "abcd efgh\nijkl mnop\nqrst uvwx";
+	// 'v' makes a linewise motion exclusive and charwise
+	vicut_integration(input, &[ "-m", "lldvj", ], "abkl mnop\nqrst uvwx");
+	vicut_integration(input, &[ "-m", "jlldvk", ], "abkl mnop\nqrst uvwx");
+	// and flips exclusive motions to inclusive, and inclusive ones to exclusive
+	vicut_integration(input, &[ "-m", "dvw", ], "fgh\nijkl mnop\nqrst uvwx");
+	vicut_integration(input, &[ "-m", "dve", ], "d efgh\nijkl mnop\nqrst uvwx");
+	vicut_integration(input, &[ "-m", "dvfe", ], "efgh\nijkl mnop\nqrst uvwx");
+	vicut_integration(input, &[ "-m", "ldvh", ], "cd efgh\nijkl mnop\nqrst uvwx");
+	// 'V' takes every line that the motion touches
+	vicut_integration(input, &[ "-m", "lldVl", ], "ijkl mnop\nqrst uvwx");
+	vicut_integration(input, &[ "-m", "wyVj", "-m", "P", ], "abcd efgh\nijkl mnop\nabcd efgh\nijkl mnop\nqrst uvwx");
+	// Ctrl-V takes the block between the cursor and where the motion lands
+	vicut_integration(input, &[ "-m", "wd<c-v>2j", ], "abcd fgh\nijkl nop\nqrst vwx");
+	vicut_integration(input, &[ "-m", "d<c-v>e", ], " efgh\nijkl mnop\nqrst uvwx");
+}
+
+#[test]
+fn normal_counted_visual() {
+	let input = "abcd efgh\nijkl mnop\nqrst uvwx";
+	vicut_integration(input, &[ "-c", "3v", ], "abc");
+	vicut_integration(input, &[ "-m", "2Vd", ], "qrst uvwx");
+	vicut_integration(input, &[ "-c", "l2<c-v>j", ], "bc\njk");
+	// A count given to 'gv' brings back the last selection at that many times the size
+	vicut_integration(input, &[ "-m", "vly0", "-c", "3gv", ], "abcd e");
+	vicut_integration(input, &[ "-m", "Vy", "-m", "2gvd", ], "qrst uvwx");
+	// The selection stops at the end of the buffer
+	vicut_integration("abc def ghi", &[ "-m", "wviwd", "-c", "2gv", ], " ghi");
+	vicut_integration("abc def ghi", &[ "-m", "wviwd2gv", ], "abc  ghi");
+	// Text objects can still extend a counted selection
+	vicut_integration(input, &[ "-c", "2viw", ], "abcd ");
+}
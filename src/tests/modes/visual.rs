@@ -128,7 +128,7 @@ fn select_lines_with_count() {
 		&[
 		  "-c", "2Vj",
 		],
-		"Line 1\nLine 2",
+		"Line 1\nLine 2\nLine 3",
 	);
 }
 
@@ -219,3 +219,130 @@ fn put_block_preserve_formatting() {
 		"abcdefgh\nabcd\nabcdefghi\nabcde\nabcdefg",
 	);
 }
+
+#[test]
+fn visual_coerce_case() {
+	// 'c' changes the selection right away, so case coercion is 'gr' in visual mode
+	vicut_integration(
+		"let fooBar = baz_qux;",
+		&[
+			"-m", "wv$grm",
+		],
+		"let FooBar = BazQux;",
+	);
+}
+
+#[test]
+fn visual_increment() {
+	vicut_integration(
+		"1\n1\n1",
+		&[ "-m", "Vjj<c-a>", ],
+		"2\n2\n2",
+	);
+	vicut_integration(
+		"0.\n0.\nx\n0.",
+		&[ "-m", "VGg<c-a>", ],
+		"1.\n2.\nx\n3.",
+	);
+	vicut_integration(
+		"1\n1\n1",
+		&[ "-m", "Vjj2g<c-x>", ],
+		"-1\n-3\n-5",
+	);
+	vicut_integration(
+		"a1 b1\nc1 d1",
+		&[ "-m", "0f1<c-v>j<c-a>", ],
+		"a2 b1\nc2 d1",
+	);
+}
+
+#[test]
+fn visual_swap_anchor() {
+	vicut_integration(
+		"one two three\nfour five six\n",
+		&[
+			"-c", "wvlloho",
+		],
+		" two",
+	);
+	vicut_integration(
+		"one two three\nfour five six\nseven eight nine\n",
+		&[
+			"-c", "jVjok",
+		],
+		"one two three\nfour five six\nseven eight nine",
+	);
+}
+
+#[test]
+fn visual_block_swap_corner() {
+	vicut_integration(
+		"one two three\nfour five six\nseven eight nine",
+		&[
+			"-m", "w<c-v>jlohd",
+		],
+		"oneo three\nfouive six\nseven eight nine",
+	);
+	// 'O' stays on the same line, so 'j' grows the block downwards
+	vicut_integration(
+		"one two three\nfour five six\nseven eight nine",
+		&[
+			"-m", "w<c-v>jlOjd",
+		],
+		"one o three\nfourive six\nseveeight nine",
+	);
+}
+
+#[test]
+fn visual_reselect_last() {
+	vicut_integration(
+		"one two three\nfour five six\n",
+		&[
+			"-m", "wvlyG",
+			"-c", "gv",
+		],
+		"tw",
+	);
+	vicut_integration(
+		"one two three\nfour five six\nseven eight nine\n",
+		&[
+			"-m", "jVjyG",
+			"-c", "gv",
+		],
+		"four five six\nseven eight nine",
+	);
+	vicut_integration(
+		"one two three\nfour five six\nseven eight nine",
+		&[
+			"-m", "w<c-v>jlyGgvOhd",
+		],
+		"oneo three\nfouive six\nseven eight nine",
+	);
+	// In visual mode, 'gv' trades the current selection for the last one
+	vicut_integration(
+		"one two three\nfour five six\n",
+		&[
+			"-c", "wvllyjvlgv",
+		],
+		"two",
+	);
+	vicut_integration(
+		"one two three\nfour five six\n",
+		&[
+			"-c", "wvllyjvlgvgv",
+		],
+		" f",
+	);
+}
+
+#[test]
+fn visual_yank_ends_selection() {
+	vicut_integration(
+		"one two three\nfour five six\n",
+		&[
+			"-m", "wvly",
+			"-c", "l",
+		],
+		"tw",
+	);
+}
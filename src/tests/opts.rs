@@ -0,0 +1,1242 @@
+use crate::tests::vicut_integration;
+
+#[test]
+fn opts_capture_context_fields() {
+	vicut_integration(
+		"foo bar\nbaz qux\nfoo biz",
+		&[
+			"--capture-lineno", "--capture-line", "--capture-word",
+			"-g", "foo",
+				"-m", "w",
+				"-n",
+			"--end",
+		],
+		"3 foo biz biz\n1 foo bar bar"
+	);
+}
+
+#[test]
+fn opts_capture_context_fields_json() {
+	vicut_integration(
+		"foo bar\nbaz qux",
+		&[
+			"--json", "--capture-line",
+			"-c", "name=first", "e",
+		],
+		"[\n  {\n    \"first\": \"foo\",\n    \"line\": \"foo bar\"\n  }\n]"
+	);
+}
+
+#[test]
+fn opts_sandbox_blocks_shell() {
+	vicut_integration(
+		"foo\nbar",
+		&[
+			"--sandbox",
+			"-m", ":r !echo hi<CR>",
+			"-m", ":1,2!tr a-z A-Z<CR>",
+		],
+		"foo\nbar"
+	);
+}
+
+#[test]
+fn opts_no_shell() {
+	vicut_integration(
+		"foo\nbar",
+		&[
+			"--no-shell",
+			"-m", ":r !echo hi<CR>",
+			"-m", ":1,2!tr a-z A-Z<CR>",
+		],
+		"foo\nbar"
+	);
+	// Unlike --sandbox, files can still be read
+	let path = std::env::temp_dir().join("vicut_no_shell_read.txt");
+	std::fs::write(&path, "baz\n").unwrap();
+	let read = format!(":r {}<CR>", path.display());
+	vicut_integration("foo", &["--no-shell", "-m", &read], "foo\nbaz");
+	std::fs::remove_file(&path).ok();
+
+	let opts = crate::Opts::from_raw("opts { no_shell }\nmove \":r !echo hi<CR>\"\n").unwrap();
+	let output = crate::execute(&opts, "foo".to_string(), None).unwrap();
+	assert_eq!(output[0][0].1.to_string(), "foo");
+}
+
+#[test]
+fn opts_shell_cwd() {
+	vicut_integration(
+		"foo",
+		&[
+			"--shell-cwd", "/",
+			"-m", ":r !pwd<CR>",
+		],
+		"foo\n/"
+	);
+}
+
+#[test]
+fn opts_shell() {
+	vicut_integration(
+		"foo",
+		&[
+			"--shell", "sh",
+			"-m", ":r !echo $0<CR>",
+		],
+		"foo\nsh"
+	);
+	// Arguments to the shell go before -c
+	vicut_integration(
+		"foo",
+		&[
+			"--shell", "sh -e",
+			"-m", ":r !false; echo no<CR>",
+		],
+		"foo"
+	);
+	assert!(crate::Opts::parse_raw(&["--shell", " ", "-c", "e"]).is_err());
+}
+
+#[test]
+fn opts_shell_cwd_filedir() {
+	use std::path::Path;
+	use crate::shell::{file_dir, ShellCwd};
+	assert_eq!(ShellCwd::from("filedir"), ShellCwd::FileDir);
+	assert_eq!(ShellCwd::from("./filedir"), ShellCwd::Dir("./filedir".into()));
+	assert_eq!(ShellCwd::FileDir.resolve(Some(Path::new("/etc/hosts"))), Some("/etc".into()));
+	assert_eq!(ShellCwd::FileDir.resolve(None), None);
+	assert_eq!(file_dir(Path::new("notes.txt")), Path::new("."));
+
+	let opts = crate::Opts::parse_raw(&["--shell-cwd", "filedir", "-m", ":r !pwd<CR>"]).unwrap();
+	let output = crate::execute(&opts, "foo".to_string(), Some("/usr/hosts".into())).unwrap();
+	assert_eq!(output[0][0].1.to_string(), "foo\n/usr\n");
+
+	let opts = crate::Opts::from_raw("move \":r !echo ${filedir}<CR>\"\n").unwrap();
+	let output = crate::execute(&opts, "foo".to_string(), Some("/etc/hosts".into())).unwrap();
+	assert_eq!(output[0][0].1.to_string(), "foo\n/etc\n");
+}
+
+#[test]
+fn opts_shell_timeout() {
+	let start = std::time::Instant::now();
+	vicut_integration(
+		"foo",
+		&[
+			"--shell-timeout", "0.1",
+			"-m", ":r !sleep 5<CR>",
+		],
+		"foo"
+	);
+	assert!(start.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[test]
+fn opts_shell_failure_leaves_buffer() {
+	vicut_integration(
+		"foo\nbar",
+		&[
+			"-m", ":%!echo oops >&2; exit 3<CR>",
+		],
+		"foo\nbar"
+	);
+}
+
+#[test]
+fn opts_equalprg() {
+	vicut_integration(
+		"foo\nbar\nbiz",
+		&[
+			"--equalprg", "tr a-z A-Z",
+			"-m", "j=j",
+		],
+		"foo\nBAR\nBIZ"
+	);
+}
+
+#[test]
+fn opts_eol_crlf() {
+	vicut_integration(
+		"foo bar\nbiz baz",
+		&[
+			"--eol", "crlf",
+			"--linewise",
+			"-c", "e",
+		],
+		"foo\r\nbiz\r"
+	);
+}
+
+#[test]
+fn opts_eol_lf() {
+	vicut_integration(
+		"foo\r\nbar\r\n",
+		&[
+			"--eol", "lf",
+			"-m", "x",
+		],
+		"oo\nbar"
+	);
+}
+
+#[test]
+fn opts_bom_strip() {
+	vicut_integration(
+		"\u{feff}foo bar",
+		&[ "--bom", "strip", "-m", "w" ],
+		"foo bar"
+	);
+	vicut_integration(
+		"\u{feff}foo bar",
+		&[ "--bom", "keep", "-m", "w" ],
+		"\u{feff}foo bar"
+	);
+}
+
+#[test]
+fn opts_build_info() {
+	let info = crate::build_info::as_json();
+	assert_eq!(info["name"], "vicut");
+	assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+	assert!(info["features"].is_array());
+	assert!(info["dependencies"]["regex"].is_string());
+}
+
+#[test]
+fn opts_no_input() {
+	vicut_integration(
+		"this is ignored",
+		&[ "--no-input", "-m", "iHello<esc>" ],
+		"Hello"
+	);
+	let opts = crate::Opts::from_raw("opts { no_input }\nmove \"iHello<esc>\"\n").unwrap();
+	assert!(opts.no_input);
+	assert!(opts.check_no_input().is_ok());
+
+	let opts = crate::Opts::parse_raw(&["--no-input", "-c", "e", "Cargo.toml"]).unwrap();
+	assert!(opts.check_no_input().is_err());
+	let opts = crate::Opts::parse_raw(&["--no-input", "-i", "-c", "e"]).unwrap();
+	assert!(opts.check_no_input().is_err());
+}
+
+#[test]
+fn opts_empty_input() {
+	vicut_integration(
+		"",
+		&[ "-m", "iHello<esc>" ],
+		"Hello"
+	);
+	vicut_integration(
+		"",
+		&[ "-c", "w" ],
+		""
+	);
+}
+
+#[test]
+fn opts_repeat_iteration_var() {
+	vicut_integration(
+		"aa\nbb\ncc\ndd\nee",
+		&[
+			"-m", "gg${{i}}j0",
+			"-c", "e",
+			"-r", "2", "2",
+		],
+		"bb cc dd"
+	);
+}
+
+#[test]
+fn opts_motion_interpolation() {
+	vicut_integration(
+		"aa\nbb\ncc\ndd\nee",
+		&[
+			"-m", "gg${i}j0",
+			"-c", "e",
+			"-r", "2", "2",
+		],
+		"bb cc dd"
+	);
+}
+
+#[test]
+fn opts_global_match_vars() {
+	vicut_integration(
+		"foo 12\nbar\nfoo 345",
+		&[
+			"-g", "\\d+",
+				"-m", "/${match}<CR>",
+				"-c", "e",
+		],
+		"345 12"
+	);
+}
+
+#[test]
+fn opts_record_per_match() {
+	vicut_integration(
+		"foo one\nbar\nfoo two\nbaz",
+		&[
+			"--record-per-match",
+			"-c", "e",
+			"-g", "^foo",
+				"-m", "w",
+				"-c", "e",
+		],
+		"foo\ntwo\none"
+	);
+	vicut_integration(
+		"foo one\nbar\nfoo two\nbaz",
+		&[
+			"--record-per-match",
+			"--json",
+			"-g", "^foo",
+				"-m", "w",
+				"-c", "name=word", "e",
+		],
+		"[\n  {\n    \"word\": \"two\"\n  },\n  {\n    \"word\": \"one\"\n  }\n]"
+	);
+}
+
+#[test]
+fn opts_max_matches() {
+	vicut_integration(
+		"foo one\nbar\nfoo two\nfoo three",
+		&[
+			"--record-per-match",
+			"-g", "^foo",
+				"--max-matches", "2",
+				"-m", "w",
+				"-c", "e",
+		],
+		"two\none"
+	);
+	vicut_integration(
+		"foo one\nbar\nfoo two",
+		&[
+			"-g", "^foo",
+				"--max-matches", "1",
+				"-m", "w",
+				"-c", "e",
+		],
+		"one"
+	);
+	let err = crate::Opts::parse_raw(&["-g", "^foo", "--max-matches", "two", "-c", "e"]).unwrap_err();
+	assert_eq!(err, "Expected a number after '--max-matches', found 'two'");
+	assert!(crate::Opts::parse_raw(&["-g", "^foo", "--max-matches"]).is_err());
+}
+
+#[test]
+fn opts_g_order() {
+	vicut_integration(
+		"foo one\nbar\nfoo two",
+		&[
+			"--record-per-match",
+			"--g-order", "forward",
+			"-g", "^foo",
+				"-m", "w",
+				"-c", "e",
+		],
+		"one\ntwo"
+	);
+	vicut_integration(
+		"foo one\nbar\nfoo two",
+		&[
+			"--record-per-match",
+			"--g-order", "reverse",
+			"-g", "^foo",
+				"-m", "w",
+				"-c", "e",
+		],
+		"two\none"
+	);
+}
+
+#[test]
+fn opts_g_order_edits() {
+	// The default order visits the last match first, so deleting lines is safe
+	vicut_integration(
+		"foo one\nbar\nfoo two\nbaz",
+		&[
+			"-g", "^foo",
+				"-m", "dd",
+		],
+		"bar\nbaz"
+	);
+	vicut_integration(
+		"foo one\nbar\nfoo two\nbaz",
+		&[
+			"--g-order", "reverse",
+			"-g", "^foo",
+				"-m", "yyP",
+		],
+		"foo one\nfoo one\nbar\nfoo two\nfoo two\nbaz"
+	);
+}
+
+#[test]
+fn opts_global_tracks_edited_lines() {
+	// Deleting lines going forward would skip or corrupt matches if line numbers weren't tracked
+	vicut_integration(
+		"foo one\nbar\nfoo two\nbaz\n",
+		&[
+			"--g-order", "forward",
+			"-g", "^foo",
+				"-m", "dd",
+		],
+		"bar\nbaz"
+	);
+	vicut_integration(
+		"foo one\nbar\nfoo two\nbaz\n",
+		&[
+			"--g-order", "forward",
+			"-g", "^foo",
+				"-m", "yyP",
+		],
+		"foo one\nfoo one\nbar\nfoo two\nfoo two\nbaz"
+	);
+	// A match that gets joined into an earlier one is gone, like with Vim's :g
+	vicut_integration(
+		"foo one\nbar\nfoo two\nfoo three\nbaz\n",
+		&[
+			"--g-order", "forward",
+			"-g", "^foo",
+				"-m", "J",
+		],
+		"foo one bar\nfoo two foo three\nbaz"
+	);
+}
+
+#[test]
+fn opts_nested_global_scoped() {
+	// The inner -g only sees the line matched by the outer one
+	vicut_integration(
+		"foo a\nbar\nfoo b\nbaz b",
+		&[
+			"-g", "^foo",
+				"-g", "b",
+					"-c", "e",
+				"--end",
+			"--end",
+		],
+		"foo"
+	);
+	vicut_integration(
+		"foo a\nbar\nfoo b\nbaz b",
+		&[
+			"-g", "^foo",
+				"-v", "b",
+					"-c", "e",
+				"--end",
+			"--end",
+		],
+		"foo"
+	);
+}
+
+#[test]
+fn opts_nested_global_context() {
+	vicut_integration(
+		"foo a\nbar\nfoo b\nbaz b\nqux",
+		&[
+			"--g-order", "forward",
+			"--record-per-match",
+			"-g", "^foo",
+				"--context", "1",
+				"-g", "b",
+					"-c", "e",
+		],
+		"bar\nbar\nfoo\nbaz"
+	);
+	let err = crate::Opts::parse_raw(&["-g", "^foo", "--context", "-1", "-g", "b", "-c", "e"]).unwrap_err();
+	assert_eq!(err, "Expected a number after '--context', found '-1'");
+	assert!(crate::Opts::parse_raw(&["-g", "^foo", "--context"]).is_err());
+}
+
+#[test]
+fn opts_assert_templates() {
+	let records = vec![
+		vec![("name".to_string(), "foo".into()), ("id".to_string(), "12".into())],
+		vec![("name".to_string(), "".into()), ("id".to_string(), "x1".into())],
+		vec![("id".to_string(), "7".into())],
+	];
+	let asserts = vec![r"{{name}} {{id:\d+}}".to_string()];
+	crate::check_record_asserts(&asserts, &records, "opts_assert_templates").unwrap();
+
+	let failures = crate::ASSERT_FAILURES.lock().unwrap()
+		.iter()
+		.filter(|msg| msg.starts_with("opts_assert_templates"))
+		.cloned()
+		.collect::<Vec<_>>();
+	assert_eq!(failures, vec![
+		"opts_assert_templates: record 2: field 'name' is empty",
+		r#"opts_assert_templates: record 2: field 'id' does not match /\d+/: "x1""#,
+		"opts_assert_templates: record 3: field 'name' is missing",
+	]);
+
+	let bad_regex = vec!["{{id:(}}".to_string()];
+	assert!(crate::check_record_asserts(&bad_regex, &records, "opts_assert_templates").is_err());
+}
+
+#[test]
+fn opts_block_fields() {
+	let input = "ab cd\nef gh\nij kl";
+	vicut_integration(input, &["--json", "-c", "<c-v>2jl"], "[\n  {\n    \"1\": [\n      \"ab\",\n      \"ef\",\n      \"ij\"\n    ]\n  }\n]");
+	vicut_integration(input, &["-c", "name=col", "w<c-v>jl", "-t", "{{col[1]}}/{{col[0]}}"], "gh/cd");
+	// Anything else is indexed by its lines
+	vicut_integration(input, &["-c", "Vj", "-t", "{{1[1]}}"], "ef gh");
+	// Without an index, the pieces are joined back up
+	vicut_integration(input, &["-c", "<c-v>jl"], "ab\nef");
+
+	let record = vec![
+		("1".to_string(), crate::FieldVal::Block(vec!["ab".into(), "ef".into()])),
+		("x[0]".to_string(), "literal".into()),
+	];
+	assert_eq!(crate::template_field(&record, "1[1]"), Ok(Some("ef".into())));
+	assert_eq!(crate::template_field(&record, "2[0]"), Ok(None));
+	assert!(crate::template_field(&record, "1[2]").is_err());
+	// A field that is really called that comes first
+	assert_eq!(crate::template_field(&record, "x[0]"), Ok(Some("literal".into())));
+
+	let opts = crate::Opts::parse_raw(&["--emit-schema", "-c", "<c-v>jl", "-c", "e"]).unwrap();
+	let schema = crate::record_schema(&opts);
+	assert_eq!(schema["items"]["properties"]["1"]["type"], "array");
+	assert_eq!(schema["items"]["properties"]["2"]["type"], "string");
+}
+
+#[test]
+fn opts_emit_schema() {
+	let opts = crate::Opts::parse_raw(&[
+		"--emit-schema",
+		"-c", "name=first", "e",
+		"-m", "w",
+		"-c", "e",
+		"-g", "foo",
+			"-c", "name=maybe", "e",
+	]).unwrap();
+	assert!(opts.emit_schema);
+	let schema = crate::record_schema(&opts);
+	let record = &schema["items"];
+	assert_eq!(schema["type"], "array");
+	assert_eq!(record["properties"]["first"]["type"], "string");
+	assert_eq!(record["properties"]["2"]["type"], "string");
+	assert_eq!(record["properties"]["maybe"]["type"], "string");
+	assert_eq!(record["required"], serde_json::json!(["first", "2"]));
+	assert_eq!(record["additionalProperties"], false);
+
+	// No fields means the whole buffer
+	let opts = crate::Opts::parse_raw(&["--emit-schema", "-m", "w"]).unwrap();
+	let schema = crate::record_schema(&opts);
+	assert_eq!(schema["items"]["required"], serde_json::json!(["0"]));
+}
+
+/// Just enough of JSON Schema to check vicut's own records against `--emit-schema`
+fn matches_schema(record: &serde_json::Value, schema: &serde_json::Value) -> bool {
+	if let Some(options) = schema["anyOf"].as_array() {
+		return options.iter().any(|option| matches_schema(record, option))
+	}
+	let Some(record) = record.as_object() else { return false };
+	let required = schema["required"].as_array().unwrap();
+	let properties = schema["properties"].as_object().unwrap();
+	required.iter().all(|name| record.contains_key(name.as_str().unwrap()))
+	&& record.iter().all(|(name, value)| match properties.get(name) {
+		Some(field) if field["type"] == "array" => value.is_array(),
+		Some(_) => value.is_string(),
+		None => schema["additionalProperties"] == true
+	})
+}
+
+#[test]
+fn opts_emit_schema_groups() {
+	let input = "foo bar\nbaz qux";
+	let programs: &[&[&str]] = &[
+		&["-c", "name=first", "e", "-n", "-m", "w", "-c", "name=second", "e"],
+		&["-c", "e", "-n", "-m", "j", "-c", "e", "-c", "name=last", "$"],
+		&["-c", "e", "-n", "-n"],
+		&["-g", "foo", "-c", "e", "-n", "--end", "-c", "name=after", "$"],
+		&["--record-per-match", "-c", "name=before", "e", "-g", "ba", "-c", "e", "--end"],
+	];
+	for program in programs {
+		let opts = crate::Opts::parse_raw(program).unwrap();
+		let schema = crate::record_schema(&opts);
+		let args = [&["--json"], *program].concat();
+		let output: serde_json::Value = serde_json::from_str(&crate::tests::call_main(&args, input).unwrap()).unwrap();
+		for record in output.as_array().unwrap() {
+			assert!(matches_schema(record, &schema["items"]), "{record} doesn't match {schema} for {program:?}");
+		}
+	}
+
+	// Each group requires its own fields, and nothing else
+	let opts = crate::Opts::parse_raw(programs[0]).unwrap();
+	let schema = crate::record_schema(&opts);
+	assert_eq!(schema["items"]["anyOf"][0]["required"], serde_json::json!(["first"]));
+	assert_eq!(schema["items"]["anyOf"][1]["required"], serde_json::json!(["second"]));
+	// A -n that might not run leaves no field required
+	let opts = crate::Opts::parse_raw(programs[3]).unwrap();
+	assert_eq!(crate::record_schema(&opts)["items"]["required"], serde_json::json!([]));
+}
+
+#[test]
+fn opts_verify_roundtrip() {
+	use crate::RoundtripCheck;
+	let opts = crate::Opts::parse_raw(&["--verify-roundtrip", "lines,bytes:4", "-c", "e"]).unwrap();
+	assert_eq!(opts.verify_roundtrip, vec![RoundtripCheck::Lines(0), RoundtripCheck::Bytes(4)]);
+	assert!(crate::Opts::parse_raw(&["--verify-roundtrip", "words"]).is_err());
+	assert!(crate::Opts::parse_raw(&["--verify-roundtrip", "bytes:lots"]).is_err());
+
+	let before = "foo\nbar\nbiz\n";
+	assert_eq!(RoundtripCheck::Lines(0).violation(before, "one\ntwo\nthree\n"), None);
+	assert!(RoundtripCheck::Lines(0).violation(before, "foo\nbar\n").is_some());
+	assert_eq!(RoundtripCheck::Lines(1).violation(before, "foo\nbar\n"), None);
+	assert_eq!(RoundtripCheck::Bytes(4).violation(before, "foo\nbar\nbiz\nbuzz"), None);
+	assert_eq!(
+		RoundtripCheck::Bytes(4).violation(before, "foo\nbar\nbiz\nbuzz\n"),
+		Some("bytes went from 12 to 17, but only a change of 4 is allowed".into())
+	);
+}
+
+#[test]
+fn opts_regex_limits() {
+	let opts = crate::Opts::parse_raw(&["--regex-timeout", "0.5", "--regex-size-limit", "4096", "-c", "e"]).unwrap();
+	assert_eq!(opts.regex_timeout, Some(std::time::Duration::from_millis(500)));
+	assert_eq!(opts.regex_size_limit, Some(4096));
+	assert!(crate::Opts::parse_raw(&["--regex-size-limit", "lots"]).is_err());
+
+	assert!(crate::build_regex(r"[a-z]{100}{100}", 1 << 16).is_err());
+	assert!(crate::build_regex(r"[a-z]+", 1 << 16).is_ok());
+
+	// A record that never finishes gets thrown out, instead of hanging forever
+	let script = "opts { regex_timeout = \"0.1\" } let n = 0 while true { n += 1 } echo \"done\"";
+	vicut_integration("foo bar", &[script], "");
+}
+
+#[test]
+fn opts_units() {
+	// 'é' is two chars here, 'e' followed by a combining accent
+	let input = "e\u{301}abcdef";
+	vicut_integration(input, &["-m", "3l", "-c", "l"], "cd");
+	vicut_integration(input, &["--units", "chars", "-m", "3l", "-c", "l"], "bc");
+	vicut_integration(input, &["--units", "bytes", "-m", "3l", "-c", "l"], "ab");
+	vicut_integration("héllo", &["--units", "bytes", "-m", "3l", "-c", "l"], "ll");
+	// Positions inside a multi-byte character stand for the start of it, instead of splitting it
+	vicut_integration("héllo wörld", &["--units", "bytes", "-m", "l", "-m", "x"], "héllo wörld");
+	vicut_integration("héllo wörld", &["--units", "bytes", "-m", "2l", "-m", "x"], "hllo wörld");
+	vicut_integration("héllo wörld", &["--units", "bytes", "-m", "2l", "-c", "l"], "él");
+	vicut_integration("héllo wörld", &["--units", "bytes", "-m", "2l", "-m", "D"], "h");
+	vicut_integration("héllo wörld", &["--units", "bytes", "-m", "9l", "-m", "rX"], "héllo wXrld");
+	// Word motions treat a multi-byte character as part of the word around it
+	vicut_integration("héllo wörld", &["--units", "bytes", "-c", "w"], "héllo w");
+	vicut_integration("héllo wörld", &["--units", "bytes", "-c", "e"], "héllo");
+	vicut_integration("café wörld", &["--units", "bytes", "-c", "e"], "café");
+	vicut_integration("café wörld", &["--units", "bytes", "-m", "$", "-c", "b"], "wörld");
+	vicut_integration("café wörld", &["--units", "bytes", "-m", "$", "-c", "ge"], "é wörld");
+	vicut_integration("café wörld x", &["--units", "bytes", "-m", "w", "-m", "diw"], "café  x");
+	vicut_integration("fooXbar\nbizXbuzz", &["--units", "chars", "-m", "3lj", "-c", "l"], "Xb");
+	assert!(crate::Opts::parse_raw(&["--units", "words"]).is_err());
+}
+
+#[test]
+fn opts_window_height() {
+	let input = (1..=50).map(|n| format!("line {n}")).collect::<Vec<_>>().join("\n");
+	vicut_integration(&input, &["--window-height", "10", "-m", "L", "-c", "2e"], "line 10");
+	vicut_integration(&input, &["--window-height", "10", "-m", "M", "-c", "2e"], "line 5");
+	vicut_integration(&input, &["--window-height", "10", "-m", "L5<c-e><c-y><c-y>L", "-c", "2e"], "line 13");
+	vicut_integration(&input, &["--window-height", "10", "-m", "40G15kL", "-m", "ggddGH", "-c", "2e"], "line 41");
+	assert!(crate::Opts::parse_raw(&["--window-height", "0"]).is_err());
+}
+
+#[test]
+fn opts_kv_separators() {
+	let input = "export PATH=/bin:/usr/bin";
+	vicut_integration(input, &["-m", "civ~<esc>"], "export PATH=~");
+	vicut_integration(input, &["--kv-separators", ":", "-m", "civ~<esc>"], "export PATH=/bin:~");
+	vicut_integration("a -> b", &["--kv-separators", "-> =", "-m", "civc<esc>"], "a -> c");
+	assert!(crate::Opts::parse_raw(&["--kv-separators", " "]).is_err());
+}
+
+#[test]
+fn opts_string_escape() {
+	let input = "select 'it''s', 'x'";
+	vicut_integration(input, &["--string-escape", "'", "-m", "ciqy<esc>"], "select 'y', 'x'");
+	vicut_integration(input, &["-m", "ciqy<esc>"], "select 'y''s', 'x'");
+	assert!(crate::Opts::parse_raw(&["--string-escape", "ab"]).is_err());
+}
+
+#[test]
+fn opts_dry_run() {
+	let diff = crate::dry_run_diff(std::path::Path::new("a.txt"), "foo\nbar\n", "foo\nBAR\n", false);
+	assert!(diff.contains("--- a/a.txt"));
+	assert!(diff.contains("+++ b/a.txt"));
+	assert!(diff.contains("-bar\n"));
+	assert!(diff.contains("+BAR\n"));
+	assert!(crate::dry_run_diff(std::path::Path::new("a.txt"), "foo\n", "foo\n", false).is_empty());
+	let opts = crate::Opts::parse_raw(&["--dry-run", "-c", "e"]).unwrap();
+	assert!(opts.dry_run && opts.edit_inplace);
+}
+
+#[test]
+fn opts_freq() {
+	let input = "the cat and the dog\nthe end and";
+	vicut_integration(input, &["--freq", "2"], "3 the\n2 and");
+	vicut_integration(input, &["--freq", "10", "--freq-pattern", r"\b[cd]\w+"], "1 cat\n1 dog");
+	vicut_integration(input, &["--freq", "1", "-m", "cwa<esc>"], "2 and");
+	vicut_integration(input, &["--freq", "1", "-t", "{{token}}: {{count}}"], "the: 3");
+	assert!(crate::Opts::parse_raw(&["--freq", "0"]).is_err());
+	assert!(crate::Opts::parse_raw(&["--freq", "1", "--freq-pattern", "("]).is_err());
+}
+
+#[test]
+fn opts_stream() {
+	let input = "ab\ncd\nef";
+	vicut_integration(input, &["--stream", "-m", "x"], "b\nd\nf");
+	vicut_integration(input, &["--stream", "--serial", "-m", "x"], "b\nd\nf");
+	vicut_integration("a b\nc d", &["--stream", "-m", "w", "-c", "e"], "b\nd");
+	vicut_integration("a b\nc d", &["--stream", "--json", "-m", "w", "-c", "name=last", "e"], "{\"last\":\"b\"}\n{\"last\":\"d\"}");
+
+	// More lines than fit in one chunk
+	let lines = (0..5000).map(|n| n.to_string()).collect::<Vec<_>>();
+	let expected = lines.iter().map(|line| format!("!{line}")).collect::<Vec<_>>();
+	vicut_integration(&lines.join("\n"), &["--stream", "-m", "I!<esc>"], &expected.join("\n"));
+}
+
+#[test]
+fn opts_read0_print0() {
+	let input = "a b\0c\nd e\0";
+	vicut_integration(input, &["-0", "--linewise", "-m", "I><esc>"], ">a b\n>c\nd e");
+	vicut_integration(input, &["--read0", "--print0", "--linewise", "--serial", "-m", "I><esc>"], ">a b\0>c\nd e\0");
+	vicut_integration(input, &["--null", "--print0", "--stream", "-m", "I><esc>"], ">a b\0>c\nd e\0");
+	vicut_integration("ab\ncd\n", &["--print0", "--linewise", "-m", "x"], "b\0d\0");
+	vicut_integration("a b\nc d", &["--print0", "-t", "<{{1}}>", "--linewise", "-m", "w", "-c", "e"], "<b>\0<d>\0");
+	// The NUL that ended a record isn't part of what gets captured
+	vicut_integration("ab\0cd\0", &["--read0", "--linewise", "-c", "$", "-t", "<{{1}}>"], "<ab>\n<cd>");
+	vicut_integration("ab\0cd\0", &["--read0", "--stream", "-c", "$", "--json"], "{\"1\":\"ab\"}\n{\"1\":\"cd\"}");
+}
+
+#[test]
+fn opts_split_records() {
+	let newline = regex::Regex::new("\n").unwrap();
+	let nul = regex::Regex::new("\0").unwrap();
+	let paragraph = regex::Regex::new("\n\n+").unwrap();
+	assert_eq!(crate::split_records("a\nb\0c\n", &newline), vec!["a\n", "b\0c\n"]);
+	assert_eq!(crate::split_records("a\nb\0c", &nul), vec!["a\nb\0", "c"]);
+	assert_eq!(crate::split_records("a\nb\n\n\nc\n", &paragraph), vec!["a\nb\n\n\n", "c\n"]);
+	assert!(crate::split_records("", &nul).is_empty());
+}
+
+#[test]
+fn opts_record_sep() {
+	let input = "a b\nc d\n\ne f\n\n\ng h";
+	vicut_integration(input, &["--record-sep", "\n\n+", "--linewise", "-m", "I><esc>"], ">a b\nc d\n\n>e f\n\n\n>g h");
+	vicut_integration(input, &["--record-sep", "\n\n+", "--stream", "-m", "I><esc>"], ">a b\nc d\n\n>e f\n\n\n>g h");
+	vicut_integration("1;2;3", &["--record-sep", ";", "--stream", "--serial", "-m", "I#<esc>"], "#1;\n#2;\n#3");
+	assert!(crate::Opts::parse_raw(&["--record-sep", "x*"]).is_err());
+}
+
+#[test]
+fn opts_record_start() {
+	let date = regex::Regex::new(r"^\d{4}-").unwrap();
+	assert_eq!(
+		crate::split_records_at_starts("2024-1 a\n  more\n2024-2 b\n", &date),
+		vec!["2024-1 a\n  more\n", "2024-2 b\n"]
+	);
+	// Lines before the first start are kept as a record of their own
+	assert_eq!(crate::split_records_at_starts("junk\n2024-1 a", &date), vec!["junk\n", "2024-1 a"]);
+	assert!(crate::split_records_at_starts("", &date).is_empty());
+
+	let input = "2024-1 a\n  more a\n2024-2 b\n  more b\n  and more\n2024-3 c";
+	vicut_integration(input, &["--record-start", r"^\d{4}-", "-m", "I><esc>"], ">2024-1 a\n  more a\n>2024-2 b\n  more b\n  and more\n>2024-3 c");
+	vicut_integration(input, &["--record-start", r"^\d{4}-", "--stream", "-m", "I><esc>"], ">2024-1 a\n  more a\n>2024-2 b\n  more b\n  and more\n>2024-3 c");
+	// Each record is one buffer, so motions can reach its continuation lines
+	vicut_integration(input, &["--record-start", r"^\d{4}-", "--serial", "-m", "G^", "-c", "$"], "more a\nand more\n2024-3 c");
+	assert!(crate::Opts::parse_raw(&["--record-start", "x*"]).is_err());
+}
+
+#[test]
+fn opts_field_newlines() {
+	use crate::exec::FieldNewlines;
+	assert_eq!(FieldNewlines::Keep.apply("a\nb\n".into()), "a\nb\n");
+	assert_eq!(FieldNewlines::Strip.apply("a\nb\r\n".into()), "a\nb");
+	assert_eq!(FieldNewlines::Strip.apply("a\n\n".into()), "a\n");
+	assert_eq!(FieldNewlines::Escape.apply("a\\b\nc\n".into()), "a\\\\b\\nc");
+	assert!(crate::Opts::parse_raw(&["--field-newlines", "drop"]).is_err());
+
+	let input = "one\ntwo\nthree";
+	vicut_integration(input, &["-c", "Vj", "-t", "<{{1}}>"], "<one\ntwo\n>");
+	vicut_integration(input, &["--field-newlines", "strip", "-c", "Vj", "-t", "<{{1}}>"], "<one\ntwo>");
+	vicut_integration(input, &["--field-newlines", "strip", "-c", "Vj", "-m", "j", "-c", "$", "-d", "|"], "one\ntwo|three");
+	vicut_integration(input, &["--field-newlines", "escape", "-c", "Vj", "-m", "j", "-c", "$", "-d", "|"], "one\\ntwo|three");
+	// Blocks have no line endings of their own to strip
+	vicut_integration(input, &["--field-newlines", "strip", "--json", "-c", "<c-v>jl"], "[\n  {\n    \"1\": [\n      \"on\",\n      \"tw\"\n    ]\n  }\n]");
+}
+
+#[test]
+fn opts_field_numbering() {
+	let input = "foo bar baz";
+	vicut_integration(input, &["--field-base", "0", "-c", "e", "-m", "w", "-c", "e", "-t", "{{0}}|{{1}}"], "foo|bar");
+	vicut_integration(input, &["--field-base", "10", "-c", "e", "-n", "-m", "w", "-c", "e", "-t", "{{10}}"], "foo\nbar");
+	vicut_integration(input, &["--field-scope", "record", "-c", "e", "-n", "-m", "w", "-c", "e", "--json"], "[\n  {\n    \"1\": \"foo\"\n  },\n  {\n    \"2\": \"bar\"\n  }\n]");
+	vicut_integration("a,b", &["--fields-by", ",", "--field-base", "0", "-t", "{{1}}{{0}}"], "ba");
+	// A lone field numbered 0 is still a field, not the untouched buffer
+	vicut_integration(input, &["--field-base", "0", "-c", "e", "-t", "<{{0}}>"], "<foo>");
+	vicut_integration("{\"msg\":\"foo bar\"}", &["--json-input", "--field", "msg", "--field-base", "0", "-c", "e"], "{\"msg\":\"foo bar\",\"0\":\"foo\"}");
+	// A field that could have been numbered 0, but wasn't captured, leaves the record alone
+	let mut opts = crate::Opts::from_raw("if 1 == 2 {\n\tcut \"e\"\n}\n").unwrap();
+	opts.field_base = Some(0);
+	opts.json_input = true;
+	opts.json_field = Some("msg".into());
+	let output = crate::execute(&opts, "{\"msg\":\"foo bar\"}".to_string(), None).unwrap();
+	assert_eq!(output[0][0].1.to_string(), "{\"msg\":\"foo bar\"}");
+	assert!(crate::Opts::parse_raw(&["--field-scope", "file"]).is_err());
+	assert!(crate::Opts::parse_raw(&["--field-base", "-1"]).is_err());
+	assert!(crate::Opts::parse_raw(&["--field-base", "18446744073709551615"]).is_err());
+	assert!(crate::Opts::parse_raw(&["--field-base", "9223372036854775807"]).is_ok());
+}
+
+#[test]
+fn opts_patch() {
+	let patch = crate::dry_run_diff(std::path::Path::new("./src/a.txt"), "foo\nbar\n", "foo\nBAR\n", true);
+	assert!(patch.starts_with("diff --git a/src/a.txt b/src/a.txt\n--- a/src/a.txt\n+++ b/src/a.txt\n"));
+	assert!(patch.contains("-bar\n+BAR\n"));
+	let cwd = std::env::current_dir().unwrap();
+	assert_eq!(crate::patch_path(&cwd.join("src/a.txt")), "src/a.txt");
+	assert_eq!(crate::patch_path(std::path::Path::new("/elsewhere/a.txt")), "/elsewhere/a.txt");
+	let opts = crate::Opts::parse_raw(&["--patch", "-c", "e"]).unwrap();
+	assert!(opts.patch && opts.dry_run && opts.edit_inplace);
+}
+
+#[test]
+fn opts_format_table() {
+	let input = "a,b \"x\"\nc d";
+	vicut_integration(input, &["--format", "csv", "--linewise", "-c", "name=first", "e", "-m", "w", "-c", "name=rest", "$"],
+		"first,rest\r\n\"a,\",\"b \"\"x\"\"\"\r\nc d,d\r");
+	vicut_integration("a\tb\\c", &["--format", "tsv", "-c", "name=all", "$"], "all\na\\tb\\\\c");
+	vicut_integration("a b\nc d", &["--format", "csv", "--stream", "-c", "name=w", "e", "-m", "w", "-c", "$"], "w,2\r\na b,b\r\nc d,d\r");
+	// Without any fields, there are no columns to make a header from
+	vicut_integration("a b\nc d", &["--format", "csv"], "a b\nc d");
+	vicut_integration("a b\nc d", &["--format", "tsv", "--linewise", "-m", "w"], "a b\nc d");
+	assert!(crate::Opts::parse_raw(&["--format", "xml"]).is_err());
+	assert!(crate::Opts::parse_raw(&["--format", "csv", "-t", "{{1}}"]).unwrap().check_format().is_err());
+	assert!(crate::Opts::parse_raw(&["--format", "csv", "--json"]).unwrap().check_format().is_err());
+	assert!(crate::Opts::parse_raw(&["--format", "csv"]).unwrap().check_format().is_ok());
+}
+
+#[test]
+fn opts_format_table_columns() {
+	let lines = vec![
+		vec![("a".to_string(), "1".into())],
+		vec![("b".to_string(), "2".into()), ("a".to_string(), "3".into())],
+	];
+	let mut columns = vec![];
+	assert_eq!(crate::format_output_table(crate::OutputFormat::Tsv, lines.clone(), &mut columns), "a\tb\n1\t\n3\t2\n");
+	assert_eq!(columns, vec!["a", "b"]);
+	assert_eq!(crate::format_output_table(crate::OutputFormat::Tsv, lines, &mut columns), "1\t\n3\t2\n");
+}
+
+#[test]
+fn opts_git() {
+	use crate::GitSelection;
+	assert_eq!(GitSelection::try_from("changed"), Ok(GitSelection::Changed));
+	assert_eq!(GitSelection::try_from(" Cached "), Ok(GitSelection::Staged));
+	assert_eq!(GitSelection::try_from("ls-files"), Ok(GitSelection::Tracked));
+	assert!(crate::Opts::parse_raw(&["--git", "xml"]).is_err());
+	let opts = crate::Opts::parse_raw(&["--git", "staged", "-c", "e"]).unwrap();
+	assert_eq!(opts.git, Some(GitSelection::Staged));
+	if let Ok(files) = crate::git_files(GitSelection::Tracked) {
+		assert!(files.iter().any(|f| f.ends_with("src/main.rs")));
+	}
+}
+
+#[test]
+fn opts_recursive() {
+	let dir = tempfile::tempdir().unwrap();
+	let root = dir.path();
+	for sub in [".git", ".hidden", "sub", "target"] {
+		std::fs::create_dir(root.join(sub)).unwrap();
+	}
+	for file in [".git/HEAD", ".hidden/a.txt", ".dotfile", "sub/b.txt", "target/c.txt", "d.txt", "skip.txt"] {
+		std::fs::write(root.join(file), "foo").unwrap();
+	}
+	std::fs::write(root.join(".gitignore"), "target\n").unwrap();
+	std::fs::write(root.join(".ignore"), "skip.txt\n").unwrap();
+
+	let walked = |hidden: bool, files: Vec<std::path::PathBuf>| crate::walk_files(&files, hidden)
+		.into_iter()
+		.map(|path| path.strip_prefix(root).unwrap().display().to_string())
+		.collect::<Vec<_>>();
+	assert_eq!(walked(false, vec![root.into()]), vec!["d.txt", "sub/b.txt"]);
+	assert_eq!(walked(true, vec![root.into()]), vec![".dotfile", ".gitignore", ".hidden/a.txt", ".ignore", "d.txt", "sub/b.txt"]);
+	// Naming a file explicitly overrides the ignore files, and nothing is listed twice
+	assert_eq!(walked(false, vec![root.join("skip.txt"), root.into(), root.join("d.txt")]), vec!["skip.txt", "d.txt", "sub/b.txt"]);
+
+	let opts = crate::Opts::parse_raw(&["-R", "--hidden", "-c", "e"]).unwrap();
+	assert!(opts.recursive && opts.hidden);
+}
+
+#[test]
+fn opts_json_input() {
+	let input = "{\"id\":1,\"msg\":\"hello world\"}\n{\"id\":2}\n{\"id\":3,\"msg\":\"foo bar\"}";
+	vicut_integration(input, &["--json-input", "--field", "msg", "-m", "wD"],
+		"{\"id\":1,\"msg\":\"hello \"}\n{\"id\":2}\n{\"id\":3,\"msg\":\"foo \"}");
+	vicut_integration(input, &["--json-input", "--field", "msg", "-c", "name=word", "e"],
+		"{\"id\":1,\"msg\":\"hello world\",\"word\":\"hello\"}\n{\"id\":2}\n{\"id\":3,\"msg\":\"foo bar\",\"word\":\"foo\"}");
+	vicut_integration("{\"n\":42}", &["--json-input", "--field", "n", "--stream", "-m", "A0<esc>"], "{\"n\":420}");
+	// Key order and untouched values are kept as written, edited values keep their type where they can
+	vicut_integration("{\"b\":2.50,\"a\":\"x y\",\"n\":42}", &["--json-input", "--field", "n", "-m", "A0<esc>"], "{\"b\":2.50,\"a\":\"x y\",\"n\":420}");
+	vicut_integration("{\"b\":2.50,\"a\":\"x y\"}", &["--json-input", "--field", "a", "-c", "name=w", "e"], "{\"b\":2.50,\"a\":\"x y\",\"w\":\"x y\"}");
+	vicut_integration("{\"b\":true}", &["--json-input", "--field", "b", "-m", "ciwfalse<esc>"], "{\"b\":false}");
+	vicut_integration("{\"b\":true}", &["--json-input", "--field", "b", "-m", "ciwnope<esc>"], "{\"b\":\"nope\"}");
+	let opts = crate::Opts::parse_raw(&["--json-input", "--field", "msg", "-c", "e"]).unwrap();
+	assert!(opts.json_input && opts.json_field.as_deref() == Some("msg"));
+	assert!(crate::execute(&opts, "[1]".into(), None).is_err());
+	assert!(crate::execute(&opts, "{\"msg\":".into(), None).is_err());
+}
+
+#[test]
+fn opts_max_filesize_binary() {
+	assert_eq!(crate::parse_filesize("512"), Ok(512));
+	assert_eq!(crate::parse_filesize("64K"), Ok(64 * 1024));
+	assert_eq!(crate::parse_filesize(" 10m "), Ok(10 * 1024 * 1024));
+	assert!(crate::parse_filesize("1X").is_err());
+	assert!(crate::parse_filesize("K").is_err());
+	assert!(crate::parse_filesize("99999999999G").is_err());
+
+	let dir = tempfile::tempdir().unwrap();
+	let text = dir.path().join("text.txt");
+	let binary = dir.path().join("binary.dat");
+	std::fs::write(&text, "foo bar\n".repeat(256)).unwrap();
+	std::fs::write(&binary, b"foo\0bar").unwrap();
+
+	let opts = crate::Opts::parse_raw(&["-c", "e"]).unwrap();
+	assert_eq!(crate::skip_reason(&opts, &text), None);
+	assert!(crate::skip_reason(&opts, &binary).is_some());
+	let opts = crate::Opts::parse_raw(&["--text", "--max-filesize", "1K", "-c", "e"]).unwrap();
+	assert!(crate::skip_reason(&opts, &text).is_some());
+	assert_eq!(crate::skip_reason(&opts, &binary), None);
+	let opts = crate::Opts::parse_raw(&["--read0", "-c", "e"]).unwrap();
+	assert_eq!(crate::skip_reason(&opts, &binary), None);
+}
+
+#[test]
+#[cfg(unix)]
+fn opts_lock_files() {
+	let dir = tempfile::tempdir().unwrap();
+	let locked = dir.path().join("locked.txt");
+	let free = dir.path().join("free.txt");
+	std::fs::write(&locked, "foo").unwrap();
+	std::fs::write(&free, "bar").unwrap();
+	let (locked_arg, free_arg) = (locked.to_str().unwrap(), free.to_str().unwrap());
+	let holder = std::fs::File::open(&locked).unwrap();
+	holder.lock().unwrap();
+
+	let mut opts = crate::Opts::parse_raw(&["-i", "-c", "e", locked_arg, free_arg]).unwrap();
+	assert!(crate::check_locks(&mut opts).is_err());
+	let mut opts = crate::Opts::parse_raw(&["-i", "--no-clobber", "-c", "e", locked_arg, free_arg]).unwrap();
+	assert!(crate::check_locks(&mut opts).is_ok());
+	assert_eq!(opts.files, vec![free.clone()]);
+	// Checking doesn't hold on to the lock
+	assert!(crate::lock_file(&opts, &free).unwrap().is_some());
+	assert!(crate::lock_file(&opts, &locked).is_err());
+
+	let waiter = std::thread::spawn(move || {
+		let opts = crate::Opts::parse_raw(&["-i", "--wait", "-c", "e", locked.to_str().unwrap()]).unwrap();
+		crate::lock_file(&opts, &locked).map(|lock| lock.is_some())
+	});
+	std::thread::sleep(std::time::Duration::from_millis(50));
+	assert!(!waiter.is_finished());
+	holder.unlock().unwrap();
+	assert_eq!(waiter.join().unwrap(), Ok(true));
+
+	// Something else changed the file between vicut reading it and writing it back
+	let opts = crate::Opts::parse_raw(&["-i", "-c", "e", free_arg]).unwrap();
+	assert_eq!(crate::read_file(&opts, &free).unwrap(), "bar");
+	std::fs::write(&free, "baz").unwrap();
+	crate::write_in_place(&opts, &free, "edited");
+	assert_eq!(std::fs::read_to_string(&free).unwrap(), "baz");
+	assert_eq!(crate::read_file(&opts, &free).unwrap(), "baz");
+	crate::write_in_place(&opts, &free, "edited");
+	assert_eq!(std::fs::read_to_string(&free).unwrap(), "edited");
+}
+
+#[test]
+fn opts_edit_cache() {
+	assert_eq!(crate::content_hash(""), "cbf29ce484222325");
+	assert_eq!(crate::content_hash("a"), "af63dc4c8601ec8c");
+
+	let dir = tempfile::tempdir().unwrap();
+	let cache_path = dir.path().join("cache.json");
+	let file = dir.path().join("a.txt");
+	std::fs::write(&file, "foo bar").unwrap();
+	let opts = crate::Opts::parse_raw(&["-i", "-c", "e"]).unwrap();
+
+	let mut cache = crate::EditCache::load(&cache_path, &opts).unwrap();
+	assert!(!cache.is_done(&file));
+	std::fs::write(&file, "foo").unwrap();
+	cache.record(&file, "foo");
+	cache.save().unwrap();
+
+	let cache = crate::EditCache::load(&cache_path, &opts).unwrap();
+	assert!(cache.is_done(&file));
+	// A different script has to run again
+	let other = crate::Opts::parse_raw(&["-i", "-c", "w"]).unwrap();
+	assert!(!crate::EditCache::load(&cache_path, &other).unwrap().is_done(&file));
+	// And so does a file that was changed since
+	std::fs::write(&file, "foo baz").unwrap();
+	assert!(!cache.is_done(&file));
+
+	std::fs::write(&cache_path, "not json").unwrap();
+	assert!(crate::EditCache::load(&cache_path, &opts).is_err());
+}
+
+#[test]
+fn opts_textwidth() {
+	assert_eq!(crate::parse_textwidth("72"), Ok(72));
+	assert!(crate::parse_textwidth("0").is_err());
+	assert!(crate::parse_textwidth("wide").is_err());
+	vicut_integration("aa bb cc\ndd", &["--textwidth", "5", "-m", "gqq"], "aa bb\ncc\ndd");
+}
+
+#[test]
+fn opts_stdin_in_files() {
+	let dir = tempfile::tempdir().unwrap();
+	let first = dir.path().join("first.txt");
+	let second = dir.path().join("second.txt");
+	std::fs::write(&first, "foo").unwrap();
+	std::fs::write(&second, "bar").unwrap();
+	let (first_arg, second_arg) = (first.to_str().unwrap(), second.to_str().unwrap());
+
+	let opts = crate::Opts::parse_raw(&["-c", "e", first_arg, "-", second_arg]).unwrap();
+	assert_eq!(opts.files, vec![first.clone(), "-".into(), second.clone()]);
+	assert!(crate::is_stdin(&opts.files[1]));
+	assert_eq!(crate::skip_reason(&opts, &opts.files[1]), None);
+
+	let list = dir.path().join("list");
+	std::fs::write(&list, format!("{second_arg}\r\n\n-\n")).unwrap();
+	assert_eq!(crate::read_file_list(list.to_str().unwrap()).unwrap(), vec![second_arg.to_string(), "-".to_string()]);
+	let opts = crate::Opts::parse_raw(&["-c", "e", first_arg, "--files-from", list.to_str().unwrap()]).unwrap();
+	assert_eq!(opts.files, vec![first, second, "-".into()]);
+	assert!(crate::read_file_list(dir.path().join("missing").to_str().unwrap()).is_err());
+}
+
+#[test]
+fn opts_program() {
+	let opts = crate::Opts::parse_raw(&["-c", "w", "--program", "*.log", "-c", "E", "--program", "*.csv", "-c", "f,", "-c", "e"]).unwrap();
+	let default = crate::Opts::parse_raw(&["-c", "w"]).unwrap().cmds;
+	let log = crate::Opts::parse_raw(&["-c", "E"]).unwrap().cmds;
+	let csv = crate::Opts::parse_raw(&["-c", "f,", "-c", "e"]).unwrap().cmds;
+	assert_eq!(opts.program_for(Some(std::path::Path::new("a.log"))), log.as_slice());
+	assert_eq!(opts.program_for(Some(std::path::Path::new("dir/b.csv"))), csv.as_slice());
+	assert_eq!(opts.program_for(Some(std::path::Path::new("c.txt"))), default.as_slice());
+	assert_eq!(opts.program_for(None), default.as_slice());
+
+	let fields = crate::execute(&opts, "x,y z".into(), Some("a.log".into())).unwrap();
+	assert_eq!(fields, vec![vec![("1".to_string(), "x,y".into())]]);
+	let fields = crate::execute(&opts, "x,y z".into(), Some("b.csv".into())).unwrap();
+	assert_eq!(fields, vec![vec![("1".to_string(), "x,".into()), ("2".to_string(), ",y".into())]]);
+
+	assert!(crate::Opts::parse_raw(&["--program", "a{b", "-c", "e"]).is_err());
+	assert!(crate::Opts::parse_raw(&["-c", "e", "--program", "*.log", "-r", "2", "1"]).is_err());
+}
+
+#[test]
+fn opts_flush() {
+	use std::io::Write;
+	let opts = crate::Opts::parse_raw(&["--flush", "line", "-c", "e"]).unwrap();
+	assert_eq!(opts.flush_policy(), crate::FlushPolicy::Line);
+	assert!(crate::Opts::parse_raw(&["--flush", "sometimes", "-c", "e"]).is_err());
+
+	let mut out = crate::OutputWriter::new(vec![], &opts);
+	write!(out, "a").unwrap();
+	assert!(out.out.get_ref().is_empty());
+	write!(out, "\nb").unwrap();
+	assert_eq!(out.out.get_ref(), b"a\nb");
+
+	let opts = crate::Opts::parse_raw(&["--flush", "record", "-c", "e"]).unwrap();
+	let mut out = crate::OutputWriter::new(vec![], &opts);
+	write!(out, "a\nb").unwrap();
+	assert!(out.out.get_ref().is_empty());
+	out.end_record();
+	assert_eq!(out.out.get_ref(), b"a\nb");
+
+	let opts = crate::Opts::parse_raw(&["--flush", "never", "-c", "e"]).unwrap();
+	let mut out = crate::OutputWriter::new(vec![], &opts);
+	write!(out, "a\nb").unwrap();
+	out.end_record();
+	assert!(out.out.get_ref().is_empty());
+
+	vicut_integration("a b\nc d", &["--stream", "--flush", "never", "-c", "e"], "a b\nc d");
+}
+
+#[test]
+fn opts_shared_registers() {
+	let input = vec!["ab"; 500].join("\n");
+	// Every record starts with empty registers, no matter which thread it lands on
+	let expected = vec!["aab"; 500].join("\n");
+	vicut_integration(&input, &["--linewise", "-m", "\"Ayl\"ap"], &expected);
+	// With --serial, registers carry over from one record to the next
+	vicut_integration("ab\ncd\nef", &["--linewise", "--serial", "-m", "\"Ayl\"ap"], "aab\ncacd\neacef");
+	// Shared registers see every append, so whichever record appends last puts all of them
+	let output = crate::tests::call_main(&["--linewise", "--shared-registers", "-m", "\"Syl\"sp"], &input).unwrap();
+	let longest = output.lines().map(str::len).max().unwrap();
+	assert_eq!(longest, 502);
+}
+
+#[test]
+fn opts_jobs() {
+	let opts = crate::Opts::parse_raw(&["--jobs", "2", "-c", "e"]).unwrap();
+	assert_eq!(opts.max_jobs, Some(2));
+	let opts = crate::Opts::parse_raw(&["-J", "1", "-c", "e"]).unwrap();
+	assert_eq!(opts.max_jobs, Some(1));
+	assert!(crate::Opts::parse_raw(&["--jobs", "0", "-c", "e"]).is_err());
+	assert!(crate::Opts::parse_raw(&["--jobs", "many", "-c", "e"]).is_err());
+	assert!(crate::Opts::parse_raw(&["--jobs"]).is_err());
+
+	vicut_integration(
+		"foo bar\nbiz baz\nbuz boz",
+		&[
+			"--linewise",
+			"--jobs", "2",
+			"-c", "e",
+		],
+		"foo\nbiz\nbuz"
+	);
+}
+
+#[test]
+fn opts_deterministic() {
+	let mut opts = crate::Opts::parse_raw(&["--deterministic", "--linewise", "-c", "e"]).unwrap();
+	opts.make_deterministic().unwrap();
+	assert_eq!(opts.max_jobs, Some(1));
+	let mut opts = crate::Opts::parse_raw(&["--deterministic", "--serial", "--linewise", "-c", "e"]).unwrap();
+	opts.make_deterministic().unwrap();
+	assert_eq!(opts.max_jobs, None);
+
+	let mut opts = crate::Opts::parse_raw(&["--deterministic", "--shared-registers", "-c", "e"]).unwrap();
+	assert!(opts.make_deterministic().is_err());
+	let mut opts = crate::Opts::parse_raw(&["--deterministic", "--regex-timeout", "1", "-c", "e"]).unwrap();
+	assert!(opts.make_deterministic().is_err());
+
+	let input = vec!["ab"; 200].join("\n");
+	let expected = vec!["aab"; 200].join("\n");
+	vicut_integration(&input, &["--deterministic", "--linewise", "-m", "\"Ayl\"ap"], &expected);
+}
+
+#[test]
+fn opts_fields_by() {
+	vicut_integration("a, b,c\nd,e,f\n", &["--linewise", "--fields-by", ",\\s*", "-t", "{{3}}-{{1}}"], "c-a\nf-d");
+	// Captured fields are numbered after the pieces
+	vicut_integration("key=val x", &["--fields-by", "=", "-m", "w", "-c", "e", "-t", "{{1}}|{{2}}|{{3}}"], "key|val x|=val");
+	// Capturing into a piece's name replaces it
+	vicut_integration("foo bar", &["--fields-by", " ", "-m", "w", "-c", "name=1", "e", "-t", "{{1}} {{2}}"], "bar bar");
+	// Every -n group starts out with the pieces, and one that captures nothing else is left out
+	vicut_integration("ab cd,e", &["--fields-by", ",", "-c", "e", "-n", "-m", "w", "-c", "e", "-n", "-t", "{{1}}|{{2}}|{{3}}"], "ab cd|e|ab\nab cd|e|cd");
+	vicut_integration(",a,", &["--fields-by", ",", "--json"], "[\n  {\n    \"1\": \"\",\n    \"2\": \"a\",\n    \"3\": \"\"\n  }\n]");
+	assert!(crate::Opts::parse_raw(&["--fields-by", "x*", "-c", "e"]).is_err());
+}
+
+#[test]
+fn opts_pattern_fields() {
+	let pattern = "pattern=(?P<user>\\w+)@(?P<host>\\w+)";
+	vicut_integration("mail alice@example", &["-c", pattern, "-t", "{{host}}: {{user}}"], "example: alice");
+	// The second match starts where the first one left off
+	vicut_integration("alice@ex and bob@host", &["-c", pattern, "-n", "-c", pattern, "-t", "{{user}}"], "alice\nbob");
+	// Optional groups that don't match are empty. Each group takes a field number, like '-c name=' does
+	vicut_integration("x=1 y", &["-c", "pattern=(?P<key>\\w+)=(?P<val>\\d+)(?P<unit>ms)?", "-c", "e", "--json"],
+		"[\n  {\n    \"4\": \" y\",\n    \"key\": \"x\",\n    \"unit\": \"\",\n    \"val\": \"1\"\n  }\n]");
+	// A miss captures nothing, and leaves the cursor where it was
+	vicut_integration("no address", &["-c", pattern, "-c", "e", "--json"], "[\n  {\n    \"1\": \"no\"\n  }\n]");
+	assert!(crate::Opts::parse_raw(&["-c", "pattern=(\\w+)"]).is_err());
+	assert!(crate::Opts::parse_raw(&["-c", "pattern=(?P<a>"]).is_err());
+}
+
+#[test]
+fn opts_on_interrupt() {
+	use crate::interrupt::InterruptPolicy;
+	assert_eq!(InterruptPolicy::try_from("finish"), Ok(InterruptPolicy::Finish));
+	assert_eq!(InterruptPolicy::try_from("ABORT"), Ok(InterruptPolicy::Abort));
+	assert!(InterruptPolicy::try_from("stop").is_err());
+
+	let opts = crate::Opts::parse_raw(&["-c", "e"]).unwrap();
+	assert_eq!(opts.on_interrupt, InterruptPolicy::Finish);
+	let opts = crate::Opts::parse_raw(&["--on-interrupt", "abort", "-c", "e"]).unwrap();
+	assert_eq!(opts.on_interrupt, InterruptPolicy::Abort);
+	assert!(crate::Opts::parse_raw(&["--on-interrupt", "later", "-c", "e"]).is_err());
+	assert!(crate::Opts::parse_raw(&["-c", "e", "--on-interrupt"]).is_err());
+}
+
+#[test]
+fn opts_stats() {
+	use std::{path::Path, time::Instant};
+	use crate::stats::Stats;
+	assert!(crate::Opts::parse_raw(&["--stats", "-c", "e"]).unwrap().stats);
+
+	let mut stats = Stats::new(true);
+	let records = vec![
+		vec![("1".to_string(), "a".into()), ("2".to_string(), "b".into())],
+		vec![("1".to_string(), "c".into())],
+	];
+	stats.record_unit(Some(Path::new("foo.txt")), 10, &records, Instant::now(), Some(0));
+	stats.record_unit(Some(Path::new("foo.txt")), 5, &records[1..], Instant::now(), None);
+	stats.bytes_out += 7;
+	let json = stats.to_json();
+	assert_eq!(json["inputs"], 2);
+	assert_eq!(json["records"], 3);
+	assert_eq!(json["fields"], 4);
+	assert_eq!(json["bytes_in"], 15);
+	assert_eq!(json["bytes_out"], 7);
+	assert_eq!(json["files"].as_array().unwrap().len(), 1);
+	assert_eq!(json["files"][0]["path"], "foo.txt");
+	assert_eq!(json["threads"][0]["thread"], "main");
+	assert_eq!(json["threads"][1]["thread"], "0");
+	assert_eq!(json["threads"][1]["records"], 1);
+
+	// Per thread timings are only broken down for --linewise
+	assert!(Stats::new(false).to_json().get("threads").is_none());
+}
+
+#[test]
+fn opts_out_template() {
+	use std::path::{Path, PathBuf};
+	let opts = crate::Opts::parse_raw(&["--out-template", "{stem}.out.{ext}", "-c", "e"]).unwrap();
+	assert_eq!(opts.out_template.as_deref(), Some("{stem}.out.{ext}"));
+	assert!(crate::Opts::parse_raw(&["--out-template", "{path}.out", "-c", "e"]).is_err());
+	assert!(crate::Opts::parse_raw(&["--out-template", "{stem", "-c", "e"]).is_err());
+	assert!(crate::Opts::parse_raw(&["-c", "e", "--out-template"]).is_err());
+
+	assert_eq!(crate::out_template_path("{stem}.out.{ext}", Path::new("src/main.rs")), PathBuf::from("src/main.out.rs"));
+	assert_eq!(crate::out_template_path("{name}.cut", Path::new("notes")), PathBuf::from("notes.cut"));
+	// Absolute paths don't follow the input file around
+	assert_eq!(crate::out_template_path("/tmp/{stem}.txt", Path::new("a/b.csv")), PathBuf::from("/tmp/b.txt"));
+
+	let dir = std::env::temp_dir().join("vicut_out_template_check");
+	std::fs::create_dir_all(&dir).unwrap();
+	let files = vec![dir.join("a.txt"), dir.join("b.txt")];
+	for file in &files {
+		std::fs::write(file, "foo").unwrap();
+	}
+	assert!(crate::check_out_template("{stem}.cut", &files).is_ok());
+	// Spelled differently, but still the input file
+	assert!(crate::check_out_template("./{name}", &files).is_err());
+	assert!(crate::check_out_template("a.txt", &files[..1]).is_err());
+	// The second file would silently replace the output of the first one
+	assert!(crate::check_out_template("out.txt", &files).is_err());
+}
+
+#[test]
+fn opts_write_prelude() {
+	use std::io::Write;
+	let dir = std::env::temp_dir().join("vicut_write_prelude");
+	std::fs::create_dir_all(&dir).unwrap();
+	let out = dir.join("out.txt");
+	std::fs::write(&out, "old\n").unwrap();
+	let opts = crate::Opts::from_raw(&format!("opts {{ write = \"{}\", backup }} cut \"e\"", out.display())).unwrap();
+	assert_eq!(opts.out_file.as_deref(), Some(out.as_path()));
+
+	let mut writer = crate::stdout_writer(&opts);
+	writeln!(writer, "new").unwrap();
+	drop(writer);
+	assert_eq!(std::fs::read_to_string(&out).unwrap(), "new\n");
+	assert_eq!(std::fs::read_to_string(dir.join("out.txt.bak")).unwrap(), "old\n");
+
+	// An input file can't also be where the output goes
+	assert!(crate::same_file(&out, &dir.join(".").join("out.txt")));
+	assert!(!crate::same_file(&out, &dir.join("out.txt.bak")));
+}
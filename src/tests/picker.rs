@@ -0,0 +1,22 @@
+use crate::picker::{fuzzy_match, parse_selection};
+
+#[test]
+fn picker_fuzzy_match() {
+	assert!(fuzzy_match("", "anything"));
+	assert!(fuzzy_match("fbr", "foo bar"));
+	assert!(fuzzy_match("FOO", "foo bar"));
+	assert!(fuzzy_match("foo bar", "foobar"));
+	assert!(!fuzzy_match("rab", "foo bar"));
+	assert!(!fuzzy_match("baz", "foo bar"));
+}
+
+#[test]
+fn picker_parse_selection() {
+	assert_eq!(parse_selection("1 3", 3).unwrap(), vec![0, 2]);
+	assert_eq!(parse_selection("2-4,1", 5).unwrap(), vec![1, 2, 3, 0]);
+	assert_eq!(parse_selection("3-1", 3).unwrap(), vec![0, 1, 2]);
+	assert_eq!(parse_selection("1 1 1", 3).unwrap(), vec![0]);
+	assert!(parse_selection("0", 3).is_err());
+	assert!(parse_selection("4", 3).is_err());
+	assert!(parse_selection("foo", 3).is_err());
+}
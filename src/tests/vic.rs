@@ -0,0 +1,836 @@
+use crate::tests::vicut_integration;
+
+#[test]
+fn vic_when_filetype() {
+	vicut_integration(
+		"#!/usr/bin/env python3\nprint('hello')",
+		&[r#"
+			when filetype == "python" {
+				move "j"
+				cut "e"
+			}
+			when filetype == "json" {
+				cut "$"
+			}
+		"#],
+		"print"
+	);
+}
+
+#[test]
+fn vic_when_filetype_no_match() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			when $filetype != "" {
+				cut "e"
+			}
+		"#],
+		"foo bar"
+	);
+}
+
+#[test]
+fn vic_echo_append() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			echo "one" >> $out
+			echo "two" 2 >> $out
+			if $out == "one
+two 2" {
+				cut "e"
+			}
+		"#],
+		"foo"
+	);
+}
+
+#[test]
+fn vic_echo_append_array() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let lines = []
+			echo "a" >> $lines
+			echo "b" >> $lines
+			if $lines == ["a", "b"] {
+				cut "e"
+			}
+		"#],
+		"foo"
+	);
+}
+
+#[test]
+fn vic_printf_format() {
+	use crate::exec::{format_printf, Val};
+	let args = [
+		Val::Str("ab".into()),
+		Val::Num(7),
+		Val::Num(255),
+		Val::Num(-3),
+		Val::Num(65),
+	];
+	assert_eq!(
+		format_printf("%-4s|%03d|%x|%05d|%c|100%%\\n", &args).unwrap(),
+		"ab  |007|ff|-0003|A|100%\n"
+	);
+	assert!(format_printf("%d", &[]).is_err());
+	assert!(format_printf("%d", &[Val::Str("nope".into())]).is_err());
+	assert!(format_printf("%s", &[Val::Num(1), Val::Num(2)]).is_err());
+}
+
+#[test]
+fn vic_return_string_output() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			cut "e"
+			return "replaced"
+			cut "w"
+		"#],
+		"replaced"
+	);
+}
+
+#[test]
+fn vic_return_exit_code_stops_script() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			cut "e"
+			return 3
+			cut "w"
+		"#],
+		"foo"
+	);
+}
+
+#[test]
+fn vic_repeat_iteration_vars() {
+	vicut_integration(
+		"aa\nbb\ncc\ndd",
+		&[r#"
+			repeat 3 {
+				move "gg${{i}}j0"
+				if $total == 3 {
+					cut "e"
+				}
+			}
+		"#],
+		"bb cc dd"
+	);
+}
+
+#[test]
+fn vic_for_iteration_vars() {
+	vicut_integration(
+		"aa\nbb\ncc\ndd",
+		&[r#"
+			for x in 5..7 {
+				move "gg${{i}}j0"
+				if $total == 2 {
+					cut "e"
+				}
+			}
+		"#],
+		"bb cc"
+	);
+}
+
+#[test]
+fn vic_iteration_vars_beside_const() {
+	vicut_integration(
+		"foo",
+		&[r#"
+			const total = 5
+			const i = 0
+			let seen = ""
+			repeat 2 {
+				push $seen "${i}/${total} "
+			}
+			for x in ["a"] {
+				push $seen "${i}/${total} "
+			}
+			return "${seen}${i}/${total}"
+		"#],
+		"1/2 2/2 1/1 0/5"
+	);
+}
+
+#[test]
+fn vic_literal_interpolation() {
+	vicut_integration(
+		"foo bar baz",
+		&[r#"
+			let pat = "baz"
+			move "/${pat}<CR>"
+			cut "e"
+		"#],
+		"baz"
+	);
+	// The older double brace form still works
+	vicut_integration(
+		"foo bar baz",
+		&[r#"
+			let motion = "w"
+			move "${{motion}}"
+			cut "e"
+		"#],
+		"bar"
+	);
+}
+
+#[test]
+fn vic_literal_interpolation_escaped() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let x = "nope"
+			return "\${x} ${x}"
+		"#],
+		"${x} nope"
+	);
+}
+
+#[test]
+fn vic_global_match_vars() {
+	vicut_integration(
+		"foo 12\nbar\nfoo 345",
+		&[r#"
+			global "\d+" {
+				if $match == "345" {
+					if $matched_lineno == 3 {
+						if $matched_line == "foo 345" {
+							cut "e"
+						}
+					}
+				}
+			}
+		"#],
+		"foo"
+	);
+}
+
+#[test]
+fn vic_global_limit() {
+	vicut_integration(
+		"foo one\nbar\nfoo two",
+		&[r#"
+			global("^foo", limit=1) {
+				move "w"
+				cut "e"
+			}
+		"#],
+		"one"
+	);
+	vicut_integration(
+		"foo one\nbar\nfoo two",
+		&[r#"
+			let n = 0
+			v("^foo", limit=$n) {
+				cut "e"
+			}
+		"#],
+		""
+	);
+}
+
+#[test]
+fn vic_g_order() {
+	vicut_integration(
+		"foo one\nbar\nfoo two",
+		&[r#"
+			opts { g_order = "forward", record_per_match }
+			global "^foo" {
+				move "w"
+				cut "e"
+			}
+		"#],
+		"one\ntwo"
+	);
+}
+
+#[test]
+fn vic_nested_global_context() {
+	vicut_integration(
+		"foo a\nbar\nfoo b\nbaz b\nqux",
+		&[r#"
+			global "^foo" {
+				global "b" {
+					cut "e"
+				}
+			}
+		"#],
+		"foo"
+	);
+	vicut_integration(
+		"foo a\nbar\nfoo b\nbaz b\nqux",
+		&[r#"
+			opts { g_order = "forward", record_per_match }
+			global("^foo", limit=1, context=1) {
+				global "b" {
+					cut "e"
+				}
+			}
+		"#],
+		"bar"
+	);
+}
+
+#[test]
+fn vic_bool_grouping() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let a = 1
+			let b = 2
+			let res = ""
+			if $a == 2 || ($a == 1 && $b == 2) {
+				push $res "grouped"
+			}
+			if !($a == 2 || $b == 3) {
+				push $res " negated"
+			}
+			if $a == 1 && $b == 2 || $a == 5 {
+				push $res " chained"
+			}
+			let flag = ($a == 1) && $b > 1
+			if $flag && !false {
+				push $res " bare"
+			}
+			let tern = ($a == 1 && $b == 3) ? "yes" : "no"
+			return "${res} ${tern}"
+		"#],
+		"grouped negated chained bare no"
+	);
+}
+
+#[test]
+fn vic_match_block() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let res = ""
+			let words = ["foo", "baz", "123", "qux"]
+			for word in $words {
+				match $word {
+					"foo" => { push $res "f" }
+					"bar" | "baz" => { push $res "b" }
+					/^\d+$/ => { push $res "n" }
+					_ => { push $res "_" }
+				}
+			}
+			return $res
+		"#],
+		"fbn_"
+	);
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			match 3 {
+				1 | 2 => { cut "e" }
+			}
+			move "w"
+			cut "e"
+		"#],
+		"bar"
+	);
+}
+
+#[test]
+fn vic_string_comparisons() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let a = "abc" < "abd"
+			let b = "b" >= "a"
+			let c = "Zebra" > "apple"
+			let d = [1, 2] < [1, 3]
+			let e = [1, 2] < [1, 2, 0]
+			let f = ["b"] <= ["a", "z"]
+			return "${a} ${b} ${c} ${d} ${e} ${f}"
+		"#],
+		"true true false true true false"
+	);
+}
+
+#[test]
+fn vic_natcmp() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let a = natcmp("file2", "file10")
+			let b = natcmp("file10", "file2")
+			let c = natcmp("a01", "a1")
+			let d = "file2" < "file10"
+			return "${a} ${b} ${c} ${d}"
+		"#],
+		"-1 1 0 false"
+	);
+}
+
+#[test]
+fn vic_float_arithmetic() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let a = 1.5 + 2
+			let b = 7 / 2
+			let c = 7 / 2.0
+			let d = -2.5 * 2
+			let e = 3
+			e += 0.25
+			let f = 2 == 2.0
+			let g = $c > 3
+			return "${a} ${b} ${c} ${d} ${e} ${f} ${g}"
+		"#],
+		"3.5 3 3.5 -5.0 3.25 true true"
+	);
+}
+
+#[test]
+fn vic_float_builtins() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let sum = 0
+			let nums = [3, 4, 4]
+			for n in $nums {
+				sum += $n
+			}
+			let sum = float($sum)
+			let avg = $sum / 3
+			let avg = round($avg, 2)
+			let a = int(3.9)
+			let b = round(2.5)
+			let c = float("0.5")
+			return "${avg} ${a} ${b} ${c}"
+		"#],
+		"3.67 3 3 0.5"
+	);
+}
+
+#[test]
+fn vic_oversized_int_literal() {
+	let err = crate::Opts::from_raw("let z = 99999999999999999999\n").unwrap_err();
+	assert!(err.contains("1:9") && err.contains("too large"), "{err}");
+	assert!(crate::vic::parse_expr("99999999999999999999").is_err());
+	assert!(crate::vic::parse_expr("9223372036854775807").is_ok());
+}
+
+#[test]
+fn vic_checked_arithmetic() {
+	use crate::exec::Val;
+	use crate::vic::BinOp;
+
+	let max = Val::Num(isize::MAX);
+	assert!(Val::arith(&BinOp::Add, &max, &Val::Num(1)).is_err());
+	assert!(Val::arith(&BinOp::Mult, &max, &Val::Num(2)).is_err());
+	assert!(Val::arith(&BinOp::Pow, &Val::Num(10), &Val::Num(40)).is_err());
+	assert_eq!(Val::arith(&BinOp::Pow, &Val::Num(2), &Val::Num(-1)), Ok(Val::Float(0.5)));
+
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let big = 4611686018427387903
+			let big = $big * 2
+			return "${big}"
+		"#],
+		"9223372036854775806"
+	);
+}
+
+#[test]
+fn vic_string_slicing() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let s = "héllo world"
+			let a = $s[1]
+			let b = $s[-1]
+			let c = $s[0..5]
+			let d = $s[6..]
+			let e = $s[..=1]
+			let f = $s[-5..-2]
+			return "${a} ${b} ${c} ${d} ${e} ${f}"
+		"#],
+		"é d héllo world hé wor"
+	);
+}
+
+#[test]
+fn vic_array_negative_index() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let arr = ["a", "b", "c", "d"]
+			let last = $arr[-1]
+			arr[-2] = "x"
+			let mid = $arr[1..3]
+			let tail = $arr[2..]
+			return "${last} ${mid} ${tail}"
+		"#],
+		"d [b, x] [x, d]"
+	);
+}
+
+#[test]
+fn vic_array_builtins() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			def is_big(n) {
+				return $n>2
+			}
+			def double(n) {
+				return $n*2
+			}
+			let arr = [3, 1, 4, 1, 5]
+			let sorted = join(sort($arr), ",")
+			let big = join(filter($arr, "is_big"), ",")
+			let doubled = join(map($arr, "double"), ",")
+			let uniq = join(unique($arr), ",")
+			let part = join(slice($arr, 1, 3), ",")
+			let n = len($arr)
+			let has = contains($arr, 4)
+			let missing = contains($arr, 9)
+			return "${sorted} ${big} ${doubled} ${uniq} ${part} ${n} ${has} ${missing}"
+		"#],
+		"1,1,3,4,5 3,4,5 6,2,8,2,10 3,1,4,5 1,4 5 true false"
+	);
+}
+
+#[test]
+fn vic_string_builtins() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let words = ["b10", "b2", "a"]
+			let sorted = join(sort($words), ",")
+			let n = len("héllo")
+			let has = contains("foobar", "oba")
+			let part = slice("foobar", -3)
+			return "${sorted} ${n} ${has} ${part}"
+		"#],
+		"a,b10,b2 5 true bar"
+	);
+}
+
+#[test]
+fn vic_for_with_index() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let arr = ["a", "b", "c"]
+			let out = ""
+			for (idx, item) in $arr {
+				push $out "${idx}=${item} "
+			}
+			return $out
+		"#],
+		"0=a 1=b 2=c "
+	);
+}
+
+#[test]
+fn vic_tuple_destructuring() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			def measure(word) {
+				let n = len($word)
+				return [$word,$n]
+			}
+			let (word, n) = measure("hello")
+			let (a, b, c) = [1, "two", 3]
+			return "${word} ${n} ${a} ${b} ${c}"
+		"#],
+		"hello 5 1 two 3"
+	);
+}
+
+#[test]
+fn vic_return_from_nested_blocks() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			def first_big(arr) {
+				for n in $arr {
+					if $n > 2 {
+						return $n
+					}
+				}
+				return 0
+			}
+			let big = first_big([1, 5, 3])
+			let none = first_big([1, 2])
+			if true {
+				return "${big} ${none}"
+			}
+			return "not reached"
+		"#],
+		"5 0"
+	);
+}
+
+#[test]
+fn vic_exit_stops_script() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			cut "e"
+			let x = 0
+			while true {
+				x += 1
+				if $x == 3 {
+					exit(0)
+				}
+			}
+			cut "w"
+		"#],
+		"foo"
+	);
+}
+
+#[test]
+fn vic_const_declaration() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			const GREETING = "hello"
+			def greet(name) {
+				return "${GREETING} ${name}"
+			}
+			return greet("world")
+		"#],
+		"hello world"
+	);
+}
+
+#[test]
+fn vic_read_only_vars() {
+	use crate::exec::{Val, ViCut};
+
+	let mut vicut = ViCut::new(String::new(), 0).unwrap();
+	vicut.set_builtin_var("filename".into(), Val::Str("foo.txt".into()));
+	assert!(vicut.set_var("filename".into(), Val::Str("bar.txt".into())).is_err());
+	vicut.set_const("LIMIT".into(), Val::Num(3)).unwrap();
+	assert!(vicut.mutate_var("LIMIT".into(), crate::vic::BinOp::Add, Val::Num(1)).is_err());
+
+	// Constants can't be shadowed in an inner scope either
+	vicut.descend();
+	assert!(vicut.set_var("LIMIT".into(), Val::Num(4)).is_err());
+	vicut.ascend();
+	assert_eq!(vicut.get_var("LIMIT"), Some(Val::Num(3)));
+}
+
+#[test]
+fn vic_dump_state() {
+	use crate::exec::{Val, ViCut};
+
+	let mut vicut = ViCut::new(String::new(), 0).unwrap();
+	vicut.set_builtin_var("filename".into(), Val::Str("foo.txt".into()));
+	vicut.set_var("name".into(), Val::Str("outer".into())).unwrap();
+	vicut.set_var("nums".into(), Val::Arr(vec![Val::Num(1), Val::Num(2)])).unwrap();
+	vicut.descend();
+	vicut.set_var("name".into(), Val::Str("inner".into())).unwrap();
+
+	let dump = vicut.dump_state();
+	assert!(dump.starts_with("variables:\n"));
+	assert!(dump.contains("  filename: string (read-only) = \"foo.txt\"\n"));
+	assert!(dump.contains("  name: string = \"inner\"\n"));
+	assert!(!dump.contains("outer"));
+	assert!(dump.contains("  nums: array = [1, 2]\n"));
+	assert!(dump.contains("registers:\n"));
+}
+
+#[test]
+fn vic_to_case() {
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let a = to_case("HTTPServer user_id", "kebab")
+			let b = to_case("first-name", "upper")
+			let c = to_case("some value", "camel")
+			return "${a} ${b} ${c}"
+		"#],
+		"http-server user-id FIRST_NAME some value"
+	);
+}
+
+#[test]
+fn vic_freq() {
+	vicut_integration(
+		"the cat and the dog\nthe end and",
+		&[r#"
+			let words = freq()
+			let top = $words[0]
+			let count = len($words)
+			let ends = freq(/[a-z]+d\b/)
+			return "${top} ${count} ${ends}"
+		"#],
+		"[the, 3] 5 [[and, 2], [end, 1]]"
+	);
+}
+
+#[test]
+fn vic_diff_apply_patch() {
+	let path = std::env::temp_dir().join(format!("vicut_diff_{}.txt", std::process::id()));
+	std::fs::write(&path, "one\ntwo\nthree\nfour\n").unwrap();
+	// Pull in changed lines from the other file, but keep the lines that only exist here
+	let script = format!(r#"
+		def adds_lines(hunk) {{
+			let added = $hunk[2]
+			let count = len($added)
+			return $count>0
+		}}
+		let hunks = diff("{}")
+		apply_patch(filter($hunks, "adds_lines"))
+	"#, path.display());
+	vicut_integration("one\nTWO\nthree\nfour\nfive\n", &[&script], "one\ntwo\nthree\nfour\nfive");
+	std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn vic_normal_range() {
+	vicut_integration(
+		"a one\nb two\nc three\nd four",
+		&[r#"
+			normal!(2, 3, "dw")
+			move "gg"
+			cut "G$"
+		"#],
+		"a one\ntwo\nthree\nd four"
+	);
+	vicut_integration(
+		"a one\nb two\nc three\nd four",
+		&[r#"
+			let end = 4
+			normal!(2, $end, "I- ")
+			move "gg0"
+			cut "G$"
+		"#],
+		"a one\n- b two\n- c three\n- d four"
+	);
+	vicut_integration(
+		"a one\nb two\nc three\nd four",
+		&[r#"
+			normal!(1, 3, "dd")
+			move "gg0"
+			cut "G$"
+		"#],
+		"d four"
+	);
+	vicut_integration(
+		"a one\nb two",
+		&[r#"
+			move "j"
+			normal!("wD")
+			move "gg0"
+			cut "G$"
+		"#],
+		"a one\nb "
+	);
+}
+
+#[test]
+fn vic_expr_register() {
+	vicut_integration(
+		"ab",
+		&[r#"
+			let n = 21
+			move "\"=$n * 2<CR>p0"
+			cut "$"
+		"#],
+		"a42b"
+	);
+}
+
+#[test]
+fn vic_sub_changes() {
+	vicut_integration(
+		"a=1\nskip\nb=2",
+		&[r#"
+			move ":%s/(\w+)=(\w+)/\2=\1/<CR>"
+			let changes = $sub_changes
+			return "${sub_count} ${changes}"
+		"#],
+		"2 [1=a, 2=b]"
+	);
+}
+
+#[test]
+fn vic_shell_args() {
+	// Arguments reach the program as-is, with no shell to split or expand them
+	vicut_integration(
+		"foo bar",
+		&[r#"
+			let word = "$HOME; echo pwned"
+			let out = shell_args(["printf", "%s|%s", $word, "a b"])
+			let piped = shell_args(["tr", "a-z", "A-Z"], "abc")
+			return "${out} ${piped}"
+		"#],
+		"$HOME; echo pwned|a b ABC"
+	);
+}
+
+#[test]
+fn vic_textobj_delim() {
+	vicut_integration(
+		"say «hello «big» world» now\n",
+		&[r#"
+			textobj("g", "«", "»")
+			move "fb"
+			cut "vig"
+			cut "v2ag"
+		"#],
+		"big «hello «big» world»"
+	);
+	// Matching delimiters take turns, like quotes
+	vicut_integration(
+		"a |b| c |d e| f\n",
+		&[r#"
+			textobj("|", "|", "|")
+			move "fc"
+			cut "vi|"
+		"#],
+		"d e"
+	);
+}
+
+#[test]
+fn vic_textobj_pattern() {
+	vicut_integration(
+		"tags @one and @two\n",
+		&[r#"
+			textobj("x", "@(\w+)")
+			cut "vix"
+			move "0"
+			cut "v2ax"
+		"#],
+		"one @two"
+	);
+	vicut_integration(
+		"tags @one and @two\n",
+		&[r#"
+			textobj("x", "@\w+")
+			move "fo"
+			normal!("dix")
+		"#],
+		"tags  and @two"
+	);
+}
+
+#[test]
+fn vic_textobj_errors() {
+	use crate::exec::ViCut;
+	use crate::linebuf::CustomTextObj;
+
+	let mut vicut = ViCut::new(String::new(), 0).unwrap();
+	assert!(vicut.define_text_obj('w', CustomTextObj::Delim("<".into(), ">".into())).is_err());
+	assert!(vicut.define_text_obj('g', CustomTextObj::Delim(String::new(), ">".into())).is_err());
+	assert!(vicut.define_text_obj('g', CustomTextObj::Delim("<<".into(), ">>".into())).is_ok());
+	assert!(vicut.current_buffer().custom_text_objs.contains_key(&'g'));
+}
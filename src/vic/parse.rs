@@ -0,0 +1,1119 @@
+//! The `pest` driven half of the `vic` language.
+//!
+//! Everything in here turns a parsed `vic` script into the `Cmd`/`Expr` values defined in the parent module.
+//! It is only compiled with the `script` feature.
+
+use std::path::PathBuf;
+
+use pest::{error::{Error, ErrorVariant}, iterators::{Pair, Pairs}, Parser};
+use pest_derive::Parser;
+use crate::{complain_and_exit, exec::Val, CondBlock, Eol, MatchArm, Opts};
+
+use super::{BinOp, BoolOp, CmdArg, Expr};
+use crate::Cmd;
+
+impl BinOp {
+	pub fn bin_op_from_rule(pair: Pair<Rule>) -> Self {
+		match pair.as_rule() {
+			Rule::add => BinOp::Add,
+			Rule::sub => BinOp::Sub,
+			Rule::mult => BinOp::Mult,
+			Rule::div => BinOp::Div,
+			Rule::modulo => BinOp::Mod,
+			Rule::pow => BinOp::Pow,
+			_ => unreachable!("Unexpected rule in bin_expr: {:?}", pair.as_rule()),
+		}
+	}
+}
+
+impl BoolOp {
+	pub fn bool_op_from_rule(mut pair: Pair<Rule>) -> Self {
+		if pair.as_rule() == Rule::bool_conjunction {
+			pair = pair.into_inner().next().unwrap();
+		}
+		match pair.as_rule() {
+			Rule::and => BoolOp::And,
+			Rule::or => BoolOp::Or,
+			Rule::eq => BoolOp::Eq,
+			Rule::ne => BoolOp::Ne,
+			Rule::lt => BoolOp::Lt,
+			Rule::gt => BoolOp::Gt,
+			Rule::le => BoolOp::LtEq,
+			Rule::ge => BoolOp::GtEq,
+			_ => unreachable!("Unexpected rule in bool_expr: {:?}", pair.as_rule()),
+		}
+	}
+}
+
+impl Expr {
+	pub fn eval_atom(pair: Pair<Rule>) -> Self {
+		match pair.as_rule() {
+			Rule::bin_lit => {
+				let mut literal = pair.into_inner();
+				let mut int = literal.next().unwrap();
+				let mut is_negated = false;
+				if let Rule::unary_minus = int.as_rule() {
+					int = int.into_inner().next().unwrap();
+					is_negated = true;
+				}
+				let int = int.as_str().parse::<i64>().unwrap();
+				if is_negated {
+					Self::Int(-int)
+				} else {
+					Self::Int(int)
+				}
+			}
+			Rule::int => {
+				let int = pair.as_str().parse::<i64>().unwrap();
+				Self::Int(int)
+			}
+			Rule::var => {
+				let var = pair.into_inner().next().unwrap()
+					.as_str().to_string();
+				Self::Var(var)
+			}
+			_ => unreachable!("Unexpected rule in bin_atom: {:?}", pair.as_rule()),
+		}
+	}
+
+	/// Wrap an expression in a '!'
+	fn negated(expr: Self) -> Self {
+		Self::BoolExp { op: BoolOp::Null, left: (true,Box::new(expr)), right: None }
+	}
+
+	pub fn from_rule(pair: Pair<Rule>) -> Self {
+		// we do a little hacking
+		let inner = if matches!(pair.as_rule(), Rule::func_call | Rule::var_ident | Rule::var_index | Rule::range | Rule::range_inclusive | Rule::bin_expr | Rule::bin_lit | Rule::bool_lit | Rule::bool_expr_single | Rule::bool_expr | Rule::int | Rule::null) {
+			pair
+		} else {
+			let rule = format!("{:?}",pair.as_rule());
+			let inner_check = pair.clone();
+			if inner_check.into_inner().next().is_none() {
+				pair
+			} else {
+				pair.into_inner().next().unwrap_or_else(|| panic!("Unwrapped nothing on rule: {rule}"))
+			}
+		};
+		match inner.as_rule() {
+			Rule::return_cmd => {
+				let return_cmd = inner.into_inner().next().unwrap()
+					.into_inner().next().unwrap()
+					.into_inner().next().unwrap();
+				let return_cmd = return_cmd.as_str().to_string();
+				Self::Return(return_cmd)
+			}
+			Rule::value => {
+				Self::from_rule(inner)
+			}
+			Rule::bool => {
+				let bool = inner.into_inner().next().unwrap();
+				match bool.as_rule() {
+					Rule::true_lit => Self::Bool(true),
+					Rule::false_lit => Self::Bool(false),
+					_ => unreachable!("Unexpected rule in bool: {:?}", bool.as_rule()),
+				}
+			}
+			Rule::literal => {
+				let literal = inner.into_inner().next().unwrap()
+					.as_str().to_string();
+				Self::Literal(literal)
+			}
+			Rule::null => {
+				Self::Null
+			}
+			Rule::var => {
+				let var = inner.into_inner().next().unwrap();
+				Self::from_rule(var)
+			}
+			Rule::var_name => {
+				let var = inner.into_inner().next().unwrap();
+				Self::from_rule(var)
+			}
+			Rule::var_index => {
+				let mut inner = inner.into_inner();
+				let var_name = inner.next().unwrap()
+					.as_str().to_string();
+				let index = inner.next().unwrap();
+				if index.as_rule() == Rule::slice {
+					let mut start = None;
+					let mut end = None;
+					let mut inclusive = false;
+					for bound in index.into_inner() {
+						match bound.as_rule() {
+							Rule::slice_start => start = Some(Box::new(Self::from_rule(bound))),
+							Rule::slice_end => end = Some(Box::new(Self::from_rule(bound))),
+							Rule::slice_incl => inclusive = true,
+							_ => unreachable!()
+						}
+					}
+					return Self::VarSlice { name: var_name, start, end, inclusive }
+				}
+				let index = Self::from_rule(index);
+				Self::VarIndex(var_name,Box::new(index))
+			}
+			Rule::var_ident => {
+				let var = inner.as_str().to_string();
+				Self::Var(var)
+			}
+			Rule::range_inclusive |
+			Rule::range => {
+				let is_inclusive = matches!(inner.as_rule(), Rule::range_inclusive);
+				let mut inner = inner.into_inner();
+				let start = inner.next().unwrap();
+				let start = Self::from_rule(start);
+				let end = inner.next().unwrap();
+				let end = Self::from_rule(end);
+				if is_inclusive {
+					Self::RangeInclusive(Box::new(start),Box::new(end))
+				} else {
+					Self::Range(Box::new(start),Box::new(end))
+				}
+			}
+			Rule::register => {
+				let reg = inner.into_inner().next().unwrap()
+					.as_str().chars().next().unwrap();
+				Self::Register(reg)
+			}
+			Rule::array => {
+				let inner = inner.into_inner();
+				let mut elements = vec![];
+				for elem in inner {
+					elements.push(Self::from_rule(elem));
+				}
+				Self::Array(elements)
+			}
+			Rule::int => {
+				let int = inner.as_str().parse::<i64>().unwrap();
+				Self::Int(int)
+			}
+			Rule::bool_lit => {
+				// This can be a nested boolean expression
+				let mut lit = inner.into_inner();
+				let first = lit.next().unwrap();
+				if first.as_rule() == Rule::not {
+					Self::negated(Self::from_rule(lit.next().unwrap()))
+				} else {
+					Self::from_rule(first)
+				}
+			}
+			Rule::true_lit => {
+				Self::Bool(true)
+			}
+			Rule::false_lit => {
+				Self::Bool(false)
+			}
+			Rule::pop_cmd => {
+				let stack_name = inner.into_inner().next().unwrap()
+					.into_inner().next().unwrap()
+					.as_str().to_string();
+				Self::Pop(stack_name)
+			}
+			Rule::bin_atom => {
+				let inner = inner.into_inner().next().unwrap();
+				Self::from_rule(inner)
+			}
+			Rule::bin_lit => {
+				let mut lit = inner.into_inner();
+				let first = lit.next().unwrap();
+				if first.as_rule() != Rule::unary_minus {
+					return Self::from_rule(first)
+				}
+				match Self::from_rule(lit.next().unwrap()) {
+					Self::Int(int) => Self::Int(-int),
+					Self::Float(float) => Self::Float(-float),
+					operand => Self::BinExp { op: BinOp::Sub, left: Box::new(Self::Int(0)), right: Box::new(operand) }
+				}
+			}
+			Rule::float => {
+				let float = inner.as_str().parse::<f64>().unwrap();
+				Self::Float(float)
+			}
+			Rule::regex_lit => {
+				let regex = inner.into_inner().next().unwrap()
+					.as_str().to_string();
+				Self::Regex(regex)
+			}
+			Rule::func_call => {
+				let mut inner = inner.into_inner();
+				let func_name = inner.next().unwrap().as_str().to_string();
+				let pair_args = inner.next().unwrap().into_inner();
+				let mut args = vec![];
+				for arg in pair_args {
+					args.push(Self::from_rule(arg));
+				}
+				Self::FuncCall(func_name,args)
+			}
+			Rule::ternary => {
+				let mut inner = inner.into_inner();
+				let cond_pair = inner.next().unwrap()
+					.into_inner().next().unwrap();
+				let true_case_pair = inner.next().unwrap()
+					.into_inner().next().unwrap();
+				let false_case_pair = inner.next().unwrap()
+					.into_inner().next().unwrap();
+
+				let cond = Self::from_rule(cond_pair);
+				let true_case = Self::from_rule(true_case_pair);
+				let false_case = Self::from_rule(false_case_pair);
+
+				Self::TernaryExp {
+					cond: (false,Box::new(cond)),
+					true_case: Box::new(true_case),
+					false_case: Box::new(false_case),
+				}
+			}
+			Rule::buf_cmd => {
+				let mut inner = inner.into_inner();
+				let cmd = inner.next().unwrap();
+				match cmd.as_rule() {
+					Rule::buf_switch => {
+						let buf_id = cmd.into_inner().next().unwrap();
+						let buf_id = Self::from_rule(buf_id);
+						Self::SwitchBuf(Box::new(buf_id))
+					}
+					Rule::buf_id => {
+						Self::GetBufId
+					}
+					_ => unreachable!("Unexpected rule in buf_cmd: {:?}", cmd.as_rule()),
+				}
+			}
+			Rule::bin_expr => {
+				let mut expr = inner.into_inner();
+				let left_pair = expr.next().unwrap().into_inner().next().unwrap();
+				let mut left = Self::from_rule(left_pair);
+
+				while let Some(op_pair) = expr.next() {
+					let op = BinOp::bin_op_from_rule(op_pair);
+					let right_pair = expr.next().unwrap().into_inner().next().unwrap();
+					let right = Self::from_rule(right_pair);
+					left = Self::BinExp { op, left: Box::new(left), right: Box::new(right) };
+				}
+
+				left
+			}
+
+			Rule::bool_expr_single => {
+				let mut expr = inner.into_inner();
+				let first = expr.next().unwrap();
+				match first.as_rule() {
+					Rule::not => {
+						let operand = Self::from_rule(expr.next().unwrap());
+						Self::negated(operand)
+					}
+					Rule::bool_lit => {
+						let left = Self::from_rule(first);
+						let op = BoolOp::bool_op_from_rule(expr.next().unwrap());
+						let right = Self::from_rule(expr.next().unwrap());
+						Self::BoolExp { op, left: (false,Box::new(left)), right: Some((false,Box::new(right))) }
+					}
+					_ => Self::from_rule(first)
+				}
+			}
+			Rule::bool_expr => {
+				// Collect the operands of each '||', where each operand is a chain of '&&'
+				let mut expr = inner.into_inner();
+				let mut or_operands = vec![];
+				let mut current = Self::from_rule(expr.next().unwrap());
+				while let Some(op_pair) = expr.next() {
+					let op = BoolOp::bool_op_from_rule(op_pair);
+					let right = Self::from_rule(expr.next().unwrap());
+					match op {
+						BoolOp::And => {
+							current = Self::BoolExp { op, left: (false,Box::new(current)), right: Some((false,Box::new(right))) };
+						}
+						_ => {
+							or_operands.push(std::mem::replace(&mut current, right));
+						}
+					}
+				}
+				for left in or_operands.into_iter().rev() {
+					current = Self::BoolExp { op: BoolOp::Or, left: (false,Box::new(left)), right: Some((false,Box::new(current))) };
+				}
+				current
+			}
+			Rule::expr => {
+				// We didn't unwrap this before getting here
+				// So let's descend further
+				let inner = inner.into_inner().next().unwrap();
+				Self::from_rule(inner)
+			}
+			_ => unreachable!("Unexpected rule in expr: {:?}", inner.as_rule()),
+		}
+	}
+}
+
+#[derive(Parser)]
+#[grammar = "vic/vic.pest"] // relative to src
+pub struct VicParser;
+
+/// Make sure that every integer literal fits in an `i64`, since the grammar allows any number of digits.
+///
+/// The rest of the parser can then unwrap them freely.
+fn check_int_literals(pairs: &Pairs<Rule>) -> Result<(), String> {
+	for pair in pairs.clone().flatten() {
+		if pair.as_rule() == Rule::int && pair.as_str().parse::<i64>().is_err() {
+			let message = format!("integer literal '{}' is too large", pair.as_str());
+			return Err(Error::new_from_span(ErrorVariant::<Rule>::CustomError { message }, pair.as_span()).to_string())
+		}
+	}
+	Ok(())
+}
+
+/// Parse a lone expression, like the one given to the `"=` register
+pub fn parse_expr(input: &str) -> Result<Expr, String> {
+	let mut pairs = VicParser::parse(Rule::expr_only, input)
+		.map_err(|e| format!("error parsing expression '{input}': {e}"))?;
+	check_int_literals(&pairs).map_err(|e| format!("error parsing expression '{input}': {e}"))?;
+	let expr = pairs
+		.next().unwrap()
+		.into_inner().next().unwrap();
+	Ok(Expr::from_rule(expr))
+}
+
+pub fn parse_vic(input: &str) -> Result<Opts, String> {
+	let mut pairs = VicParser::parse(Rule::vic, input)
+		.map_err(|e| format!("vicut: error parsing vic script: {e}"))?;
+	check_int_literals(&pairs).map_err(|e| format!("vicut: error parsing vic script: {e}"))?;
+	let pairs = pairs.next().unwrap().into_inner();
+	let mut opts = Opts::default();
+
+	for pair in pairs {
+		match pair.as_rule() {
+			Rule::prelude => {
+				let prelude_opts = pair.into_inner().next().unwrap();
+				for pair in prelude_opts.into_inner() {
+					let pair = pair.into_inner().next().unwrap();
+					match pair.as_rule() {
+						Rule::json => opts.json = true,
+						Rule::json_input => opts.json_input = true,
+						Rule::json_field => {
+							let field = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.json_field = Some(field.as_str().to_string());
+						}
+						Rule::linewise => opts.linewise = true,
+						Rule::stream => opts.stream = true,
+						Rule::read0 => opts.read0 = true,
+						Rule::print0 => opts.print0 = true,
+						Rule::trim_fields => opts.trim_fields = true,
+						Rule::serial => opts.single_thread = true,
+						Rule::shared_registers => opts.shared_registers = true,
+						Rule::deterministic => opts.deterministic = true,
+						Rule::stats => opts.stats = true,
+						Rule::keep_mode => opts.keep_mode = true,
+						Rule::backup => opts.backup_files = true,
+						Rule::wait => opts.wait_for_lock = true,
+						Rule::cache => {
+							let path = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.cache = Some(PathBuf::from(path.as_str()));
+						}
+						Rule::files_from => {
+							let list = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							for file in crate::read_file_list(list.as_str())? {
+								opts.files.push(PathBuf::from(file));
+							}
+							opts.files_from = Some(PathBuf::from(list.as_str()));
+						}
+						Rule::no_clobber => opts.no_clobber = true,
+						Rule::edit_inplace => opts.edit_inplace = true,
+						Rule::dry_run => {
+							opts.edit_inplace = true;
+							opts.dry_run = true;
+						}
+						Rule::patch => {
+							opts.edit_inplace = true;
+							opts.dry_run = true;
+							opts.patch = true;
+						}
+						Rule::recursive => opts.recursive = true,
+						Rule::hidden => opts.hidden = true,
+						Rule::text => opts.text = true,
+						Rule::max_filesize => {
+							let size = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.max_filesize = Some(crate::parse_filesize(size.as_str())?);
+						}
+						Rule::trace => opts.trace = true,
+						Rule::silent => opts.silent = true,
+						Rule::no_input => opts.no_input = true,
+						Rule::capture_line => opts.capture_line = true,
+						Rule::capture_word => opts.capture_word = true,
+						Rule::capture_lineno => opts.capture_lineno = true,
+						Rule::sandbox => opts.sandbox = true,
+						Rule::no_shell => opts.no_shell = true,
+						Rule::dump_state => opts.dump_state = true,
+						Rule::emit_schema => opts.emit_schema = true,
+						Rule::pick => opts.pick = true,
+						Rule::record_per_match => opts.record_per_match = true,
+						Rule::max_jobs => {
+							let max_jobs = pair.into_inner().next().unwrap();
+							opts.max_jobs = Some(crate::parse_jobs(max_jobs.as_str())?);
+						}
+						Rule::shell_timeout => {
+							let timeout = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.shell_timeout = Some(crate::parse_timeout(timeout.as_str())?);
+						}
+						Rule::regex_timeout => {
+							let timeout = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.regex_timeout = Some(crate::parse_timeout(timeout.as_str())?);
+						}
+						Rule::regex_size_limit => {
+							let limit = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.regex_size_limit = Some(crate::parse_size_limit(limit.as_str())?);
+						}
+						Rule::shell_cwd => {
+							let cwd = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.shell_cwd = Some(crate::shell::ShellCwd::from(cwd.as_str()));
+						}
+						Rule::shell => {
+							let shell = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.shell = Some(crate::parse_shell(shell.as_str())?);
+						}
+						Rule::equalprg => {
+							let equalprg = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.equalprg = Some(equalprg.as_str().to_string());
+						}
+						Rule::git => {
+							let selection = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.git = Some(crate::GitSelection::try_from(selection.as_str())?);
+						}
+						Rule::eol => {
+							let eol = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.eol = Some(Eol::try_from(eol.as_str())?);
+						}
+						Rule::flush => {
+							let flush = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.flush = Some(crate::FlushPolicy::try_from(flush.as_str())?);
+						}
+						Rule::on_interrupt => {
+							let policy = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.on_interrupt = crate::interrupt::InterruptPolicy::try_from(policy.as_str())?;
+						}
+						Rule::bom => {
+							let bom = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.strip_bom = crate::parse_bom_arg(bom.as_str())?;
+						}
+						Rule::g_order => {
+							let order = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.g_order = crate::GOrder::try_from(order.as_str())?;
+						}
+						Rule::units => {
+							let units = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.buf_settings.units = crate::linebuf::TextUnit::try_from(units.as_str())?;
+						}
+						Rule::textwidth => {
+							let width = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.buf_settings.textwidth = Some(crate::parse_textwidth(width.as_str())?);
+						}
+						Rule::window_height => {
+							let height = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.buf_settings.window_height = Some(crate::parse_window_height(height.as_str())?);
+						}
+						Rule::fold_method => {
+							let method = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.buf_settings.fold_method = crate::linebuf::FoldMethod::try_from(method.as_str())?;
+						}
+						Rule::sub_syntax => {
+							let syntax = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.buf_settings.sub_syntax = crate::linebuf::SubSyntax::try_from(syntax.as_str())?;
+						}
+						Rule::kv_separators => {
+							let separators = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.buf_settings.kv_separators = Some(crate::parse_kv_separators(separators.as_str())?);
+						}
+						Rule::string_escape => {
+							let escape = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.buf_settings.string_escape = Some(crate::parse_string_escape(escape.as_str())?);
+						}
+						Rule::record_sep => {
+							let pattern = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.record_sep = Some(crate::parse_record_sep(pattern.as_str())?);
+						}
+						Rule::fields_by => {
+							let pattern = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.fields_by = Some(crate::parse_fields_by(pattern.as_str())?);
+						}
+						Rule::format => {
+							let format = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.format = Some(crate::OutputFormat::try_from(format.as_str())?);
+						}
+						Rule::freq => {
+							let count = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.freq = Some(crate::parse_freq_count(count.as_str())?);
+						}
+						Rule::freq_pattern => {
+							let pattern = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.freq_pattern = Some(crate::parse_freq_pattern(pattern.as_str())?);
+						}
+						Rule::verify_roundtrip => {
+							let checks = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.verify_roundtrip = crate::RoundtripCheck::parse_list(checks.as_str())?;
+						}
+						Rule::delimiter => {
+							let delimiter = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.delimiter = Some(delimiter.as_str().to_string());
+						}
+						Rule::template => {
+							let template = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.template = Some(template.as_str().to_string());
+						}
+						Rule::record_assert => {
+							let template = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.asserts.push(template.as_str().to_string());
+						}
+						Rule::file => {
+							let file = pair.into_inner().next().unwrap()
+								.as_str().to_string();
+
+							if file == crate::STDIN_PATH {
+								opts.files.push(PathBuf::from(file));
+								continue
+							}
+							for entry in glob::glob(&file).unwrap() {
+								match entry {
+									Ok(path) => opts.files.push(path),
+									Err(e) => {
+										eprintln!("vicut: error resolving file path: {e}");
+										std::process::exit(1);
+									}
+								}
+							}
+						}
+						Rule::files => {
+							let files = pair.into_inner();
+							for file in files {
+								let file = file.as_str().to_string();
+
+								if file == crate::STDIN_PATH {
+									opts.files.push(PathBuf::from(file));
+									continue
+								}
+								for entry in glob::glob(&file).unwrap() {
+									match entry {
+										Ok(path) => opts.files.push(path),
+										Err(e) => {
+											eprintln!("vicut: error resolving file path: {e}");
+											std::process::exit(1);
+										}
+									}
+								}
+							}
+						}
+						Rule::backup_ext => {
+							let ext = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.backup_extension = Some(ext.as_str().to_string());
+						}
+						Rule::out_template => {
+							let template = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.out_template = Some(crate::parse_out_template(template.as_str())?);
+						}
+						Rule::pipe_in => {
+							let pipe_in = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.pipe_in = Some(pipe_in.as_str().to_string());
+						}
+						Rule::pipe_out => {
+							let pipe_out = pair.into_inner().next().unwrap()
+								.into_inner().next().unwrap();
+							opts.pipe_out = Some(pipe_out.as_str().to_string());
+						}
+						Rule::write => {
+							let write = pair.into_inner().next().unwrap();
+							opts.out_file = Some(PathBuf::from(write.as_str().to_string()));
+						}
+
+						_ => unreachable!("Unexpected rule in prelude: {:?}", pair.as_rule()),
+					}
+				}
+			}
+			Rule::cmd => parse_cmd(&mut opts.cmds, pair),
+			Rule::EOI => {
+				// End of input
+			}
+			_ => unreachable!("Unexpected rule in vic: {:?}", pair.as_rule()),
+		}
+	}
+	Ok(opts)
+}
+
+fn parse_cmd(cmds: &mut Vec<Cmd>, pair: Pair<Rule>) {
+	for pair in pair.into_inner() {
+		match pair.as_rule() {
+			Rule::include => {
+				let include_cmds = include_cmd(pair);
+				cmds.extend(include_cmds);
+			}
+			Rule::global_cmd => {
+				let cmd = parse_global(pair,true);
+				cmds.push(cmd);
+			}
+			Rule::not_global_cmd => {
+				let cmd = parse_global(pair,false);
+				cmds.push(cmd);
+			}
+			Rule::repeat_cmd => {
+				let repeat_cmd = parse_repeat(pair);
+				cmds.push(repeat_cmd);
+			}
+			Rule::normal_cmd => {
+				let mut args = pair.into_inner()
+					.map(|arg| CmdArg::Expr(Expr::from_rule(arg)))
+					.collect::<Vec<_>>();
+				let keys = args.pop().unwrap();
+				let range = match (args.first(), args.get(1)) {
+					(Some(start), Some(end)) => Some((start.clone(), end.clone())),
+					_ => None
+				};
+				cmds.push(Cmd::Normal { range, keys });
+			}
+			Rule::cut_cmd => {
+				let cut_cmd = parse_argument(pair.into_inner().next().unwrap());
+				let cmd = Cmd::Field(cut_cmd);
+				cmds.push(cmd);
+			}
+			Rule::move_cmd => {
+				let move_cmd = parse_argument(pair.into_inner().next().unwrap());
+				let cmd = Cmd::Motion(move_cmd);
+				cmds.push(cmd);
+			}
+			Rule::push_cmd => {
+				let mut inner = pair.into_inner();
+				let stack_name = inner.next().unwrap()
+					.into_inner().next().unwrap()
+					.into_inner().next().unwrap()
+					.as_str().to_string();
+				let expr = inner.next().unwrap();
+				let expr = Expr::from_rule(expr);
+				let cmd = Cmd::Push(CmdArg::Var(stack_name), CmdArg::Expr(expr));
+				cmds.push(cmd);
+			}
+			Rule::pop_cmd => {
+				let mut inner = pair.into_inner();
+				let stack_name = inner.next().unwrap()
+					.into_inner().next().unwrap()
+					.into_inner().next().unwrap()
+					.as_str().to_string();
+				let cmd = Cmd::Pop(CmdArg::Var(stack_name));
+				cmds.push(cmd);
+			}
+			Rule::return_cmd => {
+				if let Some(cmd) = pair.into_inner().next() {
+					let return_cmd = Expr::from_rule(cmd);
+					let cmd = Cmd::Return(CmdArg::Expr(return_cmd));
+					cmds.push(cmd);
+				} else {
+					// If there is no return command, we just push an empty return
+					cmds.push(Cmd::Return(CmdArg::Null));
+				}
+			}
+			Rule::yank_cmd => {
+				let mut inner = pair.into_inner();
+				let register = inner.next().unwrap()
+					.into_inner().next().unwrap()
+					.as_str().chars().next().unwrap();
+				let expr = inner.next().unwrap();
+				let expr = Expr::from_rule(expr);
+				let cmd = Cmd::Yank(CmdArg::Expr(expr), register);
+				cmds.push(cmd);
+			}
+			Rule::next => {
+				cmds.push(Cmd::BreakGroup);
+			}
+			Rule::break_loop => {
+				cmds.push(Cmd::LoopBreak);
+			}
+			Rule::continue_loop => {
+				cmds.push(Cmd::LoopContinue);
+			}
+			Rule::echo_cmd => {
+				let inner = pair.into_inner();
+				let mut echo_args = vec![];
+				let mut append_to = None;
+				for arg in inner {
+					if arg.as_rule() == Rule::echo_append {
+						let var_name = arg.into_inner().next().unwrap().as_str().to_string();
+						append_to = Some(var_name);
+						continue
+					}
+					let arg = CmdArg::Expr(Expr::from_rule(arg));
+					echo_args.push(arg);
+				}
+				let cmd = match append_to {
+					Some(var_name) => Cmd::EchoAppend(echo_args, var_name),
+					None => Cmd::Echo(echo_args)
+				};
+				cmds.push(cmd);
+			}
+			Rule::echoerr_cmd => {
+				let echo_args = pair.into_inner()
+					.map(|arg| CmdArg::Expr(Expr::from_rule(arg)))
+					.collect();
+				cmds.push(Cmd::EchoErr(echo_args));
+			}
+			Rule::func_def => {
+				let mut inner = pair.into_inner();
+				let mut args = vec![];
+				let mut name_and_args = inner.next().unwrap().into_inner();
+				let name = name_and_args.next().unwrap().as_str().to_string();
+				let arg_list = name_and_args.next().unwrap().into_inner();
+				for arg in arg_list {
+					let arg = arg.as_str().to_string();
+					args.push(arg);
+				}
+
+				let block = inner.next().unwrap();
+				let body = parse_block(block);
+				let cmd = Cmd::FuncDef { name, args, body };
+				cmds.push(cmd);
+			}
+			Rule::for_block => {
+				let mut inner = pair.into_inner();
+				let var_pair = inner.next().unwrap();
+				let (index_var, var_name) = if var_pair.as_rule() == Rule::for_pair {
+					// for (i, item) in ...
+					let mut names = var_pair.into_inner();
+					let index_var = names.next().unwrap().as_str().to_string();
+					let var_name = names.next().unwrap().as_str().to_string();
+					(Some(index_var), var_name)
+				} else {
+					(None, var_pair.as_str().to_string())
+				};
+				let range_pair = inner.next().unwrap();
+				let iterable = Expr::from_rule(range_pair);
+				let iterable = CmdArg::Expr(iterable);
+				let block = inner.next().unwrap();
+				let body = parse_block(block);
+				let cmd = Cmd::ForBlock { index_var, var_name, iterable, body };
+				cmds.push(cmd);
+			}
+			Rule::func_call => {
+				let mut inner = pair.into_inner();
+				let func_name = inner.next().unwrap().as_str().to_string();
+				let pair_args = inner.next().unwrap().into_inner();
+				let mut args = vec![];
+				for arg in pair_args {
+					args.push(CmdArg::Expr(Expr::from_rule(arg)));
+				}
+				cmds.push(Cmd::FuncCall { name: func_name, args });
+			}
+			Rule::until_block |
+			Rule::while_block => {
+				let is_while = pair.as_rule() == Rule::while_block;
+				let mut inner = pair.into_inner();
+				let expr_pair = inner.next().unwrap();
+				let cond = CmdArg::Expr(Expr::from_rule(expr_pair));
+				let block = inner.next().unwrap();
+				let body = parse_block(block);
+				let cmd = if is_while {
+					Cmd::WhileBlock(CondBlock { cond, cmds: body })
+				} else {
+					Cmd::UntilBlock(CondBlock { cond, cmds: body })
+				};
+				cmds.push(cmd);
+			}
+			Rule::buf_cmd => {
+				let mut inner = pair.into_inner();
+				let buf_cmd_kind = inner.next().unwrap();
+				match buf_cmd_kind.as_rule() {
+					Rule::buf_switch => {
+						let expr = buf_cmd_kind.into_inner().next().unwrap();
+						let expr = Expr::from_rule(expr);
+						let cmd = Cmd::SwitchBuf(CmdArg::Expr(expr));
+						cmds.push(cmd);
+					}
+					Rule::buf_id => {
+						let cmd = Cmd::GetBufId;
+						cmds.push(cmd);
+					}
+					_ => unreachable!()
+				}
+			}
+			Rule::if_block => {
+				let mut inner = pair.into_inner();
+				let expr_pair = inner.next().unwrap();
+				let cond = CmdArg::Expr(Expr::from_rule(expr_pair));
+				let block = inner.next().unwrap();
+				let mut cond_blocks = vec![CondBlock { cond, cmds: parse_block(block) }];
+				let mut else_block = None;
+				while let Some(block) = inner.next() {
+					match block.as_rule() {
+						Rule::elif_block => {
+							let mut inner = block.into_inner();
+							let expr_pair = inner.next().unwrap();
+							let cond = CmdArg::Expr(Expr::from_rule(expr_pair));
+							let block = inner.next().unwrap();
+							cond_blocks.push(CondBlock { cond, cmds: parse_block(block) });
+						}
+						Rule::else_block => {
+							let block = block.into_inner().next().unwrap();
+							let else_cmds = parse_block(block);
+							else_block = Some(else_cmds);
+						}
+						_ => unreachable!("Unexpected rule in if_block: {:?}", block.as_rule()),
+					}
+				}
+
+				let cmd = Cmd::IfBlock {
+					cond_blocks,
+					else_block,
+				};
+				cmds.push(cmd);
+			}
+			Rule::match_block => {
+				let mut inner = pair.into_inner();
+				let value = CmdArg::Expr(Expr::from_rule(inner.next().unwrap()));
+				let mut arms = vec![];
+				for arm in inner {
+					let mut patterns = vec![];
+					let mut block = None;
+					for part in arm.into_inner() {
+						match part.as_rule() {
+							Rule::match_any => {}
+							Rule::value => patterns.push(CmdArg::Expr(Expr::from_rule(part))),
+							Rule::block => block = Some(part),
+							_ => unreachable!("Unexpected rule in match_arm: {:?}", part.as_rule()),
+						}
+					}
+					arms.push(MatchArm { patterns, cmds: parse_block(block.unwrap()) });
+				}
+				cmds.push(Cmd::MatchBlock { value, arms });
+			}
+			Rule::when_block => {
+				// 'when filetype == "json" { ... }' is shorthand for 'if $filetype == "json" { ... }'
+				let mut inner = pair.into_inner();
+				let var_name = inner.next().unwrap().as_str().to_string();
+				let op = BoolOp::bool_op_from_rule(inner.next().unwrap());
+				let value = Expr::from_rule(inner.next().unwrap());
+				let block = inner.next().unwrap();
+				let cond = CmdArg::Expr(Expr::BoolExp {
+					op,
+					left: (false, Box::new(Expr::Var(var_name))),
+					right: Some((false, Box::new(value))),
+				});
+				let cmd = Cmd::IfBlock {
+					cond_blocks: vec![CondBlock { cond, cmds: parse_block(block) }],
+					else_block: None,
+				};
+				cmds.push(cmd);
+			}
+			Rule::const_declare => {
+				let mut inner = pair.into_inner();
+				let name = inner.next().unwrap().as_str().to_string();
+				let value = CmdArg::Expr(Expr::from_rule(inner.next().unwrap()));
+				cmds.push(Cmd::ConstDec { name, value });
+			}
+			Rule::var_add |
+			Rule::var_sub |
+			Rule::var_mult|
+			Rule::var_div |
+			Rule::var_mod |
+			Rule::var_pow |
+			Rule::var_mut |
+			Rule::var_declare => {
+				let cmd = parse_var_cmd(pair);
+				cmds.push(cmd);
+			}
+			_ => unreachable!("Unexpected rule in cmd: {:?}", pair.as_rule()),
+		}
+	}
+}
+
+fn parse_block(pair: Pair<Rule>) -> Vec<Cmd> {
+	let mut cmds = vec![];
+	for cmd in pair.into_inner() {
+		parse_cmd(&mut cmds, cmd);
+	}
+	cmds
+}
+
+fn parse_var_cmd(pair: Pair<Rule>) -> Cmd {
+	match pair.as_rule() {
+		Rule::var_add |
+		Rule::var_sub |
+		Rule::var_mult|
+		Rule::var_div |
+		Rule::var_mod |
+		Rule::var_mut |
+		Rule::var_pow => {
+			let op = match pair.as_rule() {
+				Rule::var_mut => BinOp::Equals,
+				Rule::var_add => BinOp::Add,
+				Rule::var_sub => BinOp::Sub,
+				Rule::var_mult => BinOp::Mult,
+				Rule::var_div => BinOp::Div,
+				Rule::var_mod => BinOp::Mod,
+				Rule::var_pow => BinOp::Pow,
+				_ => unreachable!("Unexpected rule in var_cmd: {:?}", pair.as_rule()),
+			};
+			let mut inner = pair.into_inner();
+			let mut name_parts = inner.next().unwrap().into_inner();
+			let first_part = name_parts.next().unwrap();
+			let (name, index) = match first_part.as_rule() {
+				Rule::var_ident => {
+					let name = first_part.as_str().to_string();
+					(name, None)
+				}
+				Rule::var_index => {
+					let mut inner = first_part.into_inner();
+					let name = inner.next().unwrap().as_str().to_string();
+					let index = inner.next().unwrap();
+					let index = Expr::from_rule(index);
+					(name, Some(CmdArg::Expr(index)))
+				}
+				_ => unreachable!("Unexpected rule in var_cmd: {:?}", first_part.as_rule()),
+
+			};
+			let expr_pair = inner.next().unwrap();
+			let exp = Expr::from_rule(expr_pair);
+			Cmd::MutateVar { name, index, op, value: CmdArg::Expr(exp) }
+		}
+		Rule::var_declare => {
+			let mut inner = pair.into_inner();
+			let name_pair = inner.next().unwrap();
+			let expr_pair = inner.next().unwrap();
+			let exp = Expr::from_rule(expr_pair);
+			if name_pair.as_rule() == Rule::var_tuple {
+				let names = name_pair.into_inner()
+					.map(|name| name.as_str().to_string())
+					.collect();
+				return Cmd::VarDecTuple { names, value: CmdArg::Expr(exp) }
+			}
+			let name = name_pair.as_str().to_string();
+			Cmd::VarDec { name, value: CmdArg::Expr(exp) }
+		}
+		_ => unreachable!("Unexpected rule in var_cmd: {:?}", pair.as_rule()),
+	}
+}
+
+fn parse_global(pair: Pair<Rule>, polarity: bool) -> Cmd {
+	let mut inner = pair.into_inner();
+	let mut limit = None;
+	let mut context = None;
+	let first = inner.next().unwrap();
+	let pattern = match first.as_rule() {
+		Rule::global_args => {
+			let mut args = first.into_inner();
+			let pattern = parse_argument(args.next().unwrap());
+			for arg in args {
+				let rule = arg.as_rule();
+				let count = parse_count(arg.into_inner().next().unwrap());
+				match rule {
+					Rule::global_limit => limit = Some(count),
+					Rule::global_context => context = Some(count),
+					_ => unreachable!("Unexpected rule in global_args: {rule:?}")
+				}
+			}
+			pattern
+		}
+		_ => parse_argument(first)
+	};
+	let block = inner.next().unwrap().into_inner();
+	let mut then_cmds = vec![];
+	let mut else_cmds = None;
+	for cmd in block {
+		parse_cmd(&mut then_cmds, cmd);
+	}
+
+	if let Some(else_block) = inner.next() {
+		let mut else_block_cmds = vec![];
+		for cmd in else_block.into_inner() {
+			parse_cmd(&mut else_block_cmds, cmd);
+		}
+		else_cmds = Some(else_block_cmds);
+	}
+
+	Cmd::Global { pattern, then_cmds, else_cmds, polarity, limit, context }
+}
+
+fn parse_repeat(pair: Pair<Rule>) -> Cmd {
+	let mut body = vec![];
+	let mut inner = pair.into_inner();
+	let repeat_count = parse_count(inner.next().unwrap());
+
+	let block = inner.next().unwrap().into_inner();
+	for cmd in block {
+		parse_cmd(&mut body, cmd);
+	}
+
+	Cmd::Repeat{ body, count: repeat_count }
+}
+
+fn parse_count(pair: Pair<Rule>) -> CmdArg {
+	match pair.as_rule() {
+		Rule::int => {
+			CmdArg::Count(pair.as_str().parse::<usize>().unwrap())
+		}
+		Rule::var => {
+			let var = pair.into_inner().next().unwrap();
+			CmdArg::Var(var.as_str().to_string())
+		}
+		_ => unreachable!("Unexpected rule in count: {:?}", pair.as_rule()),
+	}
+}
+
+fn parse_argument(pair: Pair<Rule>) -> CmdArg {
+	let pair = pair.into_inner().next().unwrap();
+	match pair.as_rule() {
+		Rule::var => {
+			let var = pair.into_inner().next().unwrap();
+			CmdArg::Var(var.as_str().to_string())
+		}
+		Rule::literal => {
+			let literal = pair.into_inner().next().unwrap();
+			CmdArg::Literal(Val::Str(literal.as_str().to_string()))
+		}
+		Rule::expr => {
+			let expr = Expr::from_rule(pair);
+			CmdArg::Expr(expr)
+		}
+		_ => unreachable!("Unexpected rule in argument: {:?}", pair.as_rule()),
+	}
+}
+
+fn include_cmd(pair: Pair<Rule>) -> Vec<Cmd> {
+	let mut cmds = vec![];
+	let mut inner = pair.into_inner();
+	let file_pair = inner.next().unwrap();
+	let file = file_pair.into_inner().next().unwrap()
+		.as_str().to_string();
+
+	let file_content = std::fs::read_to_string(&file)
+		.unwrap_or_else(|_| panic!("vicut: error reading included file '{file}'"));
+
+	let included_cmds = parse_vic(&file_content).unwrap_or_else(complain_and_exit);
+
+	cmds.extend(included_cmds.cmds);
+	cmds
+}
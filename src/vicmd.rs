@@ -6,7 +6,7 @@ use std::path::PathBuf;
 
 use bitflags::bitflags;
 
-use crate::{exec::Val, linebuf::SelectRange, modes::ex::SubFlags, register::{RegisterContent, REGISTERS}};
+use crate::{exec::Val, linebuf::SelectRange, modes::ex::SubFlags, register::{with_registers, RegisterContent}};
 
 use super::register::{append_register, read_register, write_register};
 
@@ -43,13 +43,13 @@ impl RegisterName {
 		self.count
 	}
 	pub fn is_line(&self) -> bool {
-		REGISTERS.with_borrow(|reg| reg.get_reg(self.name).is_some_and(|r| r.is_line()))
+		with_registers(|reg| reg.get_reg(self.name).is_some_and(|r| r.is_line()))
 	}
 	pub fn is_block(&self) -> bool {
-		REGISTERS.with_borrow(|reg| reg.get_reg(self.name).is_some_and(|r| r.is_block()))
+		with_registers(|reg| reg.get_reg(self.name).is_some_and(|r| r.is_block()))
 	}
 	pub fn is_span(&self) -> bool {
-		REGISTERS.with_borrow(|reg| reg.get_reg(self.name).is_some_and(|r| r.is_span()))
+		with_registers(|reg| reg.get_reg(self.name).is_some_and(|r| r.is_span()))
 	}
 	pub fn write_to_register(&self, buf: RegisterContent) {
 		if self.append {
@@ -158,6 +158,17 @@ impl ViCmd {
 		self.verb.as_ref().is_some_and(|v| matches!(v.1, Verb::ReplaceCharInplace(_,_) | Verb::ToggleCaseInplace(_))) &&
 		self.motion.is_none()
 	}
+	/// The expression typed after `"=`, which is evaluated into the register before the command runs
+	pub fn expr_register(&self) -> Option<&str> {
+		if self.register.name() != Some('=') {
+			return None
+		}
+		let (_,expr) = self.raw_seq.split_once("\"=")?;
+		expr.split_once('\r').map(|(expr,_)| expr)
+	}
+	pub fn is_macro(&self) -> bool {
+		self.verb.as_ref().is_some_and(|v| matches!(v.1, Verb::RecordMacro(_) | Verb::PlayMacro(_)))
+	}
 	pub fn is_ex_normal(&self) -> bool {
 		self.verb.as_ref().is_some_and(|v| matches!(v.1, Verb::Normal(_)))
 	}
@@ -241,6 +252,7 @@ pub enum Verb {
 	ToggleCaseRange,
 	ToLower,
 	ToUpper,
+	CoerceCase(Case),
 	Complete,
 	CompleteBackward,
 	Undo,
@@ -249,6 +261,8 @@ pub enum Verb {
 	Put(Anchor),
 	/// (old_pat,new_pat,flags)
 	Substitute(String,String,SubFlags),
+	/// (old_pat,new_pat,flags) for ':S', which keeps the case of what it replaces
+	SmartSubstitute(String,String,SubFlags),
 	RepeatSubstitute,
 	RepeatGlobal,
 	ShellCmd(String),
@@ -266,12 +280,27 @@ pub enum Verb {
 	VisualModeBlock,
 	VisualModeSelectLast,
 	SwapVisualAnchor,
+	/// `O` in visual block mode, which goes to the other corner on the same line
+	SwapVisualCorner,
 	JoinLines,
 	InsertChar(char),
 	Insert(String),
+	InsertRegister, // <c-r> in insert mode, types out the register's content
 	Indent,
 	Dedent,
 	Equalize,
+	/// Rewrap lines to `--textwidth`, for `gq`
+	Format,
+	/// `m{a-z}`, remember the cursor position
+	SetMark(char),
+	/// `q{a-z}`, start recording keys into a register
+	RecordMacro(char),
+	/// `@{a-z}`, replay the keys in a register. `@@` replays the last one played.
+	PlayMacro(char),
+	/// `<C-a>`/`<C-x>`, add to the number under or after the cursor. Negative for `<C-x>`.
+	Increment(i64),
+	/// `g<C-a>`/`g<C-x>` in visual mode, add the amount once more for each line the selection covers
+	IncrementProgressive(i64),
 	AcceptLineOrNewline,
 	EndOfFile
 }
@@ -286,6 +315,7 @@ impl Verb {
 			Self::ReplaceCharInplace(_,_) |
 			Self::ToLower |
 			Self::ToUpper |
+			Self::CoerceCase(_) |
 			Self::ToggleCaseRange |
 			Self::ToggleCaseInplace(_) |
 			Self::Put(_) |
@@ -296,7 +326,10 @@ impl Verb {
 			Self::Insert(_) |
 			Self::Indent |
 			Self::Dedent |
-			Self::Equalize
+			Self::Equalize |
+			Self::Format |
+			Self::Increment(_) |
+			Self::IncrementProgressive(_)
 		)
 	}
 	pub fn is_edit(&self) -> bool {
@@ -309,6 +342,7 @@ impl Verb {
 			Self::ToggleCaseInplace(_) |
 			Self::ToLower |
 			Self::ToUpper |
+			Self::CoerceCase(_) |
 			Self::RepeatLast |
 			Self::Put(_) |
 			Self::ReplaceMode |
@@ -316,10 +350,32 @@ impl Verb {
 			Self::JoinLines |
 			Self::InsertChar(_) |
 			Self::Insert(_) |
+			Self::InsertRegister |
 			Self::Rot13 |
+			Self::Format |
+			Self::Increment(_) |
+			Self::IncrementProgressive(_) |
 			Self::EndOfFile
 		)
 	}
+	/// Whether or not this verb reaches outside of the buffer, i.e. by spawning a shell or touching the filesystem
+	pub fn is_external(&self) -> bool {
+		matches!(self,
+			Self::ShellCmd(_) |
+			Self::Read(_) |
+			Self::Write(_) |
+			Self::Equalize
+		)
+	}
+	/// Whether or not this verb spawns `$SHELL`
+	pub fn spawns_shell(&self) -> bool {
+		matches!(self,
+			Self::ShellCmd(_) |
+			Self::Read(ReadSrc::Cmd(_)) |
+			Self::Write(WriteDest::Cmd(_)) |
+			Self::Equalize
+		)
+	}
 	pub fn is_char_insert(&self) -> bool {
 		matches!(self,
 			Self::Change |
@@ -376,6 +432,26 @@ pub enum Motion {
 	RangeInclusive(SelectRange),
 	RepeatMotion,
 	RepeatMotionRev,
+	/// 'H', relative to the virtual window
+	WindowTop,
+	/// 'M', relative to the virtual window
+	WindowMiddle,
+	/// 'L', relative to the virtual window
+	WindowBottom,
+	/// `]n`, `[n` — start of the next or previous number
+	ToNumber(Direction),
+	/// `zj`, `zk` — start of the next fold, end of the previous fold
+	ToFold(Direction),
+	/// Ctrl-E, the cursor only moves if it would leave the window
+	ScrollWindowDown,
+	/// Ctrl-Y, the cursor only moves if it would leave the window
+	ScrollWindowUp,
+	/// `` `a `` — the exact position of a mark
+	ToMark(char),
+	/// `'a` — the first non-blank character on the line of a mark
+	ToMarkLine(char),
+	/// `g,`, `g;` — start of a replacement made by the last `:s`, counting from the first one or back from the last one
+	ToSubChange(Direction),
 
 	// TODO: Not sure how to implement these in a non-interactive way...
 	ScreenLineUp,
@@ -384,6 +460,7 @@ pub enum Motion {
 	ScreenLineDownCharwise,
 	BeginningOfScreenLine,
 	FirstGraphicalOnScreenLine,
+	EndOfScreenLine,
 	HalfOfScreen,
 	HalfOfScreenLineText,
 	Null
@@ -401,6 +478,7 @@ impl Motion {
 			Self::ScreenLineUpCharwise |
 			Self::ScreenLineDownCharwise |
 			Self::ToColumn |
+			Self::HalfOfScreen |
 			Self::TextObj(TextObj::Sentence(_)) |
 			Self::TextObj(TextObj::Paragraph(_)) |
 			Self::CharSearch(Direction::Backward, _, _) |
@@ -408,6 +486,9 @@ impl Motion {
 			Self::ToBrace(_) |
 			Self::ToBracket(_) |
 			Self::ToParen(_) |
+			Self::ToNumber(_) |
+			Self::ToMark(_) |
+			Self::ToSubChange(_) |
 			Self::ScreenLineDown |
 			Self::ScreenLineUp |
 			Self::Range(_)
@@ -469,10 +550,42 @@ pub enum TextObj {
 	/// `it`, `at` — HTML/XML tags
 	Tag(Bound),
 
-	/// Custom user-defined objects maybe?
-	Custom(char),
+	/// `ia`, `aa` — a comma separated argument inside of brackets, `aa` includes one of the commas
+	Arg(Bound),
+
+	/// `in`, `an` — the number under or after the cursor, `an` includes the whitespace around it
+	Number(Bound),
+	/// `iu`, `au` — the URL under or after the cursor
+	Url(Bound),
+	/// `if`, `af` — the filesystem path under or after the cursor
+	Path(Bound),
+	/// `ie`, `ae` — the email address under or after the cursor
+	Email(Bound),
+	/// `iv`, `av` — the value of a `key=value` style pair on the cursor's line, `av` selects the whole pair
+	KeyValue(Bound),
+	/// `ic`, `ac` — the fenced code block around the cursor in Markdown, `ac` includes the fences
+	CodeFence(Bound),
+	/// `ih`, `ah` — the Markdown section the cursor is in, `ah` includes the heading
+	Section(Bound),
+	/// `il`, `al` — the Markdown list item the cursor is in, `al` includes the list marker
+	ListItem(Bound),
+	/// `iq`, `aq` — the string literal under or after the cursor, which may span lines, `aq` includes the delimiters
+	StringLiteral(Bound),
+	/// `ir`, `ar` — the text that the last `:s` put in, under or after the cursor, `ar` spans from the first replacement to the last
+	SubChange(Bound),
+
+	/// `iz`, `az` — the fold around the cursor, see `FoldMethod`
+	Fold(Bound),
+	/// `ii`, `ai` — lines at the same or deeper indentation, `ai` includes the line above
+	Indent(Bound),
+
+	/// `i{key}`, `a{key}` — an object defined in a vic script with `textobj()`, see `CustomTextObj`
+	Custom(char,Bound),
 }
 
+/// The keys that come after `i` or `a` for the built-in text objects. Any other key is a `TextObj::Custom`
+pub const TEXT_OBJ_KEYS: &str = "wWsp\"'`()b{}B[]<>tzianeufvchlqr";
+
 /// The source to read from for ex mode's `:r`
 ///
 /// * `:r <FILE>` -> ReadSrc::File(_)
@@ -504,6 +617,16 @@ pub enum LineAddr {
 	Offset(isize),
 	Pattern(String),
 	PatternRev(String),
+	/// `'a` — the line of a mark. `'<` and `'>` are the first and last line of the visual selection
+	Mark(char),
+	/// `\/`, `\?` — the next or previous line that matches the last search pattern
+	LastSearch(Direction),
+	/// `\&` — the next line that matches the pattern of the last substitution
+	LastSubstitute,
+	/// An address followed by offsets, like `/foo/+2` or `'a-3`
+	Relative(Box<LineAddr>,isize),
+	/// The second address is found starting from the line of the first, for `x;y` and searches like `/foo//bar/`
+	From(Box<LineAddr>,Box<LineAddr>),
 }
 
 /// Word sizes for motions like 'w' and 'B'
@@ -527,6 +650,54 @@ pub enum Bound {
 	Around
 }
 
+/// Identifier styles for the case coercion operators, like `crs`
+///
+/// Each one has a key for the operator, following vim-abolish, and a name for the `to_case()` builtin
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Case {
+	/// `crc`, camelCase
+	Camel,
+	/// `crm` or `crp`, PascalCase
+	Pascal,
+	/// `crs` or `cr_`, snake_case
+	Snake,
+	/// `cru` or `crU`, SCREAMING_SNAKE_CASE
+	Upper,
+	/// `crk` or `cr-`, kebab-case
+	Kebab,
+	/// `crt`, Title Case
+	Title
+}
+
+impl Case {
+	pub fn from_key(key: char) -> Option<Self> {
+		match key {
+			'c' => Some(Self::Camel),
+			'm' | 'p' => Some(Self::Pascal),
+			's' | '_' => Some(Self::Snake),
+			'u' | 'U' => Some(Self::Upper),
+			'k' | '-' => Some(Self::Kebab),
+			't' => Some(Self::Title),
+			_ => None
+		}
+	}
+}
+
+impl TryFrom<&str> for Case {
+	type Error = String;
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value {
+			"camel" => Ok(Self::Camel),
+			"pascal" | "mixed" => Ok(Self::Pascal),
+			"snake" => Ok(Self::Snake),
+			"upper" | "screaming" => Ok(Self::Upper),
+			"kebab" => Ok(Self::Kebab),
+			"title" => Ok(Self::Title),
+			_ => Err(format!("Unknown case '{value}', expected camel, pascal, snake, upper, kebab, or title"))
+		}
+	}
+}
+
 /// Motion Direction
 ///
 /// Used mainly for Motions, but is also repurposed in some places where direction matters for the logic
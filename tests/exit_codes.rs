@@ -0,0 +1,40 @@
+//! Some errors end the process right away, so like the interrupt tests, these run the real binary as a child process.
+
+use std::{io::Write, process::{Command, Output, Stdio}};
+
+fn run_vicut(input: &str, args: &[&str]) -> Output {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_vicut"))
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+	child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+	child.wait_with_output().unwrap()
+}
+
+#[test]
+fn bad_smart_substitute_pattern() {
+	let result = run_vicut("abc\n", &["-m", ":S/a{b/x/<CR>"]);
+	assert_eq!(result.status.code(), Some(1));
+	assert!(String::from_utf8_lossy(&result.stderr).contains("Unclosed brace"));
+
+	// The same as a bad pattern for ':s'
+	let result = run_vicut("abc\n", &["-m", ":s/a(/x/<CR>"]);
+	assert_eq!(result.status.code(), Some(1));
+}
+
+#[test]
+fn bad_expr_register() {
+	// The commands after a bad expression still run, but the run fails
+	let result = run_vicut("ab\n", &["-m", "\"=1/0<CR>p", "-m", "x"]);
+	assert_eq!(result.status.code(), Some(1));
+	assert_eq!(String::from_utf8_lossy(&result.stdout).trim_end(), "b");
+
+	let result = run_vicut("ab\n", &["-m", "\"=99999999999999999999<CR>p"]);
+	assert_eq!(result.status.code(), Some(1));
+
+	let result = run_vicut("ab\n", &["-m", "i<c-r>=1/0<CR><esc>"]);
+	assert_eq!(result.status.code(), Some(1));
+}
@@ -0,0 +1,62 @@
+//! SIGINT has to reach the real binary, so unlike the rest of the tests, these run it as a child process.
+#![cfg(unix)]
+
+use std::{io::{BufRead, BufReader, Write}, process::{Command, Stdio}};
+
+fn send_sigint(pid: u32) {
+	let status = Command::new("kill").args(["-INT", &pid.to_string()]).status().unwrap();
+	assert!(status.success());
+}
+
+#[test]
+fn sigint_while_waiting_for_stdin() {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_vicut"))
+		.args(["--stream", "--flush", "record", "-m", "I><esc>"])
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+	// Stdin is left open, so vicut is waiting for more input when the signal arrives
+	let mut stdin = child.stdin.take().unwrap();
+	stdin.write_all(b"a\nb\n").unwrap();
+	stdin.flush().unwrap();
+
+	let mut stdout = BufReader::new(child.stdout.take().unwrap());
+	let mut output = String::new();
+	while output.lines().count() < 2 {
+		assert_ne!(stdout.read_line(&mut output).unwrap(), 0, "output ended early: {output:?}");
+	}
+	send_sigint(child.id());
+
+	let result = child.wait_with_output().unwrap();
+	drop(stdin);
+	stdout.read_line(&mut output).unwrap();
+	assert_eq!(output, ">a\n>b\n");
+	assert_eq!(result.status.code(), Some(130));
+	let stderr = String::from_utf8_lossy(&result.stderr);
+	assert!(stderr.contains("signal=SIGINT completed=2"), "{stderr}");
+}
+
+#[test]
+fn sigint_before_stdin_ends() {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_vicut"))
+		.args(["--linewise", "-m", "I><esc>"])
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+	let mut stdin = child.stdin.take().unwrap();
+	stdin.write_all(b"a\nb\n").unwrap();
+	stdin.flush().unwrap();
+	// Nothing can be worked on until the input ends, so there's nothing to wait for but the signal handlers
+	std::thread::sleep(std::time::Duration::from_millis(300));
+	send_sigint(child.id());
+
+	let result = child.wait_with_output().unwrap();
+	drop(stdin);
+	// The same as for empty input, since no record was started
+	assert_eq!(String::from_utf8_lossy(&result.stdout).trim(), "");
+	assert_eq!(result.status.code(), Some(130));
+}
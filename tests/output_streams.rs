@@ -0,0 +1,25 @@
+//! Only a child process has its own stderr to capture, so these run the real binary like the interrupt tests.
+
+use std::{io::Write, process::{Command, Output, Stdio}};
+
+fn run_script(input: &str, script: &str) -> Output {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_vicut"))
+		.arg(script)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+	child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+	child.wait_with_output().unwrap()
+}
+
+#[test]
+fn echoerr_writes_to_stderr() {
+	let result = run_script("xy\n", "echo \"out\"\nechoerr \"oops\" 2\nprintf(\"%s-%d\\n\", \"a\", 1)");
+	assert!(result.status.success());
+	assert_eq!(String::from_utf8_lossy(&result.stderr), "oops 2\n");
+	// printf and echo share stdout, so they come out in the order they ran
+	let stdout = String::from_utf8_lossy(&result.stdout);
+	assert!(stdout.starts_with("out\na-1\n"), "stdout was {stdout:?}");
+}